@@ -1,20 +1,157 @@
-use clap::{Parser, Subcommand};
+use chrono::{DateTime, Utc};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use log::LevelFilter;
+
+use crate::client::{
+    ClientOptions, DEFAULT_MAX_ATTEMPTS, DEFAULT_MAX_REQUESTS_PER_HOST, DEFAULT_REQUEST_DELAY,
+    DEFAULT_USER_AGENT,
+};
+use crate::export::{Encoding, ExportFormat, Unencodable};
+use crate::feed;
+use crate::parser::ao3::FetchMode;
+use crate::sql::DatabaseOptions;
+use crate::structs::{Completed, TextFormat};
+use crate::update::{parse_cutoff, parse_interval};
+
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 pub(crate) struct Args {
+    /// A TOML file with defaults for some of these options. Without this, the file at
+    /// `fic_archive/config.toml` in the user's config directory is read if there is one.
+    #[arg(global = true, long)]
+    pub config: Option<PathBuf>,
     #[arg(global = true, short, long, default_value = "fic_archive.db")]
     pub db: String,
+    /// How many milliseconds to wait for the database when another process is writing to it.
+    #[arg(global = true, long, default_value_t = 5000)]
+    pub busy_timeout: u64,
+    /// Don't use SQLite's write-ahead log. By default it is used, which keeps `-wal` and `-shm`
+    /// files next to the database while it is open.
+    #[arg(global = true, long)]
+    pub no_wal: bool,
+    /// How many chapters to save at a time when adding a story. Smaller batches hold the
+    /// database lock for less time; 0 saves the whole story at once.
+    #[arg(global = true, long, default_value_t = 500)]
+    pub save_batch_size: usize,
+    /// How to download AO3 works: as one full-work page, chapter by chapter, or chosen
+    /// automatically based on the number of chapters.
+    #[arg(global = true, long, value_enum, default_value_t = FetchMode::Auto)]
+    pub ao3_fetch: FetchMode,
+    /// Keep each source's own scene-break markup instead of replacing every recognized scene
+    /// break with the same horizontal rule.
+    #[arg(global = true, long)]
+    pub raw_scene_breaks: bool,
+    /// How many requests to send to one site at a time. Raising this makes downloads faster but
+    /// risks getting rate limited or banned; 0 means no limit.
+    #[arg(global = true, long, default_value_t = DEFAULT_MAX_REQUESTS_PER_HOST)]
+    pub max_requests: usize,
+    /// How many times to try a request that fails with a server error or gets no response
+    /// before giving up on it.
+    #[arg(global = true, long, default_value_t = DEFAULT_MAX_ATTEMPTS)]
+    pub max_attempts: u32,
+    /// How many milliseconds to leave between requests to one site.
+    #[arg(global = true, long, default_value_t = DEFAULT_REQUEST_DELAY.as_millis() as u64)]
+    pub request_delay_ms: u64,
+    /// Where to keep cookies, like logins, between runs. By default they're kept next to the
+    /// database, in a file named after it ending in `.cookies.json`.
+    #[arg(global = true, long)]
+    pub cookies: Option<PathBuf>,
+    /// The user agent to send with every request.
+    #[arg(global = true, long, default_value = DEFAULT_USER_AGENT)]
+    pub user_agent: String,
+    /// How many seconds to wait for a request before giving up on it. 0 means no limit.
+    #[arg(global = true, long, default_value_t = 60)]
+    pub request_timeout: u64,
+    /// Store identical chapter text only once, even when it appears in several stories.
+    #[arg(global = true, long)]
+    pub dedup_text: bool,
+    /// Print command results as JSON on stdout. Progress and other messages go to stderr.
+    #[arg(global = true, long)]
+    pub json: bool,
+    /// Exit successfully even if some stories could not be added or updated.
+    #[arg(global = true, long)]
+    pub ignore_errors: bool,
+    /// Print more about what is going on, such as each request sent. Repeat for even more.
+    #[arg(global = true, short, long, action = ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+    /// Only print errors, leaving out warnings and progress messages.
+    #[arg(global = true, short, long)]
+    pub quiet: bool,
+    /// Don't show progress bars while downloading. They are also left out when stderr isn't a
+    /// terminal.
+    #[arg(global = true, long)]
+    pub no_progress: bool,
+    /// Download the images in chapters when they're fetched, so that they're kept in the
+    /// archive and put into EPUB exports instead of being left on the sites they came from.
+    #[arg(global = true, long)]
+    pub download_images: bool,
+    /// Show a desktop notification for each story that gets new chapters in `update` or
+    /// `daemon`.
+    #[arg(global = true, long)]
+    pub notify: bool,
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
+impl Args {
+    /// The least severe log messages to print, going by `--verbose` and `--quiet`.
+    pub fn log_level(&self) -> LevelFilter {
+        match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::Error,
+            (false, 0) => LevelFilter::Info,
+            (false, 1) => LevelFilter::Debug,
+            (false, _) => LevelFilter::Trace,
+        }
+    }
+
+    pub fn database_options(&self) -> DatabaseOptions {
+        DatabaseOptions {
+            busy_timeout: Duration::from_millis(self.busy_timeout),
+            wal: !self.no_wal,
+            save_batch_size: self.save_batch_size,
+        }
+    }
+
+    /// The file cookies are loaded from at the start of a run and saved to at the end.
+    pub fn cookie_file(&self) -> PathBuf {
+        match self.cookies {
+            Some(ref path) => path.clone(),
+            None => Path::new(&self.db).with_extension("cookies.json"),
+        }
+    }
+
+    pub fn client_options(&self) -> ClientOptions {
+        ClientOptions {
+            user_agent: self.user_agent.clone(),
+            timeout: match self.request_timeout {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+        }
+    }
+}
+
 #[derive(Debug, Subcommand)]
 pub(crate) enum Commands {
     /// Add one or more stories to the archive.
     Add {
         /// The URLs of the story or stories to add.
         stories: Vec<String>,
+        /// Only save each story's details and chapter list, leaving the chapter text to be
+        /// downloaded later with `hydrate`. Stories already in the archive are updated as usual.
+        #[arg(long)]
+        skeleton_only: bool,
+    },
+
+    /// Download the text of chapters that were saved without it, such as stories added with
+    /// `add --skeleton-only`.
+    Hydrate {
+        /// Hydrate only the story with the given URL.
+        story: Option<String>,
     },
 
     /// Check for updates to stories in the archive.
@@ -23,26 +160,258 @@ pub(crate) enum Commands {
         /// existing chapters).
         #[arg(short = 'f', long = "force")]
         force_refresh: bool,
+        /// Only re-fetch each story's details (name, description, tags, authors, and whether
+        /// it's complete), without looking for new chapters.
+        #[arg(long, conflicts_with = "force_refresh")]
+        metadata_only: bool,
+        /// Check every story, even if the last update of the whole archive was interrupted.
+        /// Otherwise an interrupted update picks up with the stories it hadn't checked yet.
+        #[arg(long)]
+        full: bool,
+        /// Give up on a story if updating it takes longer than this many seconds, counting it
+        /// as failed. 0 means no limit.
+        #[arg(long, default_value_t = 1800)]
+        timeout: u64,
+        /// Only check stories that haven't gained or changed a chapter since this, given as an
+        /// age like `12h`, `7d`, or `2w`, or as a date like `2024-01-31`.
+        #[arg(long, value_parser = parse_cutoff, conflicts_with = "story")]
+        since: Option<DateTime<Utc>>,
+        /// Also check stories marked complete, which are skipped otherwise unless `--force` is
+        /// given.
+        #[arg(long, conflicts_with = "story")]
+        all: bool,
+        /// Only show how many chapters each story would gain or have changed, going by its
+        /// chapter list, without downloading any chapters or saving anything.
+        #[arg(long, conflicts_with = "metadata_only")]
+        dry_run: bool,
         /// Refresh only the story with the given name.
         story: Option<String>,
     },
 
+    /// Stay running and update the whole archive on a schedule, as `update` would, until
+    /// stopped with Ctrl-C or SIGTERM.
+    Daemon {
+        /// How long to wait between updates, like `30m`, `6h`, or `1d`.
+        #[arg(long, value_parser = parse_interval, default_value = "6h")]
+        interval: Duration,
+        /// Run a single update and exit, with a non-zero exit code if any story failed.
+        #[arg(long)]
+        once: bool,
+        /// Write a feed of new chapters to this file after each update, as `feed --output`
+        /// does.
+        #[arg(long)]
+        feed: Option<PathBuf>,
+        /// Give up on a story if updating it takes longer than this many seconds, counting it
+        /// as failed. 0 means no limit.
+        #[arg(long, default_value_t = 1800)]
+        timeout: u64,
+    },
+
+    /// Check stories in the archive for chapters whose posting date was never filled in.
+    Verify {
+        /// Re-fetch those chapters to find their real dates.
+        #[arg(long)]
+        fix: bool,
+        /// Check only the story with the given URL.
+        story: Option<String>,
+    },
+
     /// Delete a story from the archive by ID, title, or author name. If more than one story
-    /// matches, none will be deleted.
+    /// matches, you'll be asked to pick one; with `--yes` or when not run interactively, none
+    /// will be deleted.
     Delete {
-        /// The ID, name, or author of the story to delete.,
+        /// The ID, name, or author of the story to delete.
+        search: String,
+        /// Delete without asking for confirmation.
+        #[arg(short = 'y', long = "yes")]
+        yes: bool,
+    },
+
+    /// Change the name a story is listed and exported under, such as to tidy up a thread title.
+    /// Updates keep the new name instead of going back to the one the site gives.
+    Rename {
+        /// The ID, name, or author of the story to rename.
         search: String,
+        /// The story's new name.
+        #[arg(value_parser = clap::builder::NonEmptyStringValueParser::new())]
+        name: String,
     },
 
     /// Export a story in the archive to a file.
     Export {
         /// The name or ID of the story to export.
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        story: Option<String>,
+        /// Export every story in the archive.
+        #[arg(long)]
+        all: bool,
+        /// The directory to write exported files to.
+        #[arg(long, default_value = ".")]
+        output_dir: PathBuf,
+        /// The format to write the story in.
+        #[arg(long, value_enum, default_value_t = ExportFormat::Html)]
+        format: ExportFormat,
+        /// The character encoding to write the file in. JSON is always written as UTF-8.
+        #[arg(long, alias = "output-encoding", value_enum, default_value_t = Encoding::Utf8)]
+        encoding: Encoding,
+        /// What to do with characters the encoding can't represent.
+        #[arg(long, value_enum, default_value_t = Unencodable::Replace)]
+        unencodable: Unencodable,
+    },
+
+    /// Write an Atom feed of the chapters most recently added to the archive, for following
+    /// new chapters in a feed reader. `serve` also serves it at `/feed.xml`.
+    Feed {
+        /// The file to write the feed to. It's printed instead if this isn't given.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// How many chapters to include.
+        #[arg(short = 'n', long, default_value_t = feed::DEFAULT_LENGTH)]
+        limit: usize,
+    },
+
+    /// Add a story to the archive from a file written by `export --format json`, such as one
+    /// exported on another machine.
+    Import {
+        /// The JSON file to import.
+        file: PathBuf,
+        /// If the story is already in the archive, replace its details and any of its sections
+        /// and chapters that are in the file instead of refusing. Chapters that are only in the
+        /// archive are kept.
+        #[arg(long)]
+        merge: bool,
+    },
+
+    /// Print one chapter of a story, such as to read it in a pager. A chapter that was saved
+    /// without its text is downloaded first.
+    Read {
+        /// The ID, name, or author of the story.
         story: String,
+        /// The chapter to print: its number in reading order (starting from 1), its ID, or its
+        /// name. Defaults to where you left off reading, or the first chapter.
+        #[arg(long)]
+        chapter: Option<String>,
+        /// The format to print the chapter in.
+        #[arg(long, value_enum, default_value_t = TextFormat::Text)]
+        format: TextFormat,
     },
 
     /// List all stories in the archive.
-    List {},
+    List {
+        /// Only list stories written in the given language (e.g. "en").
+        #[arg(long)]
+        language: Option<String>,
+        /// Only list stories with the given tag, like "fandom:Worm" or, for tags added with
+        /// `tag`, "user:favorites". Give more than once to only list stories with all of them.
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// Only list stories that are complete, still ongoing, or whose status isn't known.
+        #[arg(long, value_enum)]
+        status: Option<Completed>,
+        /// The order to list stories in.
+        #[arg(long, value_enum)]
+        sort: Option<ListOrder>,
+    },
+
+    /// Show the details of a story in the archive, including when it was added and last
+    /// updated.
+    Info {
+        /// The name or ID of the story to show.
+        story: String,
+    },
+
+    /// Open a story's page on the site it came from in the default web browser, such as to
+    /// leave a comment.
+    Open {
+        /// The name or ID of the story to open.
+        story: String,
+    },
+
+    /// Search the text and names of every saved chapter for a phrase.
+    Search {
+        /// The words to look for, matched as a phrase.
+        phrase: String,
+    },
+
+    /// Give a story tags of your own, like "to-reread", or take them away. They are kept apart
+    /// from the site's tags under the "user" namespace, and updates leave them alone. With
+    /// neither `--add` nor `--remove`, the story's tags of your own are listed.
+    Tag {
+        /// The ID, name, or author of the story to tag.
+        search: String,
+        /// A tag to give the story. Give more than once to add several.
+        #[arg(long, value_name = "TAG", value_parser = clap::builder::NonEmptyStringValueParser::new())]
+        add: Vec<String>,
+        /// A tag to take off the story. Give more than once to remove several.
+        #[arg(long, value_name = "TAG", value_parser = clap::builder::NonEmptyStringValueParser::new())]
+        remove: Vec<String>,
+    },
+
+    /// List every tag in the archive with how many stories have it, grouped by namespace (the
+    /// part before the `:`, like `fandom` or `genre`).
+    Tags {
+        /// Only list tags in this namespace, like "fandom".
+        #[arg(long)]
+        prefix: Option<String>,
+    },
 
     /// List all accepted sources.
     ListSources,
+
+    /// Serve the archive as an OPDS catalog, so that e-readers like KOReader can browse it and
+    /// download stories as EPUBs. The catalog is at `/opds`, and the feed written by `feed` at
+    /// `/feed.xml`.
+    Serve {
+        /// The port to listen on.
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        /// The address to listen on. The default only accepts connections from this machine;
+        /// use `0.0.0.0` to let other devices on the network connect.
+        #[arg(long, default_value = "127.0.0.1")]
+        address: IpAddr,
+    },
+
+    /// Add a WordPress site that hosts a serial, so its story can be added like any other.
+    AddSite {
+        /// The URL of the site's table of contents page.
+        toc_url: String,
+        /// Where the chapter links are on the table of contents page, as a CSS selector made of
+        /// tag names, `#ids`, and `.classes`.
+        #[arg(long, default_value = ".entry-content")]
+        toc_selector: String,
+        /// Where the text is on each chapter's page, as a CSS selector like `--toc-selector`.
+        #[arg(long, default_value = ".entry-content")]
+        content_selector: String,
+    },
+}
+
+/// Orders stories can be listed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ListOrder {
+    /// Alphabetically by name.
+    Name,
+    /// Most recently added first.
+    Added,
+    /// Most recently updated first.
+    Updated,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_level(args: &[&str]) -> LevelFilter {
+        Args::try_parse_from([&["fic_archive"], args].concat())
+            .unwrap()
+            .log_level()
+    }
+
+    #[test]
+    fn sets_log_level_from_flags() {
+        assert_eq!(log_level(&["list"]), LevelFilter::Info);
+        assert_eq!(log_level(&["-v", "list"]), LevelFilter::Debug);
+        assert_eq!(log_level(&["list", "-vv"]), LevelFilter::Trace);
+        assert_eq!(log_level(&["--quiet", "list"]), LevelFilter::Error);
+        assert!(Args::try_parse_from(["fic_archive", "-q", "-v", "list"]).is_err());
+    }
 }
@@ -1,74 +1,472 @@
-use reqwest::{Client, Response, StatusCode};
+use chrono::{DateTime, Utc};
+use cookie::Cookie;
+use cookie_store::CookieStore;
+use once_cell::sync::{Lazy, OnceCell};
+use reqwest::{
+    header::{HeaderValue, RETRY_AFTER},
+    Client, RequestBuilder, Response, StatusCode, Url,
+};
 use serde::ser::Serialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
-use std::time::Duration;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, ErrorKind, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
-use crate::Result;
+use crate::{error::ArchiveError, Result};
 
-static CLIENT: once_cell::sync::OnceCell<Client> = once_cell::sync::OnceCell::new();
+/// How many requests may be waiting on one host at once unless configured otherwise.
+pub const DEFAULT_MAX_REQUESTS_PER_HOST: usize = 8;
 
-pub async fn get(url: &str) -> Result<Response> {
-    let client: &Client =
-        CLIENT.get_or_init(|| Client::builder().cookie_store(true).build().unwrap());
-    let mut response = client.get(url).send().await?;
+/// How many times to try a request that keeps failing unless configured otherwise.
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// How long to leave between requests to one host unless configured otherwise.
+pub const DEFAULT_REQUEST_DELAY: Duration = Duration::from_millis(500);
+
+/// The longest a rate-limited request waits before it's tried again, however long the site asks
+/// for, so that a site asking for hours can't stall a run.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(10 * 60);
+
+static MAX_REQUESTS_PER_HOST: OnceCell<usize> = OnceCell::new();
+static MAX_ATTEMPTS: OnceCell<u32> = OnceCell::new();
+static REQUEST_DELAY: OnceCell<Duration> = OnceCell::new();
+/// When the next request to each host may be sent.
+static NEXT_REQUEST: Lazy<Mutex<HashMap<String, Instant>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static HOST_LIMITS: Lazy<Mutex<HashMap<String, Arc<Semaphore>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+/// The cookies every client built by [`new_client`] sends and saves.
+static COOKIES: Lazy<Arc<CookieJar>> = Lazy::new(|| Arc::new(CookieJar::default()));
+
+/// Sets how many requests may be waiting on one host at once for the rest of the run, with 0
+/// meaning no limit. Only the first call has any effect.
+pub(crate) fn set_max_requests_per_host(max: usize) {
+    let _ = MAX_REQUESTS_PER_HOST.set(max);
+}
+
+/// Sets how many times a request that keeps being rate limited or failing with a server error or
+/// without getting a response is tried before giving up, for the rest of the run. Only the first call has any
+/// effect.
+pub(crate) fn set_max_attempts(max: u32) {
+    let _ = MAX_ATTEMPTS.set(max);
+}
+
+/// Sets how long to leave between sending one request to a host and sending the next, for the
+/// rest of the run. Only the first call has any effect.
+pub(crate) fn set_request_delay(delay: Duration) {
+    let _ = REQUEST_DELAY.set(delay);
+}
+
+/// Adds a cookie, written as in a `Set-Cookie` header, to the ones sent to `url`'s site, such as
+/// a session cookie copied from a browser.
+pub(crate) fn add_cookie(cookie: &str, url: &str) -> Result<()> {
+    let url =
+        Url::parse(url).map_err(|e| ArchiveError::Internal(format!("Bad URL {}: {}", url, e)))?;
+    let cookie = Cookie::parse(cookie.to_owned())
+        .map_err(|e| ArchiveError::Internal(format!("Bad cookie {:?}: {}", cookie, e)))?;
+    COOKIES
+        .0
+        .write()
+        .unwrap()
+        .store_response_cookies(std::iter::once(cookie), &url);
+    Ok(())
+}
+
+/// Replaces the cookies requests are sent with by the ones saved to `path` by [`save_cookies`],
+/// dropping any that have expired since. A missing file means there are none yet.
+pub(crate) fn load_cookies(path: &Path) -> Result<()> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    let store = CookieStore::load_json(BufReader::new(file)).map_err(|e| {
+        ArchiveError::Internal(format!(
+            "Could not read cookies from {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    *COOKIES.0.write().unwrap() = store;
+    Ok(())
+}
+
+/// Saves the cookies sites have set during the run to `path`, so that logins carry over to the
+/// next one. Cookies that would end with a browser session are saved too, since a session here
+/// is only one run long. The file holds session tokens, so only its owner may read it.
+pub(crate) fn save_cookies(path: &Path) -> Result<()> {
+    let mut saved = String::new();
+    for cookie in COOKIES.0.read().unwrap().iter_unexpired() {
+        let line = serde_json::to_string(cookie)
+            .map_err(|e| ArchiveError::Internal(format!("Could not save a cookie: {}", e)))?;
+        saved.push_str(&line);
+        saved.push('\n');
+    }
+    // Don't leave an empty file behind after runs that never touched a site.
+    if saved.is_empty() && !path.exists() {
+        return Ok(());
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // The mode only applies to new files, so tighten up one that was already there.
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(path)?.write_all(saved.as_bytes())?;
+    Ok(())
+}
+
+/// A cookie store that can be written to disk, which reqwest's own `Jar` can't.
+#[derive(Debug, Default)]
+struct CookieJar(RwLock<CookieStore>);
+
+impl reqwest::cookie::CookieStore for CookieJar {
+    fn set_cookies(&self, headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let cookies = headers.filter_map(|header| {
+            let header = header.to_str().ok()?;
+            Cookie::parse(header.to_owned()).ok()
+        });
+        self.0.write().unwrap().store_response_cookies(cookies, url);
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let cookies = self
+            .0
+            .read()
+            .unwrap()
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        match cookies.is_empty() {
+            true => None,
+            false => HeaderValue::from_str(&cookies).ok(),
+        }
+    }
+}
+
+/// The host `url` points at, or an empty string if it doesn't point at one.
+fn host(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_owned))
+        .unwrap_or_default()
+}
+
+/// The semaphore limiting requests to the host `url` points at.
+fn host_limit(url: &str) -> Arc<Semaphore> {
+    let host = host(url);
+    let permits = match MAX_REQUESTS_PER_HOST
+        .get()
+        .copied()
+        .unwrap_or(DEFAULT_MAX_REQUESTS_PER_HOST)
+    {
+        // The most permits a tokio semaphore can hold.
+        0 => usize::MAX >> 3,
+        max => max,
+    };
+    HOST_LIMITS
+        .lock()
+        .unwrap()
+        .entry(host)
+        .or_insert_with(|| Arc::new(Semaphore::new(permits)))
+        .clone()
+}
+
+/// Waits until another request to the host `url` points at is allowed. The request counts
+/// against the limit until the returned permit is dropped.
+async fn wait_for_host(url: &str) -> OwnedSemaphorePermit {
+    host_limit(url)
+        .acquire_owned()
+        .await
+        .expect("Host request limits are never closed")
+}
+
+/// Claims the earliest time a request to `host` may be sent, given that it's now `now`, and
+/// pushes the next request to it back by `delay`.
+fn reserve_slot(host: &str, now: Instant, delay: Duration) -> Instant {
+    let mut next_request = NEXT_REQUEST.lock().unwrap();
+    let slot = next_request.get(host).map_or(now, |&next| next.max(now));
+    next_request.insert(host.to_owned(), slot + delay);
+    slot
+}
+
+/// Waits until enough time has passed since the last request to the host `url` points at.
+async fn wait_for_turn(url: &str) {
+    let delay = REQUEST_DELAY
+        .get()
+        .copied()
+        .unwrap_or(DEFAULT_REQUEST_DELAY);
+    if delay.is_zero() {
+        return;
+    }
+    let slot = reserve_slot(&host(url), Instant::now(), delay);
+    tokio::time::sleep_until(slot.into()).await;
+}
+
+/// The user agent requests are sent with unless configured otherwise.
+pub const DEFAULT_USER_AGENT: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    "/",
+    env!("CARGO_PKG_VERSION"),
+    " (fanfiction archiver)"
+);
+
+/// How the HTTP client identifies itself and how long it waits on requests.
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// The `User-Agent` header sent with every request. Some sites block requests that don't
+    /// look like they come from a real program.
+    pub user_agent: String,
+    /// How long to wait for a request to finish, from connecting to reading the whole body,
+    /// before giving up on it. `None` waits forever.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            user_agent: DEFAULT_USER_AGENT.to_owned(),
+            timeout: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+/// Builds the client that a run's requests go through. Every parser shares it, so cookies and
+/// connections carry over from one request to the next.
+pub fn new_client(options: &ClientOptions) -> Result<Client> {
+    let mut builder = Client::builder()
+        .cookie_provider(COOKIES.clone())
+        .user_agent(options.user_agent.as_str());
+    if let Some(timeout) = options.timeout {
+        builder = builder.timeout(timeout);
+    }
+    Ok(builder.build()?)
+}
+
+/// Sends a GET request to `url`, waiting out any rate limiting. Parsers can fire off as many of
+/// these at once as they like; only a limited number per host are sent at a time.
+pub async fn get(client: &Client, url: &str) -> Result<Response> {
+    send(url, || client.get(url)).await
+}
+
+pub async fn get_with_query<T: Serialize + ?Sized>(
+    client: &Client,
+    url: &str,
+    query: &T,
+) -> Result<Response> {
+    send(url, || client.get(url).query(query)).await
+}
+
+/// Sends a POST request with `form` as its URL-encoded body, such as to submit a login form.
+pub async fn post_form<T: Serialize + ?Sized>(
+    client: &Client,
+    url: &str,
+    form: &T,
+) -> Result<Response> {
+    send(url, || client.post(url).form(form)).await
+}
+
+/// Sends the request built by `request`, building and sending it again when the site is rate
+/// limiting us or the request fails in a way that might not happen a second time. Rate-limited
+/// requests wait as long as the site asks, up to [`MAX_RETRY_AFTER`]; server errors and failures
+/// to get a response at all are retried with exponential backoff. Either way, a request is only
+/// tried up to the configured number of attempts.
+async fn send(url: &str, request: impl Fn() -> RequestBuilder) -> Result<Response> {
+    let _permit = wait_for_host(url).await;
+    let max_attempts = MAX_ATTEMPTS
+        .get()
+        .copied()
+        .unwrap_or(DEFAULT_MAX_ATTEMPTS)
+        .max(1);
+    let mut attempt = 1;
     loop {
-        match response.status() {
-            StatusCode::TOO_MANY_REQUESTS => {
-                let base_url = &url[url.find("://").unwrap() + 3..];
-                let base_url = &base_url[0..base_url.find("/").unwrap_or(base_url.len())];
-                let time_to_wait: String = response.headers().get("retry-after").map_or_else(
-                    || "60".to_owned(),
-                    |v| {
-                        v.to_str()
-                            .map(|ok| ok.to_owned())
-                            .unwrap_or("60".to_owned())
-                    },
-                );
-                let time_to_wait = u64::from_str_radix(&time_to_wait, 10).expect(&format!(
-                    "retry-after header {} is not a number",
-                    time_to_wait
-                ));
-                println!(
-                    "Too many requests to {}. Sleeping for {} seconds.",
-                    base_url, time_to_wait
-                );
-                tokio::time::sleep(Duration::from_secs(time_to_wait)).await;
-                response = client.get(url).send().await?;
+        wait_for_turn(url).await;
+        log::debug!("Requesting {} (attempt {})", url, attempt);
+        let (failure, wait) = match request().send().await {
+            Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => (
+                response.status().to_string(),
+                retry_after(response.headers().get(RETRY_AFTER), Utc::now()),
+            ),
+            Ok(response) if response.status().is_server_error() => {
+                (response.status().to_string(), backoff(attempt))
+            }
+            Ok(response) => break Ok(response),
+            Err(err) if err.is_connect() || err.is_timeout() || err.is_request() => {
+                (err.to_string(), backoff(attempt))
             }
-            _ => break Ok(response),
+            Err(err) => break Err(err.into()),
+        };
+        if attempt >= max_attempts {
+            break Err(ArchiveError::RequestFailed(
+                url.to_owned(),
+                attempt,
+                failure,
+            ));
         }
+        log::info!(
+            "Request to {} failed ({}). Retrying in {} seconds.",
+            url,
+            failure,
+            wait.as_secs()
+        );
+        tokio::time::sleep(wait).await;
+        attempt += 1;
     }
 }
 
-pub async fn get_with_query<T: Serialize + ?Sized>(url: &str, query: &T) -> Result<Response> {
-    let client: &Client =
-        CLIENT.get_or_init(|| Client::builder().cookie_store(true).build().unwrap());
-    let mut response = client.get(url).query(query).send().await?;
-    loop {
-        match response.status() {
-            StatusCode::TOO_MANY_REQUESTS => {
-                let base_url = &url[url.find("://").unwrap() + 3..];
-                let base_url = &base_url[0..base_url.find("/").unwrap_or(base_url.len())];
-                let time_to_wait: String = response.headers().get("retry-after").map_or_else(
-                    || "60".to_owned(),
-                    |v| {
-                        v.to_str()
-                            .map(|ok| ok.to_owned())
-                            .unwrap_or("60".to_owned())
-                    },
-                );
-                let time_to_wait = u64::from_str_radix(&time_to_wait, 10).expect(&format!(
-                    "retry-after header {} is not a number",
-                    time_to_wait
-                ));
-                println!(
-                    "Too many requests to {}. Sleeping for {} seconds.",
-                    base_url, time_to_wait
-                );
-                tokio::time::sleep(Duration::from_secs(time_to_wait)).await;
-                response = client.get(url).query(query).send().await?;
+/// How long a `Retry-After` header says to wait, given the time it was received, but no longer
+/// than [`MAX_RETRY_AFTER`]. The header may be a number of seconds or an HTTP date; if it's
+/// missing or neither, wait a minute.
+fn retry_after(header: Option<&HeaderValue>, now: DateTime<Utc>) -> Duration {
+    requested_wait(header, now).min(MAX_RETRY_AFTER)
+}
+
+fn requested_wait(header: Option<&HeaderValue>, now: DateTime<Utc>) -> Duration {
+    let default = Duration::from_secs(60);
+    let Some(value) = header.and_then(|value| value.to_str().ok()).map(str::trim) else {
+        return default;
+    };
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Duration::from_secs(seconds);
+    }
+    match DateTime::parse_from_rfc2822(value) {
+        // A date that has already passed means the request can be retried right away.
+        Ok(date) => (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+        Err(_) => default,
+    }
+}
+
+/// How long to wait before retrying a request that has failed `attempt` times: a second after
+/// the first failure, doubling after each one after that, up to a minute.
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(1u64 << attempt.saturating_sub(1).min(6)).min(Duration::from_secs(60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_cookies_between_runs() {
+        let path = std::env::temp_dir().join(format!("fic_archive_{}.cookies", std::process::id()));
+        add_cookie("session=abc", "https://cookies.example.com").unwrap();
+        save_cookies(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        *COOKIES.0.write().unwrap() = CookieStore::default();
+        load_cookies(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let url = Url::parse("https://cookies.example.com/page").unwrap();
+        assert_eq!(
+            reqwest::cookie::CookieStore::cookies(&**COOKIES, &url),
+            Some(HeaderValue::from_static("session=abc"))
+        );
+    }
+
+    #[test]
+    fn limits_requests_per_host() {
+        let first = host_limit("https://limits.example.com/a");
+        assert!(Arc::ptr_eq(
+            &first,
+            &host_limit("https://limits.example.com/b?page=2")
+        ));
+        assert!(!Arc::ptr_eq(
+            &first,
+            &host_limit("https://other.example.com/a")
+        ));
+        assert_eq!(first.available_permits(), DEFAULT_MAX_REQUESTS_PER_HOST);
+    }
+
+    #[test]
+    fn reads_retry_after_seconds_and_dates() {
+        let now = DateTime::parse_from_rfc3339("2025-10-21T07:27:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let wait = |value: &str| retry_after(Some(&HeaderValue::from_str(value).unwrap()), now);
+        assert_eq!(wait("120"), Duration::from_secs(120));
+        assert_eq!(wait("86400"), MAX_RETRY_AFTER);
+        assert_eq!(
+            wait("Tue, 21 Oct 2025 07:29:30 GMT"),
+            Duration::from_secs(150)
+        );
+        assert_eq!(wait("Tue, 21 Oct 2025 07:00:00 GMT"), Duration::ZERO);
+        assert_eq!(wait("soon"), Duration::from_secs(60));
+        assert_eq!(wait("-5"), Duration::from_secs(60));
+        assert_eq!(retry_after(None, now), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_hosts_that_keep_rate_limiting() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/story", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(0));
+        let counted = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).await;
+                *counted.lock().unwrap() += 1;
+                let _ = stream
+                    .write_all(
+                        b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\n\
+                        Content-Length: 0\r\nConnection: close\r\n\r\n",
+                    )
+                    .await;
+            }
+        });
+
+        let client = new_client(&Default::default()).unwrap();
+        match get(&client, &url).await {
+            Err(ArchiveError::RequestFailed(failed_url, attempts, _)) => {
+                assert_eq!(failed_url, url);
+                assert_eq!(attempts, DEFAULT_MAX_ATTEMPTS);
             }
-            _ => break Ok(response),
+            other => panic!("expected the request to fail, got {:?}", other.map(|_| ())),
         }
+        assert_eq!(*requests.lock().unwrap(), DEFAULT_MAX_ATTEMPTS);
+    }
+
+    #[test]
+    fn spaces_out_requests_to_each_host() {
+        let delay = Duration::from_millis(500);
+        let now = Instant::now();
+        let slots: Vec<Duration> = (0..3)
+            .map(|_| reserve_slot("spaced.example.com", now, delay) - now)
+            .collect();
+        assert_eq!(
+            slots,
+            vec![Duration::ZERO, delay, delay * 2],
+            "requests to one host wait their turn"
+        );
+        assert_eq!(reserve_slot("other.example.com", now, delay), now);
+        // Once the host has been idle for longer than the delay, requests go out right away.
+        let later = now + delay * 10;
+        assert_eq!(reserve_slot("spaced.example.com", later, delay), later);
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        let waits: Vec<u64> = (1..=9).map(|attempt| backoff(attempt).as_secs()).collect();
+        assert_eq!(waits, vec![1, 2, 4, 8, 16, 32, 60, 60, 60]);
     }
 }
@@ -0,0 +1,168 @@
+use clap::{parser::ValueSource, ArgMatches};
+use serde::Deserialize;
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::args::{Args, Commands};
+use crate::error::ArchiveError;
+use crate::export::ExportFormat;
+use crate::Result;
+
+/// Defaults read from a TOML config file. Each setting is named after the flag it stands in
+/// for, and a flag given on the command line always wins over it.
+///
+/// ```toml
+/// db = "/home/me/fics/archive.db"
+/// max-requests = 2
+/// request-delay-ms = 1500
+/// user-agent = "my-archiver/1.0"
+/// export-format = "epub"
+/// notify = true
+///
+/// # Either of these lets works restricted to logged-in users be downloaded.
+/// ao3-username = "me"
+/// ao3-password = "hunter2"
+/// # The `_otwarchive_session` cookie from a browser that's logged in.
+/// ao3-session = "..."
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Config {
+    pub db: Option<String>,
+    pub max_requests: Option<usize>,
+    pub request_delay_ms: Option<u64>,
+    pub user_agent: Option<String>,
+    pub export_format: Option<ExportFormat>,
+    pub notify: Option<bool>,
+    pub ao3_username: Option<String>,
+    pub ao3_password: Option<String>,
+    pub ao3_session: Option<String>,
+}
+
+/// Where the config file is looked for when `--config` isn't given, such as
+/// `~/.config/fic_archive/config.toml` on Linux.
+fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("fic_archive").join("config.toml"))
+}
+
+impl Config {
+    /// Reads the config file at `path`, or at the default path if there is none. A missing file
+    /// at the default path just means there's no config, but one given with `--config` has to
+    /// exist.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path.to_owned(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text)
+                .map_err(|reason| ArchiveError::BadConfig(path.display().to_string(), reason)),
+            Err(err) if err.kind() == ErrorKind::NotFound && !required => Ok(Self::default()),
+            Err(err) => Err(ArchiveError::BadConfig(
+                path.display().to_string(),
+                err.to_string(),
+            )),
+        }
+    }
+
+    fn parse(text: &str) -> std::result::Result<Self, String> {
+        toml::from_str(text).map_err(|err| err.to_string())
+    }
+
+    /// Fills in `args` from the config wherever `matches` shows the flag was left to its
+    /// built-in default.
+    pub fn apply(&self, args: &mut Args, matches: &ArgMatches) {
+        let from_config = |matches: &ArgMatches, id: &str| {
+            matches.value_source(id) != Some(ValueSource::CommandLine)
+        };
+        if let Some(db) = self.db.clone().filter(|_| from_config(matches, "db")) {
+            args.db = db;
+        }
+        if let Some(max) = self
+            .max_requests
+            .filter(|_| from_config(matches, "max_requests"))
+        {
+            args.max_requests = max;
+        }
+        if let Some(delay) = self
+            .request_delay_ms
+            .filter(|_| from_config(matches, "request_delay_ms"))
+        {
+            args.request_delay_ms = delay;
+        }
+        if let Some(agent) = self
+            .user_agent
+            .clone()
+            .filter(|_| from_config(matches, "user_agent"))
+        {
+            args.user_agent = agent;
+        }
+        if let Some(notify) = self.notify.filter(|_| from_config(matches, "notify")) {
+            args.notify = notify;
+        }
+        if let (Some(Commands::Export { format, .. }), Some(("export", export))) =
+            (args.command.as_mut(), matches.subcommand())
+        {
+            if let Some(default) = self.export_format.filter(|_| from_config(export, "format")) {
+                *format = default;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    fn args_with(config: &str, argv: &[&str]) -> Args {
+        let matches = Args::command()
+            .try_get_matches_from([&["fic_archive"], argv].concat())
+            .unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        Config::parse(config).unwrap().apply(&mut args, &matches);
+        args
+    }
+
+    #[test]
+    fn flags_override_config_which_overrides_defaults() {
+        let config = "db = \"config.db\"\nmax-requests = 7\nexport-format = \"epub\"\n";
+        let args = args_with(config, &["export", "--all"]);
+        assert_eq!(args.db, "config.db");
+        assert_eq!(args.max_requests, 7);
+        assert!(matches!(
+            args.command,
+            Some(Commands::Export {
+                format: ExportFormat::Epub,
+                ..
+            })
+        ));
+
+        let args = args_with(
+            config,
+            &["-d", "flag.db", "export", "--all", "--format", "html"],
+        );
+        assert_eq!(args.db, "flag.db");
+        assert!(matches!(
+            args.command,
+            Some(Commands::Export {
+                format: ExportFormat::Html,
+                ..
+            })
+        ));
+
+        let args = args_with("", &["list"]);
+        assert_eq!(args.db, "fic_archive.db");
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        assert!(Config::parse("database = \"x.db\"").is_err());
+        assert!(Config::parse("export-format = \"pdf\"").is_err());
+    }
+}
@@ -1,4 +1,6 @@
-use std::{error::Error, fmt};
+use std::{error::Error, fmt, time::Duration};
+
+use crate::structs::supported_sources;
 
 #[derive(Debug)]
 pub enum ArchiveError {
@@ -6,9 +8,22 @@ pub enum ArchiveError {
     BadSource(String),
     NoIdInSource(String, String),
     PageError(String),
+    BadSelector(String, String),
     StoryNotExists(String),
+    StoryExists(String),
+    ChapterNotExists(String),
+    BadImport(String),
+    BadConfig(String, String),
+    LoginRequired(String),
+    LoginFailed(String, String),
+    NoAuthors,
+    StoriesFailed(usize, usize),
+    Unencodable(char, String),
+    TimedOut(String, Duration),
+    RequestFailed(String, u32, String),
     Io(std::io::Error),
     Request(reqwest::Error),
+    Serve(hyper::Error),
     Database(rusqlite::Error),
     Parse(chrono::format::ParseError),
     ParseInt(std::num::ParseIntError),
@@ -18,19 +33,76 @@ impl fmt::Display for ArchiveError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Self::Internal(ref s) => write!(f, "Internal error: {}", s),
-            Self::BadSource(ref s) => write!(f, "Could not convert URL {} to a story source", s),
+            Self::BadSource(ref s) => write!(
+                f,
+                "Could not convert URL {} to a story source. Supported sites are {}",
+                s,
+                supported_sources()
+                    .iter()
+                    .map(|source| source.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
             Self::NoIdInSource(ref url, ref name) => write!(
                 f,
                 "Url {url} maps to source {name} and must contain a story ID, but does not"
             ),
             Self::PageError(ref s) => write!(f, "{}", s),
+            Self::BadSelector(ref selector, ref reason) => {
+                write!(f, "Invalid selector {:?}: {}", selector, reason)
+            }
             Self::StoryNotExists(ref s) => write!(
                 f,
                 "Story {} does not exist in the archive. Try adding it first.",
                 s
             ),
+            Self::StoryExists(ref s) => write!(
+                f,
+                "Story {} is already in the archive. Use --merge to import it anyway.",
+                s
+            ),
+            Self::ChapterNotExists(ref s) => write!(f, "Chapter {} could not be found", s),
+            Self::BadImport(ref s) => write!(f, "Could not import story: {}", s),
+            Self::BadConfig(ref path, ref reason) => {
+                write!(f, "Could not read config file {}: {}", path, reason)
+            }
+            Self::LoginRequired(ref url) => write!(
+                f,
+                "{} can only be seen by logged-in users. Add a login for the site to the config \
+                file to download it.",
+                url
+            ),
+            Self::LoginFailed(ref site, ref reason) => {
+                write!(f, "Could not log in to {}: {}", site, reason)
+            }
+            Self::NoAuthors => write!(f, "Story has no authors"),
+            Self::StoriesFailed(failed, total) => write!(
+                f,
+                "{failed} of {total} {} failed",
+                if total == 1 { "story" } else { "stories" }
+            ),
+            Self::Unencodable(c, ref encoding) => write!(
+                f,
+                "Character {:?} (U+{:04X}) cannot be written in {}",
+                c, c as u32, encoding
+            ),
+            Self::TimedOut(ref url, limit) => write!(
+                f,
+                "Gave up on story at {} after {} seconds",
+                url,
+                limit.as_secs()
+            ),
+            Self::RequestFailed(ref url, attempts, ref reason) => write!(
+                f,
+                "Gave up on {} after {} {}: {}",
+                url,
+                attempts,
+                if attempts == 1 { "attempt" } else { "attempts" },
+                reason
+            ),
             Self::Io(ref err) => err.fmt(f),
             Self::Request(ref err) => err.fmt(f),
+            Self::Serve(ref err) => write!(f, "Could not serve the archive: {}", err),
             Self::Database(ref err) => err.fmt(f),
             Self::Parse(ref err) => err.fmt(f),
             Self::ParseInt(ref err) => err.fmt(f),
@@ -50,6 +122,12 @@ impl From<reqwest::Error> for ArchiveError {
     }
 }
 
+impl From<hyper::Error> for ArchiveError {
+    fn from(err: hyper::Error) -> ArchiveError {
+        Self::Serve(err)
+    }
+}
+
 impl From<rusqlite::Error> for ArchiveError {
     fn from(err: rusqlite::Error) -> ArchiveError {
         Self::Database(err)
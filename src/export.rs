@@ -0,0 +1,488 @@
+use clap::ValueEnum;
+use encoding_rs::{EncoderResult, WINDOWS_1252};
+use serde::Deserialize;
+
+use crate::error::ArchiveError;
+use crate::images::with_remote_urls;
+use crate::parser::convert_to_format;
+use crate::structs::{ChapterImage, Content, Cover, Story, TextFormat};
+use crate::Result;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+mod epub;
+mod zip;
+
+pub(crate) use self::epub::{story_to_epub, xml_text};
+
+/// The kind of file a story is exported as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ExportFormat {
+    Html,
+    Markdown,
+    /// The whole story, chapter text included, as it's stored in the archive. Always UTF-8.
+    Json,
+    /// An e-book with the story's cover, or a plain one with its name if it has none. Always
+    /// UTF-8.
+    Epub,
+}
+
+/// The character encoding exported files are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Encoding {
+    #[value(name = "utf-8")]
+    Utf8,
+    /// Latin-1 as e-readers and browsers understand it, which is to say Windows-1252.
+    #[value(name = "latin-1")]
+    Latin1,
+    /// Little-endian UTF-16 with a byte order mark.
+    #[value(name = "utf-16")]
+    Utf16,
+}
+
+impl Encoding {
+    fn charset(&self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf-8",
+            Self::Latin1 => "windows-1252",
+            Self::Utf16 => "utf-16",
+        }
+    }
+}
+
+/// What to do with characters the chosen encoding can't represent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Unencodable {
+    /// Write them as numeric character references (`&#8212;`), which displays the same.
+    Replace,
+    /// Stop the export.
+    Error,
+}
+
+/// The images saved along with a story, which only EPUBs include.
+#[derive(Debug, Default)]
+pub(crate) struct StoryMedia {
+    pub cover: Option<Cover>,
+    /// Images from chapter text, saved with `--download-images`.
+    pub images: Vec<ChapterImage>,
+}
+
+/// The name, without extension, that a story is exported under.
+pub(crate) fn file_stem(story: &Story) -> String {
+    sanitize_filename(&story.name)
+}
+
+/// Writes a story to a single file named `stem` in `dir`, returning the path written to. Only
+/// EPUBs use the story's media; other formats link to images where they came from.
+pub(crate) fn write_story(
+    story: &Story,
+    media: &StoryMedia,
+    dir: &Path,
+    stem: &str,
+    format: ExportFormat,
+    encoding: Encoding,
+    unencodable: Unencodable,
+) -> Result<PathBuf> {
+    let (bytes, extension) = match format {
+        ExportFormat::Html => (
+            encode(&story_to_html(story, encoding), encoding, unencodable)?,
+            "html",
+        ),
+        ExportFormat::Markdown => (
+            encode(&story_to_markdown(story), encoding, unencodable)?,
+            "md",
+        ),
+        // JSON has to be UTF-8, so the encoding doesn't apply.
+        ExportFormat::Json => (story_to_json(story)?.into_bytes(), "json"),
+        ExportFormat::Epub => (epub::story_to_epub(story, media), "epub"),
+    };
+    let path = dir.join(format!("{}.{}", stem, extension));
+    fs::write(&path, bytes)?;
+    Ok(path)
+}
+
+fn story_to_html(story: &Story, encoding: Encoding) -> String {
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n");
+    match story.language {
+        Some(ref language) => html.push_str(&format!("<html lang=\"{}\">\n", escape(language))),
+        None => html.push_str("<html>\n"),
+    }
+    html.push_str(&format!(
+        "<head>\n<meta charset=\"{}\">\n<title>{}</title>\n</head>\n<body>\n",
+        encoding.charset(),
+        escape(&story.name)
+    ));
+    html.push_str(&format!("<h1>{}</h1>\n", escape(&story.name)));
+    html.push_str(&format!(
+        "<p>by {}</p>\n",
+        escape(&story.authors.to_string())
+    ));
+    if let Some(ref description) = story.description {
+        html.push_str(&format!(
+            "<div class=\"description\">{}</div>\n",
+            description
+        ));
+    }
+    html.push_str("<nav class=\"contents\">\n<h2>Contents</h2>\n");
+    push_table_of_contents(&mut html, &story.chapters);
+    html.push_str("</nav>\n");
+    push_contents(&mut html, &story.chapters, 2);
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Writes a nested list linking to every section and chapter.
+fn push_table_of_contents(html: &mut String, contents: &[Content]) {
+    html.push_str("<ul>\n");
+    for content in contents {
+        html.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a>",
+            anchor(content.id()),
+            escape(content.name())
+        ));
+        if let Content::Section(section) = content {
+            html.push('\n');
+            push_table_of_contents(html, &section.chapters);
+        }
+        html.push_str("</li>\n");
+    }
+    html.push_str("</ul>\n");
+}
+
+/// Writes out sections and chapters, giving each level of nesting a smaller heading.
+fn push_contents(html: &mut String, contents: &[Content], level: usize) {
+    let level = level.min(6);
+    for content in contents {
+        html.push_str(&format!(
+            "<h{0} id=\"{1}\">{2}</h{0}>\n",
+            level,
+            anchor(content.id()),
+            escape(content.name())
+        ));
+        match content {
+            Content::Section(section) => push_contents(html, &section.chapters, level + 1),
+            Content::Chapter(chapter) => {
+                html.push_str(&with_remote_urls(chapter.text.as_str()));
+                html.push('\n');
+            }
+        }
+    }
+}
+
+fn story_to_markdown(story: &Story) -> String {
+    let mut markdown = format!("# {}\n\nby {}\n\n", story.name, story.authors);
+    if let Some(ref description) = story.description {
+        markdown.push_str(convert_to_format(description.clone(), TextFormat::Markdown).trim());
+        markdown.push_str("\n\n");
+    }
+    push_markdown_contents(&mut markdown, &story.chapters, 2);
+    markdown
+}
+
+fn push_markdown_contents(markdown: &mut String, contents: &[Content], level: usize) {
+    let level = level.min(6);
+    for content in contents {
+        markdown.push_str(&format!("{} {}\n\n", "#".repeat(level), content.name()));
+        match content {
+            Content::Section(section) => {
+                push_markdown_contents(markdown, &section.chapters, level + 1)
+            }
+            Content::Chapter(chapter) => {
+                let text = convert_to_format(
+                    with_remote_urls(chapter.text.as_str()).into_owned(),
+                    TextFormat::Markdown,
+                );
+                markdown.push_str(text.trim());
+                markdown.push_str("\n\n");
+            }
+        }
+    }
+}
+
+fn story_to_json(story: &Story) -> Result<String> {
+    let mut json = serde_json::to_string_pretty(story)
+        .map_err(|e| ArchiveError::Internal(format!("Could not serialize story: {}", e)))?;
+    json.push('\n');
+    Ok(json)
+}
+
+/// Reads a story from a file written by a JSON export.
+pub(crate) fn read_story(path: &Path) -> Result<Story> {
+    story_from_json(&fs::read_to_string(path)?)
+}
+
+/// Parses an exported story, making sure every section and chapter in it belongs to the story
+/// its source says it is.
+fn story_from_json(json: &str) -> Result<Story> {
+    let story: Story =
+        serde_json::from_str(json).map_err(|e| ArchiveError::BadImport(e.to_string()))?;
+    let prefix = format!("{}:", story.source.to_id());
+    match foreign_content(&story.chapters, &prefix) {
+        Some(id) => Err(ArchiveError::BadImport(format!(
+            "{} doesn't belong to story {}",
+            id,
+            story.source.to_id()
+        ))),
+        None => Ok(story),
+    }
+}
+
+/// The id of the first section or chapter whose id doesn't start with `prefix`, if there is one.
+fn foreign_content<'a>(contents: &'a [Content], prefix: &str) -> Option<&'a str> {
+    contents.iter().find_map(|content| {
+        if !content.id().starts_with(prefix) {
+            return Some(content.id());
+        }
+        match content {
+            Content::Section(section) => foreign_content(&section.chapters, prefix),
+            Content::Chapter(_) => None,
+        }
+    })
+}
+
+/// Turns a section or chapter id into something usable as an HTML id and link target.
+fn anchor(id: &str) -> String {
+    id.chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '-',
+        })
+        .collect()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Turns a story name into something safe to use as a file name on any platform.
+fn sanitize_filename(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let sanitized = sanitized.trim().trim_matches('.');
+    match sanitized.is_empty() {
+        true => "story".to_owned(),
+        false => sanitized.to_owned(),
+    }
+}
+
+/// Encodes exported HTML. UTF-8 is passed through untouched.
+fn encode(text: &str, encoding: Encoding, unencodable: Unencodable) -> Result<Vec<u8>> {
+    match encoding {
+        Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+        Encoding::Utf16 => {
+            let mut bytes = Vec::with_capacity(text.len() * 2 + 2);
+            bytes.extend_from_slice(&[0xFF, 0xFE]);
+            for unit in text.encode_utf16() {
+                bytes.extend_from_slice(&unit.to_le_bytes());
+            }
+            Ok(bytes)
+        }
+        Encoding::Latin1 => {
+            let mut encoder = WINDOWS_1252.new_encoder();
+            let mut bytes = Vec::with_capacity(text.len());
+            let mut rest = text;
+            loop {
+                let mut buffer = [0u8; 4096];
+                let (result, read, written) =
+                    encoder.encode_from_utf8_without_replacement(rest, &mut buffer, true);
+                bytes.extend_from_slice(&buffer[..written]);
+                rest = &rest[read..];
+                match result {
+                    EncoderResult::InputEmpty => return Ok(bytes),
+                    EncoderResult::OutputFull => continue,
+                    EncoderResult::Unmappable(c) => match unencodable {
+                        Unencodable::Replace => {
+                            bytes.extend_from_slice(format!("&#{};", c as u32).as_bytes())
+                        }
+                        Unencodable::Error => {
+                            return Err(ArchiveError::Unencodable(c, "latin-1".to_owned()))
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::{Database, DatabaseOptions};
+    use crate::structs::{
+        Author, AuthorList, Chapter, ChapterText, Completed, Section, StorySource,
+    };
+
+    #[test]
+    fn utf8_is_unchanged() {
+        let text = "Caf\u{e9} \u{2014} \u{1f600}";
+        assert_eq!(
+            encode(text, Encoding::Utf8, Unencodable::Error).unwrap(),
+            text.as_bytes()
+        );
+    }
+
+    #[test]
+    fn utf16_has_byte_order_mark() {
+        let bytes = encode("A\u{e9}", Encoding::Utf16, Unencodable::Error).unwrap();
+        assert_eq!(bytes, vec![0xFF, 0xFE, 0x41, 0x00, 0xE9, 0x00]);
+    }
+
+    #[test]
+    fn latin1_replaces_or_rejects_unencodable_characters() {
+        let text = "Caf\u{e9} \u{2014} \u{4e2d}";
+        assert_eq!(
+            encode(text, Encoding::Latin1, Unencodable::Replace).unwrap(),
+            b"Caf\xe9 \x97 &#20013;".to_vec()
+        );
+        assert!(matches!(
+            encode(text, Encoding::Latin1, Unencodable::Error),
+            Err(ArchiveError::Unencodable('\u{4e2d}', _))
+        ));
+    }
+
+    #[test]
+    fn sanitizes_filenames() {
+        assert_eq!(sanitize_filename("A/B: C?"), "A_B_ C_");
+        assert_eq!(sanitize_filename(" ..hidden. "), "hidden");
+        assert_eq!(sanitize_filename("///"), "___");
+        assert_eq!(sanitize_filename(".."), "story");
+    }
+
+    fn sectioned_story() -> Story {
+        let chapter = |id: &str, name: &str| {
+            Content::Chapter(Chapter {
+                id: format!("rr:6789:{}", id),
+                name: name.to_owned(),
+                description: None,
+                text: ChapterText::Hydrated(format!("<p>Text of {}</p>", name)),
+                url: format!("https://www.royalroad.com/fiction/6789/chapter/{}", id),
+                date_posted: Chapter::placeholder_date(),
+                author: None,
+            })
+        };
+        let source = StorySource::RoyalRoad("6789".to_owned());
+        Story {
+            name: "Sectioned".to_owned(),
+            authors: AuthorList::new(Author::new("Someone", "rr:1")),
+            description: None,
+            url: source.to_url(),
+            tags: Vec::new(),
+            chapters: vec![
+                chapter("1", "Prologue"),
+                Content::Section(Section {
+                    id: "rr:6789:book-1".to_owned(),
+                    name: "Book One".to_owned(),
+                    description: None,
+                    chapters: vec![chapter("2", "Arrival")],
+                    url: None,
+                    author: None,
+                }),
+            ],
+            source,
+            completed: Completed::Incomplete,
+            language: None,
+            cover_url: None,
+            published: None,
+            last_updated: None,
+        }
+    }
+
+    #[test]
+    fn html_links_contents_to_headings() {
+        let html = story_to_html(&sectioned_story(), Encoding::Utf8);
+        for (id, heading) in [
+            ("rr-6789-1", "<h2 id=\"rr-6789-1\">Prologue</h2>"),
+            ("rr-6789-book-1", "<h2 id=\"rr-6789-book-1\">Book One</h2>"),
+            ("rr-6789-2", "<h3 id=\"rr-6789-2\">Arrival</h3>"),
+        ] {
+            assert!(html.contains(&format!("<a href=\"#{}\">", id)), "{}", html);
+            assert!(html.contains(heading), "{}", html);
+        }
+    }
+
+    #[test]
+    fn markdown_nests_chapters_under_sections() {
+        let markdown = story_to_markdown(&sectioned_story());
+        let headings: Vec<&str> = markdown
+            .lines()
+            .filter(|line| line.starts_with('#'))
+            .collect();
+        assert_eq!(
+            headings,
+            vec!["# Sectioned", "## Prologue", "## Book One", "### Arrival"]
+        );
+        assert!(markdown.contains("Text of Arrival"), "{}", markdown);
+        assert!(!markdown.contains("<p>"), "{}", markdown);
+    }
+
+    #[test]
+    fn imports_exported_stories_unchanged() {
+        let options = DatabaseOptions::default();
+        let from = Database::new(":memory:", &options).unwrap();
+        let to = Database::new(":memory:", &options).unwrap();
+        let mut story = sectioned_story();
+        story.description = Some("<p>Things happen.</p>".to_owned());
+        story.tags = vec!["genre:Fantasy".to_owned()];
+        from.save_story(&story).unwrap();
+        let saved = from.get_story_by_id("rr:6789").unwrap().unwrap();
+
+        let json = story_to_json(&saved).unwrap();
+        to.save_story(&story_from_json(&json).unwrap()).unwrap();
+        let imported = to.get_story_by_id("rr:6789").unwrap().unwrap();
+        assert_eq!(story_to_json(&imported).unwrap(), json);
+    }
+
+    #[test]
+    fn rejects_chapters_from_other_stories() {
+        let mut story = sectioned_story();
+        if let Content::Section(ref mut section) = story.chapters[1] {
+            if let Content::Chapter(ref mut chapter) = section.chapters[0] {
+                chapter.id = "rr:1234:2".to_owned();
+            }
+        }
+        let json = story_to_json(&story).unwrap();
+        assert!(matches!(
+            story_from_json(&json),
+            Err(ArchiveError::BadImport(message)) if message.contains("rr:1234:2")
+        ));
+    }
+
+    #[test]
+    fn epub_starts_with_its_mimetype() {
+        let epub = epub::story_to_epub(&sectioned_story(), &StoryMedia::default());
+        assert_eq!(&epub[30..38], b"mimetype");
+        assert_eq!(&epub[38..58], b"application/epub+zip");
+        let text = String::from_utf8_lossy(&epub);
+        for file in [
+            "OEBPS/cover.svg",
+            "OEBPS/nav.xhtml",
+            "OEBPS/page-3.xhtml",
+            "OEBPS/content.opf",
+        ] {
+            assert!(text.contains(file), "{} is missing", file);
+        }
+        assert!(text.contains("<h2>Arrival</h2>\n<p>Text of Arrival</p>"));
+    }
+
+    #[test]
+    fn json_includes_chapter_text() {
+        let json = story_to_json(&sectioned_story()).unwrap();
+        let story: Story = serde_json::from_str(&json).unwrap();
+        assert_eq!(story.name, "Sectioned");
+        let chapters = story.all_chapters();
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[1].text.as_str(), "<p>Text of Arrival</p>");
+    }
+}
@@ -0,0 +1,399 @@
+use chrono::{SecondsFormat, Utc};
+use select::{
+    document::Document,
+    node::{Data, Node},
+    predicate,
+};
+
+use super::escape;
+use super::zip::ZipWriter;
+use super::StoryMedia;
+use crate::images::IMAGE_SCHEME;
+use crate::structs::{Content, Story};
+
+use std::collections::HashMap;
+
+/// Elements that never have content, which XHTML needs written as `<br/>` rather than `<br>`.
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Builds an EPUB 3 book of a story: a cover, a title page, and a page for each section and
+/// chapter. Stories without a saved cover get one drawn from their name and authors. Images
+/// saved from chapters are packaged with the book.
+pub(crate) fn story_to_epub(story: &Story, media: &StoryMedia) -> Vec<u8> {
+    let language = story.language.as_deref().unwrap_or("en");
+    let mut book = Book {
+        zip: ZipWriter::new(),
+        language,
+        manifest: String::new(),
+        spine: String::new(),
+        pages: 0,
+        images: HashMap::new(),
+    };
+    book.zip.add("mimetype", b"application/epub+zip");
+    book.zip.add("META-INF/container.xml", CONTAINER.as_bytes());
+
+    for image in media.images.iter() {
+        if book.images.contains_key(&image.hash) {
+            continue;
+        }
+        let file = format!("images/{}.{}", image.hash, extension(&image.media_type));
+        book.zip.add(&format!("OEBPS/{}", file), &image.bytes);
+        book.manifest.push_str(&format!(
+            "<item id=\"image-{}\" href=\"{}\" media-type=\"{}\"/>\n",
+            book.images.len() + 1,
+            file,
+            xml_text(&image.media_type)
+        ));
+        book.images.insert(image.hash.clone(), file);
+    }
+
+    let placeholder;
+    let (media_type, image): (&str, &[u8]) = match media.cover.as_ref() {
+        Some(cover) => (&cover.media_type, &cover.bytes),
+        None => {
+            placeholder = placeholder_cover(story);
+            ("image/svg+xml", placeholder.as_bytes())
+        }
+    };
+    let image_file = format!("cover.{}", extension(media_type));
+    book.zip.add(&format!("OEBPS/{}", image_file), image);
+    book.manifest.push_str(&format!(
+        "<item id=\"cover-image\" href=\"{}\" media-type=\"{}\" properties=\"cover-image\"/>\n",
+        image_file, media_type
+    ));
+    book.add_page(
+        "cover",
+        &story.name,
+        &format!(
+            "<img src=\"{}\" alt=\"{}\" style=\"max-width: 100%; max-height: 100%;\"/>\n",
+            image_file,
+            xml_text(&story.name)
+        ),
+    );
+    let mut title_page = format!(
+        "<h1>{}</h1>\n<p>by {}</p>\n",
+        xml_text(&story.name),
+        xml_text(&story.authors.to_string())
+    );
+    if let Some(ref description) = story.description {
+        title_page.push_str(&format!(
+            "<div class=\"description\">{}</div>\n",
+            to_xhtml(description, &book.images)
+        ));
+    }
+    book.add_page("title", &story.name, &title_page);
+
+    let mut nav = String::new();
+    book.add_contents(&mut nav, &story.chapters, 1);
+    book.zip.add(
+        "OEBPS/nav.xhtml",
+        page(
+            "Contents",
+            language,
+            &format!(
+                "<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n{}</nav>\n",
+                nav
+            ),
+        )
+        .as_bytes(),
+    );
+
+    let mut metadata = format!(
+        "<dc:identifier id=\"book-id\">{}</dc:identifier>\n<dc:title>{}</dc:title>\n",
+        xml_text(&story.url),
+        xml_text(&story.name)
+    );
+    for author in story.authors.authors() {
+        metadata.push_str(&format!(
+            "<dc:creator>{}</dc:creator>\n",
+            xml_text(&author.name)
+        ));
+    }
+    metadata.push_str(&format!(
+        "<dc:language>{}</dc:language>\n<dc:source>{}</dc:source>\n\
+        <meta property=\"dcterms:modified\">{}</meta>\n<meta name=\"cover\" content=\"cover-image\"/>\n",
+        xml_text(language),
+        xml_text(&story.url),
+        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+    ));
+    let package = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\">\n\
+        <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n{}</metadata>\n\
+        <manifest>\n\
+        <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+        {}</manifest>\n<spine>\n{}</spine>\n</package>\n",
+        metadata, book.manifest, book.spine
+    );
+    book.zip.add("OEBPS/content.opf", package.as_bytes());
+    book.zip.finish()
+}
+
+const CONTAINER: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>
+<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">
+<rootfiles>
+<rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>
+</rootfiles>
+</container>
+";
+
+/// An EPUB being put together, with the manifest and spine entries of the pages added so far.
+struct Book<'a> {
+    zip: ZipWriter,
+    language: &'a str,
+    manifest: String,
+    spine: String,
+    pages: usize,
+    /// Where in the book each saved image is, by its hash.
+    images: HashMap<String, String>,
+}
+
+impl Book<'_> {
+    /// Adds a page to the book after the ones already there, returning its file name.
+    fn add_page(&mut self, id: &str, title: &str, body: &str) -> String {
+        let file = format!("{}.xhtml", id);
+        self.zip.add(
+            &format!("OEBPS/{}", file),
+            page(title, self.language, body).as_bytes(),
+        );
+        // Images in chapters are left on the sites they came from, which readers have to be told.
+        let properties = match body.contains(" src=\"http") {
+            true => " properties=\"remote-resources\"",
+            false => "",
+        };
+        self.manifest.push_str(&format!(
+            "<item id=\"{}\" href=\"{}\" media-type=\"application/xhtml+xml\"{}/>\n",
+            id, file, properties
+        ));
+        self.spine
+            .push_str(&format!("<itemref idref=\"{}\"/>\n", id));
+        file
+    }
+
+    /// Adds a page for each section and chapter, writing a nested list of links to them to
+    /// `nav`. Each level of nesting gets a smaller heading.
+    fn add_contents(&mut self, nav: &mut String, contents: &[Content], level: usize) {
+        let level = level.min(6);
+        nav.push_str("<ol>\n");
+        for content in contents {
+            self.pages += 1;
+            let mut body = format!("<h{0}>{1}</h{0}>\n", level, xml_text(content.name()));
+            match content {
+                Content::Section(section) => {
+                    if let Some(ref description) = section.description {
+                        body.push_str(&to_xhtml(description, &self.images));
+                    }
+                }
+                Content::Chapter(chapter) => {
+                    body.push_str(&to_xhtml(chapter.text.as_str(), &self.images))
+                }
+            }
+            let file = self.add_page(&format!("page-{}", self.pages), content.name(), &body);
+            nav.push_str(&format!(
+                "<li><a href=\"{}\">{}</a>",
+                file,
+                xml_text(content.name())
+            ));
+            match content {
+                Content::Section(section) if !section.chapters.is_empty() => {
+                    nav.push('\n');
+                    self.add_contents(nav, &section.chapters, level + 1);
+                }
+                _ => (),
+            }
+            nav.push_str("</li>\n");
+        }
+        nav.push_str("</ol>\n");
+    }
+}
+
+fn page(title: &str, language: &str, body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!DOCTYPE html>\n\
+        <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" \
+        xml:lang=\"{0}\" lang=\"{0}\">\n<head>\n<title>{1}</title>\n</head>\n<body>\n{2}</body>\n</html>\n",
+        xml_text(language),
+        xml_text(title),
+        body
+    )
+}
+
+/// The file extension for an image type, so that readers that go by extension still work.
+fn extension(media_type: &str) -> &str {
+    match media_type {
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/svg+xml" => "svg",
+        _ => "jpg",
+    }
+}
+
+/// Draws a plain cover with the story's name and authors, for stories without a cover image.
+fn placeholder_cover(story: &Story) -> String {
+    let mut svg = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <svg xmlns=\"http://www.w3.org/2000/svg\" width=\"600\" height=\"800\" viewBox=\"0 0 600 800\">\n\
+        <rect width=\"600\" height=\"800\" fill=\"#2e3440\"/>\n",
+    );
+    let lines = wrap(&story.name, 22);
+    let mut y = 240;
+    for line in lines.iter().take(8) {
+        svg.push_str(&format!(
+            "<text x=\"300\" y=\"{}\" font-family=\"serif\" font-size=\"44\" fill=\"#eceff4\" \
+            text-anchor=\"middle\">{}</text>\n",
+            y,
+            xml_text(line)
+        ));
+        y += 56;
+    }
+    svg.push_str(&format!(
+        "<text x=\"300\" y=\"{}\" font-family=\"serif\" font-size=\"28\" fill=\"#d8dee9\" \
+        text-anchor=\"middle\">{}</text>\n</svg>\n",
+        y + 40,
+        xml_text(&story.authors.to_string())
+    ));
+    svg
+}
+
+/// Splits text into lines of at most `width` characters, breaking between words. Words longer
+/// than that get a line to themselves.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 1 + word.chars().count() <= width => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_owned()),
+        }
+    }
+    lines
+}
+
+/// Rewrites HTML as XHTML, which is what EPUB pages have to be: void elements are closed, and
+/// text and attributes are escaped. Elements and attributes whose names XML wouldn't accept,
+/// like the `o:p` that Word leaves behind, are dropped, but what's inside them is kept. Saved
+/// images are pointed at their place in `images`, or back at where they came from if they
+/// aren't there.
+fn to_xhtml(html: &str, images: &HashMap<String, String>) -> String {
+    let document = Document::from(html);
+    let mut xhtml = String::new();
+    if let Some(body) = document.find(predicate::Name("body")).next() {
+        for child in body.children() {
+            push_xhtml(&mut xhtml, &child, images);
+        }
+    }
+    xhtml
+}
+
+fn push_xhtml(xhtml: &mut String, node: &Node, images: &HashMap<String, String>) {
+    match node.data() {
+        Data::Text(text) => xhtml.push_str(&xml_text(text)),
+        Data::Comment(_) => (),
+        Data::Element(..) => {
+            let name = node.name().unwrap_or_default();
+            if name == "script" {
+                return;
+            }
+            if !is_xml_name(name) {
+                for child in node.children() {
+                    push_xhtml(xhtml, &child, images);
+                }
+                return;
+            }
+            xhtml.push('<');
+            xhtml.push_str(name);
+            for (attr, value) in node
+                .attrs()
+                .filter(|(attr, _)| is_xml_name(attr) && *attr != "xmlns")
+            {
+                let value = match value.strip_prefix(IMAGE_SCHEME) {
+                    Some(hash) if attr == "src" => images
+                        .get(hash)
+                        .map(String::as_str)
+                        .or_else(|| node.attr("data-original-src"))
+                        .unwrap_or(value),
+                    _ => value,
+                };
+                xhtml.push_str(&format!(" {}=\"{}\"", attr, xml_text(value)));
+            }
+            if VOID_ELEMENTS.contains(&name) {
+                xhtml.push_str("/>");
+                return;
+            }
+            xhtml.push('>');
+            for child in node.children() {
+                push_xhtml(xhtml, &child, images);
+            }
+            xhtml.push_str(&format!("</{}>", name));
+        }
+    }
+}
+
+/// Whether a name is one XML accepts without a namespace.
+fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Escapes text for XML, leaving out control characters XML doesn't allow.
+pub(crate) fn xml_text(text: &str) -> String {
+    escape(&text.replace(
+        |c: char| c.is_control() && !matches!(c, '\t' | '\n' | '\r'),
+        "",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_html_as_xhtml() {
+        assert_eq!(
+            to_xhtml(
+                "<p class=\"a\">One<br>two &amp; <o:p>three</o:p></p><hr>",
+                &HashMap::new()
+            ),
+            "<p class=\"a\">One<br/>two &amp; three</p><hr/>"
+        );
+        assert_eq!(
+            to_xhtml("<p>Bad\u{8}<script>x()</script></p>", &HashMap::new()),
+            "<p>Bad</p>"
+        );
+    }
+
+    #[test]
+    fn points_saved_images_into_the_book() {
+        let images = HashMap::from([("abc".to_owned(), "images/abc.png".to_owned())]);
+        assert_eq!(
+            to_xhtml(
+                "<img src=\"archive-image:abc\" data-original-src=\"https://a.example/1.png\">\
+                <img src=\"archive-image:def\" data-original-src=\"https://a.example/2.png\">",
+                &images
+            ),
+            "<img src=\"images/abc.png\" data-original-src=\"https://a.example/1.png\"/>\
+            <img src=\"https://a.example/2.png\" data-original-src=\"https://a.example/2.png\"/>"
+        );
+    }
+
+    #[test]
+    fn wraps_cover_titles() {
+        assert_eq!(
+            wrap("The Quick Brown Fox Jumps Over", 15),
+            vec!["The Quick Brown", "Fox Jumps Over"]
+        );
+        assert_eq!(
+            wrap("Supercalifragilistic Day", 10),
+            vec!["Supercalifragilistic", "Day"]
+        );
+    }
+}
@@ -0,0 +1,137 @@
+/// Builds a ZIP archive in memory. Files are stored without compression, which is all an EPUB
+/// needs, and which keeps its `mimetype` file readable at the fixed offset the format asks for.
+pub(super) struct ZipWriter {
+    bytes: Vec<u8>,
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Every file gets the earliest date ZIP can hold, 1980-01-01, so the same story always makes
+/// the same archive.
+const DOS_DATE: u16 = (1 << 5) | 1;
+/// Marks file names as UTF-8.
+const UTF8_NAMES: u16 = 1 << 11;
+const VERSION: u16 = 20;
+
+impl ZipWriter {
+    pub fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Adds a file. Files are written in the order they're added.
+    pub fn add(&mut self, name: &str, data: &[u8]) {
+        let entry = Entry {
+            name: name.to_owned(),
+            crc: crc32(data),
+            size: data.len() as u32,
+            offset: self.bytes.len() as u32,
+        };
+        self.put_u32(0x04034b50);
+        self.put_u16(VERSION);
+        self.put_file_details(&entry);
+        self.put_u16(0);
+        self.bytes.extend_from_slice(entry.name.as_bytes());
+        self.bytes.extend_from_slice(data);
+        self.entries.push(entry);
+    }
+
+    /// Writes the archive's directory of files and returns the finished archive.
+    pub fn finish(mut self) -> Vec<u8> {
+        let directory_offset = self.bytes.len() as u32;
+        let entries = std::mem::take(&mut self.entries);
+        for entry in entries.iter() {
+            self.put_u32(0x02014b50);
+            self.put_u16(VERSION);
+            self.put_u16(VERSION);
+            self.put_file_details(entry);
+            // No extra field or comment, on the first disk, with no file attributes.
+            for _ in 0..4 {
+                self.put_u16(0);
+            }
+            self.put_u32(0);
+            self.put_u32(entry.offset);
+            self.bytes.extend_from_slice(entry.name.as_bytes());
+        }
+        let directory_size = self.bytes.len() as u32 - directory_offset;
+        self.put_u32(0x06054b50);
+        self.put_u16(0);
+        self.put_u16(0);
+        self.put_u16(entries.len() as u16);
+        self.put_u16(entries.len() as u16);
+        self.put_u32(directory_size);
+        self.put_u32(directory_offset);
+        self.put_u16(0);
+        self.bytes
+    }
+
+    /// Writes the fields that the local and central headers share, from the flags up to the
+    /// length of the name.
+    fn put_file_details(&mut self, entry: &Entry) {
+        self.put_u16(UTF8_NAMES);
+        // Stored without compression, at midnight.
+        self.put_u16(0);
+        self.put_u16(0);
+        self.put_u16(DOS_DATE);
+        self.put_u32(entry.crc);
+        self.put_u32(entry.size);
+        self.put_u32(entry.size);
+        self.put_u16(entry.name.len() as u16);
+    }
+
+    fn put_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xEDB88320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn writes_files_in_order_without_compression() {
+        let mut zip = ZipWriter::new();
+        zip.add("mimetype", b"application/epub+zip");
+        zip.add("a.txt", b"hello");
+        let bytes = zip.finish();
+        assert_eq!(&bytes[..4], b"PK\x03\x04");
+        assert_eq!(&bytes[30..38], b"mimetype");
+        assert_eq!(&bytes[38..58], b"application/epub+zip");
+        // The end of the central directory lists both files.
+        let end = &bytes[bytes.len() - 22..];
+        assert_eq!(&end[..4], b"PK\x05\x06");
+        assert_eq!(u16::from_le_bytes([end[10], end[11]]), 2);
+    }
+}
@@ -0,0 +1,82 @@
+use chrono::{SecondsFormat, Utc};
+
+use crate::export::xml_text;
+use crate::structs::NewChapter;
+
+/// How many chapters the feed holds unless asked for more or fewer.
+pub(crate) const DEFAULT_LENGTH: usize = 50;
+
+/// Builds an Atom feed with an entry for each of `chapters`, which should be newest first.
+pub(crate) fn chapters_feed(chapters: &[NewChapter]) -> String {
+    let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    let updated = chapters
+        .first()
+        .and_then(entry_date)
+        .unwrap_or_else(|| now.clone());
+    let mut feed = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+        <id>urn:fic_archive:new-chapters</id>\n\
+        <title>New chapters</title>\n\
+        <updated>{}</updated>\n\
+        <author><name>fic_archive</name></author>\n",
+        updated
+    );
+    for chapter in chapters {
+        feed.push_str(&format!(
+            "<entry>\n<id>urn:fic_archive:chapter:{}</id>\n<title>{}: {}</title>\n\
+            <updated>{}</updated>\n",
+            xml_text(&chapter.chapter_id),
+            xml_text(&chapter.story_name),
+            xml_text(&chapter.chapter_name),
+            entry_date(chapter).unwrap_or_else(|| now.clone())
+        ));
+        if let Some(posted) = chapter.date_posted {
+            feed.push_str(&format!(
+                "<published>{}</published>\n",
+                posted.to_rfc3339_opts(SecondsFormat::Secs, true)
+            ));
+        }
+        feed.push_str(&format!(
+            "<link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>\n\
+            <summary>New chapter of {}</summary>\n</entry>\n",
+            xml_text(&chapter.url),
+            xml_text(&chapter.story_name)
+        ));
+    }
+    feed.push_str("</feed>\n");
+    feed
+}
+
+/// When a chapter turned up in the archive, or failing that when it was posted.
+fn entry_date(chapter: &NewChapter) -> Option<String> {
+    chapter
+        .added_at
+        .or(chapter.date_posted)
+        .map(|date| date.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    #[test]
+    fn writes_an_entry_per_chapter() {
+        let chapter = NewChapter {
+            story_id: "rr:1".to_owned(),
+            story_name: "Tea & Swords".to_owned(),
+            chapter_id: "rr:1:2".to_owned(),
+            chapter_name: "Chapter <2>".to_owned(),
+            url: "https://www.royalroad.com/fiction/1/chapter/2?a=1&b=2".to_owned(),
+            date_posted: DateTime::parse_from_rfc3339("2024-01-01T10:00:00+00:00").ok(),
+            added_at: DateTime::parse_from_rfc3339("2024-01-02T08:30:00+00:00").ok(),
+        };
+        let feed = chapters_feed(&[chapter]);
+        assert!(feed.contains("<updated>2024-01-02T08:30:00Z</updated>\n<author>"));
+        assert!(feed.contains("<title>Tea &amp; Swords: Chapter &lt;2&gt;</title>"));
+        assert!(feed.contains("<published>2024-01-01T10:00:00Z</published>"));
+        assert!(feed.contains("href=\"https://www.royalroad.com/fiction/1/chapter/2?a=1&amp;b=2\""));
+        assert_eq!(feed.matches("<entry>").count(), 1);
+    }
+}
@@ -0,0 +1,118 @@
+use once_cell::sync::{Lazy, OnceCell};
+use regex::{Captures, Regex};
+use select::{document::Document, predicate::Name};
+use sha2::{Digest, Sha256};
+
+use std::borrow::Cow;
+
+/// What the `src` of an image saved in the archive starts with, followed by the image's hash.
+pub(crate) const IMAGE_SCHEME: &str = "archive-image:";
+
+static DOWNLOAD_IMAGES: OnceCell<bool> = OnceCell::new();
+
+/// Matches the `src` of an image that's still on the site it came from.
+static REMOTE_IMAGE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"(?i)<img\b[^>]*?\ssrc="(?P<url>https?://[^"]+)""#).unwrap());
+
+/// Matches the attributes [`localize`] gives a saved image.
+static LOCAL_IMAGE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"src="archive-image:(?P<hash>[0-9a-f]+)" data-original-src="(?P<url>[^"]*)""#)
+        .unwrap()
+});
+
+/// Sets whether images in chapters fetched for the rest of the run are downloaded into the
+/// archive. They aren't unless this is turned on. Only the first call has any effect.
+pub(crate) fn set_download_images(enabled: bool) {
+    let _ = DOWNLOAD_IMAGES.set(enabled);
+}
+
+pub(crate) fn downloading_images() -> bool {
+    DOWNLOAD_IMAGES.get().copied().unwrap_or(false)
+}
+
+/// The URLs of the images in chapter HTML that haven't been saved to the archive, each once,
+/// as they're written in the HTML (with entities like `&amp;` left in).
+pub(crate) fn remote_image_urls(html: &str) -> Vec<&str> {
+    let mut urls: Vec<&str> = Vec::new();
+    for captures in REMOTE_IMAGE.captures_iter(html) {
+        let url = captures.name("url").map_or("", |url| url.as_str());
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls
+}
+
+/// Decodes the entities in an image URL as written by [`remote_image_urls`], like `&amp;` or
+/// `&#38;`, giving the URL to download it from.
+pub(crate) fn decoded_url(url: &str) -> String {
+    Document::from(format!("<img src=\"{}\">", url).as_str())
+        .find(Name("img"))
+        .next()
+        .and_then(|img| img.attr("src").map(str::to_owned))
+        .unwrap_or_else(|| url.to_owned())
+}
+
+/// Points every image in `html` whose `src` is `url`, as written by [`remote_image_urls`], at
+/// the saved image with the given hash. Where it came from is kept in `data-original-src`.
+pub(crate) fn localize(html: &str, url: &str, hash: &str) -> String {
+    html.replace(
+        &format!("src=\"{}\"", url),
+        &format!(
+            "src=\"{}{}\" data-original-src=\"{}\"",
+            IMAGE_SCHEME, hash, url
+        ),
+    )
+}
+
+/// Undoes [`localize`], pointing saved images back at where they came from. This is the HTML
+/// as the site had it, which is what's compared to see if a chapter has been edited.
+pub(crate) fn with_remote_urls(html: &str) -> Cow<'_, str> {
+    LOCAL_IMAGE.replace_all(html, |captures: &Captures| {
+        format!("src=\"{}\"", &captures["url"])
+    })
+}
+
+/// The hash a saved image is known by.
+pub(crate) fn image_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_entities_in_urls() {
+        assert_eq!(
+            decoded_url("https://example.com/a.png?x=1&amp;y=2&#38;z=&quot;3&quot;&#x41;"),
+            "https://example.com/a.png?x=1&y=2&z=\"3\"A"
+        );
+        assert_eq!(
+            decoded_url("http://example.com/b.gif"),
+            "http://example.com/b.gif"
+        );
+    }
+
+    #[test]
+    fn localizes_and_restores_image_sources() {
+        let html = "<p><img src=\"https://example.com/a.png?x=1&amp;y=2\" alt=\"A\"></p>\
+            <p><img alt=\"B\" src=\"http://example.com/b.gif\"><img src=\"https://example.com/a.png?x=1&amp;y=2\"></p>";
+        let urls = remote_image_urls(html);
+        assert_eq!(
+            urls,
+            vec![
+                "https://example.com/a.png?x=1&amp;y=2",
+                "http://example.com/b.gif"
+            ]
+        );
+
+        let local = localize(html, urls[0], "abc123");
+        assert_eq!(local.matches("src=\"archive-image:abc123\"").count(), 2);
+        assert_eq!(remote_image_urls(&local), vec!["http://example.com/b.gif"]);
+        assert_eq!(with_remote_urls(&local), html);
+    }
+}
@@ -0,0 +1,56 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::progress;
+
+/// Writes log messages from this crate to stderr, where they stay out of the way of command
+/// results and `--json` output. Messages from dependencies are left out; they are rarely useful
+/// to someone archiving stories and some are very chatty.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+            && metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let prefix = match record.level() {
+            Level::Error => "error: ",
+            Level::Warn => "warning: ",
+            Level::Info => "",
+            Level::Debug => "debug: ",
+            Level::Trace => "trace: ",
+        };
+        progress::suspend(|| eprintln!("{}{}", prefix, record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Starts logging messages at `level` and above. Only the first call has any effect.
+pub(crate) fn init(level: LevelFilter) {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Stops logging until the returned guard is dropped, for while the terminal belongs to the TUI
+/// and anything written to stderr would end up drawn over it.
+pub(crate) fn pause() -> Paused {
+    let level = log::max_level();
+    log::set_max_level(LevelFilter::Off);
+    Paused(level)
+}
+
+pub(crate) struct Paused(LevelFilter);
+
+impl Drop for Paused {
+    fn drop(&mut self) {
+        log::set_max_level(self.0);
+    }
+}
@@ -1,230 +1,1487 @@
-use clap::Parser;
-use futures::future::join_all;
-use std::collections::HashSet;
+use chrono::{DateTime, FixedOffset};
+use clap::{CommandFactory, FromArgMatches};
+use reqwest::Client;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use self::args::{Args, Commands::*};
+use self::args::{Args, Commands::*, ListOrder};
+use self::config::Config;
 use self::error::ArchiveError;
-use self::sql::Database;
-use self::structs::{Content, StorySource, SOURCES_LIST};
+use self::export::{Encoding, ExportFormat, StoryMedia, Unencodable};
+use self::parser::wordpress::WordPressSite;
+use self::prompt::{confirm, describe, resolve_story};
+use self::sql::{Database, StoryFilter};
+use self::structs::{
+    supported_sources, ChapterText, Completed, ListedStory, Story, StoryDates, StorySource,
+    TagCount, TextFormat, USER_TAG_NAMESPACE,
+};
 use self::tui::start_tui;
+use self::update::{ArchiveUpdate, StoryUpdate, UpdateOptions};
+
+/// Prints a message meant for people rather than scripts. It goes to stdout normally, and to
+/// stderr when `--json` has claimed stdout for structured output.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::output::json() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
 
 mod args;
 mod client;
+mod config;
 mod error;
+mod export;
+mod feed;
+mod images;
+mod logger;
+mod notify;
+mod output;
 mod parser;
+mod progress;
+mod prompt;
+mod serve;
 mod sql;
 mod structs;
+mod style;
+mod text;
 mod tui;
+mod update;
 
 pub type Result<T> = std::result::Result<T, ArchiveError>;
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
-    let db = Database::new(&args.db)?;
+async fn main() {
+    if let Err(err) = run().await {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
 
+async fn run() -> Result<()> {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    logger::init(args.log_level());
+    progress::set_enabled(!args.no_progress && !args.quiet);
+    let config = Config::load(args.config.as_deref())?;
+    config.apply(&mut args, &matches);
+    output::set_json(args.json);
+    notify::set_enabled(args.notify);
+    let db = Database::new(&args.db, &args.database_options())?.dedup_chapter_text(args.dedup_text);
+    client::set_max_requests_per_host(args.max_requests);
+    client::set_max_attempts(args.max_attempts);
+    client::set_request_delay(Duration::from_millis(args.request_delay_ms));
+    client::load_cookies(&args.cookie_file())?;
+    let client = client::new_client(&args.client_options())?;
+    for site in db.wordpress_sites()? {
+        parser::wordpress::register_site(site)?;
+    }
+    parser::ao3::set_fetch_mode(args.ao3_fetch);
+    match (config.ao3_username, config.ao3_password) {
+        (Some(username), Some(password)) => parser::ao3::set_login(username, password),
+        (None, None) => (),
+        _ => log::warn!("An AO3 login needs both ao3-username and ao3-password; ignoring it."),
+    }
+    if let Some(session) = config.ao3_session {
+        client::add_cookie(
+            &format!("_otwarchive_session={}", session),
+            "https://archiveofourown.org",
+        )?;
+    }
+    parser::set_normalize_scene_breaks(!args.raw_scene_breaks);
+    images::set_download_images(args.download_images);
+
+    // Cookies are saved even when a command fails, since a login may have worked regardless.
+    // Failing to save them is only a warning, so that it doesn't hide how the command went.
+    let cookie_file = args.cookie_file();
+    let result = run_command(args, client, db).await;
+    if let Err(err) = client::save_cookies(&cookie_file) {
+        log::warn!(
+            "Could not save cookies to {}: {}",
+            cookie_file.display(),
+            err
+        );
+    }
+    result
+}
+
+async fn run_command(args: Args, client: Client, db: Database) -> Result<()> {
     match args.command {
         Some(sub) => match sub {
-            Add { stories } => add_stories(stories, &db).await?,
+            Add {
+                stories,
+                skeleton_only,
+            } => add_stories(stories, skeleton_only, args.ignore_errors, &client, &db).await?,
+            Hydrate { story } => {
+                hydrate_stories(
+                    match story {
+                        Some(s) => Some(StorySource::from_url(&s)?),
+                        None => None,
+                    },
+                    args.ignore_errors,
+                    &client,
+                    &db,
+                )
+                .await?
+            }
             Update {
                 story,
                 force_refresh,
+                metadata_only,
+                full,
+                timeout,
+                since,
+                all,
+                dry_run,
+            } => {
+                let story = match story {
+                    Some(s) => Some(StorySource::from_url(&s)?),
+                    None => None,
+                };
+                let options = UpdateOptions {
+                    force_refresh,
+                    metadata_only,
+                    resume: !full,
+                    timeout: match timeout {
+                        0 => None,
+                        secs => Some(Duration::from_secs(secs)),
+                    },
+                    since,
+                    include_complete: all || force_refresh,
+                    dry_run,
+                };
+                match dry_run {
+                    true => {
+                        preview_update(story, options, args.ignore_errors, &client, &db).await?
+                    }
+                    false => {
+                        update_archive(story, options, args.ignore_errors, &client, &db).await?
+                    }
+                }
+            }
+            Daemon {
+                interval,
+                once,
+                feed,
+                timeout,
             } => {
-                update_archive(
+                let options = UpdateOptions {
+                    resume: true,
+                    timeout: match timeout {
+                        0 => None,
+                        secs => Some(Duration::from_secs(secs)),
+                    },
+                    ..Default::default()
+                };
+                run_daemon(interval, once, feed, options, &client, &db).await?
+            }
+            Verify { story, fix } => {
+                verify_archive(
                     match story {
                         Some(s) => Some(StorySource::from_url(&s)?),
                         None => None,
                     },
-                    force_refresh,
+                    fix,
+                    args.ignore_errors,
+                    &client,
                     &db,
                 )
                 .await?
             }
-            Delete { search } => delete_story(search, &db).await?,
-            Export { .. } => {
-                todo!()
-            }
-            List { .. } => list_stories(&db).await?,
-            ListSources => println!(
-                "{}",
-                SOURCES_LIST.into_iter().rev().enumerate().rev().fold(
-                    String::new(),
-                    |mut acc, (idx, source)| {
-                        acc.push_str(source);
-                        if idx > 0 {
-                            acc.push('\n');
-                        }
-                        acc
-                    }
-                )
-            ),
+            Delete { search, yes } => delete_story(search, yes, &db).await?,
+            Rename { search, name } => rename_story(search, name, &db)?,
+            Export {
+                story,
+                all,
+                output_dir,
+                format,
+                encoding,
+                unencodable,
+            } => {
+                let export = ExportOptions {
+                    dir: output_dir,
+                    format,
+                    encoding,
+                    unencodable,
+                };
+                match story {
+                    Some(story) if !all => export_story(story, &export, &db).await?,
+                    _ => export_all(&export, args.ignore_errors, &db).await?,
+                }
+            }
+            Feed { output, limit } => write_feed(output, limit, &db)?,
+            Import { file, merge } => import_story(&file, merge, &db)?,
+            Read {
+                story,
+                chapter,
+                format,
+            } => read_chapter(story, chapter, format, &client, &db).await?,
+            List {
+                language,
+                tags,
+                status,
+                sort,
+            } => {
+                let filter = StoryFilter {
+                    language,
+                    tags,
+                    completed: status,
+                };
+                list_stories(&filter, sort, &db).await?
+            }
+            Info { story } => story_info(story, &db).await?,
+            Open { story } => open_story(story, &db)?,
+            Search { phrase } => search_text(phrase, &db).await?,
+            Tag {
+                search,
+                add,
+                remove,
+            } => tag_story(search, add, remove, &db)?,
+            Tags { prefix } => list_tags(prefix, &db)?,
+            Serve { port, address } => serve::serve(db, SocketAddr::new(address, port)).await?,
+            ListSources => output::report(&supported_sources(), |sources| {
+                for source in sources.iter() {
+                    println!("{}", source);
+                }
+            })?,
+            AddSite {
+                toc_url,
+                toc_selector,
+                content_selector,
+            } => add_site(&toc_url, &toc_selector, &content_selector, &db)?,
         },
-        None => start_tui(args).await?,
+        None => start_tui(args, &db).await?,
     }
 
     Ok(())
 }
 
-async fn add_stories(stories: Vec<String>, db: &Database) -> Result<()> {
-    let mut errors: Vec<ArchiveError> = Vec::new();
+/// A site added with `add-site`.
+#[derive(Debug, Serialize)]
+struct AddedSite {
+    host: String,
+    url: String,
+}
+
+fn add_site(
+    toc_url: &str,
+    toc_selector: &str,
+    content_selector: &str,
+    db: &Database,
+) -> Result<()> {
+    let site = WordPressSite::from_toc_url(toc_url, toc_selector, content_selector)?;
+    db.add_wordpress_site(&site)?;
+    let added = AddedSite {
+        url: site.base_url(),
+        host: site.host.clone(),
+    };
+    parser::wordpress::register_site(site)?;
+    output::report(&added, |added| {
+        println!(
+            "Added site {}. Add its story with `add {}`.",
+            added.host, added.url
+        )
+    })
+}
+
+/// The outcome of adding one story, as reported by `add`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+enum AddResult {
+    Added {
+        url: String,
+        name: String,
+        chapters: usize,
+        /// Whether only the chapter list was saved, without chapter text.
+        skeleton: bool,
+    },
+    Updated {
+        url: String,
+        new_chapters: usize,
+    },
+    Failed {
+        url: String,
+        error: String,
+    },
+}
+
+/// The outcome of an `update` run.
+#[derive(Debug, Serialize)]
+struct UpdateResult {
+    force_refresh: bool,
+    metadata_only: bool,
+    new_chapters: usize,
+    changed_chapters: usize,
+    repaired_dates: usize,
+    updated_stories: usize,
+    failed_stories: usize,
+    skipped_stories: usize,
+}
+
+/// Turns the number of stories that failed in a command into its result, so that a run where
+/// anything failed exits with a non-zero code unless `--ignore-errors` was given.
+fn check_failures(failed: usize, total: usize, ignore_errors: bool) -> Result<()> {
+    if failed > 0 && !ignore_errors {
+        Err(ArchiveError::StoriesFailed(failed, total))
+    } else {
+        Ok(())
+    }
+}
+
+async fn add_stories(
+    stories: Vec<String>,
+    skeleton_only: bool,
+    ignore_errors: bool,
+    client: &Client,
+    db: &Database,
+) -> Result<()> {
+    let mut results: Vec<AddResult> = Vec::with_capacity(stories.len());
     for story in stories.iter() {
-        match StorySource::from_url(&story) {
-            Ok(source) => match add_story(source, db).await {
-                Ok(_) => (),
-                Err(err) => errors.push(err),
+        let result = match StorySource::from_url(story) {
+            Ok(source) => add_story(source, skeleton_only, client, db).await,
+            Err(err) => Err(err),
+        };
+        let result = match result {
+            Ok(added) => added,
+            Err(err) => AddResult::Failed {
+                url: story.clone(),
+                error: err.to_string(),
             },
-            Err(err) => errors.push(err),
         };
+        // Say how each story went as soon as it's done rather than after the whole batch. With
+        // `--json` only failures are mentioned along the way, on stderr.
+        if !output::json() || matches!(result, AddResult::Failed { .. }) {
+            describe_add_result(&result);
+        }
+        results.push(result);
     }
-    errors.into_iter().next().map(|e| Err(e)).unwrap_or(Ok(()))
+    output::report(&results, |_| ())?;
+    let failed = results
+        .iter()
+        .filter(|result| matches!(result, AddResult::Failed { .. }))
+        .count();
+    check_failures(failed, results.len(), ignore_errors)
 }
 
-async fn add_story(source: StorySource, db: &Database) -> Result<()> {
+fn describe_add_result(result: &AddResult) {
+    match result {
+        AddResult::Added {
+            name,
+            chapters,
+            skeleton,
+            ..
+        } => println!(
+            "Added story {}{} ({} chapter{})",
+            name,
+            if *skeleton { " as a skeleton" } else { "" },
+            chapters,
+            if *chapters == 1 { "" } else { "s" }
+        ),
+        AddResult::Updated { url, new_chapters } => println!(
+            "Updated story at {} with {} new chapters.",
+            url, new_chapters
+        ),
+        AddResult::Failed { url, error } => {
+            log::error!("Failed to add story at {}: {}", url, error)
+        }
+    }
+}
+
+async fn add_story(
+    source: StorySource,
+    skeleton_only: bool,
+    client: &Client,
+    db: &Database,
+) -> Result<AddResult> {
     let exists = db.story_exists_with_id(&source.to_id())?;
     let url = source.to_url();
     if exists {
-        let new_chapters = update_story(source, false, db).await?;
-        println!(
-            "Updated story at {} with {} new chapters.",
-            url, new_chapters
-        );
+        let new_chapters = db
+            .update_one(client, source, &UpdateOptions::default())
+            .await
+            .result?
+            .new_chapters;
+        Ok(AddResult::Updated { url, new_chapters })
     } else {
-        let story = source.parser().get_story(source).await?;
+        let story = match skeleton_only {
+            true => source.parser().get_skeleton(client, source).await?,
+            false => source.parser().get_story(client, source).await?,
+        };
         db.save_story(&story)?;
-        println!(
-            "Added story {} ({} chapter{})",
-            story.name,
-            story.num_chapters(),
-            if story.num_chapters() == 1 { "" } else { "s" }
-        );
+        db.update_cover(client, &story).await?;
+        db.download_images(client, &story.source.to_id(), None)
+            .await?;
+        Ok(AddResult::Added {
+            url,
+            chapters: story.num_chapters(),
+            name: story.name,
+            skeleton: skeleton_only,
+        })
+    }
+}
+
+fn changed_note(changed_chapters: usize) -> String {
+    match changed_chapters {
+        0 => String::new(),
+        1 => " and 1 changed chapter".to_owned(),
+        count => format!(" and {} changed chapters", count),
+    }
+}
+
+fn repaired_note(repaired_dates: usize) -> String {
+    match repaired_dates {
+        0 => String::new(),
+        1 => " and repaired 1 chapter date".to_owned(),
+        count => format!(" and repaired {} chapter dates", count),
     }
-    Ok(())
 }
 
 async fn update_archive(
     story: Option<StorySource>,
-    force_refresh: bool,
+    options: UpdateOptions,
+    ignore_errors: bool,
+    client: &Client,
     db: &Database,
 ) -> Result<()> {
     match story {
         Some(source) => {
             let url = source.to_url();
-            let result = update_story(source, force_refresh, db).await?;
+            let (update, failed) =
+                match db.update_one(client, source.clone(), &options).await.result {
+                    Ok(update) => (update, 0),
+                    Err(err) if ignore_errors => {
+                        log::error!("Failed to update story at {}: {}", url, err);
+                        (StoryUpdate::default(), 1)
+                    }
+                    Err(err) => return Err(err),
+                };
+            notify_new_chapters(&[(source, update.new_chapters)], db)?;
+            let result = UpdateResult {
+                force_refresh: options.force_refresh,
+                metadata_only: options.metadata_only,
+                new_chapters: update.new_chapters,
+                changed_chapters: update.changed_chapters,
+                repaired_dates: update.repaired_dates,
+                updated_stories: 1 - failed,
+                failed_stories: failed,
+                skipped_stories: 0,
+            };
+            output::report(&result, |result| {
+                if result.failed_stories == 0 && result.metadata_only {
+                    println!("Updated details of story at {}.", url)
+                } else if result.failed_stories == 0 {
+                    println!(
+                        "{}pdated story at {} with {} new chapters{}{}.",
+                        if result.force_refresh { "Force-u" } else { "U" },
+                        url,
+                        result.new_chapters,
+                        changed_note(result.changed_chapters),
+                        repaired_note(result.repaired_dates)
+                    )
+                }
+            })
+        }
+        None => {
+            let ArchiveUpdate {
+                reports,
+                skipped_complete,
+            } = db.update_all(client, &options).await?;
+            let story_count = reports.len();
+            let mut arrived = Vec::new();
+            let (update, failed) = reports.into_iter().fold(
+                (StoryUpdate::default(), 0),
+                |mut acc, report| match report.result {
+                    Ok(update) => {
+                        arrived.push((report.source, update.new_chapters));
+                        acc.0.new_chapters += update.new_chapters;
+                        acc.0.changed_chapters += update.changed_chapters;
+                        acc.0.repaired_dates += update.repaired_dates;
+                        acc
+                    }
+                    Err(err) => {
+                        log::error!(
+                            "Failed to update story at {}: {}",
+                            report.source.to_url(),
+                            err
+                        );
+                        (acc.0, acc.1 + 1)
+                    }
+                },
+            );
+            let result = UpdateResult {
+                force_refresh: options.force_refresh,
+                metadata_only: options.metadata_only,
+                new_chapters: update.new_chapters,
+                changed_chapters: update.changed_chapters,
+                repaired_dates: update.repaired_dates,
+                updated_stories: story_count - failed,
+                failed_stories: failed,
+                skipped_stories: skipped_complete,
+            };
+            notify_new_chapters(&arrived, db)?;
+            output::report(&result, |result| {
+                if result.metadata_only {
+                    println!(
+                        "Updated details of {} stories. Failed to update {} stories.",
+                        result.updated_stories, result.failed_stories,
+                    );
+                } else {
+                    println!(
+                        "{}pdated archive. Got {} new chapters{} from {} stories{}. Failed to update {} stories.",
+                        if result.force_refresh { "Force-u" } else { "U" },
+                        result.new_chapters,
+                        changed_note(result.changed_chapters),
+                        result.updated_stories,
+                        repaired_note(result.repaired_dates),
+                        result.failed_stories,
+                    );
+                }
+                if result.skipped_stories > 0 {
+                    println!(
+                        "Skipped {} complete stories. Use --all to check them too.",
+                        result.skipped_stories
+                    );
+                }
+            })?;
+            check_failures(failed, story_count, ignore_errors)
+        }
+    }
+}
+
+/// What `update --dry-run` found would change in one story.
+#[derive(Debug, Serialize)]
+struct UpdatePreview {
+    id: String,
+    name: String,
+    url: String,
+    new_chapters: usize,
+    changed_chapters: usize,
+}
+
+/// The outcome of `update --dry-run`.
+#[derive(Debug, Serialize)]
+struct DryRunResult {
+    /// The stories that would gain or have changed chapters, by name.
+    stories: Vec<UpdatePreview>,
+    checked_stories: usize,
+    failed_stories: usize,
+    skipped_stories: usize,
+}
+
+/// Shows what `update` would change in one story or the whole archive, without changing it.
+async fn preview_update(
+    story: Option<StorySource>,
+    options: UpdateOptions,
+    ignore_errors: bool,
+    client: &Client,
+    db: &Database,
+) -> Result<()> {
+    let single = story.is_some();
+    let ArchiveUpdate {
+        reports,
+        skipped_complete,
+    } = match story {
+        Some(source) => ArchiveUpdate {
+            reports: vec![db.update_one(client, source, &options).await],
+            skipped_complete: 0,
+        },
+        None => db.update_all(client, &options).await?,
+    };
+    let story_count = reports.len();
+    let mut stories = Vec::new();
+    let mut failed = 0;
+    for report in reports {
+        let url = report.source.to_url();
+        match report.result {
+            Ok(update) if update.new_chapters + update.changed_chapters > 0 => {
+                let id = report.source.to_id();
+                stories.push(UpdatePreview {
+                    name: db.story_name(&id)?.unwrap_or_else(|| url.clone()),
+                    id,
+                    url,
+                    new_chapters: update.new_chapters,
+                    changed_chapters: update.changed_chapters,
+                });
+            }
+            Ok(_) => (),
+            Err(err) if single && !ignore_errors => return Err(err),
+            Err(err) => {
+                log::error!("Failed to check story at {}: {}", url, err);
+                failed += 1;
+            }
+        }
+    }
+    stories.sort_by_key(|story| story.name.to_lowercase());
+    let result = DryRunResult {
+        stories,
+        checked_stories: story_count - failed,
+        failed_stories: failed,
+        skipped_stories: skipped_complete,
+    };
+    output::report(&result, |result| {
+        for story in result.stories.iter() {
+            let edited = match story.changed_chapters {
+                0 => String::new(),
+                1 => " and update 1 edited chapter".to_owned(),
+                count => format!(" and update {} edited chapters", count),
+            };
             println!(
-                "{}pdated story at {} with {} new chapters.",
-                if force_refresh { "Force-u" } else { "U" },
-                url,
-                result
+                "{}: would add {} chapter{}{}",
+                story.name,
+                story.new_chapters,
+                if story.new_chapters == 1 { "" } else { "s" },
+                edited
             );
-            Ok(())
         }
-        None => {
-            let stories = db.get_all_stories()?;
-            let story_count = stories.len();
-            let (new_chaps, failed) = join_all(
-                stories
-                    .into_iter()
-                    .map(|s| update_story(s.source, force_refresh, db)),
-            )
-            .await
-            .into_iter()
-            .fold((0, 0), |acc, x| match x {
-                Ok(num) => (acc.0 + num, acc.1),
-                Err(_) => (acc.0, acc.1 + 1),
-            });
+        match result.stories.len() {
+            0 => println!(
+                "No new or changed chapters in {} stories.",
+                result.checked_stories
+            ),
+            count => println!(
+                "Would update {} of {} stories. Nothing was downloaded or saved.",
+                count, result.checked_stories
+            ),
+        }
+        if result.failed_stories > 0 {
+            println!("Failed to check {} stories.", result.failed_stories);
+        }
+        if result.skipped_stories > 0 {
             println!(
-                "{}pdated archive. Got {} new chapters from {} stories. Failed to update {} stories.",
-                if force_refresh { "Force-u" } else { "U" },
-                new_chaps,
-                story_count - failed,
-                failed,
+                "Skipped {} complete stories. Use --all to check them too.",
+                result.skipped_stories
             );
-            Ok(())
         }
+    })?;
+    check_failures(failed, story_count, ignore_errors)
+}
+
+/// Shows desktop notifications for the stories in `updates` that got new chapters, if they're
+/// turned on.
+fn notify_new_chapters(updates: &[(StorySource, usize)], db: &Database) -> Result<()> {
+    if !notify::enabled() {
+        return Ok(());
     }
+    let mut named = Vec::new();
+    for (source, new_chapters) in updates.iter().filter(|(_, new)| *new > 0) {
+        let name = db
+            .story_name(&source.to_id())?
+            .unwrap_or_else(|| source.to_url());
+        named.push((name, *new_chapters));
+    }
+    notify::notify_new_chapters(&named);
+    Ok(())
 }
 
-async fn update_story(source: StorySource, force_refresh: bool, db: &Database) -> Result<usize> {
-    let parser = source.parser();
-    if force_refresh {
-        let story = parser.get_story(source).await?;
-        db.save_story(&story)?;
-        Ok(story.num_chapters())
-    } else {
-        let existing_story = db
-            .get_story_by_id(source.to_id().as_str())?
-            .ok_or_else(|| ArchiveError::StoryNotExists(source.to_url()))?;
-        let new_skeleton = parser.get_skeleton(source).await?;
+/// Updates the whole archive every `interval` until a shutdown signal arrives, or just once if
+/// `once` is set. A failed update is logged rather than ending the loop; if it was cut short,
+/// the next one picks up where it left off.
+async fn run_daemon(
+    interval: Duration,
+    once: bool,
+    feed: Option<PathBuf>,
+    options: UpdateOptions,
+    client: &Client,
+    db: &Database,
+) -> Result<()> {
+    loop {
+        log::info!("Checking the archive for updates.");
+        let cycle = async {
+            let updated = update_archive(None, options.clone(), false, client, db).await;
+            // Stories that did update still belong in the feed when others failed.
+            if let Some(ref path) = feed {
+                write_feed(Some(path.clone()), feed::DEFAULT_LENGTH, db)?;
+            }
+            updated
+        };
+        tokio::select! {
+            result = cycle => {
+                // A single run reports failures through its exit code, like `update` does.
+                if once {
+                    return result;
+                }
+                if let Err(err) = result {
+                    log::error!("Update failed: {}", err);
+                }
+            }
+            _ = shutdown_signal() => break,
+        }
+        log::info!("Next update in {}.", describe_interval(interval));
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => (),
+            _ = shutdown_signal() => break,
+        }
+    }
+    log::info!("Shutting down.");
+    Ok(())
+}
 
-        // Get a list of existing chapters and a list of fetched chapters, then filter to only fetched chapters that aren't saved.
-        let mut existing_chapters: HashSet<String> =
-            HashSet::with_capacity(existing_story.chapters.len());
-        existing_story
-            .chapters
-            .iter()
-            .for_each(|chap| flatten_content(&mut existing_chapters, chap));
-        let mut new_chapters: HashSet<String> = HashSet::with_capacity(new_skeleton.chapters.len());
-        new_skeleton
-            .chapters
-            .iter()
-            .for_each(|chap| flatten_content(&mut new_chapters, chap));
-        let new_chapters: Vec<String> = new_chapters
+/// Writes an interval in the largest whole unit it can be given in, like `6h`.
+fn describe_interval(interval: Duration) -> String {
+    let secs = interval.as_secs();
+    match secs {
+        s if s > 0 && s % (24 * 3600) == 0 => format!("{}d", s / (24 * 3600)),
+        s if s > 0 && s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s > 0 && s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+/// Waits until the process is asked to stop, by Ctrl-C or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => (),
+                _ = terminate.recv() => (),
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// One story's result from `hydrate`.
+#[derive(Debug, Serialize)]
+struct HydratedStory {
+    url: String,
+    hydrated_chapters: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+async fn hydrate_stories(
+    story: Option<StorySource>,
+    ignore_errors: bool,
+    client: &Client,
+    db: &Database,
+) -> Result<()> {
+    let sources: Vec<StorySource> = match story {
+        Some(source) => vec![source],
+        None => db
+            .get_all_stories()?
             .into_iter()
-            .filter(|c| !existing_chapters.contains(c))
-            .collect();
-
-        // If there are any new chapters, fetch the story and save them.
-        let mut added_chapters = 0;
-        if !new_chapters.is_empty() {
-            let new_story = parser.fill_skeleton(new_skeleton).await?;
-            for chapter in new_chapters.into_iter() {
-                match new_story.find_chapter(chapter) {
-                    Some(found) => {
-                        db.save_content(
-                            found.chapter,
-                            new_story.source.to_id().as_str(),
-                            found.parent.map(|content| content.id()),
-                        )?;
-                        added_chapters += 1;
-                    }
-                    None => unreachable!(),
+            .map(|story| story.source)
+            .collect(),
+    };
+    let mut results = Vec::with_capacity(sources.len());
+    for source in sources {
+        let url = source.to_url();
+        let result = match db.hydrate_story(client, source).await {
+            Ok(hydrated_chapters) => HydratedStory {
+                url,
+                hydrated_chapters,
+                error: None,
+            },
+            Err(err) => {
+                log::error!("Failed to hydrate story at {}: {}", url, err);
+                HydratedStory {
+                    url,
+                    hydrated_chapters: 0,
+                    error: Some(err.to_string()),
                 }
             }
-        }
-        Ok(added_chapters)
+        };
+        results.push(result);
     }
+    output::report(&results, |results| {
+        for result in results.iter().filter(|result| result.hydrated_chapters > 0) {
+            println!(
+                "Downloaded {} chapter{} of story at {}",
+                result.hydrated_chapters,
+                if result.hydrated_chapters == 1 {
+                    ""
+                } else {
+                    "s"
+                },
+                result.url
+            );
+        }
+        let hydrated: usize = results.iter().map(|result| result.hydrated_chapters).sum();
+        println!(
+            "Hydrated {} chapters across {} stories.",
+            hydrated,
+            results.len()
+        );
+    })?;
+    let failed = results
+        .iter()
+        .filter(|result| result.error.is_some())
+        .count();
+    check_failures(failed, results.len(), ignore_errors)
 }
 
-fn flatten_content(set: &mut HashSet<String>, content: &Content) {
-    set.insert(content.id().to_owned());
-    if let Content::Section(s) = content {
-        for chapter in s.chapters.iter() {
-            flatten_content(set, chapter);
+/// One story's result from `verify`.
+#[derive(Debug, Serialize)]
+struct VerifiedStory {
+    id: String,
+    name: String,
+    undated_chapters: usize,
+    repaired_dates: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+async fn verify_archive(
+    story: Option<StorySource>,
+    fix: bool,
+    ignore_errors: bool,
+    client: &Client,
+    db: &Database,
+) -> Result<()> {
+    let sources: Vec<StorySource> = match story {
+        Some(source) => vec![source],
+        None => db
+            .get_all_stories()?
+            .into_iter()
+            .map(|story| story.source)
+            .collect(),
+    };
+    let mut results = Vec::with_capacity(sources.len());
+    for source in sources {
+        let id = source.to_id();
+        let story = db
+            .get_story_by_id(&id)?
+            .ok_or_else(|| ArchiveError::StoryNotExists(source.to_url()))?;
+        let mut undated = HashSet::new();
+        update::placeholder_dated(&mut undated, &story.chapters);
+        let mut result = VerifiedStory {
+            id,
+            name: story.name,
+            undated_chapters: undated.len(),
+            repaired_dates: 0,
+            error: None,
+        };
+        if fix && !undated.is_empty() {
+            match db.refresh_story(client, source, false).await {
+                Ok(update) => result.repaired_dates = update.repaired_dates,
+                Err(err) => result.error = Some(err.to_string()),
+            }
         }
+        results.push(result);
     }
+    output::report(&results, |results| {
+        for result in results.iter().filter(|result| result.undated_chapters > 0) {
+            match result.error {
+                Some(ref err) => log::error!("Failed to repair \"{}\": {}", result.name, err),
+                None if fix => println!(
+                    "\"{}\": repaired {} of {} chapter dates",
+                    result.name, result.repaired_dates, result.undated_chapters
+                ),
+                None => println!(
+                    "\"{}\": {} chapter{} with no known date (run with --fix to repair)",
+                    result.name,
+                    result.undated_chapters,
+                    if result.undated_chapters == 1 {
+                        ""
+                    } else {
+                        "s"
+                    }
+                ),
+            }
+        }
+        let undated: usize = results.iter().map(|result| result.undated_chapters).sum();
+        let repaired: usize = results.iter().map(|result| result.repaired_dates).sum();
+        println!(
+            "Checked {} stories. Found {} chapters with no known date{}.",
+            results.len(),
+            undated,
+            if fix {
+                format!(" and repaired {}", repaired)
+            } else {
+                String::new()
+            }
+        );
+    })?;
+    let failed = results
+        .iter()
+        .filter(|result| result.error.is_some())
+        .count();
+    check_failures(failed, results.len(), ignore_errors)
 }
 
-async fn delete_story(search: String, db: &Database) -> Result<()> {
-    let matches = db.fuzzy_get_story(search.as_str())?;
-    match matches.len() {
-        0 => println!("No matching stories found. Please try another search."),
-        // 1 => sql::delete_story_by_id(matches[0])?,
-        1 => println!("Got one story back! Id: {}", matches[0]),
-        _ => todo!(),
+/// A story renamed with `rename`.
+#[derive(Debug, Serialize)]
+struct RenamedStory {
+    id: String,
+    old_name: String,
+    name: String,
+}
+
+fn rename_story(search: String, name: String, db: &Database) -> Result<()> {
+    let id = match resolve_story(&search, true, db)? {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+    let old_name = db
+        .story_name(&id)?
+        .ok_or_else(|| ArchiveError::StoryNotExists(id.clone()))?;
+    let name = name.trim().to_owned();
+    db.rename_story(&id, &name)?;
+    let renamed = RenamedStory { id, old_name, name };
+    output::report(&renamed, |renamed| {
+        println!("Renamed \"{}\" to \"{}\".", renamed.old_name, renamed.name)
+    })
+}
+
+async fn delete_story(search: String, yes: bool, db: &Database) -> Result<()> {
+    let id = match resolve_story(&search, !yes, db)? {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+    let story = db
+        .get_story_by_id(&id)?
+        .ok_or_else(|| ArchiveError::StoryNotExists(id.clone()))?;
+    status!(
+        "{}: {} chapter{}",
+        describe(&story),
+        story.num_chapters(),
+        if story.num_chapters() == 1 { "" } else { "s" }
+    );
+    if !yes && !confirm("Delete this story?")? {
+        status!("Not deleting.");
+        return Ok(());
     }
-    Ok(())
+    let deleted = db.delete_story_by_id(&id)?;
+    output::report(&deleted, |deleted| {
+        println!(
+            "Deleted \"{}\". Removed {} chapters, {} sections, {} authors, and {} tags.",
+            story.name, deleted.chapters, deleted.sections, deleted.authors, deleted.tags
+        )
+    })
+}
+
+/// The outcome of an `export`.
+#[derive(Debug, Serialize)]
+struct ExportResult {
+    id: String,
+    path: String,
+}
+
+/// Where and how `export` writes files.
+struct ExportOptions {
+    dir: PathBuf,
+    format: ExportFormat,
+    encoding: Encoding,
+    unencodable: Unencodable,
+}
+
+async fn export_story(search: String, options: &ExportOptions, db: &Database) -> Result<()> {
+    let id = match resolve_story(&search, true, db)? {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+    let story = db
+        .get_story_by_id(&id)?
+        .ok_or_else(|| ArchiveError::StoryNotExists(id.clone()))?;
+    fs::create_dir_all(&options.dir)?;
+    let path = write_export(&story, &export::file_stem(&story), options, db)?;
+    let result = ExportResult {
+        id,
+        path: path.display().to_string(),
+    };
+    output::report(&result, |result| {
+        println!("Exported \"{}\" to {}", story.name, result.path)
+    })
+}
+
+/// The outcome of `feed --output`.
+#[derive(Debug, Serialize)]
+struct FeedResult {
+    path: String,
+    chapters: usize,
+}
+
+fn write_feed(output: Option<PathBuf>, limit: usize, db: &Database) -> Result<()> {
+    let chapters = db.recent_chapters(limit)?;
+    let feed = feed::chapters_feed(&chapters);
+    match output {
+        Some(path) => {
+            fs::write(&path, feed)?;
+            let result = FeedResult {
+                path: path.display().to_string(),
+                chapters: chapters.len(),
+            };
+            output::report(&result, |result| {
+                println!(
+                    "Wrote a feed of {} chapters to {}",
+                    result.chapters, result.path
+                )
+            })
+        }
+        None => {
+            print!("{}", feed);
+            Ok(())
+        }
+    }
+}
+
+/// The outcome of `export --all`.
+#[derive(Debug, Serialize)]
+struct ExportAllResult {
+    exported: Vec<ExportResult>,
+    failed_stories: usize,
 }
 
-async fn list_stories(db: &Database) -> Result<()> {
+async fn export_all(options: &ExportOptions, ignore_errors: bool, db: &Database) -> Result<()> {
     let stories = db.get_all_stories()?;
-    stories.into_iter().for_each(|ls| {
+    fs::create_dir_all(&options.dir)?;
+    let mut used_stems = HashSet::new();
+    let mut exported = Vec::with_capacity(stories.len());
+    let mut failed = 0;
+    for listed in stories.iter() {
+        let id = listed.source.to_id();
+        let result = db
+            .get_story_by_id(&id)
+            .and_then(|story| story.ok_or_else(|| ArchiveError::StoryNotExists(id.clone())))
+            .and_then(|story| {
+                // Two stories with the same name would otherwise overwrite each other.
+                let mut stem = export::file_stem(&story);
+                if !used_stems.insert(stem.clone()) {
+                    stem = format!("{} ({})", stem, id.replace(':', " "));
+                    used_stems.insert(stem.clone());
+                }
+                write_export(&story, &stem, options, db)
+            });
+        match result {
+            Ok(path) => exported.push(ExportResult {
+                id,
+                path: path.display().to_string(),
+            }),
+            Err(err) => {
+                log::error!("Failed to export \"{}\": {}", listed.name, err);
+                failed += 1;
+            }
+        }
+    }
+    let result = ExportAllResult {
+        exported,
+        failed_stories: failed,
+    };
+    output::report(&result, |result| {
         println!(
-            "\"{}\" by {} ({} chapter{})",
-            ls.name,
-            ls.author,
-            ls.chapter_count,
-            if ls.chapter_count == 1 { "" } else { "s" }
+            "Exported {} stories to {}. Failed to export {} stories.",
+            result.exported.len(),
+            options.dir.display(),
+            result.failed_stories
         )
+    })?;
+    check_failures(failed, stories.len(), ignore_errors)
+}
+
+fn write_export(
+    story: &Story,
+    stem: &str,
+    options: &ExportOptions,
+    db: &Database,
+) -> Result<PathBuf> {
+    let media = match options.format {
+        ExportFormat::Epub => StoryMedia {
+            cover: db.get_cover(&story.source.to_id())?,
+            images: db.get_story_images(&story.source.to_id())?,
+        },
+        _ => StoryMedia::default(),
+    };
+    export::write_story(
+        story,
+        &media,
+        &options.dir,
+        stem,
+        options.format,
+        options.encoding,
+        options.unencodable,
+    )
+}
+
+/// The outcome of `import`.
+#[derive(Debug, Serialize)]
+struct ImportResult {
+    id: String,
+    name: String,
+    chapters: usize,
+    /// Whether the story was already in the archive and the file was merged into it.
+    merged: bool,
+}
+
+fn import_story(path: &Path, merge: bool, db: &Database) -> Result<()> {
+    let story = export::read_story(path)?;
+    let id = story.source.to_id();
+    let merged = db.story_exists_with_id(&id)?;
+    match merged {
+        true if !merge => return Err(ArchiveError::StoryExists(id)),
+        true => {
+            db.save_story_metadata(&story)?;
+            db.save_story_contents(&story)?;
+            db.mark_updated(&id)?;
+        }
+        false => db.save_story(&story)?,
+    }
+    let result = ImportResult {
+        id,
+        name: story.name.clone(),
+        chapters: story.num_chapters(),
+        merged,
+    };
+    output::report(&result, |result| {
+        println!(
+            "{} \"{}\" ({} chapter{})",
+            if result.merged { "Merged" } else { "Imported" },
+            result.name,
+            result.chapters,
+            if result.chapters == 1 { "" } else { "s" }
+        )
+    })
+}
+
+/// A chapter printed by `read`.
+#[derive(Debug, Serialize)]
+struct ReadResult {
+    story_id: String,
+    chapter_id: String,
+    name: String,
+    text: String,
+}
+
+async fn read_chapter(
+    search: String,
+    chapter: Option<String>,
+    format: TextFormat,
+    client: &Client,
+    db: &Database,
+) -> Result<()> {
+    let id = match resolve_story(&search, true, db)? {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+    let story = db
+        .get_story_by_id(&id)?
+        .ok_or_else(|| ArchiveError::StoryNotExists(id.clone()))?;
+    let found = match chapter {
+        Some(ref chapter) => story.chapter_matching(chapter),
+        None => {
+            let progress = db.get_progress(&id)?;
+            let chapters = story.all_chapters();
+            progress
+                .and_then(|progress| {
+                    chapters
+                        .iter()
+                        .find(|chapter| chapter.id == progress.chapter_id)
+                        .copied()
+                })
+                .or_else(|| chapters.first().copied())
+        }
+    };
+    let mut found = found
+        .cloned()
+        .ok_or_else(|| ArchiveError::ChapterNotExists(chapter.unwrap_or_else(|| "1".to_owned())))?;
+    if let ChapterText::Dehydrated = found.text {
+        found = db.hydrate_chapter(client, &id, &found.id).await?;
+    }
+    let result = ReadResult {
+        story_id: id,
+        chapter_id: found.id,
+        name: found.name,
+        text: parser::convert_to_format(found.text.as_str().to_owned(), format),
+    };
+    output::report(&result, |result| println!("{}", result.text.trim_end()))
+}
+
+async fn list_stories(filter: &StoryFilter, sort: Option<ListOrder>, db: &Database) -> Result<()> {
+    let mut stories: Vec<ListedStory> = db.get_stories(filter)?;
+    match sort {
+        Some(ListOrder::Name) => stories.sort_by_key(|ls| ls.name.to_lowercase()),
+        // Most recent first, with stories whose dates aren't known at the end.
+        Some(ListOrder::Added) => stories.sort_by_key(|ls| Reverse(ls.dates.added_at)),
+        Some(ListOrder::Updated) => stories.sort_by_key(|ls| Reverse(ls.dates.updated_at)),
+        None => (),
+    }
+    output::report(&stories, |stories| {
+        for ls in stories {
+            println!(
+                "{} \"{}\" by {} ({} chapter{})",
+                match ls.completed {
+                    Completed::Complete => "[Complete]",
+                    Completed::Incomplete => "[Ongoing]",
+                    Completed::Unknown => "[?]",
+                },
+                style::bold(&ls.name),
+                ls.author,
+                ls.chapter_count,
+                if ls.chapter_count == 1 { "" } else { "s" }
+            )
+        }
+    })
+}
+
+async fn search_text(phrase: String, db: &Database) -> Result<()> {
+    let hits = db.search_text(&phrase)?;
+    output::report(&hits, |hits| {
+        if hits.is_empty() {
+            println!("No chapters contain \"{}\".", phrase);
+        }
+        for hit in hits {
+            println!("\"{}\": {}", style::bold(&hit.story_name), hit.chapter_name);
+            println!("    {}", highlight(&hit.snippet));
+        }
+    })
+}
+
+/// A story's own tags after `tag` changed them.
+#[derive(Debug, Serialize)]
+struct TaggedStory {
+    id: String,
+    name: String,
+    added: Vec<String>,
+    removed: Vec<String>,
+    tags: Vec<String>,
+}
+
+fn tag_story(search: String, add: Vec<String>, remove: Vec<String>, db: &Database) -> Result<()> {
+    let id = match resolve_story(&search, true, db)? {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+    let name = db
+        .story_name(&id)?
+        .ok_or_else(|| ArchiveError::StoryNotExists(id.clone()))?;
+    // The namespace can be given or left off.
+    let user_tag = |tag: &str| {
+        let tag = tag.trim();
+        let prefix = format!("{}:", USER_TAG_NAMESPACE);
+        match tag.to_lowercase().starts_with(&prefix) {
+            true => tag[prefix.len()..].trim().to_owned(),
+            false => tag.to_owned(),
+        }
+    };
+    let mut added = Vec::new();
+    for tag in add.iter().map(|tag| user_tag(tag)) {
+        if !tag.is_empty() && db.add_user_tag(&id, &tag)? {
+            added.push(tag);
+        }
+    }
+    let mut removed = Vec::new();
+    for tag in remove.iter().map(|tag| user_tag(tag)) {
+        if db.remove_user_tag(&id, &tag)? {
+            removed.push(tag);
+        }
+    }
+    let tagged = TaggedStory {
+        tags: db.user_tags(&id)?,
+        id,
+        name,
+        added,
+        removed,
+    };
+    output::report(&tagged, |tagged| {
+        if !tagged.added.is_empty() {
+            println!("Added {}.", tagged.added.join(", "));
+        }
+        if !tagged.removed.is_empty() {
+            println!("Removed {}.", tagged.removed.join(", "));
+        }
+        match tagged.tags.is_empty() {
+            true => println!("\"{}\" has no tags of your own.", tagged.name),
+            false => println!("\"{}\" is tagged {}.", tagged.name, tagged.tags.join(", ")),
+        }
+    })
+}
+
+fn list_tags(prefix: Option<String>, db: &Database) -> Result<()> {
+    let prefix = prefix.map(|prefix| prefix.trim_end_matches(':').to_lowercase());
+    let mut namespaces: BTreeMap<String, Vec<TagCount>> = BTreeMap::new();
+    for count in db.tag_counts()? {
+        let (namespace, name) = count.name.split_once(':').unwrap_or(("", &count.name));
+        if prefix
+            .as_ref()
+            .is_some_and(|prefix| *prefix != namespace.to_lowercase())
+        {
+            continue;
+        }
+        namespaces
+            .entry(namespace.to_owned())
+            .or_default()
+            .push(TagCount {
+                name: name.to_owned(),
+                stories: count.stories,
+            });
+    }
+    output::report(&namespaces, |namespaces| {
+        if namespaces.is_empty() {
+            println!("No tags found.");
+        }
+        for (namespace, tags) in namespaces {
+            match namespace.is_empty() {
+                true => println!("{}", style::bold("(no namespace)")),
+                false => println!("{}", style::bold(namespace)),
+            }
+            for tag in tags {
+                println!("    {} ({})", tag.name, tag.stories);
+            }
+        }
+    })
+}
+
+/// Shows the matches marked in a search snippet in bold, if output is styled.
+fn highlight(snippet: &str) -> String {
+    if !style::should_style() {
+        return snippet.to_owned();
+    }
+    snippet
+        .split("**")
+        .enumerate()
+        .map(|(idx, part)| match idx % 2 {
+            1 => style::bold(part),
+            _ => part.to_owned(),
+        })
+        .collect()
+}
+
+/// A story whose page was opened with `open`.
+#[derive(Debug, Serialize)]
+struct OpenedStory {
+    id: String,
+    name: String,
+    url: String,
+}
+
+fn open_story(search: String, db: &Database) -> Result<()> {
+    let id = match resolve_story(&search, true, db)? {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+    let story = db
+        .get_story_by_id(&id)?
+        .ok_or_else(|| ArchiveError::StoryNotExists(id.clone()))?;
+    open::that(&story.url)?;
+    let opened = OpenedStory {
+        id,
+        name: story.name,
+        url: story.url,
+    };
+    output::report(&opened, |opened| {
+        println!("Opened \"{}\" at {}.", opened.name, opened.url)
+    })
+}
+
+/// The details shown by `info`.
+#[derive(Debug, Serialize)]
+struct StoryInfo {
+    id: String,
+    name: String,
+    authors: Vec<String>,
+    url: String,
+    site: &'static str,
+    completed: Completed,
+    language: Option<String>,
+    chapters: usize,
+    words: usize,
+    /// When the source says the story was published and last updated.
+    published: Option<DateTime<FixedOffset>>,
+    last_updated: Option<DateTime<FixedOffset>>,
+    /// When the first and last chapters were posted, as far as is known.
+    first_posted: Option<DateTime<FixedOffset>>,
+    last_posted: Option<DateTime<FixedOffset>>,
+    /// Tags grouped by namespace, with un-namespaced tags under the empty string.
+    tags: BTreeMap<String, Vec<String>>,
+    #[serde(flatten)]
+    dates: StoryDates,
+    /// The name of the chapter the story was last left off at in the reader.
+    last_read: Option<String>,
+}
+
+async fn story_info(search: String, db: &Database) -> Result<()> {
+    let id = match resolve_story(&search, true, db)? {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+    let story = db
+        .get_story_by_id(&id)?
+        .ok_or_else(|| ArchiveError::StoryNotExists(id.clone()))?;
+    let chapters = story.all_chapters();
+    let posted = chapters
+        .iter()
+        .filter(|chapter| !chapter.has_placeholder_date())
+        .map(|chapter| chapter.date_posted);
+    let last_read = db.get_progress(&id)?.and_then(|progress| {
+        chapters
+            .iter()
+            .find(|chapter| chapter.id == progress.chapter_id)
+            .map(|chapter| chapter.name.clone())
     });
-    Ok(())
+    let info = StoryInfo {
+        dates: db.get_story_dates(&id)?,
+        id,
+        name: story.name.clone(),
+        authors: story
+            .authors
+            .authors()
+            .iter()
+            .map(|author| author.name.clone())
+            .collect(),
+        url: story.url.clone(),
+        site: story.source.site_name(),
+        completed: story.completed,
+        language: story.language.clone(),
+        chapters: chapters.len(),
+        words: story.total_word_count(),
+        published: story.published,
+        last_updated: story.last_updated,
+        first_posted: posted.clone().min(),
+        last_posted: posted.max(),
+        tags: story.tags_by_namespace(),
+        last_read,
+    };
+    output::report(&info, |info| {
+        let date = |date: Option<DateTime<FixedOffset>>| match date {
+            Some(date) => date.format("%Y-%m-%d %H:%M").to_string(),
+            None => "unknown".to_owned(),
+        };
+        println!("{}", style::bold(&info.name));
+        println!("By:            {}", info.authors.join(", "));
+        println!("ID:            {}", info.id);
+        println!("URL:           {}", info.url);
+        println!("Site:          {}", info.site);
+        println!(
+            "Status:        {}",
+            match info.completed {
+                Completed::Complete => "Complete",
+                Completed::Incomplete => "Ongoing",
+                Completed::Unknown => "Unknown",
+            }
+        );
+        if let Some(ref language) = info.language {
+            println!("Language:      {}", language);
+        }
+        println!("Chapters:      {} ({} words)", info.chapters, info.words);
+        if info.published.is_some() || info.last_updated.is_some() {
+            println!("Published:     {}", date(info.published));
+            println!("Site updated:  {}", date(info.last_updated));
+        }
+        println!("First posted:  {}", date(info.first_posted));
+        println!("Last posted:   {}", date(info.last_posted));
+        println!("Added:         {}", date(info.dates.added_at));
+        println!("Updated:       {}", date(info.dates.updated_at));
+        if let Some(ref chapter) = info.last_read {
+            println!("Last read:     {}", chapter);
+        }
+        if !info.tags.is_empty() {
+            println!("Tags:");
+            for (namespace, tags) in info.tags.iter() {
+                match namespace.is_empty() {
+                    true => println!("  {}", tags.join(", ")),
+                    false => println!("  {}: {}", namespace, tags.join(", ")),
+                }
+            }
+        }
+    })
 }
@@ -0,0 +1,126 @@
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Past this many stories with new chapters, one notification sums them all up instead of each
+/// getting its own.
+const MAX_NOTIFICATIONS: usize = 5;
+
+/// Turns desktop notifications on or off. They are off unless this is called with `true`.
+pub(crate) fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether updates should show desktop notifications.
+pub(crate) fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Shows a desktop notification for each story that got new chapters in an update, given as
+/// its name and how many chapters it got.
+pub(crate) fn notify_new_chapters(updates: &[(String, usize)]) {
+    for (summary, body) in notifications(updates) {
+        send(&summary, &body);
+    }
+}
+
+/// The title and text of each notification to show for `updates`.
+fn notifications(updates: &[(String, usize)]) -> Vec<(String, String)> {
+    let updates: Vec<&(String, usize)> = updates.iter().filter(|(_, new)| *new > 0).collect();
+    if updates.len() > MAX_NOTIFICATIONS {
+        let total: usize = updates.iter().map(|(_, new)| new).sum();
+        let names: Vec<&str> = updates.iter().map(|(name, _)| name.as_str()).collect();
+        return vec![(
+            format!("{} new chapters in {} stories", total, updates.len()),
+            names.join(", "),
+        )];
+    }
+    updates
+        .into_iter()
+        .map(|(name, new)| {
+            let summary = match new {
+                1 => "1 new chapter".to_owned(),
+                new => format!("{} new chapters", new),
+            };
+            (summary, format!("of {}", name))
+        })
+        .collect()
+}
+
+/// Hands a notification to the desktop's notification tool without waiting for it. If that
+/// can't be done, it's only logged, so that a headless machine doesn't fail an update.
+fn send(summary: &str, body: &str) {
+    let mut command = match notify_command(summary, body) {
+        Some(command) => command,
+        None => {
+            log::warn!("Desktop notifications aren't supported on this platform.");
+            return;
+        }
+    };
+    let shown = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    match shown {
+        // Waited on elsewhere so that a long-running daemon doesn't collect finished processes.
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+        }
+        Err(err) => log::warn!("Could not show a notification: {}", err),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn notify_command(summary: &str, body: &str) -> Option<Command> {
+    let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+    let mut command = Command::new("osascript");
+    command.arg("-e").arg(format!(
+        "display notification {} with title {}",
+        quote(body),
+        quote(summary)
+    ));
+    Some(command)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn notify_command(summary: &str, body: &str) -> Option<Command> {
+    let mut command = Command::new("notify-send");
+    command.args(["--app-name", "fic_archive", summary, body]);
+    Some(command)
+}
+
+#[cfg(not(unix))]
+fn notify_command(_summary: &str, _body: &str) -> Option<Command> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifies_per_story_until_there_are_too_many() {
+        let updates = vec![
+            ("First".to_owned(), 3),
+            ("Unchanged".to_owned(), 0),
+            ("Second".to_owned(), 1),
+        ];
+        assert_eq!(
+            notifications(&updates),
+            vec![
+                ("3 new chapters".to_owned(), "of First".to_owned()),
+                ("1 new chapter".to_owned(), "of Second".to_owned()),
+            ]
+        );
+
+        let updates: Vec<(String, usize)> = (1..=MAX_NOTIFICATIONS + 1)
+            .map(|n| (format!("Story {}", n), 2))
+            .collect();
+        let batched = notifications(&updates);
+        assert_eq!(batched.len(), 1);
+        assert_eq!(batched[0].0, "12 new chapters in 6 stories");
+        assert!(batched[0].1.starts_with("Story 1, Story 2"));
+    }
+}
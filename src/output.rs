@@ -0,0 +1,47 @@
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+
+use std::io::{self, Write};
+
+use crate::error::ArchiveError;
+use crate::Result;
+
+static JSON: OnceCell<bool> = OnceCell::new();
+
+/// Switches every command to emitting JSON on stdout, with messages for humans moved to stderr.
+/// Only the first call has any effect.
+pub(crate) fn set_json(enabled: bool) {
+    let _ = JSON.set(enabled);
+}
+
+/// Whether command results are being emitted as JSON.
+pub(crate) fn json() -> bool {
+    JSON.get().copied().unwrap_or(false)
+}
+
+/// Prints a command's result: as a single JSON document on stdout when `--json` is set,
+/// otherwise by calling `human` to describe it in prose.
+pub(crate) fn report<T: Serialize + ?Sized>(value: &T, human: impl FnOnce(&T)) -> Result<()> {
+    if json() {
+        let mut stdout = io::stdout().lock();
+        serde_json::to_writer_pretty(&mut stdout, value)
+            .map_err(|e| ArchiveError::Internal(format!("Could not serialize output: {}", e)))?;
+        writeln!(stdout)?;
+    } else {
+        human(value);
+    }
+    Ok(())
+}
+
+/// Writes a prompt without a trailing newline to wherever status messages go and flushes it so
+/// that it shows up before reading the user's answer.
+pub(crate) fn prompt(text: &str) -> Result<()> {
+    if json() {
+        eprint!("{}", text);
+        io::stderr().flush()?;
+    } else {
+        print!("{}", text);
+        io::stdout().flush()?;
+    }
+    Ok(())
+}
@@ -4,90 +4,339 @@ use chrono::{
     offset::{FixedOffset, Local, TimeZone},
     DateTime,
 };
+use clap::ValueEnum;
+use futures::future::join_all;
+use once_cell::sync::OnceCell;
 use regex::Regex;
+use reqwest::{Client, Url};
 use select::{
     document::Document,
     node::Node,
     predicate::{self, Predicate},
 };
 
+use std::collections::HashSet;
+
 use crate::{
-    client::get_with_query,
+    client::{get, get_with_query, post_form},
     error::ArchiveError,
-    parser::Parser,
-    structs::{Author, AuthorList, Chapter, ChapterText, Completed, Content, Story, StorySource},
+    parser::{
+        chapter_html, completion_status, date_or_placeholder, fetch_chapters, parse_blocking,
+        unclassified_tag, Parser, StatusLabels,
+    },
+    structs::{
+        Author, AuthorList, Chapter, ChapterText, Completed, Content, Section, Story, StorySource,
+    },
     Result,
 };
 
 static CHAPTER_REGEX: (&str, once_cell::sync::OnceCell<regex::Regex>) =
     (r"/chapters/(\d+)", once_cell::sync::OnceCell::new());
+static WORK_REGEX: (&str, once_cell::sync::OnceCell<regex::Regex>) = (
+    r"^(?:https://archiveofourown\.org)?/works/(\d+)$",
+    once_cell::sync::OnceCell::new(),
+);
+
+/// Works with more chapters than this are fetched chapter-by-chapter in [`FetchMode::Auto`].
+const FULL_WORK_CHAPTER_LIMIT: usize = 50;
+
+static FETCH_MODE: OnceCell<FetchMode> = OnceCell::new();
+
+/// How the chapter text of an AO3 work is downloaded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FetchMode {
+    /// Use the full-work page for small works and fetch large works chapter by chapter.
+    #[default]
+    Auto,
+    /// Always download the entire work as a single page.
+    #[value(name = "full")]
+    FullWork,
+    /// Always fetch the chapter index and then each chapter page individually.
+    #[value(name = "chapters")]
+    PerChapter,
+}
+
+/// Sets the fetch mode used by every AO3 parser for the rest of the run. Only the first
+/// call has any effect.
+pub(crate) fn set_fetch_mode(mode: FetchMode) {
+    let _ = FETCH_MODE.set(mode);
+}
+
+const LOGIN_URL: &str = "https://archiveofourown.org/users/login";
+
+static LOGIN: OnceCell<(String, String)> = OnceCell::new();
+/// How logging in went, once it's been tried. Logging in is only tried once a run, the first
+/// time a page turns out to need it.
+static LOGGED_IN: tokio::sync::OnceCell<std::result::Result<(), String>> =
+    tokio::sync::OnceCell::const_new();
+
+/// Sets the username and password used to log in to AO3 for works that only logged-in users
+/// can see. Only the first call has any effect.
+pub(crate) fn set_login(username: String, password: String) {
+    let _ = LOGIN.set((username, password));
+}
+
+/// Fetches the text of an AO3 page, agreeing to see adult content along the way. Pages that
+/// only logged-in users may see send us to the login page instead; if a login is configured,
+/// we log in and try once more.
+async fn get_page(client: &Client, url: &str, query: &[(&str, &str)]) -> Result<String> {
+    let query = [&[("view_adult", "true")], query].concat();
+    let response = get_with_query(client, url, &query).await?;
+    if !is_login_page(response.url()) {
+        return Ok(response.text().await?);
+    }
+    let Some((username, password)) = LOGIN.get() else {
+        return Err(ArchiveError::LoginRequired(url.to_owned()));
+    };
+    LOGGED_IN
+        .get_or_init(|| async {
+            log_in(client, username, password)
+                .await
+                .map_err(|err| err.to_string())
+        })
+        .await
+        .clone()
+        .map_err(|reason| ArchiveError::LoginFailed("AO3".to_owned(), reason))?;
+    let response = get_with_query(client, url, &query).await?;
+    match is_login_page(response.url()) {
+        true => Err(ArchiveError::LoginRequired(url.to_owned())),
+        false => Ok(response.text().await?),
+    }
+}
+
+fn is_login_page(url: &Url) -> bool {
+    url.path() == "/users/login"
+}
+
+/// Submits AO3's login form, leaving the session cookie it sets in the client's cookies.
+async fn log_in(client: &Client, username: &str, password: &str) -> Result<()> {
+    log::info!("Logging in to AO3 as {}.", username);
+    let page = get(client, LOGIN_URL).await?.text().await?;
+    let token = parse_blocking(move || authenticity_token(&page)).await?;
+    let response = post_form(
+        client,
+        LOGIN_URL,
+        &[
+            ("authenticity_token", token.as_str()),
+            ("user[login]", username),
+            ("user[password]", password),
+            ("user[remember_me]", "1"),
+            ("commit", "Log in"),
+        ],
+    )
+    .await?;
+    // A successful login moves on to the user's dashboard; a failed one shows the form again.
+    match is_login_page(response.url()) {
+        true => Err(ArchiveError::PageError(
+            "the username or password was not accepted".to_owned(),
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Finds the token that AO3's login form has to be submitted with.
+fn authenticity_token(page: &str) -> Result<String> {
+    Document::from_read(page.as_bytes())?
+        .find(
+            predicate::Attr("id", "new_user")
+                .descendant(predicate::Attr("name", "authenticity_token")),
+        )
+        .next()
+        .and_then(|input| input.attr("value"))
+        .map(str::to_owned)
+        .ok_or(ArchiveError::PageError(
+            "AO3: Could not find the login form's authenticity token".to_owned(),
+        ))
+}
 
 pub(crate) struct AO3Parser;
 
 #[async_trait]
 impl Parser for AO3Parser {
-    async fn get_skeleton(&self, source: StorySource) -> Result<Story> {
-        let main_page = get_with_query(
-            &source.to_url(),
-            &[("view_adult", "true"), ("view_full_work", "true")],
-        )
-        .await?
-        .text()
-        .await?;
-        let navigate = get_with_query(
-            &format!("{}/navigate", source.to_url()),
-            &[("view_adult", "true")],
-        )
-        .await?
+    async fn get_skeleton(&self, client: &Client, source: StorySource) -> Result<Story> {
+        match source {
+            StorySource::AO3Series(_) => get_series(client, source, false).await,
+            _ => get_work_skeleton(client, source).await,
+        }
+    }
+
+    async fn get_metadata(&self, client: &Client, source: StorySource) -> Result<Story> {
+        match source {
+            StorySource::AO3Series(_) => get_series(client, source, true).await,
+            _ => get_work_metadata(client, source).await,
+        }
+    }
+
+    async fn fill_skeleton(&self, client: &Client, mut skeleton: Story) -> Result<Story> {
+        let name = skeleton.name.clone();
+        let hydrate = skeleton
+            .all_chapters_mut()
+            .into_iter()
+            .filter(|chapter| matches!(chapter.text, ChapterText::Dehydrated))
+            .map(|chapter| async {
+                let page = get_page(client, &chapter.url, &[]).await?;
+                let url = chapter.url.clone();
+                let text = parse_blocking(move || {
+                    let document = Document::from_read(page.as_bytes())?;
+                    let node = document
+                        .find(predicate::Attr("id", "chapters").child(predicate::Class("chapter")))
+                        .next()
+                        .ok_or(ArchiveError::PageError(format!(
+                            "AO3: Could not find chapter body ([id=\"chapters\"] > .chapter) for chapter at {}",
+                            url
+                        )))?;
+                    get_chapter_text(&node, &url)
+                })
+                .await?;
+                chapter.text = ChapterText::Hydrated(text);
+                Ok(())
+            });
+
+        let results: Vec<Result<()>> = fetch_chapters(&name, hydrate).await;
+        match results.into_iter().find(|res| res.is_err()) {
+            Some(err) => Err(err.unwrap_err()),
+            None => Ok(skeleton),
+        }
+    }
+
+    async fn get_story(&self, client: &Client, source: StorySource) -> Result<Story> {
+        let story = self.get_skeleton(client, source).await?;
+        self.fill_skeleton(client, story).await
+    }
+}
+
+async fn get_work_skeleton(client: &Client, source: StorySource) -> Result<Story> {
+    let navigate = get_page(client, &format!("{}/navigate", source.to_url()), &[]).await?;
+    let per_chapter = parse_blocking({
+        let navigate = navigate.clone();
+        move || {
+            Ok(fetch_per_chapter(&Document::from_read(
+                navigate.as_bytes(),
+            )?))
+        }
+    })
+    .await?;
+
+    let main_page = match per_chapter {
+        true => get_page(client, &source.to_url(), &[]).await?,
+        false => get_page(client, &source.to_url(), &[("view_full_work", "true")]).await?,
+    };
+    parse_blocking(move || parse_work(source, &main_page, &navigate, per_chapter)).await
+}
+
+async fn get_work_metadata(client: &Client, source: StorySource) -> Result<Story> {
+    // The work's own page without `view_full_work` has all of its details but only the
+    // first chapter, so it's parsed the same way as a work fetched chapter by chapter.
+    let navigate = get_page(client, &format!("{}/navigate", source.to_url()), &[]).await?;
+    let main_page = get_page(client, &source.to_url(), &[]).await?;
+    parse_blocking(move || parse_work(source, &main_page, &navigate, true)).await
+}
+
+/// Builds a story out of every work in a series, with each work as a section holding its
+/// chapters. With `metadata_only`, each work is fetched as by [`Parser::get_metadata`], which
+/// skips downloading its chapter text.
+async fn get_series(client: &Client, source: StorySource, metadata_only: bool) -> Result<Story> {
+    let url = source.to_url();
+    let first_page = get_page(client, &url, &[]).await?;
+    let mut series = parse_blocking({
+        let source = source.clone();
+        move || parse_series_page(&source, &first_page)
+    })
+    .await?;
+    let other_pages = (2..=series.pages).map(|page| {
+        let (url, source) = (&url, source.clone());
+        async move {
+            let page = page.to_string();
+            let text = get_page(client, url, &[("page", page.as_str())]).await?;
+            parse_blocking(move || parse_series_page(&source, &text)).await
+        }
+    });
+    for page in join_all(other_pages).await {
+        series.work_ids.extend(page?.work_ids);
+    }
+    let mut seen = HashSet::new();
+    series.work_ids.retain(|id| seen.insert(id.clone()));
+
+    let works = series.work_ids.iter().map(|id| {
+        let work = StorySource::AO3(id.clone());
+        async move {
+            match metadata_only {
+                true => get_work_metadata(client, work).await,
+                false => get_work_skeleton(client, work).await,
+            }
+        }
+    });
+    let works = join_all(works)
+        .await
+        .into_iter()
+        .collect::<Result<Vec<Story>>>()?;
+    Ok(series_story(source, series, works))
+}
+
+/// Builds a story from the text of a work's main page and navigate page. `per_chapter` should
+/// match how the main page was fetched: when set, chapters come from the navigate page's index
+/// and are left dehydrated for `fill_skeleton`.
+fn parse_work(
+    source: StorySource,
+    main_page: &str,
+    navigate: &str,
+    per_chapter: bool,
+) -> Result<Story> {
+    let main_page = Document::from_read(main_page.as_bytes())?;
+    let navigate = Document::from_read(navigate.as_bytes())?;
+
+    let name = main_page
+        .find(predicate::Class("title").and(predicate::Class("heading")))
+        .next()
+        .ok_or(ArchiveError::PageError(format!(
+            "AO3: Could not find title (.title.heading) for story at {}",
+            source.to_url(),
+        )))?
         .text()
-        .await?;
-        let main_page = Document::from_read(main_page.as_bytes())?;
-        let navigate = Document::from_read(navigate.as_bytes())?;
+        .trim()
+        .to_owned();
 
-        let name = main_page
-            .find(predicate::Class("title").and(predicate::Class("heading")))
-            .next()
-            .ok_or(ArchiveError::PageError(format!(
-                "AO3: Could not find title (.title.heading) for story at {}",
-                source.to_url(),
-            )))?
-            .text()
-            .trim()
-            .to_owned();
-
-        let author = main_page
-            .find(predicate::Attr("rel", "author").and(predicate::Attr("href", ())))
-            .next()
-            .ok_or(ArchiveError::PageError(format!(
-                "AO3: Could not find author ([rel=\"author\" href]) for story at {}",
-                source.to_url(),
-            )))?;
-        let author_url = author
-            .attr("href")
-            .expect("Author link should have href because of find() conditions");
-        let author = Author {
-            name: author.text(),
-            id: format!(
-                "ao3{}",
-                author_url
-                    .replace("/users/", "")
-                    .splitn(2, "/pseuds/")
-                    .fold(String::new(), |mut acc, s| {
-                        acc.push(':');
-                        acc.push_str(s);
-                        acc
-                    }),
-            ),
-        };
+    let authors = get_authors(&main_page, &source)?;
 
-        let description = main_page
-            .find(predicate::Class("summary").child(predicate::Class("userstuff")))
-            .next()
-            .map(|n| n.children().map(|elem| elem.inner_html()).collect());
-        let url = source.to_url();
-        let tags = get_tags(&main_page);
-        let completed = get_completed(&main_page, &source);
+    let description = main_page
+        .find(predicate::Class("summary").child(predicate::Class("userstuff")))
+        .next()
+        .map(|n| n.children().map(|elem| elem.inner_html()).collect());
+    let url = source.to_url();
+    let tags = get_tags(&main_page);
+    let completed = get_completed(&main_page, &source);
+    let language = get_language(&main_page);
+    let cover_url = get_cover_url(&main_page);
+    let (published, last_updated) = get_story_dates(&main_page);
 
+    let chapters: Vec<Result<Content>> = if per_chapter {
+        navigate
+            .find(chapter_index_links())
+            .map(|link| {
+                let url = link
+                    .attr("href")
+                    .expect("Node should have href guaranteed by find() conditions")
+                    .to_owned();
+                let id = get_chapter_id_from_url(&url, &source)?;
+                let full_title = link.text();
+                let name = match full_title.split_once(". ") {
+                    Some((_, name)) => name.trim().to_owned(),
+                    None => full_title.trim().to_owned(),
+                };
+                let date_posted =
+                    date_or_placeholder(get_chapter_date_posted(&navigate, &url, &source), &url);
+                Ok(Content::Chapter(Chapter {
+                    id,
+                    name,
+                    description: None,
+                    text: ChapterText::Dehydrated,
+                    url: format!("https://archiveofourown.org{}", url),
+                    date_posted,
+                    author: None,
+                }))
+            })
+            .collect()
+    } else {
         let chapters = main_page
             .find(predicate::Attr("id", "chapters"))
             .next()
@@ -99,13 +348,16 @@ impl Parser for AO3Parser {
             .children()
             .filter(|c| c.is(predicate::Class("chapter")))
             .peekable();
-        let chapters = if children.peek().is_some() {
+        if children.peek().is_some() {
             children
                 .map(|chapter| {
                     let url = get_chapter_url(&chapter, &source)?;
                     let id = get_chapter_id(&chapter, &source)?;
                     let name = get_chapter_name(&chapter, &source)?;
-                    let date_posted = get_chapter_date_posted(&navigate, &url, &source)?;
+                    let date_posted = date_or_placeholder(
+                        get_chapter_date_posted(&navigate, &url, &source),
+                        &url,
+                    );
                     let text = get_chapter_text(&chapter, &url)?;
                     Ok(Content::Chapter(Chapter {
                         id,
@@ -128,7 +380,7 @@ impl Parser for AO3Parser {
                         source.to_url()
                     )))?
                     .text();
-                let date_posted = date_string_to_datetime(posted_on)?;
+                let date_posted = date_or_placeholder(date_string_to_datetime(posted_on), &url);
                 let text = get_chapter_text(&chapters, &url)?;
                 Ok(Content::Chapter(Chapter {
                     id: format!("{}:", source.to_id()),
@@ -140,51 +392,257 @@ impl Parser for AO3Parser {
                     author: None,
                 }))
             }]
-        };
-
-        if chapters.iter().find(|c| c.is_err()).is_some() {
-            return Err(chapters
-                .into_iter()
-                .find(|c| c.is_err())
-                .unwrap()
-                .unwrap_err());
         }
+    };
 
-        let chapters = chapters
+    if chapters.iter().find(|c| c.is_err()).is_some() {
+        return Err(chapters
             .into_iter()
-            .map(|c| c.expect("If there was an error we would have returned already."))
-            .collect();
+            .find(|c| c.is_err())
+            .unwrap()
+            .unwrap_err());
+    }
 
-        Ok(Story {
-            name: name.trim().to_owned(),
-            authors: AuthorList::new(author),
-            description: description.map(|d: String| d.trim().to_owned()),
-            url,
-            tags,
-            chapters,
-            source,
-            completed,
+    let chapters = chapters
+        .into_iter()
+        .map(|c| c.expect("If there was an error we would have returned already."))
+        .collect();
+
+    Ok(Story {
+        name: name.trim().to_owned(),
+        authors,
+        description: description.map(|d: String| d.trim().to_owned()),
+        url,
+        tags,
+        chapters,
+        source,
+        completed,
+        language,
+        cover_url,
+        published,
+        last_updated,
+    })
+}
+
+/// What one page of a series' listing says about it.
+struct SeriesPage {
+    name: String,
+    authors: AuthorList,
+    description: Option<String>,
+    work_ids: Vec<String>,
+    /// How many pages the series' list of works is split across.
+    pages: usize,
+}
+
+fn parse_series_page(source: &StorySource, page: &str) -> Result<SeriesPage> {
+    let document = Document::from_read(page.as_bytes())?;
+    let name = document
+        .find(predicate::Name("h2").and(predicate::Class("heading")))
+        .next()
+        .ok_or(ArchiveError::PageError(format!(
+            "AO3: Could not find title (h2.heading) for series at {}",
+            source.to_url(),
+        )))?
+        .text()
+        .trim()
+        .to_owned();
+    let description = document
+        .find(series_meta().descendant(predicate::Class("userstuff")))
+        .next()
+        .map(|node| node.inner_html().trim().to_owned());
+    let work_ids = document
+        .find(
+            predicate::Class("blurb")
+                .descendant(predicate::Class("heading"))
+                .descendant(predicate::Name("a").and(predicate::Attr("href", ()))),
+        )
+        .filter_map(|link| {
+            WORK_REGEX
+                .1
+                .get_or_init(|| Regex::new(WORK_REGEX.0).unwrap())
+                .captures(link.attr("href")?)
+                .map(|captures| captures[1].to_owned())
         })
+        .collect();
+    let pages = document
+        .find(predicate::Class("pagination").descendant(predicate::Name("a")))
+        .filter_map(|link| link.text().trim().parse::<usize>().ok())
+        .max()
+        .unwrap_or(1);
+    Ok(SeriesPage {
+        name,
+        authors: get_authors(&document, source)?,
+        description,
+        work_ids,
+        pages,
+    })
+}
+
+fn series_meta() -> impl Predicate {
+    predicate::Class("series").and(predicate::Class("meta"))
+}
+
+/// Puts a series' works together into one story, with a section for each work. Chapter ids are
+/// prefixed with the series' id so that they don't clash with the same work saved on its own.
+fn series_story(source: StorySource, series: SeriesPage, works: Vec<Story>) -> Story {
+    let series_id = source.to_id();
+    let author_ids = |authors: &AuthorList| -> Vec<String> {
+        authors
+            .authors()
+            .iter()
+            .map(|author| author.id.clone())
+            .collect()
+    };
+    let mut tags: Vec<String> = Vec::new();
+    for tag in works.iter().flat_map(|work| work.tags.iter()) {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+    let mut languages: Vec<&String> = works
+        .iter()
+        .filter_map(|work| work.language.as_ref())
+        .collect();
+    languages.dedup();
+    let language = match languages[..] {
+        [language] => Some(language.clone()),
+        _ => None,
+    };
+
+    Story {
+        completed: series_completed(works.iter().map(|work| work.completed)),
+        cover_url: works.iter().find_map(|work| work.cover_url.clone()),
+        published: works.iter().filter_map(|work| work.published).min(),
+        last_updated: works.iter().filter_map(|work| work.last_updated).max(),
+        language,
+        tags,
+        chapters: works
+            .into_iter()
+            .map(|mut work| {
+                let work_id = work.source.to_id();
+                let oneshot_id = format!("{}:", work_id);
+                for chapter in work.all_chapters_mut() {
+                    chapter.id = match chapter.id == oneshot_id {
+                        true => format!("{}:{}", series_id, oneshot_id),
+                        false => format!("{}:{}", series_id, chapter.id),
+                    };
+                }
+                // Only credit a work's author separately if they aren't the series' authors.
+                let author = match author_ids(&work.authors) == author_ids(&series.authors) {
+                    true => None,
+                    false => work.authors.authors().first().cloned(),
+                };
+                Content::Section(Section {
+                    id: format!("{}:{}", series_id, work_id),
+                    name: work.name,
+                    description: work.description,
+                    chapters: work.chapters,
+                    url: Some(work.url),
+                    author,
+                })
+            })
+            .collect(),
+        name: series.name,
+        authors: series.authors,
+        description: series.description,
+        url: source.to_url(),
+        source,
+    }
+}
+
+/// A series is complete once every work in it is, and ongoing while any of them are.
+fn series_completed(works: impl Iterator<Item = Completed>) -> Completed {
+    let works: Vec<Completed> = works.collect();
+    if works.contains(&Completed::Incomplete) {
+        Completed::Incomplete
+    } else if !works.is_empty() && works.iter().all(|work| *work == Completed::Complete) {
+        Completed::Complete
+    } else {
+        Completed::Unknown
     }
+}
 
-    async fn fill_skeleton(&self, skeleton: Story) -> Result<Story> {
-        Ok(skeleton)
+/// Gets everyone credited in the work's byline, in the order AO3 lists them. Each author's id
+/// is built from their profile link, so the same person writing under different pseuds gets a
+/// separate author for each pseud. Works with no linked author, like anonymous ones, get a
+/// placeholder.
+fn get_authors(document: &Document, source: &StorySource) -> Result<AuthorList> {
+    let byline: Vec<Node> = document
+        .find(
+            predicate::Class("byline")
+                .or(series_meta())
+                .descendant(author_links()),
+        )
+        .collect();
+    // Only fall back to author links elsewhere on the page if the byline doesn't have any. A
+    // series lists its authors with its other details instead of in a byline.
+    let links = match byline.is_empty() {
+        true => document.find(author_links()).collect(),
+        false => byline,
+    };
+    let mut authors: Vec<Author> = Vec::new();
+    for link in links {
+        let href = link
+            .attr("href")
+            .expect("Author link should have href because of find() conditions");
+        let author = Author {
+            name: link.text().trim().to_owned(),
+            id: get_author_id(href),
+        };
+        if !authors.iter().any(|existing| existing.id == author.id) {
+            authors.push(author);
+        }
+    }
+    if authors.is_empty() {
+        // Anonymous works have a byline with no profile links. Give them a placeholder author
+        // named after whatever the byline says rather than failing.
+        let name = document
+            .find(predicate::Class("byline"))
+            .map(|byline| byline.text().trim().to_owned())
+            .find(|name| !name.is_empty())
+            .unwrap_or_else(|| "Anonymous".to_owned());
+        log::warn!(
+            "AO3: No author profile linked for story at {}, so crediting it to {:?}",
+            source.to_url(),
+            name
+        );
+        authors.push(Author {
+            id: format!("ao3:{}", name.to_lowercase()),
+            name,
+        });
     }
+    AuthorList::from_list(authors)
+}
 
-    async fn get_story(&self, source: StorySource) -> Result<Story> {
-        self.get_skeleton(source).await
+fn author_links() -> impl Predicate {
+    predicate::Attr("rel", "author").and(predicate::Attr("href", ()))
+}
+
+/// Turns a profile link like `/users/name/pseuds/pseud` into an id like `ao3:name:pseud`.
+fn get_author_id(href: &str) -> String {
+    let path = match href.find("/users/") {
+        Some(idx) => &href[idx + "/users/".len()..],
+        None => href,
+    };
+    let mut parts = path.split('/').filter(|part| !part.is_empty());
+    let user = parts.next().unwrap_or_default();
+    match (parts.next(), parts.next()) {
+        (Some("pseuds"), Some(pseud)) => format!("ao3:{}:{}", user, pseud),
+        _ => format!("ao3:{}", user),
     }
 }
 
 fn get_chapter_id(chapter: &Node, source: &StorySource) -> Result<String> {
     let href = get_chapter_url(chapter, source)?;
+    get_chapter_id_from_url(&href, source)
+}
 
+fn get_chapter_id_from_url(href: &str, source: &StorySource) -> Result<String> {
     Ok(CHAPTER_REGEX
         .1
         .get_or_init(|| Regex::new(CHAPTER_REGEX.0).unwrap())
-        .captures(&href)
-        .unwrap()
-        .get(1)
+        .captures(href)
+        .and_then(|captures| captures.get(1))
         .ok_or(ArchiveError::PageError(format!(
             "AO3: Could not find chapter id in chapter link {} for story at {}",
             href,
@@ -194,6 +652,26 @@ fn get_chapter_id(chapter: &Node, source: &StorySource) -> Result<String> {
         .to_owned())
 }
 
+/// Matches the chapter links in the chapter index on a work's navigate page.
+fn chapter_index_links() -> impl Predicate {
+    predicate::Name("ol")
+        .and(predicate::Class("index"))
+        .descendant(predicate::Name("a").and(predicate::Attr("href", ())))
+}
+
+/// Decides whether a work should be fetched one chapter at a time rather than as a single
+/// full-work page, based on the configured [`FetchMode`] and the size of the chapter index.
+/// Oneshots are always fetched as a full work so that their chapter id stays stable.
+fn fetch_per_chapter(navigate: &Document) -> bool {
+    let chapter_count = navigate.find(chapter_index_links()).count();
+    match FETCH_MODE.get().copied().unwrap_or_default() {
+        _ if chapter_count <= 1 => false,
+        FetchMode::Auto => chapter_count > FULL_WORK_CHAPTER_LIMIT,
+        FetchMode::FullWork => false,
+        FetchMode::PerChapter => true,
+    }
+}
+
 fn get_chapter_name(chapter: &Node, source: &StorySource) -> Result<String> {
     let full_title = chapter
         .descendants()
@@ -205,7 +683,7 @@ fn get_chapter_name(chapter: &Node, source: &StorySource) -> Result<String> {
         .text()
         .trim()
         .to_owned();
-    Ok(full_title.splitn(2, ':').nth(1).or(full_title.splitn(2, ':').next()).ok_or(ArchiveError::PageError(format!("Expected chapter title to look like \"Chapter <num>\" or \"Chapter <num>: <name>\" but got {} for story at {}", full_title, source.to_url())))?.to_string())
+    Ok(full_title.split_once(':').map(|x| x.1).or(full_title.split(':').next()).ok_or(ArchiveError::PageError(format!("Expected chapter title to look like \"Chapter <num>\" or \"Chapter <num>: <name>\" but got {} for story at {}", full_title, source.to_url())))?.to_string())
 }
 
 fn get_chapter_url(chapter: &Node, source: &StorySource) -> Result<String> {
@@ -245,16 +723,16 @@ fn get_chapter_date_posted(
 }
 
 fn date_string_to_datetime(date: String) -> Result<DateTime<FixedOffset>> {
-    let posted_on = date.replace('(', "").replace(')', "");
+    let posted_on = date.replace(['(', ')'], "");
     let date_posted = posted_on.trim();
     let timezone = FixedOffset::west(Local::now().offset().utc_minus_local());
-    Ok(timezone
+    timezone
         .from_local_datetime(&NaiveDate::parse_from_str(date_posted, "%F")?.and_hms(3, 0, 0))
         .earliest()
         .ok_or(ArchiveError::PageError(format!(
             "AO3: Could not convert date string {} to a date",
             date
-        )))?)
+        )))
 }
 
 fn get_chapter_text(chapter: &Node, chapter_url: &String) -> Result<String> {
@@ -268,7 +746,7 @@ fn get_chapter_text(chapter: &Node, chapter_url: &String) -> Result<String> {
         .children()
         .find(|c| c.is(predicate::Class("userstuff")));
 
-    Ok(format!(
+    Ok(chapter_html(format!(
         "{}{}{}",
         top_notes.map(|n| n.inner_html()).unwrap_or_default(),
         chapter_text
@@ -278,7 +756,7 @@ fn get_chapter_text(chapter: &Node, chapter_url: &String) -> Result<String> {
             .map(|node| node.html())
             .collect::<String>(),
         bottom_notes.map(|n| n.inner_html()).unwrap_or_default()
-    ))
+    )))
 }
 
 /// TODO Support series listings and collections at some point?
@@ -312,7 +790,7 @@ fn get_tags(document: &Document) -> Vec<String> {
                         "fandom" => format!("fandom:{}", node.text().trim()),
                         "relationship" => format!("relationship:{}", node.text().trim()),
                         "character" => format!("character:{}", node.text().trim()),
-                        _ => node.text().trim().to_owned(),
+                        _ => unclassified_tag(&node.text()),
                     })
                     .collect(),
             }
@@ -320,17 +798,312 @@ fn get_tags(document: &Document) -> Vec<String> {
         .collect()
 }
 
-fn get_completed(document: &Document, source: &StorySource) -> Completed {
-    document.find(
-        predicate::Class("stats").child(predicate::Name("dt").and(predicate::Class("status"))))
+/// Gets the work's language from its `dd.language` entry, preferring the `lang` code AO3 puts
+/// on the element over the human-readable name.
+fn get_language(document: &Document) -> Option<String> {
+    document
+        .find(predicate::Name("dd").and(predicate::Class("language")))
         .next()
-        .map(|node| match node.text().trim().to_lowercase().as_ref() {
-            "updated:" => Completed::Incomplete,
-            "completed:" => Completed::Complete,
-            _ => {
-                println!("Encountered unexpected value {} in story status tag (.stats > dt.status) for story at {}", node.text().trim().to_lowercase(), source.to_url());
-                Completed::Unknown
-            },
+        .map(|dd| match dd.attr("lang") {
+            Some(code) => code.trim().to_owned(),
+            None => dd.text().trim().to_owned(),
+        })
+        .filter(|language| !language.is_empty())
+}
+
+/// Gets when a work was published and when it was last updated from its stats. A work that
+/// has never been updated only has a published date, which is also when it last changed.
+fn get_story_dates(
+    document: &Document,
+) -> (Option<DateTime<FixedOffset>>, Option<DateTime<FixedOffset>>) {
+    let date = |class: &'static str| {
+        document
+            .find(
+                predicate::Class("stats").child(predicate::Name("dd").and(predicate::Class(class))),
+            )
+            .next()
+            .and_then(|dd| date_string_to_datetime(dd.text()).ok())
+    };
+    let published = date("published");
+    (published, date("status").or(published))
+}
+
+/// AO3 has no dedicated cover field, so authors who want one embed it in the summary or the
+/// work's notes. Takes the first image found there, if any.
+fn get_cover_url(document: &Document) -> Option<String> {
+    document
+        .find(
+            predicate::Class("preface")
+                .descendant(predicate::Class("summary").or(predicate::Class("notes")))
+                .descendant(predicate::Name("img")),
+        )
+        .filter_map(|img| img.attr("src"))
+        .map(|src| src.trim())
+        .find_map(|src| {
+            if src.starts_with("https://") || src.starts_with("http://") {
+                Some(src.to_owned())
+            } else if src.starts_with("//") {
+                Some(format!("https:{}", src))
+            } else if src.starts_with('/') {
+                Some(format!("https://archiveofourown.org{}", src))
+            } else {
+                None
+            }
         })
-        .unwrap_or(Completed::Complete) // If there is no "status" stat it's a oneshot and thus complete.
+}
+
+/// AO3 labels a multi-chapter work's latest date "Updated:" while it's in progress and
+/// "Completed:" once it's finished.
+const STATUS_LABELS: StatusLabels = StatusLabels {
+    complete: &["Completed:"],
+    incomplete: &["Updated:"],
+};
+
+/// Works with only one chapter posted have no status label, so for those the chapter count
+/// (like `1/1` or `1/?`) decides: a work is complete once it has as many chapters as planned.
+/// A oneshot is always `1/1`, so it's complete.
+fn get_completed(document: &Document, source: &StorySource) -> Completed {
+    let label = document
+        .find(
+            predicate::Class("stats").child(predicate::Name("dt").and(predicate::Class("status"))),
+        )
+        .next()
+        .map(|node| node.text());
+    if label.is_some() {
+        return completion_status(label.as_deref(), &STATUS_LABELS, source);
+    }
+    let chapters = document
+        .find(
+            predicate::Class("stats")
+                .child(predicate::Name("dd").and(predicate::Class("chapters"))),
+        )
+        .next()
+        .map(|node| node.text());
+    match chapters
+        .as_deref()
+        .and_then(|count| count.trim().split_once('/'))
+    {
+        Some((posted, planned)) if posted.trim() == planned.trim() => Completed::Complete,
+        Some((_, planned)) if planned.trim() == "?" => Completed::Incomplete,
+        Some((posted, planned)) => match (
+            posted.trim().parse::<usize>(),
+            planned.trim().parse::<usize>(),
+        ) {
+            (Ok(posted), Ok(planned)) if posted < planned => Completed::Incomplete,
+            _ => Completed::Unknown,
+        },
+        None => Completed::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TWO_AUTHOR_WORK: &str = include_str!("../../tests/fixtures/ao3/two_author_work.html");
+    const TWO_AUTHOR_NAVIGATE: &str =
+        include_str!("../../tests/fixtures/ao3/two_author_navigate.html");
+    const SERIES: &str = include_str!("../../tests/fixtures/ao3/series.html");
+
+    #[test]
+    fn finds_login_form_tokens() {
+        let page = r#"<form id="new_user" action="/users/login" method="post">
+            <input type="hidden" name="authenticity_token" value="abc123" />
+            <input type="text" name="user[login]" id="user_login" />
+            </form>"#;
+        assert_eq!(authenticity_token(page).unwrap(), "abc123");
+        assert!(authenticity_token("<form id=\"search\"></form>").is_err());
+        assert!(is_login_page(
+            &Url::parse("https://archiveofourown.org/users/login?restricted=true").unwrap()
+        ));
+        assert!(!is_login_page(
+            &Url::parse("https://archiveofourown.org/works/1").unwrap()
+        ));
+    }
+
+    #[test]
+    fn builds_author_ids_from_profile_links() {
+        assert_eq!(get_author_id("/users/name/pseuds/pseud"), "ao3:name:pseud");
+        assert_eq!(
+            get_author_id("https://archiveofourown.org/users/name/pseuds/name/works"),
+            "ao3:name:name"
+        );
+        assert_eq!(get_author_id("/users/name"), "ao3:name");
+    }
+
+    #[test]
+    fn parses_every_author_of_a_collaboration() {
+        let source = StorySource::AO3("12345".to_owned());
+        let story = parse_work(source, TWO_AUTHOR_WORK, TWO_AUTHOR_NAVIGATE, false).unwrap();
+        let authors: Vec<(&str, &str)> = story
+            .authors
+            .authors()
+            .iter()
+            .map(|author| (author.id.as_str(), author.name.as_str()))
+            .collect();
+        assert_eq!(
+            authors,
+            vec![
+                ("ao3:firstwriter:firstwriter", "firstwriter"),
+                ("ao3:secondwriter:Second%20Pen", "Second Pen (secondwriter)"),
+            ]
+        );
+    }
+
+    #[test]
+    fn namespaces_every_tag() {
+        let document = Document::from_read(TWO_AUTHOR_WORK.as_bytes()).unwrap();
+        assert_eq!(
+            get_tags(&document),
+            vec![
+                "rating:general audiences",
+                "fandom:Original Work",
+                "tag:Collaboration",
+                "lang:English",
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_series_page() {
+        let source = StorySource::AO3Series("678".to_owned());
+        let series = parse_series_page(&source, SERIES).unwrap();
+        assert_eq!(series.name, "A Series of Works");
+        assert_eq!(series.authors.to_string(), "first, second");
+        assert_eq!(
+            series.description.as_deref(),
+            Some("<p>Stories that go together.</p>")
+        );
+        assert_eq!(series.work_ids, vec!["111", "222"]);
+        assert_eq!(series.pages, 3);
+    }
+
+    #[test]
+    fn builds_series_with_a_section_per_work() {
+        let source = StorySource::AO3Series("678".to_owned());
+        let series = parse_series_page(&source, SERIES).unwrap();
+        let works = ["111", "222"]
+            .into_iter()
+            .map(|id| {
+                let work = StorySource::AO3(id.to_owned());
+                parse_work(work, TWO_AUTHOR_WORK, TWO_AUTHOR_NAVIGATE, false).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let chapter_count = works[0].num_chapters();
+        let story = series_story(source, series, works);
+
+        assert_eq!(story.name, "A Series of Works");
+        assert_eq!(story.num_chapters(), chapter_count * 2);
+        let sections: Vec<(&str, Option<&str>)> = story
+            .chapters
+            .iter()
+            .map(|content| match content {
+                Content::Section(section) => (section.id.as_str(), section.url.as_deref()),
+                Content::Chapter(chapter) => panic!("unexpected chapter {}", chapter.id),
+            })
+            .collect();
+        assert_eq!(
+            sections,
+            vec![
+                (
+                    "ao3series:678:ao3:111",
+                    Some("https://archiveofourown.org/works/111")
+                ),
+                (
+                    "ao3series:678:ao3:222",
+                    Some("https://archiveofourown.org/works/222")
+                ),
+            ]
+        );
+        assert!(story
+            .all_chapters()
+            .iter()
+            .all(|chapter| chapter.id.starts_with("ao3series:678:")));
+    }
+
+    #[test]
+    fn series_is_complete_when_every_work_is() {
+        use Completed::*;
+        assert_eq!(series_completed([Complete, Complete].into_iter()), Complete);
+        assert_eq!(
+            series_completed([Complete, Incomplete, Unknown].into_iter()),
+            Incomplete
+        );
+        assert_eq!(series_completed([Complete, Unknown].into_iter()), Unknown);
+        assert_eq!(series_completed([].into_iter()), Unknown);
+    }
+
+    #[test]
+    fn credits_anonymous_works_to_a_placeholder() {
+        let page = "<h3 class=\"byline heading\">\n  Anonymous\n</h3>";
+        let document = Document::from_read(page.as_bytes()).unwrap();
+        let authors = get_authors(&document, &StorySource::AO3("12345".to_owned())).unwrap();
+        assert_eq!(authors.authors().len(), 1);
+        assert_eq!(authors.authors()[0].name, "Anonymous");
+        assert_eq!(authors.authors()[0].id, "ao3:anonymous");
+
+        let document = Document::from_read("<p>No byline</p>".as_bytes()).unwrap();
+        let authors = get_authors(&document, &StorySource::AO3("12345".to_owned())).unwrap();
+        assert_eq!(authors.to_string(), "Anonymous");
+    }
+
+    fn completed_with_stats(stats: &str) -> Completed {
+        let page = format!("<dl class=\"stats\">{}</dl>", stats);
+        let document = Document::from_read(page.as_bytes()).unwrap();
+        get_completed(&document, &StorySource::AO3("12345".to_owned()))
+    }
+
+    #[test]
+    fn maps_status_labels() {
+        let status = |label: &str| {
+            completed_with_stats(&format!(
+                "<dt class=\"status\">{}</dt><dd class=\"status\">2021-01-01</dd>\
+                <dt class=\"chapters\">Chapters:</dt><dd class=\"chapters\">2/2</dd>",
+                label
+            ))
+        };
+        assert_eq!(status("Updated:"), Completed::Incomplete);
+        assert_eq!(status("Completed:"), Completed::Complete);
+        assert_eq!(status("Abandoned:"), Completed::Unknown);
+    }
+
+    #[test]
+    fn reads_published_and_updated_dates() {
+        let dates = |stats: &str| {
+            let page = format!("<dl class=\"stats\">{}</dl>", stats);
+            let (published, updated) =
+                get_story_dates(&Document::from_read(page.as_bytes()).unwrap());
+            let day = |date: Option<DateTime<FixedOffset>>| date.map(|date| date.date_naive());
+            (day(published), day(updated))
+        };
+        let day = |date: &str| NaiveDate::parse_from_str(date, "%F").ok();
+        assert_eq!(
+            dates(
+                "<dt class=\"published\">Published:</dt><dd class=\"published\">2020-01-15</dd>\
+                <dt class=\"status\">Completed:</dt><dd class=\"status\">2021-03-02</dd>"
+            ),
+            (day("2020-01-15"), day("2021-03-02"))
+        );
+        // A work that was never updated last changed when it was published.
+        assert_eq!(
+            dates("<dt class=\"published\">Published:</dt><dd class=\"published\">2020-01-15</dd>"),
+            (day("2020-01-15"), day("2020-01-15"))
+        );
+        assert_eq!(dates(""), (None, None));
+    }
+
+    #[test]
+    fn falls_back_to_chapter_count_without_a_status() {
+        let chapters = |count: &str| {
+            completed_with_stats(&format!(
+                "<dt class=\"chapters\">Chapters:</dt><dd class=\"chapters\">{}</dd>",
+                count
+            ))
+        };
+        // A oneshot is posted as 1/1 and is complete.
+        assert_eq!(chapters("1/1"), Completed::Complete);
+        assert_eq!(chapters("1/?"), Completed::Incomplete);
+        assert_eq!(chapters("1/3"), Completed::Incomplete);
+        assert_eq!(completed_with_stats(""), Completed::Unknown);
+    }
 }
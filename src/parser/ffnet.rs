@@ -0,0 +1,382 @@
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset, TimeZone};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::Client;
+use select::{
+    document::Document,
+    node::Node,
+    predicate::{self, Predicate},
+};
+
+use crate::{
+    client::get,
+    error::ArchiveError,
+    parser::{
+        chapter_html, completion_status, fetch_chapters, parse_blocking, Parser, StatusLabels,
+    },
+    structs::{Author, AuthorList, Chapter, ChapterText, Completed, Content, Story, StorySource},
+    Result,
+};
+
+static AUTHOR_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"/u/(\d+)").unwrap());
+static CHAPTER_NAME_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\d+\.\s*").unwrap());
+
+pub(crate) struct FFNetParser;
+
+#[async_trait]
+impl Parser for FFNetParser {
+    async fn get_skeleton(&self, client: &Client, source: StorySource) -> Result<Story> {
+        let page = get(client, &chapter_url(&source, 1)).await?.text().await?;
+        parse_blocking(move || parse_story(source, &page)).await
+    }
+
+    async fn fill_skeleton(&self, client: &Client, mut skeleton: Story) -> Result<Story> {
+        let name = skeleton.name.clone();
+        let hydrate = skeleton
+            .chapters
+            .iter_mut()
+            .filter_map(|content| match content {
+                Content::Section(_) => None,
+                Content::Chapter(chapter) => Some(chapter),
+            })
+            .filter(|chapter| matches!(chapter.text, ChapterText::Dehydrated))
+            .map(|chapter| async {
+                let page = get(client, &chapter.url).await?.text().await?;
+                let text = parse_blocking(move || {
+                    get_chapter_text(&Document::from_read(page.as_bytes())?)
+                })
+                .await?;
+                chapter.text = ChapterText::Hydrated(text);
+                Ok(())
+            });
+        fetch_chapters(&name, hydrate)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<()>>>()?;
+        Ok(skeleton)
+    }
+
+    async fn get_story(&self, client: &Client, source: StorySource) -> Result<Story> {
+        let story = self.get_skeleton(client, source).await?;
+        self.fill_skeleton(client, story).await
+    }
+}
+
+fn chapter_url(source: &StorySource, number: usize) -> String {
+    format!("{}/{}/", source.to_url(), number)
+}
+
+/// Builds a skeleton from the first chapter's page, which has the story's details and a list of
+/// every chapter.
+fn parse_story(source: StorySource, page: &str) -> Result<Story> {
+    let document = Document::from_read(page.as_bytes())?;
+    let profile = document
+        .find(predicate::Attr("id", "profile_top"))
+        .next()
+        .ok_or_else(|| {
+            ArchiveError::PageError(format!(
+                "FFNet: couldn't find story details (#profile_top) for story at {}",
+                source.to_url()
+            ))
+        })?;
+    let name = profile
+        .find(predicate::Name("b").and(predicate::Class("xcontrast_txt")))
+        .next()
+        .map(|title| title.text().trim().to_owned())
+        .ok_or_else(|| {
+            ArchiveError::PageError(format!(
+                "FFNet: couldn't find the title of story at {}",
+                source.to_url()
+            ))
+        })?;
+    let author = profile
+        .find(predicate::Name("a"))
+        .find_map(|link| {
+            let id = AUTHOR_REGEX.captures(link.attr("href")?)?.get(1)?.as_str();
+            Some(Author {
+                id: format!("ffnet:{}", id),
+                name: link.text().trim().to_owned(),
+            })
+        })
+        .ok_or_else(|| {
+            ArchiveError::PageError(format!(
+                "FFNet: couldn't find the author of story at {}",
+                source.to_url()
+            ))
+        })?;
+    let description = profile
+        .find(predicate::Name("div").and(predicate::Class("xcontrast_txt")))
+        .map(|summary| summary.text().trim().to_owned())
+        .find(|summary| !summary.is_empty());
+    let details = profile
+        .find(predicate::Class("xgray"))
+        .next()
+        .ok_or_else(|| {
+            ArchiveError::PageError(format!(
+                "FFNet: couldn't find the details line of story at {}",
+                source.to_url()
+            ))
+        })?;
+    let fields = details_fields(&details);
+
+    let mut tags = Vec::new();
+    if let Some(fandom) = document
+        .find(predicate::Attr("id", "pre_story_links").descendant(predicate::Name("a")))
+        .last()
+    {
+        tags.push(format!("fandom:{}", fandom.text().trim()));
+    }
+    if let Some(rating) = fields
+        .iter()
+        .find_map(|field| field.strip_prefix("Rated:"))
+        .map(|rating| rating.trim().trim_start_matches("Fiction").trim())
+    {
+        tags.push(format!("rating:{}", rating.to_lowercase()));
+    }
+    // After the rating come the language, the genres, and the characters. Stories don't have to
+    // list genres or characters, but every field after them has a label.
+    let language = fields.get(1).cloned();
+    let mut unlabeled = fields
+        .iter()
+        .skip(2)
+        .take_while(|field| !field.contains(':'));
+    let mut next = unlabeled.next();
+    if let Some(genres) = next.and_then(|field| split_genres(field)) {
+        tags.extend(genres.into_iter().map(|genre| format!("genre:{}", genre)));
+        next = unlabeled.next();
+    }
+    if let Some(characters) = next {
+        tags.extend(
+            characters
+                .split([',', '[', ']'])
+                .map(str::trim)
+                .filter(|character| !character.is_empty())
+                .map(|character| format!("character:{}", character)),
+        );
+    }
+
+    let status = fields
+        .iter()
+        .find_map(|field| field.strip_prefix("Status:"))
+        .map(str::trim);
+    // FFNet only labels finished stories; any other story is still being written.
+    let completed = match completion_status(status, &STATUS_LABELS, &source) {
+        Completed::Unknown => Completed::Incomplete,
+        completed => completed,
+    };
+
+    // FFNet doesn't say when each chapter was posted, only when the story was first published,
+    // so every chapter gets that date.
+    let published = details
+        .find(predicate::Attr("data-xutime", ()))
+        .filter_map(|time| time.attr("data-xutime")?.parse::<i64>().ok())
+        .min()
+        .map(|timestamp| FixedOffset::east(0).timestamp(timestamp, 0))
+        .unwrap_or_else(Chapter::placeholder_date);
+    let chapter_names: Vec<String> =
+        match document.find(predicate::Attr("id", "chap_select")).next() {
+            Some(select) => select
+                .find(predicate::Name("option"))
+                .map(|option| {
+                    CHAPTER_NAME_REGEX
+                        .replace(option.text().trim(), "")
+                        .into_owned()
+                })
+                .collect(),
+            // Stories with a single chapter have no chapter list.
+            None => vec![name.clone()],
+        };
+    let chapters = chapter_names
+        .into_iter()
+        .enumerate()
+        .map(|(idx, chapter_name)| {
+            Content::Chapter(new_chapter(&source, idx + 1, chapter_name, published))
+        })
+        .collect();
+
+    let cover_url = profile
+        .find(predicate::Name("img").and(predicate::Class("cimage")))
+        .next()
+        .and_then(|img| img.attr("src"))
+        .map(|src| match src.starts_with("//") {
+            true => format!("https:{}", src),
+            false => src.to_owned(),
+        });
+
+    Ok(Story {
+        name,
+        authors: AuthorList::new(author),
+        description,
+        url: source.to_url(),
+        tags,
+        chapters,
+        source,
+        completed,
+        language,
+        cover_url,
+        published: None,
+        last_updated: None,
+    })
+}
+
+fn new_chapter(
+    source: &StorySource,
+    number: usize,
+    name: String,
+    date_posted: DateTime<FixedOffset>,
+) -> Chapter {
+    Chapter {
+        id: format!("{}:{}", source.to_id(), number),
+        name,
+        description: None,
+        text: ChapterText::Dehydrated,
+        url: chapter_url(source, number),
+        date_posted,
+        author: None,
+    }
+}
+
+/// Splits the details line (`Rated: Fiction T - English - Adventure - Chapters: 12 - ...`) into
+/// its fields.
+fn details_fields(details: &Node) -> Vec<String> {
+    details
+        .text()
+        .split(" - ")
+        .map(|field| field.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|field| !field.is_empty())
+        .collect()
+}
+
+/// Splits a field like `Adventure/Hurt/Comfort` into genres, or returns `None` if it isn't a list
+/// of genres.
+fn split_genres(field: &str) -> Option<Vec<&'static str>> {
+    let mut genres = Vec::new();
+    let mut rest = field.trim();
+    while !rest.is_empty() {
+        // Hurt/Comfort has a slash in its name, so match whole genres rather than splitting.
+        let genre = GENRES
+            .iter()
+            .filter(|genre| rest.starts_with(*genre))
+            .max_by_key(|genre| genre.len())?;
+        genres.push(*genre);
+        rest = rest[genre.len()..].trim_start_matches('/');
+    }
+    Some(genres)
+}
+
+fn get_chapter_text(document: &Document) -> Result<String> {
+    document
+        .find(predicate::Attr("id", "storytext"))
+        .next()
+        .map(|text| chapter_html(text.inner_html()))
+        .ok_or_else(|| ArchiveError::PageError("FFNet: couldn't find chapter text".to_owned()))
+}
+
+const STATUS_LABELS: StatusLabels = StatusLabels {
+    complete: &["Complete"],
+    incomplete: &[],
+};
+
+/// The genres FFNet lets stories be filed under.
+const GENRES: [&str; 20] = [
+    "Adventure",
+    "Angst",
+    "Crime",
+    "Drama",
+    "Family",
+    "Fantasy",
+    "Friendship",
+    "General",
+    "Horror",
+    "Humor",
+    "Hurt/Comfort",
+    "Mystery",
+    "Parody",
+    "Poetry",
+    "Romance",
+    "Sci-Fi",
+    "Spiritual",
+    "Supernatural",
+    "Suspense",
+    "Tragedy",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STORY_PAGE: &str = include_str!("../../tests/fixtures/ffnet/story.html");
+
+    #[test]
+    fn parses_story_details_and_chapter_list() {
+        let source = StorySource::FFNet("12345".to_owned());
+        let story = parse_story(source, STORY_PAGE).unwrap();
+        assert_eq!(story.name, "A Test Story");
+        assert_eq!(story.authors.to_string(), "Some Writer");
+        assert_eq!(story.authors.authors()[0].id, "ffnet:678");
+        assert_eq!(story.description.as_deref(), Some("Things happen."));
+        assert_eq!(story.language.as_deref(), Some("English"));
+        assert_eq!(story.completed, Completed::Complete);
+        assert_eq!(
+            story.tags,
+            vec![
+                "fandom:Harry Potter",
+                "rating:t",
+                "genre:Adventure",
+                "genre:Hurt/Comfort",
+                "character:Harry P.",
+                "character:Hermione G.",
+            ]
+        );
+        let chapters: Vec<(&str, &str, &str)> = story
+            .all_chapters()
+            .iter()
+            .map(|chapter| {
+                (
+                    chapter.id.as_str(),
+                    chapter.name.as_str(),
+                    chapter.url.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            chapters,
+            vec![
+                (
+                    "ffnet:12345:1",
+                    "The Beginning",
+                    "https://www.fanfiction.net/s/12345/1/"
+                ),
+                (
+                    "ffnet:12345:2",
+                    "The End",
+                    "https://www.fanfiction.net/s/12345/2/"
+                ),
+            ]
+        );
+        assert_eq!(
+            story.all_chapters()[0].date_posted,
+            FixedOffset::east(0).timestamp(1500000000, 0)
+        );
+    }
+
+    #[test]
+    fn splits_genres() {
+        assert_eq!(
+            split_genres("Hurt/Comfort/Romance"),
+            Some(vec!["Hurt/Comfort", "Romance"])
+        );
+        assert_eq!(split_genres("Humor"), Some(vec!["Humor"]));
+        assert_eq!(split_genres("Harry P., Hermione G."), None);
+    }
+
+    #[test]
+    fn reads_chapter_text() {
+        let document = Document::from_read(STORY_PAGE.as_bytes()).unwrap();
+        assert_eq!(
+            get_chapter_text(&document).unwrap(),
+            "<p>It was a dark and stormy night.</p><p>The end.</p>"
+        );
+    }
+}
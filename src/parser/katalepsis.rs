@@ -1,252 +1,449 @@
 use async_trait::async_trait;
-use chrono::{DateTime, FixedOffset, TimeZone};
 use futures::future::join_all;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::Client;
 use select::{
     document::Document,
     node::Data::Text,
-    node::Node,
     predicate::{self, Predicate},
 };
 
-use std::iter;
-
 use crate::{
     client::get,
-    parser::Parser,
+    parser::{
+        parse_blocking,
+        wordpress::{fill_chapters, next_chapter_url, Selector},
+        Parser,
+    },
     structs::{
         Author, AuthorList, Chapter, ChapterText, Completed, Content, Section, Story, StorySource,
     },
     Result,
 };
 
+use std::collections::HashSet;
+
+/// Where the text is on each chapter's page.
+static CONTENT: Lazy<Selector> = Lazy::new(|| Selector::parse(".entry-content").unwrap());
+
+/// Matches the `X.Y` arc and chapter number that regular chapters are titled with.
+static CHAPTER_NUMBER: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\s*(\d+)\.(\d+)\b").unwrap());
+
+/// The id of the section that chapters without an `X.Y` number, like bonus chapters, go in.
+const BONUS_SECTION: &str = "katalepsis:bonus";
+
+/// How many unlisted chapters in a row are followed before giving up, in case the "Next
+/// Chapter" links go around in a circle.
+const MAX_UNLISTED: usize = 50;
+
+/// Katalepsis is a WordPress serial, so its chapters are fetched by the generic WordPress parser.
+/// Only the table of contents, which groups chapters into arcs, needs parsing of its own.
 pub(crate) struct KatalepsisParser;
 
 #[async_trait]
 impl Parser for KatalepsisParser {
-    async fn get_skeleton(&self, source: StorySource) -> Result<Story> {
-        let main_page = get(&source.to_url()).await?.text().await?;
-        let main_page = Document::from_read(main_page.as_bytes())?;
-
-        let name = "Katalepsis".to_owned();
-        let author = Author::new("HY", "katalepsis:");
-        let description: Option<String> = Some(
-            main_page
-                .find(predicate::Class("entry-content").child(predicate::Name("p")))
-                .take(3)
-                .map(|elem| elem.inner_html())
-                .collect(),
-        );
-        let url = source.to_url();
-        let tags: Vec<String> = Vec::new();
-        let chapters: Vec<Content> = main_page
-            .find(predicate::Attr("id", "secondary").child(predicate::Name("aside")))
-            .find(|node| match node.first_child() {
-                None => false,
-                Some(child) => {
-                    child.is(predicate::Name("h3")) && child.text().as_str() == "Archive"
-                }
-            })
-            .expect("Could not find post archive in right-hand panel")
-            .children()
-            .find(|node| matches!(node.attr("class"), Some("textwidget")))
-            .expect("Post archive is empty")
-            .children()
-            .filter(|child| child.is(predicate::Name("ul")))
-            .flat_map(|arc_ul| arc_ul.children())
-            .filter(|arc_li| arc_li.name().is_some())
-            .map(|arc_li| {
-                let arc_name = arc_li
-                    .children()
-                    .find(|child| matches!(child.data(), Text(_)));
-                if arc_name.is_none() {
-                    println!("Arc name was none for:\n{:?}", arc_li);
-                }
-                let arc_name = arc_name
-                    .expect("<li> for arc should have a text node with arc name")
-                    .text()
-                    .replacen('(', "", 1)
-                    .replacen(')', ":", 1);
-                let arc_num = &arc_name[4..arc_name.find(':').unwrap()];
-                let chapters = arc_li
-                    .children()
-                    .find(|child| child.is(predicate::Name("ul")))
-                    .expect("<li> for arc should have a <ul> for chapters")
-                    .children()
-                    .filter(|chapter_li| match chapter_li.first_child() {
-                        Some(child) => child.is(predicate::Name("a")),
-                        None => false,
-                    })
-                    .map(|chapter_li| chapter_li.first_child().unwrap())
-                    .map(|a_tag| {
-                        let chap_num_owner = a_tag.text();
-                        let chap_num = chap_num_owner.split('.').nth(1).unwrap_or_else(|| {
-                            panic!(
-                                "Chapter number should be of the format X.Y but is {}",
-                                a_tag.text()
-                            )
-                        });
-                        Content::Chapter(Chapter {
-                            id: format!("katalepsis:{}:{}", arc_num, chap_num),
-                            name: format!("{} - {}", arc_name, a_tag.text()),
-                            description: None,
-                            text: ChapterText::Dehydrated,
-                            url: a_tag
-                                .attr("href")
-                                .expect("Chapter tag should have an href")
-                                .to_owned(),
-                            date_posted: FixedOffset::east(0).datetime_from_str("0", "%s").unwrap(),
-                            author: None,
-                        })
-                    })
-                    .collect();
-                Content::Section(Section {
-                    id: format!("katalepsis:{}", arc_num),
-                    name: arc_name,
-                    description: None,
-                    chapters,
-                    url: None,
-                    author: None,
-                })
-            })
-            .collect();
+    async fn get_skeleton(&self, client: &Client, source: StorySource) -> Result<Story> {
+        let main_page = get(client, &source.to_url()).await?.text().await?;
+        let mut story = parse_blocking(move || parse_archive(source, &main_page)).await?;
+        let unlisted = find_unlisted_chapters(client, &story).await?;
+        add_unlisted_chapters(&mut story, unlisted);
+        Ok(story)
+    }
 
-        Ok(Story {
-            name,
-            authors: AuthorList::new(author),
-            description,
-            url,
-            tags,
-            chapters,
-            source,
-            completed: Completed::Incomplete,
-        })
+    async fn fill_skeleton(&self, client: &Client, skeleton: Story) -> Result<Story> {
+        fill_chapters(client, skeleton, &CONTENT).await
     }
 
-    async fn fill_skeleton(&self, mut skeleton: Story) -> Result<Story> {
-        let mut chapters: Vec<&mut Chapter> = Vec::with_capacity(skeleton.num_chapters());
-        for content in skeleton.chapters.iter_mut() {
-            match content {
-                Content::Section(ref mut sec) => chapters_from_section(sec, &mut chapters),
-                Content::Chapter(ref mut chap) => chapters.push(chap),
-            }
+    async fn get_story(&self, client: &Client, source: StorySource) -> Result<Story> {
+        let story = self.get_skeleton(client, source).await?;
+        self.fill_skeleton(client, story).await
+    }
+}
+
+/// A chapter found by following "Next Chapter" links that the sidebar doesn't list.
+#[derive(Debug, Clone, PartialEq)]
+struct UnlistedChapter {
+    title: String,
+    url: String,
+}
+
+/// The sidebar lags behind new posts and leaves out interludes and bonus chapters, which come
+/// between arcs. To find them, this follows the "Next Chapter" links from the last listed
+/// chapter of each arc until they lead back to a listed chapter.
+async fn find_unlisted_chapters(client: &Client, story: &Story) -> Result<Vec<UnlistedChapter>> {
+    let listed: HashSet<String> = story
+        .all_chapters()
+        .iter()
+        .map(|chapter| page_key(&chapter.url))
+        .collect();
+    let arc_ends = story.chapters.iter().filter_map(|content| match content {
+        Content::Section(section) => match section.chapters.last() {
+            Some(Content::Chapter(chapter)) => Some(chapter.url.as_str()),
+            _ => None,
+        },
+        Content::Chapter(chapter) => Some(chapter.url.as_str()),
+    });
+    let chains = join_all(arc_ends.map(|url| follow_next_links(client, url, &listed))).await;
+
+    let mut found: Vec<UnlistedChapter> = Vec::new();
+    for chapter in chains
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+    {
+        if !found
+            .iter()
+            .any(|other| page_key(&other.url) == page_key(&chapter.url))
+        {
+            found.push(chapter);
         }
+    }
+    Ok(found)
+}
 
-        let hydrate = chapters.into_iter().map(|chap| async {
-            let page = get(&chap.url).await?.text().await?;
-            let document = Document::from_read(page.as_bytes())?;
-
-            let mut cw_empty_owner;
-            let mut cw_some_owner;
-            let content_warnings: &mut dyn Iterator<Item = String> = match document
-                .find(
-                    predicate::Class("entry-content")
-                        .child(predicate::Name("details"))
-                        .child(predicate::Name("p")),
-                )
-                .next()
-            {
-                Some(node) => {
-                    if node.text().trim().starts_with("None") {
-                        cw_empty_owner = iter::empty::<String>();
-                        &mut cw_empty_owner
-                    } else {
-                        cw_some_owner = iter::once(format!(
-                            "<b>Content Warnings:</b><br>{}",
-                            node.inner_html().trim()
-                        ));
-                        &mut cw_some_owner
-                    }
-                }
-                None => {
-                    cw_empty_owner = iter::empty::<String>();
-                    &mut cw_empty_owner
-                }
-            };
-            let body_elems: Vec<Node> = document
-                .find(predicate::Class("entry-content").child(predicate::Name("p")))
-                .collect();
-            let mut chapter_start_index: Option<usize> = None;
-            let mut chapter_end_index: Option<usize> = None;
-            for (idx, elem) in body_elems.iter().enumerate() {
-                if elem.inner_html().contains(">Previous Chapter<")
-                    || elem.inner_html().contains(">Next Chapter<")
-                {
-                    if chapter_start_index.is_none() {
-                        chapter_start_index = Some(idx);
-                    } else {
-                        chapter_end_index = Some(idx);
+/// Follows the "Next Chapter" links from the chapter at `url`, returning every chapter
+/// passed through before reaching one in `listed` or one without a next chapter.
+async fn follow_next_links(
+    client: &Client,
+    url: &str,
+    listed: &HashSet<String>,
+) -> Result<Vec<UnlistedChapter>> {
+    let mut found: Vec<UnlistedChapter> = Vec::new();
+    let mut url = url.to_owned();
+    loop {
+        let page = get(client, &url).await?.text().await?;
+        let (title, next) = parse_blocking(move || parse_chapter_links(&page)).await?;
+        if !listed.contains(&page_key(&url)) {
+            found.push(UnlistedChapter {
+                title: title.unwrap_or_else(|| url.clone()),
+                url,
+            });
+        }
+        url = match next {
+            Some(next) if !listed.contains(&page_key(&next)) => next,
+            _ => break,
+        };
+        if found.len() >= MAX_UNLISTED || found.iter().any(|chapter| chapter.url == url) {
+            log::warn!(
+                "Katalepsis: stopped following \"Next Chapter\" links at {} after {} chapters",
+                url,
+                found.len()
+            );
+            break;
+        }
+    }
+    Ok(found)
+}
+
+/// Gets a chapter page's title and where its "Next Chapter" link goes.
+fn parse_chapter_links(page: &str) -> Result<(Option<String>, Option<String>)> {
+    let document = Document::from_read(page.as_bytes())?;
+    let title = document
+        .find(predicate::Class("entry-title"))
+        .next()
+        .map(|title| title.text().trim().to_owned())
+        .filter(|title| !title.is_empty());
+    Ok((title, next_chapter_url(&document)))
+}
+
+/// What's left of a chapter's URL once the parts that can differ between links to the same
+/// page, like the scheme or a trailing slash, are taken off.
+fn page_key(url: &str) -> String {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let url = url.strip_prefix("www.").unwrap_or(url);
+    url.trim_end_matches('/').to_lowercase()
+}
+
+/// Adds chapters the sidebar doesn't list to `story`. Those numbered `X.Y` go in arc `X` after
+/// any lower-numbered chapters, in a new section if the sidebar doesn't have that arc yet.
+/// Anything else is a bonus chapter and goes in a section of its own at the end.
+fn add_unlisted_chapters(story: &mut Story, unlisted: Vec<UnlistedChapter>) {
+    for chapter in unlisted {
+        let number = CHAPTER_NUMBER
+            .captures(&chapter.title)
+            .map(|captures| (captures[1].to_owned(), captures[2].to_owned()));
+        let (section_id, chapter_id) = match number {
+            Some((ref arc, ref number)) => (
+                format!("katalepsis:{}", arc),
+                format!("katalepsis:{}:{}", arc, number),
+            ),
+            None => (
+                BONUS_SECTION.to_owned(),
+                format!("{}:{}", BONUS_SECTION, chapter_slug(&chapter.url)),
+            ),
+        };
+        if story.all_chapters().iter().any(|c| c.id == chapter_id) {
+            continue;
+        }
+        let section = match find_section(&story.chapters, &section_id) {
+            Some(index) => index,
+            None => {
+                let name = match number {
+                    Some((ref arc, _)) => format!("Arc {}", arc),
+                    None => "Bonus Chapters".to_owned(),
+                };
+                // New arcs go before the bonus chapters, which stay last.
+                let index = match number {
+                    Some(_) => {
+                        find_section(&story.chapters, BONUS_SECTION).unwrap_or(story.chapters.len())
                     }
-                }
-            }
-            let chapter_start_index = chapter_start_index.unwrap();
-            let chapter_end_index = chapter_end_index.unwrap();
-            let chapter_paragraphs = body_elems[chapter_start_index + 1..chapter_end_index]
-                .iter()
-                .map(|chap| {
-                    chap.inner_html()
-                        .replace(">* * *<", " align=\"center\">* * *<")
-                        .replace("==", "<span align=\"center\">* * *</span>")
-                });
-            let mut a_n_empty_owner;
-            let mut a_n_some_owner;
-            let a_n_paragraphs: &mut dyn Iterator<Item = String> =
-                if chapter_end_index == body_elems.len() - 1 {
-                    a_n_empty_owner = iter::empty::<String>();
-                    &mut a_n_empty_owner
-                } else {
-                    a_n_some_owner = iter::once("<b>Author's Notes:</b>".to_owned()).chain(
-                        body_elems[chapter_end_index + 1..]
-                            .iter()
-                            .map(|chap| chap.inner_html()),
-                    );
-                    &mut a_n_some_owner
+                    None => story.chapters.len(),
                 };
+                story.chapters.insert(
+                    index,
+                    Content::Section(Section {
+                        id: section_id.clone(),
+                        name,
+                        description: None,
+                        chapters: Vec::new(),
+                        url: None,
+                        author: None,
+                    }),
+                );
+                index
+            }
+        };
+        let section = match &mut story.chapters[section] {
+            Content::Section(section) => section,
+            Content::Chapter(_) => unreachable!("find_section only finds sections"),
+        };
+        let position = match number {
+            Some((_, ref number)) => {
+                let number: usize = number.parse().unwrap_or(usize::MAX);
+                section
+                    .chapters
+                    .iter()
+                    .rposition(|content| {
+                        let listed = content.id().rsplit(':').next().unwrap_or_default();
+                        listed.parse::<usize>().is_ok_and(|listed| listed <= number)
+                    })
+                    .map_or(0, |index| index + 1)
+            }
+            None => section.chapters.len(),
+        };
+        let name = match number {
+            Some(_) => format!("{} - {}", section.name, chapter.title),
+            None => chapter.title,
+        };
+        section.chapters.insert(
+            position,
+            Content::Chapter(Chapter {
+                id: chapter_id,
+                name,
+                description: None,
+                text: ChapterText::Dehydrated,
+                url: chapter.url,
+                date_posted: Chapter::placeholder_date(),
+                author: None,
+            }),
+        );
+    }
+}
+
+fn find_section(contents: &[Content], id: &str) -> Option<usize> {
+    contents
+        .iter()
+        .position(|content| matches!(content, Content::Section(section) if section.id == id))
+}
+
+/// The last part of a chapter's URL path, which WordPress makes from its title.
+fn chapter_slug(url: &str) -> String {
+    url.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or(url)
+        .to_owned()
+}
 
-            let body_text = ChapterText::Hydrated(
-                content_warnings
-                    .chain(chapter_paragraphs)
-                    .chain(a_n_paragraphs)
-                    .filter(|html| {
-                        !html.contains(">Previous Chapter<") && !html.contains(">Next Chapter<")
+/// Builds a story skeleton from the arc and chapter list in the "Archive" sidebar of the
+/// site's front page.
+fn parse_archive(source: StorySource, main_page: &str) -> Result<Story> {
+    let main_page = Document::from_read(main_page.as_bytes())?;
+
+    let name = "Katalepsis".to_owned();
+    let author = Author::new("HY", "katalepsis:");
+    let description: Option<String> = Some(
+        main_page
+            .find(predicate::Class("entry-content").child(predicate::Name("p")))
+            .take(3)
+            .map(|elem| elem.inner_html())
+            .collect(),
+    );
+    let url = source.to_url();
+    let tags: Vec<String> = Vec::new();
+    let chapters: Vec<Content> = main_page
+        .find(predicate::Attr("id", "secondary").child(predicate::Name("aside")))
+        .find(|node| match node.first_child() {
+            None => false,
+            Some(child) => child.is(predicate::Name("h3")) && child.text().as_str() == "Archive",
+        })
+        .expect("Could not find post archive in right-hand panel")
+        .children()
+        .find(|node| matches!(node.attr("class"), Some("textwidget")))
+        .expect("Post archive is empty")
+        .children()
+        .filter(|child| child.is(predicate::Name("ul")))
+        .flat_map(|arc_ul| arc_ul.children())
+        .filter(|arc_li| arc_li.name().is_some())
+        .map(|arc_li| {
+            let arc_name = arc_li
+                .children()
+                .find(|child| matches!(child.data(), Text(_)));
+            if arc_name.is_none() {
+                log::warn!("Arc name was none for:\n{:?}", arc_li);
+            }
+            let arc_name = arc_name
+                .expect("<li> for arc should have a text node with arc name")
+                .text()
+                .replacen('(', "", 1)
+                .replacen(')', ":", 1);
+            let arc_num = &arc_name[4..arc_name.find(':').unwrap()];
+            let chapters = arc_li
+                .children()
+                .find(|child| child.is(predicate::Name("ul")))
+                .expect("<li> for arc should have a <ul> for chapters")
+                .children()
+                .filter(|chapter_li| match chapter_li.first_child() {
+                    Some(child) => child.is(predicate::Name("a")),
+                    None => false,
+                })
+                .map(|chapter_li| chapter_li.first_child().unwrap())
+                .map(|a_tag| {
+                    let chap_num_owner = a_tag.text();
+                    let chap_num = chap_num_owner.split('.').nth(1).unwrap_or_else(|| {
+                        panic!(
+                            "Chapter number should be of the format X.Y but is {}",
+                            a_tag.text()
+                        )
+                    });
+                    Content::Chapter(Chapter {
+                        id: format!("katalepsis:{}:{}", arc_num, chap_num),
+                        name: format!("{} - {}", arc_name, a_tag.text()),
+                        description: None,
+                        text: ChapterText::Dehydrated,
+                        url: a_tag
+                            .attr("href")
+                            .expect("Chapter tag should have an href")
+                            .to_owned(),
+                        date_posted: Chapter::placeholder_date(),
+                        author: None,
                     })
-                    .collect(),
-            );
-            let date_posted = document
-                .find(predicate::Class("entry-date"))
-                .next()
-                .expect("Could not find chapter posted-on date")
-                .attr("datetime")
-                .expect("Could not find chapter posted-on date attr");
-            let date_posted = DateTime::parse_from_rfc3339(date_posted).unwrap_or_else(|_| {
-                panic!(
-                    "Chapter posted-on date ({}) did not conform to rfc3339",
-                    date_posted
-                )
-            });
+                })
+                .collect();
+            Content::Section(Section {
+                id: format!("katalepsis:{}", arc_num),
+                name: arc_name,
+                description: None,
+                chapters,
+                url: None,
+                author: None,
+            })
+        })
+        .collect();
+
+    Ok(Story {
+        name,
+        authors: AuthorList::new(author),
+        description,
+        url,
+        tags,
+        chapters,
+        source,
+        // The site hosts a single serial that is still being written and has no status label.
+        completed: Completed::Incomplete,
+        language: Some("en".to_owned()),
+        cover_url: None,
+        published: None,
+        last_updated: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            chap.text = body_text;
-            chap.date_posted = date_posted;
-            Ok(())
-        });
+    const ARCHIVE: &str =
+        "<div id=\"secondary\"><aside><h3>Archive</h3><div class=\"textwidget\"><ul>\
+        <li>(Arc 1) Welcome<ul>\
+        <li><a href=\"https://katalepsis.net/2019/01/01/1-1/\">1.1</a></li>\
+        <li><a href=\"https://katalepsis.net/2019/01/08/1-3/\">1.3</a></li>\
+        </ul></li></ul></div></aside></div>";
 
-        let results = join_all(hydrate).await;
-        match results.into_iter().find(|res| res.is_err()) {
-            Some(err) => Err(err.unwrap_err()),
-            None => Ok(skeleton),
+    fn unlisted(title: &str, url: &str) -> UnlistedChapter {
+        UnlistedChapter {
+            title: title.to_owned(),
+            url: url.to_owned(),
         }
     }
 
-    async fn get_story(&self, source: StorySource) -> Result<Story> {
-        let story = self.get_skeleton(source).await?;
-        self.fill_skeleton(story).await
+    #[test]
+    fn files_unlisted_chapters_by_number() {
+        let mut story = parse_archive(StorySource::Katalepsis, ARCHIVE).unwrap();
+        add_unlisted_chapters(
+            &mut story,
+            vec![
+                unlisted("1.2", "https://katalepsis.net/2019/01/04/1-2/"),
+                unlisted(
+                    "Bonus: Raine",
+                    "https://katalepsis.net/2019/01/10/bonus-raine/",
+                ),
+                unlisted("2.1", "https://katalepsis.net/2019/01/12/2-1/"),
+                // Already listed under another link.
+                unlisted("1.3", "http://katalepsis.net/2019/01/08/1-3"),
+            ],
+        );
+        let sections: Vec<(&str, Vec<&str>)> = story
+            .chapters
+            .iter()
+            .map(|content| match content {
+                Content::Section(section) => (
+                    section.name.as_str(),
+                    section
+                        .chapters
+                        .iter()
+                        .map(|chapter| chapter.id())
+                        .collect(),
+                ),
+                Content::Chapter(chapter) => panic!("unexpected chapter {}", chapter.id),
+            })
+            .collect();
+        assert_eq!(
+            sections,
+            vec![
+                (
+                    "Arc 1: Welcome",
+                    vec!["katalepsis:1:1", "katalepsis:1:2", "katalepsis:1:3"]
+                ),
+                ("Arc 2", vec!["katalepsis:2:1"]),
+                ("Bonus Chapters", vec!["katalepsis:bonus:bonus-raine"]),
+            ]
+        );
+        let names: Vec<&str> = story
+            .all_chapters()
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(names[1], "Arc 1: Welcome - 1.2");
+        assert_eq!(names[4], "Bonus: Raine");
     }
-}
 
-fn chapters_from_section<'a>(section: &'a mut Section, vec: &mut Vec<&'a mut Chapter>) {
-    for content in section.chapters.iter_mut() {
-        match content {
-            Content::Section(ref mut sec) => chapters_from_section(sec, vec),
-            Content::Chapter(ref mut chap) => vec.push(chap),
-        }
+    #[test]
+    fn reads_chapter_title_and_next_link() {
+        let page = "<h1 class=\"entry-title\">Bonus: Raine</h1><div class=\"entry-content\">\
+            <p><a href=\"https://katalepsis.net/2019/01/04/1-2/\">Previous Chapter</a> | \
+            <a href=\"https://katalepsis.net/2019/01/12/2-1/\">Next Chapter</a></p></div>";
+        assert_eq!(
+            parse_chapter_links(page).unwrap(),
+            (
+                Some("Bonus: Raine".to_owned()),
+                Some("https://katalepsis.net/2019/01/12/2-1/".to_owned())
+            )
+        );
+        assert_eq!(
+            page_key("https://www.katalepsis.net/2019/01/12/2-1/"),
+            page_key("http://katalepsis.net/2019/01/12/2-1")
+        );
     }
 }
@@ -1,50 +1,630 @@
 use async_trait::async_trait;
-// use html2md::parse_html;
-// use pandoc::{InputFormat, InputKind, OutputFormat, OutputKind, PandocOutput};
+use chrono::{DateTime, FixedOffset};
+use once_cell::sync::{Lazy, OnceCell};
+use regex::Regex;
+use reqwest::Client;
+
+use html2md::parse_html;
+use pandoc::{InputFormat, InputKind, OutputFormat, OutputKind, PandocOutput};
+
+use std::future::Future;
+use std::process::{Command, Stdio};
+use std::sync::RwLock;
 
 use crate::{
     error::ArchiveError,
-    structs::{Story, StorySource},
+    progress,
+    structs::{Chapter, ChapterText, Completed, Story, StorySource, TextFormat},
+    text::{html_to_text, sanitize_html},
+    Result,
 };
 
 pub mod ao3;
+pub mod ffnet;
 pub mod katalepsis;
 pub mod royalroad;
+pub mod wordpress;
 pub mod xenforo;
 
+/// Downloads stories from one site.
+///
+/// Every request goes through the `client` passed in, which callers share across a whole run so
+/// that cookies and connections are reused between stories.
 #[async_trait]
-pub trait Parser {
-    async fn get_skeleton(&self, source: StorySource) -> Result<Story, ArchiveError>;
-    async fn fill_skeleton(&self, skeleton: Story) -> Result<Story, ArchiveError>;
-    async fn get_story(&self, source: StorySource) -> Result<Story, ArchiveError>;
-}
-
-// fn convert_to_format(html: String, format: TextFormat) -> String {
-//     custom_convert_to_format(html, format, None)
-// }
-
-// fn custom_convert_to_format(
-//     html: String,
-//     format: TextFormat,
-//     custom_behavior: Option<Box<dyn Fn(String, TextFormat) -> String>>,
-// ) -> String {
-//     let initial_text = match format {
-//         TextFormat::Html => html,
-//         TextFormat::Markdown => {
-//             let mut pandoc = pandoc::new();
-//             pandoc
-//                 .set_input_format(InputFormat::Html, Vec::new())
-//                 .set_output_format(OutputFormat::MarkdownStrict, Vec::new())
-//                 .set_input(InputKind::Pipe(html.clone()))
-//                 .set_output(OutputKind::Pipe);
-//             match pandoc.execute() {
-//                 Ok(PandocOutput::ToBuffer(text)) => text,
-//                 _ => parse_html(html.as_str()),
-//             }
-//         }
-//     };
-//     match custom_behavior {
-//         Some(f) => f(initial_text, format),
-//         None => initial_text,
-//     }
-// }
+pub trait Parser: Send + Sync {
+    async fn get_skeleton(&self, client: &Client, source: StorySource) -> Result<Story>;
+    async fn fill_skeleton(&self, client: &Client, skeleton: Story) -> Result<Story>;
+    async fn get_story(&self, client: &Client, source: StorySource) -> Result<Story>;
+
+    /// Gets a story's details without its chapter text. Its chapter list may be incomplete or
+    /// empty and shouldn't be saved.
+    async fn get_metadata(&self, client: &Client, source: StorySource) -> Result<Story> {
+        self.get_skeleton(client, source).await
+    }
+
+    /// Downloads the text of one chapter of `story` into `chapter`, without fetching the rest of
+    /// the story. `story` says where the chapter is, which some sources need to find it, but its
+    /// other chapters don't need to have text. A placeholder date is filled in too if the real
+    /// one turns up.
+    ///
+    /// By default this fills in a copy of `story` that has only this chapter.
+    async fn hydrate_chapter(
+        &self,
+        client: &Client,
+        story: &Story,
+        chapter: &mut Chapter,
+    ) -> Result<()> {
+        let mut skeleton = story.with_chapters(&|id| id == chapter.id);
+        for listed in skeleton.all_chapters_mut() {
+            listed.text = ChapterText::Dehydrated;
+        }
+        let filled = self.fill_skeleton(client, skeleton).await?;
+        let fetched = filled
+            .all_chapters()
+            .into_iter()
+            .find(|fetched| fetched.id == chapter.id)
+            .ok_or_else(|| ArchiveError::ChapterNotExists(chapter.id.clone()))?;
+        chapter.text = fetched.text.clone();
+        if chapter.has_placeholder_date() {
+            chapter.date_posted = fetched.date_posted;
+        }
+        Ok(())
+    }
+}
+
+/// Runs synchronous, CPU-bound work such as building a `Document` and walking it with selectors
+/// on tokio's blocking thread pool, so that parsing a large page doesn't stall the async workers
+/// that are driving other requests.
+pub(crate) async fn parse_blocking<T, F>(parse: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(parse)
+        .await
+        .map_err(|e| ArchiveError::Internal(format!("Page parsing task failed: {}", e)))?
+}
+
+/// Waits for the requests fetching a story's chapters, with a progress bar showing how many of
+/// them are done.
+pub(crate) async fn fetch_chapters<F: Future>(
+    story_name: &str,
+    fetches: impl IntoIterator<Item = F>,
+) -> Vec<F::Output> {
+    progress::join_counted(format!("Downloading \"{}\"", story_name), fetches).await
+}
+
+/// Builds a parser for a registered source.
+pub type ParserFactory = fn() -> Box<dyn Parser>;
+
+struct Registration {
+    prefix: &'static str,
+    pattern: Regex,
+    url_template: &'static str,
+    factory: ParserFactory,
+}
+
+static REGISTRY: Lazy<RwLock<Vec<Registration>>> = Lazy::new(|| {
+    #[rustfmt::skip]
+    let builtin: [(&'static str, &str, &'static str, ParserFactory); 8] = [
+        ("ao3", r"^https?://(?:www\.)?archiveofourown\.org/works/(?P<id>\d+)(?:[/?#].*)?$", "https://archiveofourown.org/works/{id}", || Box::new(ao3::AO3Parser)),
+        ("ao3series", r"^https?://(?:www\.)?archiveofourown\.org/series/(?P<id>\d+)(?:[/?#].*)?$", "https://archiveofourown.org/series/{id}", || Box::new(ao3::AO3Parser)),
+        ("ffnet", r"^https?://(?:www\.|m\.)?fanfiction\.net/s/(?P<id>\d+)(?:[/?#].*)?$", "https://www.fanfiction.net/s/{id}", || Box::new(ffnet::FFNetParser)),
+        ("katalepsis", r"^https?://(?:www\.)?katalepsis\.net(?:[/?#].*)?$", "https://katalepsis.net", || Box::new(katalepsis::KatalepsisParser)),
+        ("qq", r"^https?://forums?\.questionablequesting\.com/threads/(?:[^/]+\.)?(?P<id>\d+)(?:[/?#].*)?$", "https://forum.questionablequesting.com/threads/{id}", || Box::new(xenforo::XenforoParser)),
+        ("rr", r"^https?://(?:www\.)?royalroad\.com/fiction/(?P<id>\d+)(?:[/?#].*)?$", "https://www.royalroad.com/fiction/{id}", || Box::new(royalroad::RoyalRoadParser)),
+        ("sb", r"^https?://forums\.spacebattles\.com/threads/(?:[^/]+\.)?(?P<id>\d+)(?:[/?#].*)?$", "https://forums.spacebattles.com/threads/{id}", || Box::new(xenforo::XenforoParser)),
+        ("sv", r"^https?://forums\.sufficientvelocity\.com/threads/(?:[^/]+\.)?(?P<id>\d+)(?:[/?#].*)?$", "https://forums.sufficientvelocity.com/threads/{id}", || Box::new(xenforo::XenforoParser)),
+    ];
+    RwLock::new(
+        builtin
+            .into_iter()
+            .map(|(prefix, pattern, url_template, factory)| Registration {
+                prefix,
+                pattern: Regex::new(pattern).unwrap(),
+                url_template,
+                factory,
+            })
+            .collect(),
+    )
+});
+
+/// Registers a parser for URLs matching `url_pattern` under the source prefix `prefix`.
+///
+/// The pattern should capture the story's ID in a group named `id` if the source has one.
+/// `url_template` is the story's URL, with `{id}` standing in for that ID. Registering a prefix
+/// that already exists replaces the earlier registration, so built-in sources can be overridden.
+/// Stories from a prefix without a [`StorySource`] variant of its own are
+/// [`StorySource::Registered`].
+pub fn register_parser(
+    prefix: &'static str,
+    url_pattern: &str,
+    url_template: &'static str,
+    factory: ParserFactory,
+) -> Result<()> {
+    let pattern = Regex::new(url_pattern).map_err(|e| {
+        ArchiveError::Internal(format!("Invalid URL pattern for source {}: {}", prefix, e))
+    })?;
+    let mut registry = REGISTRY.write().unwrap();
+    registry.retain(|r| r.prefix != prefix);
+    registry.push(Registration {
+        prefix,
+        pattern,
+        url_template,
+        factory,
+    });
+    Ok(())
+}
+
+/// Finds the registered source whose URL pattern matches `url`, returning its prefix and the
+/// story ID captured from the URL, if any.
+pub(crate) fn match_url(url: &str) -> Option<(&'static str, Option<String>)> {
+    let registry = REGISTRY.read().unwrap();
+    registry.iter().find_map(|registration| {
+        registration.pattern.captures(url).map(|captures| {
+            (
+                registration.prefix,
+                captures.name("id").map(|id| id.as_str().to_owned()),
+            )
+        })
+    })
+}
+
+/// Builds the parser registered for the given source prefix.
+pub(crate) fn parser_for(prefix: &str) -> Option<Box<dyn Parser>> {
+    let registry = REGISTRY.read().unwrap();
+    registry
+        .iter()
+        .find(|registration| registration.prefix == prefix)
+        .map(|registration| (registration.factory)())
+}
+
+/// Builds the URL of a story from the registered source with the given prefix, filling its ID
+/// into the source's URL template.
+pub(crate) fn story_url(prefix: &str, id: Option<&str>) -> Option<String> {
+    let registry = REGISTRY.read().unwrap();
+    registry
+        .iter()
+        .find(|registration| registration.prefix == prefix)
+        .map(|registration| {
+            registration
+                .url_template
+                .replace("{id}", id.unwrap_or_default())
+        })
+}
+
+/// The labels a source uses for whether a story is finished.
+pub(crate) struct StatusLabels {
+    pub complete: &'static [&'static str],
+    pub incomplete: &'static [&'static str],
+}
+
+/// Namespaces a tag the source doesn't classify any further, like AO3's additional tags, so that
+/// every tag has a namespace. See [`Story::tags`] for the others.
+pub(crate) fn unclassified_tag(name: &str) -> String {
+    format!("tag:{}", name.trim())
+}
+
+/// Gets a chapter's posting date from the result of reading it, or if it couldn't be read, warns
+/// and falls back to [`Chapter::placeholder_date`]. A single unreadable date shouldn't stop the
+/// rest of the story from being saved, and chapters with the placeholder date are picked up by
+/// `verify --fix` later.
+pub(crate) fn date_or_placeholder(
+    date: Result<DateTime<FixedOffset>>,
+    chapter_url: &str,
+) -> DateTime<FixedOffset> {
+    date.unwrap_or_else(|err| {
+        log::warn!(
+            "Could not read the posting date of the chapter at {}: {}. Using a placeholder \
+            until `verify --fix` finds the real one.",
+            chapter_url,
+            err
+        );
+        Chapter::placeholder_date()
+    })
+}
+
+/// Maps a source's status label to a completion status. Labels are compared case-insensitively
+/// and with surrounding whitespace ignored. A label the source isn't known to use, or no label
+/// at all, is [`Completed::Unknown`] rather than a guess either way; sources that can tell
+/// from something else (like AO3's chapter count) should check that before giving up.
+pub(crate) fn completion_status(
+    label: Option<&str>,
+    labels: &StatusLabels,
+    source: &StorySource,
+) -> Completed {
+    let label = match label.map(str::trim) {
+        Some(label) => label,
+        None => return Completed::Unknown,
+    };
+    let matches = |known: &[&str]| known.iter().any(|k| k.eq_ignore_ascii_case(label));
+    if matches(labels.complete) {
+        Completed::Complete
+    } else if matches(labels.incomplete) {
+        Completed::Incomplete
+    } else {
+        log::warn!(
+            "Encountered unexpected completion status {:?} for story at {}",
+            label,
+            source.to_url()
+        );
+        Completed::Unknown
+    }
+}
+
+/// What every recognized scene break is turned into when scene breaks are normalized.
+pub(crate) const SCENE_BREAK: &str = "<hr class=\"scene-break\" />";
+
+static NORMALIZE_SCENE_BREAKS: OnceCell<bool> = OnceCell::new();
+
+/// Matches horizontal rules and single paragraphs, whose contents are checked with
+/// [`is_scene_break`].
+static SCENE_BREAK_CANDIDATE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?is)<hr\b[^>]*>|<(?:p|center)\b[^>]*>(?P<inner>.*?)</(?:p|center)>").unwrap()
+});
+
+static TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+/// Sets whether chapter text fetched for the rest of the run has its scene breaks normalized.
+/// They are unless this is turned off. Only the first call has any effect.
+pub(crate) fn set_normalize_scene_breaks(enabled: bool) {
+    let _ = NORMALIZE_SCENE_BREAKS.set(enabled);
+}
+
+pub(crate) fn normalizing_scene_breaks() -> bool {
+    NORMALIZE_SCENE_BREAKS.get().copied().unwrap_or(true)
+}
+
+/// Cleans up a chapter's HTML before it is stored: see [`sanitize_html`] for what's always
+/// removed. Scene breaks are also normalized unless that's been turned off.
+pub(crate) fn chapter_html(html: String) -> String {
+    let html = sanitize_html(&html);
+    match normalizing_scene_breaks() {
+        true => normalize_scene_breaks(&html),
+        false => html,
+    }
+}
+
+/// Replaces every scene break in `html`, whether it's a horizontal rule or a paragraph holding
+/// only a divider like `* * *`, `==`, `~~~`, or `⁂`, with [`SCENE_BREAK`].
+pub(crate) fn normalize_scene_breaks(html: &str) -> String {
+    SCENE_BREAK_CANDIDATE
+        .replace_all(html, |captures: &regex::Captures| {
+            match captures.name("inner") {
+                Some(inner) if !is_scene_break(&TAG.replace_all(inner.as_str(), "")) => {
+                    captures[0].to_owned()
+                }
+                _ => SCENE_BREAK.to_owned(),
+            }
+        })
+        .into_owned()
+}
+
+/// Whether a paragraph's text is nothing but a scene-break divider.
+pub(crate) fn is_scene_break(text: &str) -> bool {
+    let text = text.replace("&nbsp;", " ");
+    let marks: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let is_mark = |c: &char| matches!(c, '*' | '=' | '~' | '-' | '#' | '•' | '◇' | '⁂');
+    !marks.is_empty() && marks.iter().all(is_mark) && (marks.len() >= 2 || marks[0] == '⁂')
+}
+
+/// Whether the `pandoc` binary can be run. Checked once, the first time Markdown is needed;
+/// if it can't, a warning is printed and Markdown is produced by the built-in converter.
+static PANDOC_AVAILABLE: Lazy<bool> = Lazy::new(|| {
+    let available = Command::new("pandoc")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    if !available {
+        log::warn!(
+            "pandoc is not installed, so Markdown will be converted with a simpler \
+            fallback that may lose some formatting."
+        );
+    }
+    available
+});
+
+/// Converts stored chapter HTML to the given format. Markdown goes through pandoc when it is
+/// installed and falls back to `html2md` otherwise, or if pandoc fails on a chapter. Plain text
+/// comes from [`html_to_text`].
+pub(crate) fn convert_to_format(html: String, format: TextFormat) -> String {
+    match format {
+        TextFormat::Html => html,
+        TextFormat::Markdown => {
+            let converted = match *PANDOC_AVAILABLE {
+                true => pandoc_markdown(&html),
+                false => None,
+            };
+            converted.unwrap_or_else(|| parse_html(&html))
+        }
+        TextFormat::Text => html_to_text(&html),
+    }
+}
+
+fn pandoc_markdown(html: &str) -> Option<String> {
+    let mut pandoc = pandoc::new();
+    pandoc
+        .set_input_format(InputFormat::Html, Vec::new())
+        .set_output_format(OutputFormat::MarkdownStrict, Vec::new())
+        .set_input(InputKind::Pipe(html.to_owned()))
+        .set_output(OutputKind::Pipe);
+    match pandoc.execute() {
+        Ok(PandocOutput::ToBuffer(text)) => Some(text),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use reqwest::Client;
+
+    use super::{
+        convert_to_format, is_scene_break, normalize_scene_breaks, register_parser,
+        wordpress::{register_site, WordPressSite},
+        Parser, SCENE_BREAK,
+    };
+
+    use crate::error::ArchiveError;
+    use crate::structs::{
+        Author, AuthorList, Chapter, ChapterText, Completed, Content, Section, Story, StorySource,
+        TextFormat,
+    };
+
+    /// Builds a story whose chapters are already hydrated and whose URLs point at a port nothing
+    /// listens on, so any attempt to fetch them fails.
+    fn hydrated_story(source: StorySource) -> Story {
+        let chapter = |num: usize| {
+            Content::Chapter(Chapter {
+                id: format!("{}:{}", source.to_id(), num),
+                name: format!("Chapter {}", num),
+                description: None,
+                text: ChapterText::Hydrated(format!("<p>Text of chapter {}</p>", num)),
+                url: format!("http://127.0.0.1:9/chapters/{}", num),
+                date_posted: DateTime::parse_from_rfc3339("2020-01-01T12:00:00+00:00").unwrap(),
+                author: None,
+            })
+        };
+        Story {
+            name: "Already Fetched".to_owned(),
+            authors: AuthorList::new(Author::new("Someone", "someone")),
+            description: None,
+            url: "http://127.0.0.1:9/".to_owned(),
+            tags: Vec::new(),
+            chapters: vec![
+                chapter(1),
+                Content::Section(Section {
+                    id: format!("{}:section", source.to_id()),
+                    name: "Section".to_owned(),
+                    description: None,
+                    chapters: vec![chapter(2)],
+                    url: None,
+                    author: None,
+                }),
+                chapter(3),
+            ],
+            source,
+            completed: Completed::Unknown,
+            language: None,
+            cover_url: None,
+            published: None,
+            last_updated: None,
+        }
+    }
+
+    fn texts(contents: &[Content]) -> Vec<String> {
+        contents
+            .iter()
+            .flat_map(|content| match content {
+                Content::Chapter(chapter) => vec![chapter.text.as_str().to_owned()],
+                Content::Section(section) => texts(&section.chapters),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn fill_skeleton_skips_hydrated_chapters() {
+        let sources = [
+            StorySource::AO3("1".to_owned()),
+            StorySource::AO3Series("1".to_owned()),
+            StorySource::FFNet("1".to_owned()),
+            StorySource::Katalepsis,
+            StorySource::QuestionableQuesting("1".to_owned()),
+            StorySource::RoyalRoad("1".to_owned()),
+            StorySource::SpaceBattles("1".to_owned()),
+            StorySource::SufficientVelocity("1".to_owned()),
+            StorySource::WordPress {
+                base_url: "https://hydrated.example.com".to_owned(),
+            },
+        ];
+        register_site(
+            WordPressSite::from_toc_url(
+                "https://hydrated.example.com/table-of-contents/",
+                ".entry-content",
+                ".entry-content",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let client = crate::client::new_client(&Default::default()).unwrap();
+        for source in sources {
+            let prefix = source.prefix().to_owned();
+            let skeleton = hydrated_story(source.clone());
+            let expected = texts(&skeleton.chapters);
+            let filled = source
+                .parser()
+                .fill_skeleton(&client, skeleton)
+                .await
+                .unwrap_or_else(|err| panic!("{} re-fetched a hydrated chapter: {}", prefix, err));
+            assert_eq!(texts(&filled.chapters), expected, "{}", prefix);
+        }
+    }
+
+    /// Fills in every dehydrated chapter it's given with its id, remembering which those were.
+    struct RecordingParser(std::sync::Mutex<Vec<String>>);
+
+    #[async_trait::async_trait]
+    impl Parser for RecordingParser {
+        async fn get_skeleton(&self, _: &Client, _: StorySource) -> crate::Result<Story> {
+            Err(ArchiveError::Internal("Not used by this test".to_owned()))
+        }
+
+        async fn fill_skeleton(&self, _: &Client, mut skeleton: Story) -> crate::Result<Story> {
+            for chapter in skeleton.all_chapters_mut() {
+                self.0.lock().unwrap().push(chapter.id.clone());
+                if let ChapterText::Dehydrated = chapter.text {
+                    chapter.text = ChapterText::Hydrated(format!("<p>Fetched {}</p>", chapter.id));
+                }
+            }
+            Ok(skeleton)
+        }
+
+        async fn get_story(&self, _: &Client, _: StorySource) -> crate::Result<Story> {
+            Err(ArchiveError::Internal("Not used by this test".to_owned()))
+        }
+    }
+
+    #[tokio::test]
+    async fn hydrates_one_chapter_at_a_time() {
+        let story = hydrated_story(StorySource::RoyalRoad("1".to_owned()));
+        let mut chapter = story.all_chapters()[1].clone();
+        chapter.text = ChapterText::Dehydrated;
+        let parser = RecordingParser(Default::default());
+        let client = crate::client::new_client(&Default::default()).unwrap();
+        parser
+            .hydrate_chapter(&client, &story, &mut chapter)
+            .await
+            .unwrap();
+        assert_eq!(chapter.text.as_str(), "<p>Fetched rr:1:2</p>");
+        assert_eq!(*parser.0.lock().unwrap(), vec!["rr:1:2".to_owned()]);
+    }
+
+    #[tokio::test]
+    async fn adds_sources_through_the_registry() {
+        register_parser(
+            "test",
+            r"^https://stories\.example\.org/s/(?P<id>\d+)(?:[/?#].*)?$",
+            "https://stories.example.org/s/{id}",
+            || Box::new(RecordingParser(Default::default())),
+        )
+        .unwrap();
+        let source = StorySource::from_url("https://stories.example.org/s/42/chapter-1").unwrap();
+        assert_eq!(
+            source,
+            StorySource::Registered {
+                prefix: "test".to_owned(),
+                id: Some("42".to_owned()),
+            }
+        );
+        assert_eq!(source.to_id(), "test:42");
+        assert_eq!(source.to_url(), "https://stories.example.org/s/42");
+
+        let story = hydrated_story(StorySource::RoyalRoad("1".to_owned()));
+        let mut chapter = story.all_chapters()[0].clone();
+        chapter.text = ChapterText::Dehydrated;
+        let client = crate::client::new_client(&Default::default()).unwrap();
+        source
+            .parser()
+            .hydrate_chapter(&client, &story, &mut chapter)
+            .await
+            .unwrap();
+        assert_eq!(chapter.text.as_str(), "<p>Fetched rr:1:1</p>");
+    }
+
+    #[test]
+    fn recognizes_scene_break_dividers() {
+        for text in [
+            "* * *",
+            "***",
+            "==",
+            "~~~",
+            "- - -",
+            "###",
+            "⁂",
+            " &nbsp;*&nbsp;*&nbsp;* ",
+        ] {
+            assert!(is_scene_break(text), "{:?} should be a scene break", text);
+        }
+        for text in ["", "*", "-", "Chapter 1", "* emphasis *", "==> arrow"] {
+            assert!(
+                !is_scene_break(text),
+                "{:?} should not be a scene break",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn normalizes_each_source_style() {
+        let cases = [
+            // AO3 and RoyalRoad authors usually use a horizontal rule or a paragraph of asterisks.
+            ("<p>Before.</p><hr><p>After.</p>", 1),
+            ("<p>Before.</p><hr style=\"width: 50%\" /><p>After.</p>", 1),
+            (
+                "<p>Before.</p><p style=\"text-align: center\">* * *</p><p>After.</p>",
+                1,
+            ),
+            // Katalepsis uses `==` or centered asterisks.
+            ("<p>Before.</p><p>==</p><p>After.</p>", 1),
+            (
+                "<p>Before.</p><p align=\"center\"><span>* * *</span></p><p>After.</p>",
+                1,
+            ),
+            // Forum posts often center a divider, sometimes in bold.
+            ("<p>Before.</p><center><b>~~~</b></center><p>After.</p>", 1),
+            (
+                "<p>Before.</p><p><strong>⁂</strong></p><p>After.</p><hr />",
+                2,
+            ),
+        ];
+        for (html, breaks) in cases {
+            let normalized = normalize_scene_breaks(html);
+            assert_eq!(normalized.matches(SCENE_BREAK).count(), breaks, "{}", html);
+            assert!(normalized.starts_with("<p>Before.</p>"), "{}", normalized);
+            assert!(normalized.contains("<p>After.</p>"), "{}", normalized);
+        }
+    }
+
+    #[test]
+    fn leaves_ordinary_paragraphs_alone() {
+        let html = "<p>She said *this* -- twice.</p><p>==&gt; Next</p>";
+        assert_eq!(normalize_scene_breaks(html), html);
+    }
+
+    #[test]
+    fn converts_to_markdown_with_or_without_pandoc() {
+        let html = "<p>Some <strong>bold</strong> text.</p>".to_owned();
+        assert_eq!(convert_to_format(html.clone(), TextFormat::Html), html);
+        let markdown = convert_to_format(html, TextFormat::Markdown);
+        assert!(markdown.contains("**bold**"), "{:?}", markdown);
+        assert!(!markdown.contains("<p>"), "{:?}", markdown);
+    }
+
+    #[test]
+    fn matches_status_labels_loosely() {
+        use super::{completion_status, StatusLabels};
+        let labels = StatusLabels {
+            complete: &["Complete"],
+            incomplete: &["Ongoing"],
+        };
+        let source = StorySource::RoyalRoad("6789".to_owned());
+        assert_eq!(
+            completion_status(Some(" COMPLETE\n"), &labels, &source),
+            Completed::Complete
+        );
+        assert_eq!(
+            completion_status(Some("ongoing"), &labels, &source),
+            Completed::Incomplete
+        );
+        assert_eq!(
+            completion_status(Some("Dropped"), &labels, &source),
+            Completed::Unknown
+        );
+        assert_eq!(
+            completion_status(None, &labels, &source),
+            Completed::Unknown
+        );
+    }
+}
@@ -1,13 +1,16 @@
 use async_trait::async_trait;
 use chrono::DateTime;
-use futures::future::join_all;
 use regex::Regex;
+use reqwest::Client;
 use select::{document::Document, predicate, predicate::Predicate};
 
 use crate::{
     client::get,
     error::ArchiveError,
-    parser::Parser,
+    parser::{
+        chapter_html, completion_status, date_or_placeholder, fetch_chapters, unclassified_tag,
+        Parser, StatusLabels,
+    },
     structs::{Author, AuthorList, Chapter, ChapterText, Completed, Content, Story, StorySource},
     Result,
 };
@@ -19,8 +22,8 @@ pub(crate) struct RoyalRoadParser;
 
 #[async_trait]
 impl Parser for RoyalRoadParser {
-    async fn get_skeleton(&self, source: StorySource) -> Result<Story> {
-        let main_page = get(&source.to_url()).await?.text().await?;
+    async fn get_skeleton(&self, client: &Client, source: StorySource) -> Result<Story> {
+        let main_page = get(client, &source.to_url()).await?.text().await?;
         let main_page = Document::from_read(main_page.as_bytes())?;
         let chapters = main_page
             .find(
@@ -64,19 +67,19 @@ impl Parser for RoyalRoadParser {
                 );
                 let time_string = content_a
                     .children()
-                    .filter(|c| c.is(predicate::Name("time")))
-                    .map(|c| {
-                        c.attr("datetime")
-                            .expect("time tag should have datetime attr")
-                    })
-                    .next()
-                    .expect("Chapter content tag should have <time> child");
-                let date_posted = DateTime::parse_from_rfc3339(time_string).unwrap_or_else(|_| {
-                    panic!(
-                        "Chapter posted-on date ({}) did not conform to rfc3339",
-                        time_string
-                    )
-                });
+                    .find(|c| c.is(predicate::Name("time")))
+                    .and_then(|c| c.attr("datetime"))
+                    .ok_or_else(|| {
+                        ArchiveError::PageError(format!(
+                            "Royalroad: couldn't find the posting date (time[datetime]) of chapter at {}",
+                            url
+                        ))
+                    });
+                let date_posted = date_or_placeholder(
+                    time_string
+                        .and_then(|time| Ok(DateTime::parse_from_rfc3339(time)?)),
+                    &url,
+                );
 
                 Content::Chapter(Chapter {
                     id: format!(
@@ -128,11 +131,9 @@ impl Parser for RoyalRoadParser {
             )
             .map(|elem| elem.inner_html())
             .collect();
-        let tags = main_page
-            .find(predicate::Class("tags").child(predicate::Name("a")))
-            .map(|elem| elem.text())
-            .collect();
+        let tags = get_tags(&main_page);
         let completed = get_completed(&main_page, &source)?;
+        let cover_url = get_cover_url(&main_page);
 
         Ok(Story {
             name: title,
@@ -143,10 +144,15 @@ impl Parser for RoyalRoadParser {
             chapters,
             source,
             completed,
+            language: Some("en".to_owned()),
+            cover_url,
+            published: None,
+            last_updated: None,
         })
     }
 
-    async fn fill_skeleton(&self, mut skeleton: Story) -> Result<Story> {
+    async fn fill_skeleton(&self, client: &Client, mut skeleton: Story) -> Result<Story> {
+        let name = skeleton.name.clone();
         let hydrate = skeleton
             .chapters
             .iter_mut()
@@ -154,18 +160,16 @@ impl Parser for RoyalRoadParser {
                 Content::Section(_) => None,
                 Content::Chapter(c) => Some(c),
             })
+            .filter(|chapter| matches!(chapter.text, ChapterText::Dehydrated))
             .map(|chapter| async {
-                let page = get(&chapter.url).await?.text().await?;
+                let page = get(client, &chapter.url).await?.text().await?;
                 Ok((chapter, page))
             });
 
-        let results = join_all(hydrate).await;
-        if results.iter().any(|res: &Result<(_, _)>| res.is_err()) {
-            return Err(ArchiveError::Internal("Oopsie!".to_owned()));
-        }
-
-        let mut results: Vec<(&mut Chapter, String)> =
-            results.into_iter().map(|r| r.unwrap()).collect();
+        let mut results: Vec<(&mut Chapter, String)> = fetch_chapters(&name, hydrate)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
         rayon::scope(|s| {
             for (chapter, page) in results.iter_mut() {
                 s.spawn(|_| {
@@ -175,21 +179,28 @@ impl Parser for RoyalRoadParser {
                         .find(predicate::Class("chapter-content").child(predicate::Name("p")))
                         .map(|elem| elem.html())
                         .collect();
-                    chapter.text = ChapterText::Hydrated(body_text);
+                    chapter.text = ChapterText::Hydrated(chapter_html(body_text));
                 });
             }
         });
         Ok(skeleton)
     }
 
-    async fn get_story(&self, source: StorySource) -> Result<Story> {
-        let story = self.get_skeleton(source).await?;
-        self.fill_skeleton(story).await
+    async fn get_story(&self, client: &Client, source: StorySource) -> Result<Story> {
+        let story = self.get_skeleton(client, source).await?;
+        self.fill_skeleton(client, story).await
     }
 }
 
+/// Royalroad's status labels. Stories on hiatus haven't been finished, so they count as
+/// incomplete; dropped stories and stubs never will be, so they stay unknown.
+const STATUS_LABELS: StatusLabels = StatusLabels {
+    complete: &["COMPLETED"],
+    incomplete: &["ONGOING", "HIATUS"],
+};
+
 fn get_completed(document: &Document, source: &StorySource) -> Result<Completed> {
-    Ok(document
+    let label = document
         .find(predicate::Class("fiction-info"))
         .next()
         .ok_or(ArchiveError::PageError(format!(
@@ -197,15 +208,131 @@ fn get_completed(document: &Document, source: &StorySource) -> Result<Completed>
             source.to_url()
         )))?
         .descendants()
-        .find(|d| {
-            d.is(predicate::Class("label")) && {
-                d.text().trim() == "ONGOING" || d.text().trim() == "COMPLETED"
-            }
-        })
-        .map(|d| match d.text().trim() {
-            "COMPLETED" => Completed::Complete,
-            "ONGOING" => Completed::Incomplete,
-            _ => unreachable!(),
+        .filter(|d| d.is(predicate::Class("label")))
+        .map(|d| d.text())
+        .find(|text| {
+            let text = text.trim();
+            STATUS_LABELS
+                .complete
+                .iter()
+                .chain(STATUS_LABELS.incomplete)
+                .any(|label| label.eq_ignore_ascii_case(text))
+        });
+    Ok(completion_status(label.as_deref(), &STATUS_LABELS, source))
+}
+
+/// Gets the story's tags, namespacing Royalroad's genres and content warnings. Its other tags
+/// (like "Female Lead" or "LitRPG") aren't sorted into kinds, so they're left unclassified.
+fn get_tags(document: &Document) -> Vec<String> {
+    let tags = document
+        .find(predicate::Class("tags").child(predicate::Name("a")))
+        .map(|elem| elem.text().trim().to_owned())
+        .map(
+            |tag| match GENRES.iter().find(|genre| genre.eq_ignore_ascii_case(&tag)) {
+                Some(genre) => format!("genre:{}", genre),
+                None => unclassified_tag(&tag),
+            },
+        );
+    let warnings = document
+        .find(
+            predicate::Class("font-red-sunglo")
+                .child(predicate::Name("ul"))
+                .child(predicate::Name("li")),
+        )
+        .map(|elem| format!("warning:{}", elem.text().trim()));
+    tags.chain(warnings).collect()
+}
+
+/// The genres Royalroad lists among a story's tags.
+const GENRES: [&str; 15] = [
+    "Action",
+    "Adventure",
+    "Comedy",
+    "Contemporary",
+    "Drama",
+    "Fantasy",
+    "Historical",
+    "Horror",
+    "Mystery",
+    "Psychological",
+    "Romance",
+    "Satire",
+    "Sci-fi",
+    "Short Story",
+    "Tragedy",
+];
+
+/// Gets the story's cover image, skipping the placeholder Royalroad shows for stories without
+/// one.
+fn get_cover_url(document: &Document) -> Option<String> {
+    document
+        .find(predicate::Class("cover-art-container").descendant(predicate::Name("img")))
+        .next()
+        .and_then(|img| img.attr("src"))
+        .map(|src| src.trim())
+        .filter(|src| !src.is_empty() && !src.contains("nocover"))
+        .map(|src| match src.starts_with('/') {
+            true => format!("https://www.royalroad.com{}", src),
+            false => src.to_owned(),
         })
-        .unwrap_or(Completed::Unknown))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completed_with_labels(labels: &[&str]) -> Completed {
+        let page = format!(
+            "<div class=\"fiction-info\">{}</div>",
+            labels
+                .iter()
+                .map(|label| format!("<span class=\"label\">{}</span>", label))
+                .collect::<String>()
+        );
+        let document = Document::from_read(page.as_bytes()).unwrap();
+        get_completed(&document, &StorySource::RoyalRoad("6789".to_owned())).unwrap()
+    }
+
+    #[test]
+    fn maps_status_labels() {
+        assert_eq!(
+            completed_with_labels(&["Original", "COMPLETED"]),
+            Completed::Complete
+        );
+        assert_eq!(
+            completed_with_labels(&["Original", "ONGOING"]),
+            Completed::Incomplete
+        );
+        assert_eq!(completed_with_labels(&["HIATUS"]), Completed::Incomplete);
+        assert_eq!(completed_with_labels(&["STUB"]), Completed::Unknown);
+        assert_eq!(completed_with_labels(&["Original"]), Completed::Unknown);
+    }
+
+    #[test]
+    fn namespaces_tags() {
+        let page = r#"<span class="tags">
+            <a class="fiction-tag" href="/fictions/search?tagsAdd=fantasy">Fantasy</a>
+            <a class="fiction-tag" href="/fictions/search?tagsAdd=scifi">Sci-fi</a>
+            <a class="fiction-tag" href="/fictions/search?tagsAdd=female_lead">Female Lead</a>
+        </span>
+        <div class="text-center font-red-sunglo">
+            <strong>Warning</strong>
+            <div class="font-red-sunglo bold">This fiction contains:</div>
+            <ul class="list-inline">
+                <li>Profanity</li>
+                <li>Graphic Violence</li>
+            </ul>
+        </div>"#;
+        let document = Document::from_read(page.as_bytes()).unwrap();
+        assert_eq!(
+            get_tags(&document),
+            vec![
+                "genre:Fantasy",
+                "genre:Sci-fi",
+                "tag:Female Lead",
+                "warning:Profanity",
+                "warning:Graphic Violence",
+            ]
+        );
+    }
 }
@@ -0,0 +1,566 @@
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::{Client, Url};
+use select::{
+    document::Document,
+    node::Node,
+    predicate::{self, Predicate},
+};
+
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+use crate::{
+    client::get,
+    error::ArchiveError,
+    parser::{chapter_html, fetch_chapters, parse_blocking, register_parser, Parser},
+    structs::{Author, AuthorList, Chapter, ChapterText, Completed, Content, Story, StorySource},
+    Result,
+};
+
+/// The source prefix every WordPress site is registered under.
+pub(crate) const PREFIX: &str = "wp";
+
+/// Matches the text of the links WordPress serials put above and below each chapter.
+static NAVIGATION_LINK: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)^\s*(?:previous|last|next)\s+chapter\s*$").unwrap());
+
+static SITES: Lazy<RwLock<Vec<WordPressSite>>> = Lazy::new(Default::default);
+
+/// A WordPress blog that hosts a single serial, along with where to find its chapters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WordPressSite {
+    /// The site's host name without any leading `www.`, like `parahumans.wordpress.com`.
+    pub host: String,
+    /// The path of the page listing every chapter, like `/table-of-contents/`.
+    pub toc_path: String,
+    /// Selects the part of the table of contents page that holds the chapter links.
+    pub toc_selector: String,
+    /// Selects the chapter text on each chapter's page.
+    pub content_selector: String,
+}
+
+impl WordPressSite {
+    /// Describes a site from the URL of its table of contents page.
+    pub fn from_toc_url(toc_url: &str, toc_selector: &str, content_selector: &str) -> Result<Self> {
+        let url = Url::parse(toc_url)
+            .map_err(|e| ArchiveError::PageError(format!("Invalid URL {}: {}", toc_url, e)))?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| ArchiveError::PageError(format!("URL {} has no host", toc_url)))?;
+        let site = Self {
+            host: host.trim_start_matches("www.").to_owned(),
+            toc_path: url.path().to_owned(),
+            toc_selector: toc_selector.to_owned(),
+            content_selector: content_selector.to_owned(),
+        };
+        Selector::parse(&site.toc_selector)?;
+        Selector::parse(&site.content_selector)?;
+        Ok(site)
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("https://{}", self.host)
+    }
+}
+
+/// Makes stories on `site` available to add, replacing any earlier registration for its host.
+pub fn register_site(site: WordPressSite) -> Result<()> {
+    let mut sites = SITES.write().unwrap();
+    sites.retain(|registered| registered.host != site.host);
+    sites.push(site);
+    let hosts: Vec<String> = sites.iter().map(|site| regex::escape(&site.host)).collect();
+    register_parser(
+        PREFIX,
+        &format!(
+            r"^https?://(?:www\.)?(?P<id>{})(?:[/?#].*)?$",
+            hosts.join("|")
+        ),
+        "https://{id}",
+        || Box::new(WordPressParser),
+    )
+}
+
+fn site_for(source: &StorySource) -> Result<WordPressSite> {
+    let base_url = match source {
+        StorySource::WordPress { base_url } => base_url,
+        _ => {
+            return Err(ArchiveError::Internal(format!(
+                "WordPress parser was given a story from {}",
+                source.site_name()
+            )))
+        }
+    };
+    let host = base_url
+        .split_once("://")
+        .map_or(base_url.as_str(), |(_, host)| host);
+    SITES
+        .read()
+        .unwrap()
+        .iter()
+        .find(|site| site.host == host)
+        .cloned()
+        .ok_or_else(|| ArchiveError::BadSource(source.to_url()))
+}
+
+pub(crate) struct WordPressParser;
+
+#[async_trait]
+impl Parser for WordPressParser {
+    async fn get_skeleton(&self, client: &Client, source: StorySource) -> Result<Story> {
+        let site = site_for(&source)?;
+        let toc_url = format!("{}{}", site.base_url(), site.toc_path);
+        let page = get(client, &toc_url).await?.text().await?;
+        parse_blocking(move || parse_toc(source, &site, &page)).await
+    }
+
+    async fn fill_skeleton(&self, client: &Client, skeleton: Story) -> Result<Story> {
+        let site = site_for(&skeleton.source)?;
+        fill_chapters(client, skeleton, &Selector::parse(&site.content_selector)?).await
+    }
+
+    async fn get_story(&self, client: &Client, source: StorySource) -> Result<Story> {
+        let story = self.get_skeleton(client, source).await?;
+        self.fill_skeleton(client, story).await
+    }
+}
+
+/// Builds a skeleton from the site's table of contents, with a chapter for every link to
+/// another page on the site, in the order they're listed.
+fn parse_toc(source: StorySource, site: &WordPressSite, page: &str) -> Result<Story> {
+    let document = Document::from_read(page.as_bytes())?;
+    let toc = Selector::parse(&site.toc_selector)?.find(&document);
+    if toc.is_empty() {
+        return Err(ArchiveError::PageError(format!(
+            "WordPress: couldn't find the table of contents ({}) at {}{}",
+            site.toc_selector,
+            site.base_url(),
+            site.toc_path
+        )));
+    }
+    let base = Url::parse(&site.base_url())
+        .and_then(|base| base.join(&site.toc_path))
+        .map_err(|e| ArchiveError::Internal(format!("Invalid site URL: {}", e)))?;
+
+    let mut seen = HashSet::new();
+    let chapters: Vec<Content> = toc
+        .iter()
+        .flat_map(|node| node.find(predicate::Name("a")))
+        .filter_map(|link| {
+            let mut url = base.join(link.attr("href")?).ok()?;
+            url.set_fragment(None);
+            let host = url.host_str()?.trim_start_matches("www.");
+            let name = collapse_whitespace(&link.text());
+            if host != site.host || url.path() == site.toc_path || name.is_empty() {
+                return None;
+            }
+            seen.insert(url.to_string()).then_some((url, name))
+        })
+        .enumerate()
+        .map(|(idx, (url, name))| {
+            let slug = url
+                .path_segments()
+                .and_then(|mut segments| segments.rfind(|segment| !segment.is_empty()))
+                .map(str::to_owned)
+                .or_else(|| url.query().map(str::to_owned))
+                .unwrap_or_else(|| (idx + 1).to_string());
+            Content::Chapter(Chapter {
+                id: format!("{}:{}", source.to_id(), slug),
+                name,
+                description: None,
+                text: ChapterText::Dehydrated,
+                url: url.to_string(),
+                date_posted: Chapter::placeholder_date(),
+                author: None,
+            })
+        })
+        .collect();
+
+    let meta = |property: &str| {
+        let named = predicate::Attr("property", property).or(predicate::Attr("name", property));
+        document
+            .find(predicate::Name("meta").and(named))
+            .find_map(|meta| meta.attr("content"))
+            .map(collapse_whitespace)
+            .filter(|content| !content.is_empty())
+    };
+    let text_of = |class: &str| {
+        document
+            .find(predicate::Class(class))
+            .map(|node| collapse_whitespace(&node.text()))
+            .find(|text| !text.is_empty())
+    };
+    let name = text_of("site-title")
+        .or_else(|| meta("og:site_name"))
+        .unwrap_or_else(|| site.host.clone());
+    let author = meta("author")
+        .or_else(|| {
+            document
+                .find(predicate::Attr("rel", "author"))
+                .map(|link| collapse_whitespace(&link.text()))
+                .find(|text| !text.is_empty())
+        })
+        .unwrap_or_else(|| name.clone());
+
+    Ok(Story {
+        authors: AuthorList::new(Author::new(author, format!("{}:", source.to_id()))),
+        description: text_of("site-description").or_else(|| meta("description")),
+        url: source.to_url(),
+        tags: Vec::new(),
+        chapters,
+        completed: Completed::Unknown,
+        language: document
+            .find(predicate::Name("html"))
+            .find_map(|html| html.attr("lang"))
+            .map(str::to_owned),
+        cover_url: meta("og:image"),
+        published: None,
+        last_updated: None,
+        name,
+        source,
+    })
+}
+
+/// Downloads the text and posting date of every chapter in `skeleton` that doesn't have its
+/// text yet, reading the text from the part of each page that `content` selects.
+pub(crate) async fn fill_chapters(
+    client: &Client,
+    mut skeleton: Story,
+    content: &Selector,
+) -> Result<Story> {
+    let name = skeleton.name.clone();
+    let hydrate = skeleton
+        .all_chapters_mut()
+        .into_iter()
+        .filter(|chap| matches!(chap.text, ChapterText::Dehydrated))
+        .map(|chap| async {
+            let page = get(client, &chap.url).await?.text().await?;
+            let content = content.clone();
+            let url = chap.url.clone();
+            let (body_text, date_posted) =
+                parse_blocking(move || parse_chapter(&page, &content, &url)).await?;
+            chap.text = body_text;
+            chap.date_posted = date_posted;
+            Ok(())
+        });
+
+    let results = fetch_chapters(&name, hydrate).await;
+    match results.into_iter().find(|res| res.is_err()) {
+        Some(err) => Err(err.unwrap_err()),
+        None => Ok(skeleton),
+    }
+}
+
+/// Extracts a chapter's text, content warnings, and author's notes from its page, along with
+/// the date it was posted.
+///
+/// The chapter is everything between the first and last paragraphs with "Previous Chapter" or
+/// "Next Chapter" links, and anything after the last one is the author's notes. A `<details>`
+/// block holds the content warnings.
+pub(crate) fn parse_chapter(
+    page: &str,
+    content: &Selector,
+    url: &str,
+) -> Result<(ChapterText, DateTime<FixedOffset>)> {
+    let document = Document::from_read(page.as_bytes())?;
+    let body = content.find(&document).into_iter().next().ok_or_else(|| {
+        ArchiveError::PageError(format!(
+            "WordPress: couldn't find chapter text ({}) for chapter at {}",
+            content, url
+        ))
+    })?;
+
+    let mut html = String::new();
+    if let Some(warnings) = body
+        .children()
+        .find(|child| child.is(predicate::Name("details")))
+        .and_then(|details| details.find(predicate::Name("p")).next())
+        .filter(|warnings| !warnings.text().trim().starts_with("None"))
+    {
+        html.push_str(&format!(
+            "<p><b>Content Warnings:</b><br>{}</p>",
+            warnings.inner_html().trim()
+        ));
+    }
+
+    let elements: Vec<Node> = body
+        .children()
+        .filter(|child| child.name().is_some() && !is_clutter(child))
+        .collect();
+    let navigation: Vec<usize> = elements
+        .iter()
+        .enumerate()
+        .filter(|(_, elem)| is_navigation(elem))
+        .map(|(idx, _)| idx)
+        .collect();
+    let (start, end) = match navigation[..] {
+        [] => (0, elements.len()),
+        [0] => (1, elements.len()),
+        [only] => (0, only),
+        [first, .., last] => (first + 1, last),
+    };
+    html.extend(
+        elements[start..end]
+            .iter()
+            .filter(|elem| !is_navigation(elem))
+            .map(|elem| elem.html()),
+    );
+    if end + 1 < elements.len() {
+        html.push_str("<p><b>Author's Notes:</b></p>");
+        html.extend(elements[end + 1..].iter().map(|elem| elem.html()));
+    }
+
+    let date_posted = document
+        .find(predicate::Class("entry-date").or(predicate::Class("published")))
+        .find_map(|date| date.attr("datetime"))
+        .or_else(|| {
+            document
+                .find(predicate::Attr("property", "article:published_time"))
+                .find_map(|meta| meta.attr("content"))
+        })
+        .ok_or_else(|| {
+            ArchiveError::PageError(format!(
+                "WordPress: couldn't find the posting date of chapter at {}",
+                url
+            ))
+        })?;
+    let date_posted = DateTime::parse_from_rfc3339(date_posted)?;
+
+    Ok((ChapterText::Hydrated(chapter_html(html)), date_posted))
+}
+
+/// Where a chapter page's "Next Chapter" link goes, if it has one.
+pub(crate) fn next_chapter_url(document: &Document) -> Option<String> {
+    document
+        .find(predicate::Name("a"))
+        .filter(|link| NAVIGATION_LINK.is_match(&link.text()))
+        .find(|link| link.text().to_lowercase().contains("next"))
+        .and_then(|link| link.attr("href"))
+        .map(|url| url.to_owned())
+}
+
+/// Whether an element is a paragraph of links to the previous or next chapter.
+fn is_navigation(elem: &Node) -> bool {
+    elem.find(predicate::Name("a"))
+        .any(|link| NAVIGATION_LINK.is_match(&link.text()))
+}
+
+/// Whether an element is something WordPress adds to every post, like sharing buttons, rather
+/// than part of the chapter.
+fn is_clutter(elem: &Node) -> bool {
+    matches!(elem.name(), Some("script" | "style" | "details"))
+        || elem.attr("class").is_some_and(|class| {
+            class
+                .split_whitespace()
+                .any(|class| class.starts_with("sharedaddy") || class.starts_with("jp-"))
+        })
+        || elem.attr("id").is_some_and(|id| id.starts_with("jp-"))
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// A CSS selector made of compound selectors like `div.entry-content` or `#secondary`, joined
+/// by descendant combinators. `select` can't parse CSS, and this is all site configs need.
+#[derive(Debug, Clone)]
+pub(crate) struct Selector {
+    source: String,
+    parts: Vec<Compound>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Compound {
+    name: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+}
+
+impl Selector {
+    pub(crate) fn parse(selector: &str) -> Result<Self> {
+        let invalid = |reason: &str| ArchiveError::BadSelector(selector.to_owned(), reason.into());
+        let parts = selector
+            .split_whitespace()
+            .map(|part| {
+                let mut compound = Compound::default();
+                // Split before every `#` and `.`, keeping them with the name that follows.
+                let mut rest = part;
+                while !rest.is_empty() {
+                    let len = rest[1..].find(['#', '.']).map_or(rest.len(), |pos| pos + 1);
+                    let (token, remainder) = rest.split_at(len);
+                    rest = remainder;
+                    let valid = |name: &str| {
+                        !name.is_empty()
+                            && name
+                                .chars()
+                                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                    };
+                    match token.split_at(1) {
+                        ("#", id) if valid(id) && compound.id.is_none() => {
+                            compound.id = Some(id.to_owned())
+                        }
+                        (".", class) if valid(class) => compound.classes.push(class.to_owned()),
+                        _ if valid(token) && compound.name.is_none() => {
+                            compound.name = Some(token.to_ascii_lowercase())
+                        }
+                        _ => return Err(invalid(&format!("can't understand {:?}", token))),
+                    }
+                }
+                Ok(compound)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if parts.is_empty() {
+            return Err(invalid("it's empty"));
+        }
+        Ok(Self {
+            source: selector.trim().to_owned(),
+            parts,
+        })
+    }
+
+    /// Finds every node in `document` the selector matches, in document order.
+    pub(crate) fn find<'a>(&self, document: &'a Document) -> Vec<Node<'a>> {
+        let mut nodes: Vec<Node> = document.find(&self.parts[0]).collect();
+        for part in self.parts[1..].iter() {
+            let mut seen = HashSet::new();
+            nodes = nodes
+                .iter()
+                .flat_map(|node| node.find(part))
+                .filter(|node| seen.insert(node.index()))
+                .collect();
+            nodes.sort_by_key(|node| node.index());
+        }
+        nodes
+    }
+}
+
+impl std::fmt::Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Predicate for &Compound {
+    fn matches(&self, node: &Node) -> bool {
+        self.name
+            .as_ref()
+            .is_none_or(|name| node.name() == Some(name))
+            && self
+                .id
+                .as_ref()
+                .is_none_or(|id| node.attr("id") == Some(id))
+            && self
+                .classes
+                .iter()
+                .all(|class| node.is(predicate::Class(class.as_str())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOC_PAGE: &str = include_str!("../../tests/fixtures/wordpress/toc.html");
+    const CHAPTER_PAGE: &str = include_str!("../../tests/fixtures/wordpress/chapter.html");
+
+    fn site() -> WordPressSite {
+        WordPressSite::from_toc_url(
+            "https://www.serial.example.com/table-of-contents/",
+            "#main .entry-content",
+            ".entry-content",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn describes_site_from_toc_url() {
+        let site = site();
+        assert_eq!(site.host, "serial.example.com");
+        assert_eq!(site.toc_path, "/table-of-contents/");
+        assert!(WordPressSite::from_toc_url("https://example.com/", "div..x", "p").is_err());
+    }
+
+    #[test]
+    fn registers_sites_for_their_urls() {
+        register_site(site()).unwrap();
+        let source = StorySource::from_url("https://serial.example.com/2020/01/01/one/").unwrap();
+        assert_eq!(
+            source,
+            StorySource::WordPress {
+                base_url: "https://serial.example.com".to_owned()
+            }
+        );
+        assert_eq!(source.to_id(), "wp:serial.example.com");
+        assert_eq!(site_for(&source).unwrap(), site());
+    }
+
+    #[test]
+    fn parses_table_of_contents() {
+        let source = StorySource::WordPress {
+            base_url: "https://serial.example.com".to_owned(),
+        };
+        let story = parse_toc(source, &site(), TOC_PAGE).unwrap();
+        assert_eq!(story.name, "A Web Serial");
+        assert_eq!(story.authors.to_string(), "Some Writer");
+        assert_eq!(story.description.as_deref(), Some("Things happen, weekly."));
+        assert_eq!(story.language.as_deref(), Some("en-US"));
+        let chapters: Vec<(&str, &str, &str)> = story
+            .all_chapters()
+            .iter()
+            .map(|chapter| {
+                (
+                    chapter.id.as_str(),
+                    chapter.name.as_str(),
+                    chapter.url.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            chapters,
+            vec![
+                (
+                    "wp:serial.example.com:beginning-1-1",
+                    "Beginning 1.1",
+                    "https://serial.example.com/2020/01/01/beginning-1-1/"
+                ),
+                (
+                    "wp:serial.example.com:beginning-1-2",
+                    "Beginning 1.2",
+                    "https://serial.example.com/2020/01/08/beginning-1-2/"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_chapter_between_navigation_links() {
+        let content = Selector::parse(".entry-content").unwrap();
+        let (text, date) = parse_chapter(CHAPTER_PAGE, &content, "chapter").unwrap();
+        assert_eq!(
+            text.as_str(),
+            "<p><b>Content Warnings:</b><br>Peril.</p>\
+             <p>It was a dark and stormy night.</p>\
+             <p>The end.</p>\
+             <p><b>Author's Notes:</b></p><p>Thanks for reading!</p>"
+        );
+        assert_eq!(date.to_rfc3339(), "2020-01-01T12:00:00+00:00");
+        let document = Document::from_read(CHAPTER_PAGE.as_bytes()).unwrap();
+        assert_eq!(
+            next_chapter_url(&document).as_deref(),
+            Some("https://serial.example.com/2020/01/08/beginning-1-2/")
+        );
+    }
+
+    #[test]
+    fn finds_nodes_with_descendant_selectors() {
+        let document = Document::from_read(TOC_PAGE.as_bytes()).unwrap();
+        let links = Selector::parse("#main .entry-content a")
+            .unwrap()
+            .find(&document);
+        assert_eq!(links.len(), 4);
+        assert!(Selector::parse("").is_err());
+        assert!(Selector::parse("div#a#b").is_err());
+    }
+}
@@ -1,250 +1,651 @@
 use async_trait::async_trait;
 use chrono::DateTime;
 use futures::future::join_all;
+use rayon::prelude::*;
 use regex::Regex;
+use reqwest::Client;
 use select::{
     document::Document,
+    node::Node,
     predicate::{self, Predicate},
 };
 
 use crate::{
     client::get,
     error::ArchiveError,
-    parser::Parser,
-    structs::{Author, AuthorList, Chapter, ChapterText, Completed, Content, Story, StorySource},
+    parser::{
+        chapter_html, completion_status, date_or_placeholder, fetch_chapters, parse_blocking,
+        Parser, StatusLabels,
+    },
+    structs::{
+        Author, AuthorList, Chapter, ChapterText, Completed, Content, Section, Story, StorySource,
+    },
+    text::escape_html,
     Result,
 };
 
+use std::collections::{HashMap, HashSet};
+
 static CHAPTER_REGEX: (&str, once_cell::sync::OnceCell<regex::Regex>) =
     (r"#post-(\d+)", once_cell::sync::OnceCell::new());
 static AUTHOR_REGEX: (&str, once_cell::sync::OnceCell<regex::Regex>) =
     (r"/members/(?:.+\.)?(\d+)", once_cell::sync::OnceCell::new());
+static CATEGORY_REGEX: (&str, once_cell::sync::OnceCell<regex::Regex>) = (
+    r"threadmark_category=(\d+)",
+    once_cell::sync::OnceCell::new(),
+);
 
 pub(crate) struct XenforoParser;
 
 #[async_trait]
 impl Parser for XenforoParser {
-    async fn get_skeleton(&self, source: StorySource) -> Result<Story> {
-        let main_page = get(&format!("{}/threadmarks", source.to_url()))
-            .await?
-            .text()
-            .await?;
-        let document = Document::from_read(main_page.as_bytes())?;
+    async fn get_skeleton(&self, client: &Client, source: StorySource) -> Result<Story> {
+        let main = fetch_listing(client, &source, &listing_url(&source, None)).await?;
+        // The main listing only has the default category's threadmarks. Any other categories are
+        // linked from tabs at its top and have listings of their own.
+        let categories = parse_blocking({
+            let first_page = main.pages[0].clone();
+            move || threadmark_categories(&first_page)
+        })
+        .await?;
+        let listings = categories.into_iter().map(|category| {
+            let source = &source;
+            async move {
+                let url = listing_url(source, Some(&category.id));
+                Ok((category, fetch_listing(client, source, &url).await?))
+            }
+        });
+        let categories = join_all(listings)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
 
-        let name = document
-            .find(predicate::Class("threadmarkListingHeader-name"))
+        parse_blocking(move || parse_threadmarks(source, &main, &categories)).await
+    }
 
-            .next()
-            .ok_or(ArchiveError::PageError(format!(
-                "Xenforo: Could not find title (.threadmarkListingHeader-name) for story at {}/threadmarks",
-                source.to_url()
-            )))?
-            .children()
-            .filter(|c| c.name().is_none()) // Text nodes have None for name()
-            .next()
-            .ok_or(ArchiveError::PageError(format!("Xenforo: Could not find text in title (.threadmarkListingHeader-name) for story at {}/threadmarks", source. to_url())))?
-            .text()
-            .replace(" - Threadmarks", "")
-            .trim()
-            .to_owned();
-        let authors: Vec<Result<Author>> = document.find(predicate::Class("username"))
-            .map(|node| {
-                let author_url = node
-                    .attr("href")
-                    .ok_or(
-                        ArchiveError::PageError(
-                            format!(
-                                "Xenforo: Could not find user profile link (.username[href]) for user {} in story at {}/threadmarks",
-                                node.text().trim().to_owned(),
-                                source.to_url())))?;
-                let author_id = AUTHOR_REGEX
-                    .1
-                    .get_or_init(|| Regex::new(AUTHOR_REGEX.0).unwrap())
-                    .captures(author_url)
-                    .unwrap()
-                    .get(1)
-                    .ok_or(ArchiveError::PageError(format!(
-                        "Xenforo: Could not find author id in author link {} for story at {}/threadmarks",
-                        author_url,
-                        source.to_url()
-                    )))?
-                    .as_str();
-                Ok(Author {
-                    id: format!("{}:{}", source.prefix(), author_id),
-                    name: node.text().trim().to_owned(),
-                })
-            })
-            .collect();
-        if authors.iter().find(|res| res.is_err()).is_some() {
-            return Err(authors
-                .into_iter()
-                .find(|res| res.is_err())
-                .unwrap()
-                .unwrap_err());
+    async fn fill_skeleton(&self, client: &Client, mut skeleton: Story) -> Result<Story> {
+        // Each category has its own reader view, so group the chapters still to be filled in by
+        // the category they belong to.
+        let mut wanted = vec![(None, dehydrated_ids(&skeleton.chapters))];
+        for content in skeleton.chapters.iter() {
+            if let Content::Section(section) = content {
+                let category = section_category(&skeleton.source, &section.id);
+                wanted.push((category, dehydrated_ids(&section.chapters)));
+            }
+        }
+        let mut texts = HashMap::new();
+        for (category, chapter_ids) in wanted {
+            // Chapter text comes from the category's reader pages, which all have to be searched,
+            // so only fetch them if there's something left to fill in.
+            if chapter_ids.is_empty() {
+                continue;
+            }
+            texts.extend(
+                reader_texts(
+                    client,
+                    &skeleton.name,
+                    &skeleton.source,
+                    category.as_deref(),
+                    chapter_ids,
+                )
+                .await?,
+            );
+        }
+        for chap in skeleton.all_chapters_mut() {
+            if let Some(text) = texts.remove(&chap.chapter_id()) {
+                chap.text = ChapterText::Hydrated(chapter_html(text));
+            }
         }
-        let authors: Vec<Author> = authors.into_iter().map(|res| res.unwrap()).collect();
 
-        let description = None;
+        Ok(skeleton)
+    }
 
-        let url = source.to_url();
+    async fn get_story(&self, client: &Client, source: StorySource) -> Result<Story> {
+        let story = self.get_skeleton(client, source).await?;
+        self.fill_skeleton(client, story).await
+    }
+}
 
-        let tags = Vec::new();
+/// The pages of one threadmark listing, in order, along with the HTML that fills in each
+/// collapsed run of threadmarks on them, keyed by fetch URL.
+struct Listing {
+    pages: Vec<String>,
+    fillers: HashMap<String, String>,
+}
 
-        let completed = document
-            .find(predicate::Class("pairs--rows"))
-            .find(|node| {
-                node.children()
-                    .find(|c| {
-                        c.is(predicate::Name("dt"))
-                            && c.text().trim().to_lowercase() == "index progress"
-                    })
-                    .is_some()
-            })
-            .map(|node| {
-                match node
-                    .children()
-                    .find(|c| c.is(predicate::Name("dd")))
-                    .map(|c| c.text())
-                    .unwrap_or("not found".to_owned())
-                    .as_ref()
-                {
-                    "Complete" => Completed::Complete,
-                    "Ongoing" => Completed::Incomplete,
-                    _ => Completed::Unknown,
-                }
-            })
-            .unwrap_or(Completed::Unknown);
-
-        let chapters: Vec<Result<Content>> = document.find(predicate::Class("structItem--threadmark"))
-            .map(|node| {
-                let chapter_info = node.descendants().find(|node| node.is(predicate::Class("structItem-title"))).ok_or(
-                    ArchiveError::PageError(format!("Xenforo: Could not find threadmark title container (.structItem-title) for a threadmark for story at {}/threadmarks", source.to_url())))?
-                    .descendants()
-                    .find(|node| node.is(predicate::Name("a").and(predicate::Attr("href", ()))))
-                    .ok_or(ArchiveError::PageError(format!("Xenforo: Could not find threadmark link (.structItem-title a) for a threadmark for story at {}/threadmarks", source.to_url())))?;
-                let chapter_url = chapter_info.attr("href").expect("Should not fail due to filter above.");
-                let chapter_id = CHAPTER_REGEX
-                    .1
-                    .get_or_init(|| Regex::new(CHAPTER_REGEX.0).unwrap())
-                    .captures(chapter_url)
-                    .unwrap()
-                    .get(1)
-                    .ok_or(ArchiveError::PageError(format!(
-                        "Xenforo: Could not find chapter id in chapter link {} for story at {}/threadmarks",
-                        chapter_url,
-                        source.to_url()
-                    )))?
-                    .as_str()
-                    .to_owned();
-                let chapter_url = format!("{}/posts/{}", source.to_base_url(), chapter_id);
-                let chapter_title = chapter_info.text().trim().to_string();
-
-                let time_string = node.descendants().find(|node| node.is(predicate::Name("time").and(predicate::Attr("datetime", ())))).ok_or(
-                    ArchiveError::PageError(format!("Xenforo: Could not find threadmark date posted (structItem--threadmark time[datetime]) for a threadmark for story at {}/threadmarks", source.to_url())))?.attr("datetime").expect("Should not fail due to filter above.");
-                let date_posted = DateTime::parse_from_str(time_string, "%FT%T%z").unwrap_or_else(|_| {
-                    panic!(
-                        "Chapter posted-on date ({}) did not conform to rfc3339",
-                        time_string
-                    )
-                });
-
-                let author_name = node.attr("data-content-author").ok_or(ArchiveError::PageError(format!("Xenforo: Could not find author name (structItem--threadmark.data-content-author for a threadmark for story at {}/threadmarks", source.to_url())))?;
-
-                Ok(Content::Chapter(Chapter {
-                    id: format!("{}:{}", source.to_id(), chapter_id),
-                    name: chapter_title,
-                    description: None,
-                    text: ChapterText::Dehydrated,
-                    url: chapter_url,
-                    date_posted,
-                    author: Some(authors.iter().find(|a| a.name == author_name).ok_or(ArchiveError::PageError(format!("Xenforo: Could not find an author (.username) matching {} for story at {}/threadmarks", author_name, source.to_url())))?.clone()),
-                }))
-            })
-            .collect();
-        if chapters.iter().find(|r| r.is_err()).is_some() {
-            return Err(chapters
-                .into_iter()
-                .find(Result::is_err)
-                .unwrap()
-                .unwrap_err());
-        }
+/// A threadmark category other than the thread's default one, like "Apocrypha".
+#[derive(Debug, PartialEq)]
+struct Category {
+    id: String,
+    name: String,
+}
 
-        Ok(Story {
-            name,
-            authors: AuthorList::from_list(authors),
-            description,
-            url,
-            tags,
-            chapters: chapters.into_iter().map(Result::unwrap).collect(),
-            source,
-            completed,
-        })
+/// The URL of a thread's threadmark listing, either for its default category or for the one with
+/// the given id.
+fn listing_url(source: &StorySource, category: Option<&str>) -> String {
+    match category {
+        Some(category) => format!(
+            "{}/threadmarks?threadmark_category={}",
+            source.to_url(),
+            category
+        ),
+        None => format!("{}/threadmarks", source.to_url()),
+    }
+}
+
+/// The URL of the given page of a thread's reader view, either for its default category or for
+/// the one with the given id.
+fn reader_url(source: &StorySource, category: Option<&str>, page: usize) -> String {
+    let url = format!("{}/reader/page-{}", source.to_url(), page);
+    match category {
+        Some(category) => format!("{}?threadmark_category={}", url, category),
+        None => url,
     }
+}
+
+async fn fetch_listing(
+    client: &Client,
+    source: &StorySource,
+    listing_url: &str,
+) -> Result<Listing> {
+    let first_page = get(client, listing_url).await?.text().await?;
+    let page_count = parse_blocking({
+        let first_page = first_page.clone();
+        let listing_url = listing_url.to_owned();
+        move || page_count(&Document::from_read(first_page.as_bytes())?, &listing_url)
+    })
+    .await?;
+
+    // Long threadmark listings are split over several pages, and each page may collapse a run
+    // of threadmarks into a filler that has to be fetched separately.
+    let separator = if listing_url.contains('?') { '&' } else { '?' };
+    let other_pages = (2..=page_count).map(|num| {
+        let url = format!("{}{}page={}", listing_url, separator, num);
+        async move { Ok(get(client, &url).await?.text().await?) }
+    });
+    let mut pages = vec![first_page];
+    pages.extend(extract_error(join_all(other_pages).await)?);
 
-    async fn fill_skeleton(&self, mut skeleton: Story) -> Result<Story> {
-        let page_list: Vec<String> = {
-            let first_page = get(format!("{}/reader", skeleton.source.to_url()).as_ref())
-                .await?
-                .text()
-                .await?;
-            let first_page = Document::from_read(first_page.as_bytes())?;
-
-            let last_page = first_page.find(predicate::Class("pageNav-main")).next()
-                .map(|node| match node.descendants()
-                        .filter(|d| d.is(predicate::Name("a").and(predicate::Attr("href", ()))))
-                        .last()
-                    {
-                        Some(last_page) => usize::from_str_radix(&last_page.text(), 10).map_err(ArchiveError::from),
-                        None => Err(ArchiveError::PageError(format!(
-                            "Xenforo: Could not find pageNav (.pageNav-main a[href]) for story at {}/reader",
-                            skeleton.source.to_url()
-                        ))),
-                    })
-                .unwrap_or(Ok(1))?;
-            (1..=last_page)
-                .map(|num| format!("{}/reader/page-{}", skeleton.source.to_url(), num))
-                .collect()
+    let filler_list = pages.iter().map(|page| {
+        let page = page.clone();
+        parse_blocking(move || filler_urls(&page))
+    });
+    let filler_list: Vec<String> = extract_error(join_all(filler_list).await)?
+        .into_iter()
+        .flatten()
+        .collect();
+    let base_url = source.to_base_url();
+    let fillers = filler_list.into_iter().map(|fetch_url| {
+        let full_url = if fetch_url.starts_with('/') {
+            format!("{}{}", base_url, fetch_url)
+        } else {
+            fetch_url.clone()
         };
-        let page_list = page_list
-            .into_iter()
-            .map(|p| async move { Ok(get(p.as_ref()).await?.text().await?) });
-        let pages = join_all(page_list).await;
-        let pages = extract_error(pages)?
-            .into_iter()
-            .map(|text| Document::from_read(text.as_bytes()).map_err(ArchiveError::from))
-            .collect();
-        let pages = extract_error(pages)?;
-        let maybe_text: Vec<Result<(&mut Chapter, ChapterText)>> = skeleton
-            .chapters
-            .iter_mut()
-            .filter_map(|content| match content {
-                Content::Section(_) => None,
-                Content::Chapter(chap) => Some(chap),
-            })
-            .map(|chap| {
-                let chapter_id = chap.chapter_id();
-                let selector = format!("js-post-{}", chapter_id);
-                let elem = pages.iter().find(|page| page.find(predicate::Attr("id", selector.as_ref())).next().is_some()).ok_or(
-                    ArchiveError::PageError(format!("Xenforo: could not find a post for chapter with id {chapter_id} (.js-post-{chapter_id}) on any page for story at {}/reader", skeleton.source.to_url())))?;
-                let content = elem.find(predicate::Attr("id", selector.as_ref())).next().unwrap().descendants().find(|d| d.is(predicate::Class("bbWrapper"))).ok_or(
-                    ArchiveError::PageError(format!("Xenforo: could not find text content for post with id {chapter_id} (.js-post-{chapter_id} .bbWrapper) on any page for story at {}/reader", skeleton.source.to_url())))?;
-                Ok((chap, ChapterText::Hydrated(content.inner_html())))
+        async move { Ok((fetch_url, get(client, &full_url).await?.text().await?)) }
+    });
+    let fillers: HashMap<String, String> = extract_error(join_all(fillers).await)?
+        .into_iter()
+        .collect();
+
+    Ok(Listing { pages, fillers })
+}
+
+/// Fetches every page of a thread's reader view for the given category and finds the post body
+/// for each of the given chapter ids on them.
+async fn reader_texts(
+    client: &Client,
+    story_name: &str,
+    source: &StorySource,
+    category: Option<&str>,
+    chapter_ids: Vec<String>,
+) -> Result<HashMap<String, String>> {
+    let first_page = get(client, &reader_url(source, category, 1))
+        .await?
+        .text()
+        .await?;
+    let last_page = parse_blocking({
+        let reader_url = reader_url(source, category, 1);
+        move || page_count(&Document::from_read(first_page.as_bytes())?, &reader_url)
+    })
+    .await?;
+    let page_list = (1..=last_page).map(|num| {
+        let url = reader_url(source, category, num);
+        async move { Ok(get(client, &url).await?.text().await?) }
+    });
+    let pages = extract_error(fetch_chapters(story_name, page_list).await)?;
+    let story_url = source.to_url();
+    parse_blocking(move || find_chapter_texts(&pages, chapter_ids, &story_url)).await
+}
+
+/// The ids of the chapters directly in `contents` that haven't been filled in yet.
+fn dehydrated_ids(contents: &[Content]) -> Vec<String> {
+    contents
+        .iter()
+        .filter_map(|content| match content {
+            Content::Chapter(chap) if matches!(chap.text, ChapterText::Dehydrated) => {
+                Some(chap.chapter_id())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The id of the section holding a thread's threadmarks in the given category.
+fn section_id(source: &StorySource, category: &str) -> String {
+    format!("{}:category:{}", source.to_id(), category)
+}
+
+/// The threadmark category a section was built from, or `None` if it wasn't built from one.
+fn section_category(source: &StorySource, section_id: &str) -> Option<String> {
+    section_id
+        .strip_prefix(&format!("{}:category:", source.to_id()))
+        .map(str::to_owned)
+}
+
+/// Gets the threadmark categories other than the one being shown from the tabs at the top of a
+/// threadmark listing page. Threads with a single category have no tabs.
+fn threadmark_categories(page: &str) -> Result<Vec<Category>> {
+    let document = Document::from_read(page.as_bytes())?;
+    let regex = CATEGORY_REGEX
+        .1
+        .get_or_init(|| Regex::new(CATEGORY_REGEX.0).unwrap());
+    let mut seen = HashSet::new();
+    Ok(document
+        .find(predicate::Name("a").and(predicate::Class("tabs-tab")))
+        .filter(|tab| !tab.is(predicate::Class("is-active")))
+        .filter_map(|tab| {
+            let id = regex
+                .captures(tab.attr("href")?)?
+                .get(1)?
+                .as_str()
+                .to_owned();
+            let name = tab.text().split_whitespace().collect::<Vec<_>>().join(" ");
+            Some(Category { id, name })
+        })
+        .filter(|category| seen.insert(category.id.clone()))
+        .collect())
+}
+
+/// Builds a story skeleton from a thread's threadmark listings. Threadmarks in the default
+/// category are the story's top-level chapters, and every other category becomes a section
+/// after them.
+fn parse_threadmarks(
+    source: StorySource,
+    main: &Listing,
+    categories: &[(Category, Listing)],
+) -> Result<Story> {
+    let main = ParsedListing::new(main)?;
+    let categories = categories
+        .iter()
+        .map(|(category, listing)| Ok((category, ParsedListing::new(listing)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let document = main
+        .documents
+        .first()
+        .ok_or(ArchiveError::PageError(format!(
+            "Xenforo: Got no threadmark pages for story at {}/threadmarks",
+            source.to_url()
+        )))?;
+
+    let name = document
+        .find(predicate::Class("threadmarkListingHeader-name"))
+
+        .next()
+        .ok_or(ArchiveError::PageError(format!(
+            "Xenforo: Could not find title (.threadmarkListingHeader-name) for story at {}/threadmarks",
+            source.to_url()
+        )))?
+        .children().find(|c| c.name().is_none())
+        .ok_or(ArchiveError::PageError(format!("Xenforo: Could not find text in title (.threadmarkListingHeader-name) for story at {}/threadmarks", source. to_url())))?
+        .text()
+        .replace(" - Threadmarks", "")
+        .trim()
+        .to_owned();
+    let authors: Vec<Result<Author>> = std::iter::once(&main)
+        .chain(categories.iter().map(|(_, listing)| listing))
+        .flat_map(|listing| listing.documents.iter().chain(listing.fillers.values()))
+        .flat_map(|document| document.find(predicate::Class("username")))
+        .map(|node| {
+            let author_url = node
+                .attr("href")
+                .ok_or(
+                    ArchiveError::PageError(
+                        format!(
+                            "Xenforo: Could not find user profile link (.username[href]) for user {} in story at {}/threadmarks",
+                            node.text().trim().to_owned(),
+                            source.to_url())))?;
+            let author_id = AUTHOR_REGEX
+                .1
+                .get_or_init(|| Regex::new(AUTHOR_REGEX.0).unwrap())
+                .captures(author_url)
+                .and_then(|captures| captures.get(1))
+                .ok_or(ArchiveError::PageError(format!(
+                    "Xenforo: Could not find author id in author link {} for story at {}/threadmarks",
+                    author_url,
+                    source.to_url()
+                )))?
+                .as_str();
+            Ok(Author {
+                id: format!("{}:{}", source.prefix(), author_id),
+                name: node.text().trim().to_owned(),
             })
+        })
+        .collect();
+    let mut authors: Vec<Author> = extract_error(authors)?;
+    let mut seen_authors = HashSet::new();
+    authors.retain(|author| seen_authors.insert(author.id.clone()));
+
+    let description = None;
+
+    let url = source.to_url();
+
+    let tags = Vec::new();
+
+    let completed = get_completed(document, &source);
+
+    let mut chapters = main.chapters(&source, &authors)?;
+    for (category, listing) in categories.iter() {
+        let category_chapters = listing.chapters(&source, &authors)?;
+        if category_chapters.is_empty() {
+            continue;
+        }
+        chapters.push(Content::Section(Section {
+            id: section_id(&source, &category.id),
+            name: category.name.clone(),
+            description: None,
+            chapters: category_chapters,
+            url: Some(listing_url(&source, Some(&category.id))),
+            author: None,
+        }));
+    }
+
+    Ok(Story {
+        name,
+        authors: AuthorList::from_list(authors)?,
+        description,
+        url,
+        tags,
+        chapters,
+        source,
+        completed,
+        language: None,
+        cover_url: None,
+        published: None,
+        last_updated: None,
+    })
+}
+
+/// A threadmark listing with its pages and fillers parsed.
+struct ParsedListing<'a> {
+    documents: Vec<Document>,
+    fillers: HashMap<&'a str, Document>,
+}
+
+impl<'a> ParsedListing<'a> {
+    fn new(listing: &'a Listing) -> Result<Self> {
+        let documents = listing
+            .pages
+            .iter()
+            .map(|page| Document::from_read(page.as_bytes()).map_err(ArchiveError::from))
             .collect();
+        let documents = extract_error(documents)?;
+        let fillers = extract_error(
+            listing
+                .fillers
+                .iter()
+                .map(|(url, html)| Ok((url.as_str(), Document::from_read(html.as_bytes())?)))
+                .collect(),
+        )?
+        .into_iter()
+        .collect();
+        Ok(Self { documents, fillers })
+    }
 
-        let maybe_text = extract_error(maybe_text)?;
-        maybe_text.into_iter().for_each(|(chap, text)| {
-            chap.text = text;
-        });
+    /// Builds a chapter from every threadmark in the listing, in order.
+    fn chapters(&self, source: &StorySource, authors: &[Author]) -> Result<Vec<Content>> {
+        let mut chapters: Vec<Content> = Vec::new();
+        for document in self.documents.iter() {
+            for node in document.find(threadmark_or_filler()) {
+                if node.is(predicate::Class("structItem--threadmark")) {
+                    chapters.push(parse_threadmark(&node, source, authors)?);
+                    continue;
+                }
+                let filler = node
+                    .find(predicate::Attr("data-fetchurl", ()))
+                    .next()
+                    .and_then(|n| n.attr("data-fetchurl"))
+                    .and_then(|fetch_url| self.fillers.get(fetch_url))
+                    .ok_or(ArchiveError::PageError(format!("Xenforo: Could not load collapsed threadmarks (.structItem--threadmark-filler [data-fetchurl]) for story at {}/threadmarks", source.to_url())))?;
+                for node in filler.find(predicate::Class("structItem--threadmark")) {
+                    chapters.push(parse_threadmark(&node, source, authors)?);
+                }
+            }
+        }
+        Ok(chapters)
+    }
+}
 
-        Ok(skeleton)
+/// Matches threadmark entries in a threadmark listing, along with the placeholders XenForo
+/// leaves where it has collapsed a run of threadmarks to be loaded on demand.
+fn threadmark_or_filler() -> impl Predicate {
+    predicate::Class("structItem--threadmark").or(predicate::Class("structItem--threadmark-filler"))
+}
+
+/// Builds a chapter from a single `.structItem--threadmark` entry in a threadmark listing.
+fn parse_threadmark(node: &Node, source: &StorySource, authors: &[Author]) -> Result<Content> {
+    let chapter_info = node.descendants().find(|node| node.is(predicate::Class("structItem-title"))).ok_or(
+        ArchiveError::PageError(format!("Xenforo: Could not find threadmark title container (.structItem-title) for a threadmark for story at {}/threadmarks", source.to_url())))?
+        .descendants()
+        .find(|node| node.is(predicate::Name("a").and(predicate::Attr("href", ()))))
+        .ok_or(ArchiveError::PageError(format!("Xenforo: Could not find threadmark link (.structItem-title a) for a threadmark for story at {}/threadmarks", source.to_url())))?;
+    let chapter_url = chapter_info
+        .attr("href")
+        .expect("Should not fail due to filter above.");
+    let chapter_id = CHAPTER_REGEX
+        .1
+        .get_or_init(|| Regex::new(CHAPTER_REGEX.0).unwrap())
+        .captures(chapter_url)
+        .and_then(|captures| captures.get(1))
+        .ok_or(ArchiveError::PageError(format!(
+            "Xenforo: Could not find chapter id in chapter link {} for story at {}/threadmarks",
+            chapter_url,
+            source.to_url()
+        )))?
+        .as_str()
+        .to_owned();
+    let chapter_url = format!("{}/posts/{}", source.to_base_url(), chapter_id);
+    let chapter_title = chapter_info.text().trim().to_string();
+
+    let time_string = node.descendants().find(|node| node.is(predicate::Name("time").and(predicate::Attr("datetime", ())))).ok_or(
+        ArchiveError::PageError(format!("Xenforo: Could not find threadmark date posted (structItem--threadmark time[datetime]) for a threadmark for story at {}/threadmarks", source.to_url())))?.attr("datetime").expect("Should not fail due to filter above.");
+    let date_posted = date_or_placeholder(
+        DateTime::parse_from_str(time_string, "%FT%T%z").map_err(ArchiveError::from),
+        &chapter_url,
+    );
+
+    let author_name = node.attr("data-content-author").ok_or(ArchiveError::PageError(format!("Xenforo: Could not find author name (structItem--threadmark.data-content-author for a threadmark for story at {}/threadmarks", source.to_url())))?;
+
+    Ok(Content::Chapter(Chapter {
+        id: format!("{}:{}", source.to_id(), chapter_id),
+        name: chapter_title,
+        description: None,
+        text: ChapterText::Dehydrated,
+        url: chapter_url,
+        date_posted,
+        author: Some(authors.iter().find(|a| a.name == author_name).ok_or(ArchiveError::PageError(format!("Xenforo: Could not find an author (.username) matching {} for story at {}/threadmarks", author_name, source.to_url())))?.clone()),
+    }))
+}
+
+/// Gets the number of pages in a paginated listing from its page navigation, or 1 if there is
+/// no page navigation. `url` is only used in error messages.
+fn page_count(document: &Document, url: &str) -> Result<usize> {
+    document
+        .find(predicate::Class("pageNav-main"))
+        .next()
+        .map(|node| {
+            match node
+                .descendants()
+                .filter(|d| d.is(predicate::Name("a").and(predicate::Attr("href", ()))))
+                .last()
+            {
+                Some(last_page) => last_page
+                    .text()
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(ArchiveError::from),
+                None => Err(ArchiveError::PageError(format!(
+                    "Xenforo: Could not find pageNav (.pageNav-main a[href]) for story at {}",
+                    url
+                ))),
+            }
+        })
+        .unwrap_or(Ok(1))
+}
+
+/// Gets the URLs that load each collapsed run of threadmarks on a threadmark listing page.
+fn filler_urls(page: &str) -> Result<Vec<String>> {
+    let document = Document::from_read(page.as_bytes())?;
+    Ok(document
+        .find(
+            predicate::Class("structItem--threadmark-filler")
+                .descendant(predicate::Attr("data-fetchurl", ())),
+        )
+        .filter_map(|node| node.attr("data-fetchurl"))
+        .map(|url| url.to_owned())
+        .collect())
+}
+
+/// Finds the post body for each of the given chapter ids across the pages of a thread's reader
+/// view, returning the inner HTML of each post keyed by chapter id. Pages are parsed in parallel.
+fn find_chapter_texts(
+    pages: &[String],
+    chapter_ids: Vec<String>,
+    story_url: &str,
+) -> Result<HashMap<String, String>> {
+    let found: Vec<Result<Vec<(String, String)>>> = pages
+        .par_iter()
+        .map(|page| {
+            let document = Document::from_read(page.as_bytes())?;
+            Ok(chapter_ids
+                .iter()
+                .filter_map(|chapter_id| {
+                    let selector = format!("js-post-{}", chapter_id);
+                    document
+                        .find(predicate::Attr("id", selector.as_ref()))
+                        .next()
+                        .map(|post| (chapter_id, post))
+                })
+                .map(|(chapter_id, post)| {
+                    let content = post.descendants().find(|d| d.is(predicate::Class("bbWrapper"))).ok_or(
+                        ArchiveError::PageError(format!("Xenforo: could not find text content for post with id {chapter_id} (.js-post-{chapter_id} .bbWrapper) on any page for story at {}/reader", story_url)))?;
+                    Ok((chapter_id.clone(), expand_bbcode(&content)))
+                })
+                .collect::<Vec<Result<(String, String)>>>())
+        })
+        .map(|page: Result<Vec<Result<(String, String)>>>| extract_error(page?))
+        .collect();
+    let texts: HashMap<String, String> = extract_error(found)?.into_iter().flatten().collect();
+    match chapter_ids.iter().find(|id| !texts.contains_key(*id)) {
+        Some(chapter_id) => Err(ArchiveError::PageError(format!("Xenforo: could not find a post for chapter with id {chapter_id} (.js-post-{chapter_id}) on any page for story at {}/reader", story_url))),
+        None => Ok(texts),
     }
+}
 
-    async fn get_story(&self, source: StorySource) -> Result<Story> {
-        let story = self.get_skeleton(source).await?;
-        self.fill_skeleton(story).await
+/// Returns the inner HTML of a post body with its BBCode blocks made readable without the
+/// forum's scripts: spoilers become `<details>` labeled with their title, and quotes become
+/// blockquotes headed by who said them.
+fn expand_bbcode(post: &Node) -> String {
+    let mut html = String::new();
+    for child in post.children() {
+        push_expanded(&mut html, &child);
     }
+    html
 }
+
+fn push_expanded(html: &mut String, node: &Node) {
+    let block = predicate::Class("bbCodeSpoiler").or(predicate::Class("bbCodeBlock--quote"));
+    if node.is(predicate::Class("bbCodeSpoiler")) {
+        let title = node
+            .find(predicate::Class("bbCodeSpoiler-button-title"))
+            .next()
+            .map(|title| title.text().trim().to_owned())
+            .filter(|title| !title.is_empty());
+        let label = match title {
+            Some(title) => format!("Spoiler: {}", title),
+            None => "Spoiler".to_owned(),
+        };
+        html.push_str(&format!(
+            "<details class=\"spoiler\" open><summary>{}</summary>",
+            escape_html(&label, false)
+        ));
+        if let Some(content) = node.find(predicate::Class("bbCodeSpoiler-content")).next() {
+            for child in content.children() {
+                push_expanded(html, &child);
+            }
+        }
+        html.push_str("</details>");
+    } else if node.is(predicate::Class("bbCodeBlock--quote")) {
+        html.push_str("<blockquote class=\"quote\">");
+        let attribution = match node.attr("data-quote").map(str::trim) {
+            Some(name) if !name.is_empty() => Some(format!("{} said:", name)),
+            _ => node
+                .find(predicate::Class("bbCodeBlock-title"))
+                .next()
+                .map(|title| title.text().trim().to_owned())
+                .filter(|title| !title.is_empty()),
+        };
+        if let Some(attribution) = attribution {
+            html.push_str(&format!(
+                "<p class=\"quote-attribution\"><strong>{}</strong></p>",
+                escape_html(&attribution, false)
+            ));
+        }
+        let content = node
+            .find(predicate::Class("bbCodeBlock-expandContent"))
+            .next()
+            .or_else(|| node.find(predicate::Class("bbCodeBlock-content")).next());
+        if let Some(content) = content {
+            for child in content.children() {
+                push_expanded(html, &child);
+            }
+        }
+        html.push_str("</blockquote>");
+    } else if node.find(block).next().is_some() {
+        let name = node.name().unwrap_or_default();
+        html.push('<');
+        html.push_str(name);
+        for (attr, value) in node.attrs() {
+            html.push_str(&format!(" {}=\"{}\"", attr, escape_html(value, true)));
+        }
+        html.push('>');
+        for child in node.children() {
+            push_expanded(html, &child);
+        }
+        html.push_str(&format!("</{}>", name));
+    } else {
+        html.push_str(&node.html());
+    }
+}
+
+/// The labels Xenforo forums put under "Index progress" on the threadmarks page.
+const STATUS_LABELS: StatusLabels = StatusLabels {
+    complete: &["Complete"],
+    incomplete: &["Ongoing", "Hiatus"],
+};
+
+fn get_completed(document: &Document, source: &StorySource) -> Completed {
+    let label = document
+        .find(predicate::Class("pairs--rows"))
+        .find(|node| {
+            node.children().any(|c| {
+                c.is(predicate::Name("dt")) && c.text().trim().to_lowercase() == "index progress"
+            })
+        })
+        .and_then(|node| node.children().find(|c| c.is(predicate::Name("dd"))))
+        .map(|dd| dd.text());
+    completion_status(label.as_deref(), &STATUS_LABELS, source)
+}
+
 fn extract_error<O: core::fmt::Debug>(list: Vec<Result<O>>) -> Result<Vec<O>> {
     if list.iter().find(|i| i.is_err()).is_some() {
         Err(list.into_iter().find(Result::is_err).unwrap().unwrap_err())
@@ -252,3 +653,179 @@ fn extract_error<O: core::fmt::Debug>(list: Vec<Result<O>>) -> Result<Vec<O>> {
         Ok(list.into_iter().map(Result::unwrap).collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_1: &str = include_str!("../../tests/fixtures/xenforo/threadmarks_page_1.html");
+    const PAGE_2: &str = include_str!("../../tests/fixtures/xenforo/threadmarks_page_2.html");
+    const FILLER: &str = include_str!("../../tests/fixtures/xenforo/threadmarks_filler.html");
+    const CATEGORY: &str = include_str!("../../tests/fixtures/xenforo/threadmarks_category.html");
+    const FILLER_URL: &str = "/threads/a-long-quest.1234/threadmarks-load-range?min=2&max=4";
+
+    #[test]
+    fn finds_threadmark_page_count() {
+        let document = Document::from_read(PAGE_1.as_bytes()).unwrap();
+        assert_eq!(page_count(&document, "threadmarks").unwrap(), 2);
+        let document = Document::from_read(FILLER.as_bytes()).unwrap();
+        assert_eq!(page_count(&document, "threadmarks").unwrap(), 1);
+    }
+
+    #[test]
+    fn finds_filler_urls() {
+        assert_eq!(filler_urls(PAGE_1).unwrap(), vec![FILLER_URL.to_owned()]);
+        assert!(filler_urls(PAGE_2).unwrap().is_empty());
+    }
+
+    fn main_listing() -> Listing {
+        Listing {
+            pages: vec![PAGE_1.to_owned(), PAGE_2.to_owned()],
+            fillers: HashMap::from([(FILLER_URL.to_owned(), FILLER.to_owned())]),
+        }
+    }
+
+    #[test]
+    fn collects_threadmarks_from_every_page_and_filler() {
+        let source = StorySource::SpaceBattles("1234".to_owned());
+        let story = parse_threadmarks(source, &main_listing(), &[]).unwrap();
+
+        assert_eq!(story.name, "A Long Quest");
+        assert_eq!(story.authors.len(), 1);
+        let names: Vec<&str> = story
+            .chapters
+            .iter()
+            .map(|content| match content {
+                Content::Chapter(chapter) => chapter.name.as_str(),
+                Content::Section(section) => section.name.as_str(),
+            })
+            .collect();
+        assert_eq!(
+            names,
+            (1..=7)
+                .map(|num| format!("Chapter {}", num))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn missing_filler_is_an_error() {
+        let source = StorySource::SpaceBattles("1234".to_owned());
+        let listing = Listing {
+            pages: vec![PAGE_1.to_owned()],
+            fillers: HashMap::new(),
+        };
+        assert!(parse_threadmarks(source, &listing, &[]).is_err());
+    }
+
+    #[test]
+    fn unreadable_dates_get_the_placeholder() {
+        let source = StorySource::SpaceBattles("1234".to_owned());
+        let listing = Listing {
+            pages: vec![PAGE_2.replacen("datetime=\"", "datetime=\"sometime ", 1)],
+            fillers: HashMap::new(),
+        };
+        let story = parse_threadmarks(source, &listing, &[]).unwrap();
+        let chapters = story.all_chapters();
+        assert!(chapters[0].has_placeholder_date());
+        assert!(!chapters[1].has_placeholder_date());
+    }
+
+    #[test]
+    fn finds_other_threadmark_categories() {
+        assert_eq!(
+            threadmark_categories(PAGE_1).unwrap(),
+            vec![Category {
+                id: "16".to_owned(),
+                name: "Apocrypha".to_owned()
+            }]
+        );
+        assert!(threadmark_categories(PAGE_2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn groups_other_categories_into_sections() {
+        let source = StorySource::SpaceBattles("1234".to_owned());
+        let apocrypha = Category {
+            id: "16".to_owned(),
+            name: "Apocrypha".to_owned(),
+        };
+        let listing = Listing {
+            pages: vec![CATEGORY.to_owned()],
+            fillers: HashMap::new(),
+        };
+        let story =
+            parse_threadmarks(source.clone(), &main_listing(), &[(apocrypha, listing)]).unwrap();
+
+        assert_eq!(story.chapters.len(), 8);
+        assert_eq!(story.num_chapters(), 8);
+        assert_eq!(story.authors.len(), 2);
+        let section = match story.chapters.last() {
+            Some(Content::Section(section)) => section,
+            other => panic!("expected a section, got {:?}", other),
+        };
+        assert_eq!(section.id, "sb:1234:category:16");
+        assert_eq!(section.name, "Apocrypha");
+        assert_eq!(
+            section.url.as_deref(),
+            Some("https://forums.spacebattles.com/threads/1234/threadmarks?threadmark_category=16")
+        );
+        assert_eq!(
+            section_category(&source, &section.id).as_deref(),
+            Some("16")
+        );
+        match section.chapters.as_slice() {
+            [Content::Chapter(chapter)] => {
+                assert_eq!(chapter.id, "sb:1234:2001");
+                assert_eq!(chapter.name, "Omake: The Party");
+                assert_eq!(chapter.author.as_ref().unwrap().name, "A Reader");
+            }
+            other => panic!("expected one chapter, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn maps_index_progress() {
+        let source = StorySource::SpaceBattles("1234".to_owned());
+        let completed = |progress: &str| {
+            let page = format!(
+                "<dl class=\"pairs pairs--rows\"><dt>Index progress</dt><dd>{}</dd></dl>",
+                progress
+            );
+            get_completed(&Document::from_read(page.as_bytes()).unwrap(), &source)
+        };
+        assert_eq!(completed("Complete"), Completed::Complete);
+        assert_eq!(completed("Ongoing"), Completed::Incomplete);
+        assert_eq!(completed("Hiatus"), Completed::Incomplete);
+        assert_eq!(completed("Cancelled"), Completed::Unknown);
+        let document = Document::from_read("<p>No index</p>".as_bytes()).unwrap();
+        assert_eq!(get_completed(&document, &source), Completed::Unknown);
+    }
+
+    #[test]
+    fn expands_spoilers_and_quotes() {
+        let post = "<div class=\"bbWrapper\"><p>Vote:</p>\
+            <div class=\"bbCodeSpoiler\"><button class=\"bbCodeSpoiler-button\"><span>Spoiler: \
+            <span class=\"bbCodeSpoiler-button-title\">Stats &amp; skills</span></span></button>\
+            <div class=\"bbCodeSpoiler-content\"><div class=\"bbCodeBlock bbCodeBlock--spoiler\">\
+            <div class=\"bbCodeBlock-content\">STR 10\
+            <blockquote data-quote=\"A Reader\" class=\"bbCodeBlock bbCodeBlock--expandable bbCodeBlock--quote\">\
+            <div class=\"bbCodeBlock-title\"><a href=\"/goto/post?id=1\">A Reader said:</a></div>\
+            <div class=\"bbCodeBlock-content\"><div class=\"bbCodeBlock-expandContent\">More <b>stats</b>!</div>\
+            <div class=\"bbCodeBlock-expandLink\"><a>Click to expand...</a></div></div></blockquote>\
+            </div></div></div></div>\
+            <div class=\"bbCodeSpoiler\"><button class=\"bbCodeSpoiler-button\">Spoiler</button>\
+            <div class=\"bbCodeSpoiler-content\">Untitled</div></div></div>";
+        let document = Document::from_read(post.as_bytes()).unwrap();
+        let wrapper = document.find(predicate::Class("bbWrapper")).next().unwrap();
+        assert_eq!(
+            expand_bbcode(&wrapper),
+            "<p>Vote:</p>\
+            <details class=\"spoiler\" open><summary>Spoiler: Stats &amp; skills</summary>\
+            <div class=\"bbCodeBlock bbCodeBlock--spoiler\"><div class=\"bbCodeBlock-content\">STR 10\
+            <blockquote class=\"quote\"><p class=\"quote-attribution\"><strong>A Reader said:</strong></p>\
+            More <b>stats</b>!</blockquote></div></div></details>\
+            <details class=\"spoiler\" open><summary>Spoiler</summary>Untitled</details>"
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use futures::future::join_all;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use once_cell::sync::Lazy;
+
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Every progress bar is drawn through this, so that bars for several stories being fetched at
+/// once stack up instead of overwriting each other. Nothing is drawn when stderr isn't a terminal.
+static BARS: Lazy<MultiProgress> =
+    Lazy::new(|| MultiProgress::with_draw_target(ProgressDrawTarget::stderr()));
+
+/// Turns progress bars on or off. They are on unless this is called with `false`.
+pub(crate) fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Runs `f` with any progress bars cleared from the screen, so that what it prints doesn't end
+/// up tangled with them. They are drawn again afterwards.
+pub(crate) fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    BARS.suspend(f)
+}
+
+/// Waits for all of `futures` like `join_all`, showing a bar labelled with `message` that
+/// advances as each one finishes. A single future doesn't get a bar.
+pub(crate) async fn join_counted<F: Future>(
+    message: impl Into<String>,
+    futures: impl IntoIterator<Item = F>,
+) -> Vec<F::Output> {
+    let futures: Vec<F> = futures.into_iter().collect();
+    if futures.len() < 2 || !ENABLED.load(Ordering::Relaxed) {
+        return join_all(futures).await;
+    }
+    // The bar is a handle to shared state, so every future can advance the same one.
+    let bar = BARS.add(
+        ProgressBar::new(futures.len() as u64)
+            .with_style(
+                ProgressStyle::with_template("{msg} [{bar:30}] {pos} of {len}")
+                    .expect("progress template should be valid")
+                    .progress_chars("=> "),
+            )
+            .with_message(message.into()),
+    );
+    let results = join_all(futures.into_iter().map(|future| {
+        let bar = bar.clone();
+        async move {
+            let output = future.await;
+            bar.inc(1);
+            output
+        }
+    }))
+    .await;
+    bar.finish_and_clear();
+    BARS.remove(&bar);
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn keeps_results_in_order() {
+        let delays = [30, 10, 20];
+        let results = join_counted(
+            "Testing",
+            delays.iter().map(|&ms| async move {
+                tokio::time::sleep(std::time::Duration::from_millis(ms)).await;
+                ms
+            }),
+        )
+        .await;
+        assert_eq!(results, delays);
+    }
+}
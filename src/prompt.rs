@@ -0,0 +1,76 @@
+use std::io::{self, IsTerminal};
+
+use crate::output;
+use crate::sql::Database;
+use crate::structs::Story;
+use crate::Result;
+
+/// Asks a yes/no question on stdin. Anything other than an explicit "y" or "yes" is a no.
+pub(crate) fn confirm(question: &str) -> Result<bool> {
+    output::prompt(&format!("{} [y/N] ", question))?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Resolves a search to the id of a single story in the archive.
+///
+/// If more than one story matches, the candidates are listed and, when `interactive` is set and
+/// stdin is a terminal, the user is asked to pick one by number. Otherwise the candidates are
+/// printed and `None` is returned so that nothing is acted on by guesswork. `None` is also
+/// returned when nothing matches or the user declines to choose.
+pub(crate) fn resolve_story(
+    search: &str,
+    interactive: bool,
+    db: &Database,
+) -> Result<Option<String>> {
+    let matches = db.fuzzy_get_story(search)?;
+    match matches.len() {
+        0 => {
+            status!("No matching stories found. Please try another search.");
+            Ok(None)
+        }
+        1 => Ok(matches.into_iter().next()),
+        count => {
+            status!("{} stories match \"{}\":", count, search);
+            for (idx, id) in matches.iter().enumerate() {
+                match db.get_story_by_id(id)? {
+                    Some(story) => status!("  {}. {}", idx + 1, describe(&story)),
+                    None => status!("  {}. {}", idx + 1, id),
+                }
+            }
+            if !interactive || !io::stdin().is_terminal() {
+                status!("Refine your search or use a story's ID to select just one.");
+                return Ok(None);
+            }
+            loop {
+                output::prompt(&format!(
+                    "Choose a story [1-{}], or press enter to cancel: ",
+                    count
+                ))?;
+                let mut answer = String::new();
+                io::stdin().read_line(&mut answer)?;
+                let answer = answer.trim();
+                if answer.is_empty() {
+                    return Ok(None);
+                }
+                match answer.parse::<usize>() {
+                    Ok(choice) if (1..=count).contains(&choice) => {
+                        return Ok(matches.into_iter().nth(choice - 1));
+                    }
+                    _ => status!("\"{}\" is not one of the listed stories.", answer),
+                }
+            }
+        }
+    }
+}
+
+/// A one-line description of a story: its title, authors, and id.
+pub(crate) fn describe(story: &Story) -> String {
+    format!(
+        "\"{}\" by {} ({})",
+        story.name,
+        story.authors,
+        story.source.to_id()
+    )
+}
@@ -0,0 +1,377 @@
+use chrono::{SecondsFormat, Utc};
+use hyper::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::Url;
+
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::export::{file_stem, story_to_epub, xml_text, StoryMedia};
+use crate::feed::{chapters_feed, DEFAULT_LENGTH};
+use crate::sql::Database;
+use crate::structs::{Completed, ListedStory};
+use crate::Result;
+
+/// How many stories are listed on each page of the catalog.
+const PAGE_SIZE: usize = 50;
+
+const CATALOG_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=acquisition";
+const SEARCH_TYPE: &str = "application/opensearchdescription+xml";
+
+/// Serves the archive as an OPDS catalog on `addr` until interrupted, so that e-readers can
+/// browse it and download stories as EPUBs. The catalog is at `/opds`, and a feed of new
+/// chapters at `/feed.xml`.
+pub(crate) async fn serve(db: Database, addr: SocketAddr) -> Result<()> {
+    // The connection can't be shared between requests, so they take turns with it.
+    let db = Arc::new(Mutex::new(db));
+    let make_service = make_service_fn(move |_| {
+        let db = db.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let db = db.clone();
+                async move { Ok::<_, Infallible>(respond(db, &req).await) }
+            }))
+        }
+    });
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    status!(
+        "Serving the archive at http://{}/opds. Press Ctrl-C to stop.",
+        addr
+    );
+    server
+        .with_graceful_shutdown(crate::shutdown_signal())
+        .await?;
+    Ok(())
+}
+
+async fn respond(db: Arc<Mutex<Database>>, req: &Request<Body>) -> Response<Body> {
+    // Only the path and query matter, so any host will do for parsing.
+    let url = match Url::parse(&format!("http://localhost{}", req.uri())) {
+        Ok(url) => url,
+        Err(_) => return status_response(StatusCode::BAD_REQUEST),
+    };
+    if req.method() != Method::GET {
+        return status_response(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    let path = url.path().to_owned();
+    // Waiting for the database and building a large story's EPUB can take a while, so it's done
+    // on the blocking thread pool, where it doesn't hold up other requests.
+    let routed = tokio::task::spawn_blocking(move || {
+        let db = match db.lock() {
+            Ok(db) => db,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        route(&db, &url)
+    })
+    .await;
+    match routed {
+        Ok(Ok(response)) => response,
+        Ok(Err(err)) => {
+            log::error!("Could not serve {}: {}", path, err);
+            status_response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        Err(err) => {
+            log::error!("Serving {} failed: {}", path, err);
+            status_response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn route(db: &Database, url: &Url) -> Result<Response<Body>> {
+    let path = url.path();
+    if path == "/" || path == "/opds" {
+        let query = query_param(url, "q");
+        let page = query_param(url, "page")
+            .and_then(|page| page.parse::<usize>().ok())
+            .unwrap_or(1)
+            .max(1);
+        let mut stories = match query {
+            Some(ref query) => {
+                let matches: HashSet<String> = db.fuzzy_get_story(query)?.into_iter().collect();
+                db.get_all_stories()?
+                    .into_iter()
+                    .filter(|story| matches.contains(&story.source.to_id()))
+                    .collect()
+            }
+            None => db.get_all_stories()?,
+        };
+        stories.sort_by_cached_key(|story| story.name.to_lowercase());
+        let shown = stories.iter().skip((page - 1) * PAGE_SIZE).take(PAGE_SIZE);
+        let mut covered = HashSet::new();
+        for story in shown {
+            let id = story.source.to_id();
+            if db.saved_cover_url(&id)?.is_some() {
+                covered.insert(id);
+            }
+        }
+        let feed = catalog_feed(&stories, &covered, page, query.as_deref());
+        return Ok(body_response(CATALOG_TYPE, feed.into_bytes()));
+    }
+    if path == "/feed.xml" {
+        let feed = chapters_feed(&db.recent_chapters(DEFAULT_LENGTH)?);
+        return Ok(body_response("application/atom+xml", feed.into_bytes()));
+    }
+    if path == "/opds/search.xml" {
+        return Ok(body_response(
+            SEARCH_TYPE,
+            SEARCH_DESCRIPTION.as_bytes().to_vec(),
+        ));
+    }
+    if let Some(id) = story_id(path, ".epub") {
+        let story = match db.get_story_by_id(&id)? {
+            Some(story) => story,
+            None => return Ok(status_response(StatusCode::NOT_FOUND)),
+        };
+        let media = StoryMedia {
+            cover: db.get_cover(&id)?,
+            images: db.get_story_images(&id)?,
+        };
+        let mut response = body_response("application/epub+zip", story_to_epub(&story, &media));
+        let disposition = format!(
+            "attachment; filename*=UTF-8''{}.epub",
+            utf8_percent_encode(&file_stem(&story), NON_ALPHANUMERIC)
+        );
+        if let Ok(value) = disposition.parse() {
+            response.headers_mut().insert(CONTENT_DISPOSITION, value);
+        }
+        return Ok(response);
+    }
+    if let Some(id) = story_id(path, "/cover") {
+        return Ok(match db.get_cover(&id)? {
+            Some(cover) => body_response(&cover.media_type, cover.bytes),
+            None => status_response(StatusCode::NOT_FOUND),
+        });
+    }
+    Ok(status_response(StatusCode::NOT_FOUND))
+}
+
+/// The decoded value of the first query parameter called `name`, if it has one.
+fn query_param(url: &Url, name: &str) -> Option<String> {
+    url.query_pairs()
+        .find(|(key, value)| key == name && !value.is_empty())
+        .map(|(_, value)| value.into_owned())
+}
+
+/// The story id in a path like `/opds/stories/<id><suffix>`, as written by [`story_path`].
+fn story_id(path: &str, suffix: &str) -> Option<String> {
+    let encoded = path.strip_prefix("/opds/stories/")?.strip_suffix(suffix)?;
+    if encoded.is_empty() || encoded.contains('/') {
+        return None;
+    }
+    let id = percent_decode_str(encoded).decode_utf8().ok()?;
+    Some(id.into_owned())
+}
+
+/// The path of a story's EPUB or cover. Ids can contain `:` and `/`, so they are
+/// percent-encoded to keep them to one path segment.
+fn story_path(id: &str, suffix: &str) -> String {
+    format!(
+        "/opds/stories/{}{}",
+        utf8_percent_encode(id, NON_ALPHANUMERIC),
+        suffix
+    )
+}
+
+/// The link to a page of the catalog, keeping the search that's being paged through.
+fn page_link(page: usize, query: Option<&str>) -> String {
+    let mut params = Vec::new();
+    if let Some(query) = query {
+        params.push(format!(
+            "q={}",
+            utf8_percent_encode(query, NON_ALPHANUMERIC)
+        ));
+    }
+    if page > 1 {
+        params.push(format!("page={}", page));
+    }
+    match params.is_empty() {
+        true => "/opds".to_owned(),
+        false => format!("/opds?{}", params.join("&amp;")),
+    }
+}
+
+/// Builds an OPDS acquisition feed listing one page of `stories`. `covered` holds the ids of the
+/// stories on the page that have a saved cover.
+fn catalog_feed(
+    stories: &[ListedStory],
+    covered: &HashSet<String>,
+    page: usize,
+    query: Option<&str>,
+) -> String {
+    let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    let title = match query {
+        Some(query) => format!("Stories matching \"{}\"", query),
+        None => "fic_archive".to_owned(),
+    };
+    let mut feed = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <feed xmlns=\"http://www.w3.org/2005/Atom\" xmlns:dc=\"http://purl.org/dc/terms/\" \
+        xmlns:opensearch=\"http://a9.com/-/spec/opensearch/1.1/\">\n\
+        <id>urn:fic_archive:catalog</id>\n\
+        <title>{}</title>\n\
+        <updated>{}</updated>\n\
+        <link rel=\"self\" href=\"{}\" type=\"{CATALOG_TYPE}\"/>\n\
+        <link rel=\"start\" href=\"/opds\" type=\"{CATALOG_TYPE}\"/>\n\
+        <link rel=\"search\" href=\"/opds/search.xml\" type=\"{SEARCH_TYPE}\"/>\n",
+        xml_text(&title),
+        now,
+        page_link(page, query),
+    );
+    if page > 1 {
+        feed.push_str(&format!(
+            "<link rel=\"previous\" href=\"{}\" type=\"{CATALOG_TYPE}\"/>\n",
+            page_link(page - 1, query)
+        ));
+    }
+    if stories.len() > page * PAGE_SIZE {
+        feed.push_str(&format!(
+            "<link rel=\"next\" href=\"{}\" type=\"{CATALOG_TYPE}\"/>\n",
+            page_link(page + 1, query)
+        ));
+    }
+    feed.push_str(&format!(
+        "<opensearch:totalResults>{}</opensearch:totalResults>\n\
+        <opensearch:itemsPerPage>{}</opensearch:itemsPerPage>\n\
+        <opensearch:startIndex>{}</opensearch:startIndex>\n",
+        stories.len(),
+        PAGE_SIZE,
+        (page - 1) * PAGE_SIZE + 1
+    ));
+    for story in stories.iter().skip((page - 1) * PAGE_SIZE).take(PAGE_SIZE) {
+        push_entry(
+            &mut feed,
+            story,
+            covered.contains(&story.source.to_id()),
+            &now,
+        );
+    }
+    feed.push_str("</feed>\n");
+    feed
+}
+
+fn push_entry(feed: &mut String, story: &ListedStory, has_cover: bool, now: &str) {
+    let id = story.source.to_id();
+    let updated = story
+        .dates
+        .updated_at
+        .or(story.dates.added_at)
+        .map(|date| date.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_else(|| now.to_owned());
+    let status = match story.completed {
+        Completed::Complete => ", complete",
+        Completed::Incomplete => ", ongoing",
+        Completed::Unknown => "",
+    };
+    feed.push_str(&format!(
+        "<entry>\n<title>{}</title>\n<id>urn:fic_archive:story:{}</id>\n<updated>{}</updated>\n\
+        <author><name>{}</name></author>\n",
+        xml_text(&story.name),
+        xml_text(&id),
+        updated,
+        xml_text(&story.author)
+    ));
+    if let Some(ref language) = story.language {
+        feed.push_str(&format!(
+            "<dc:language>{}</dc:language>\n",
+            xml_text(language)
+        ));
+    }
+    feed.push_str(&format!(
+        "<content type=\"text\">{} chapters{}.</content>\n",
+        story.chapter_count, status
+    ));
+    if has_cover {
+        let cover = story_path(&id, "/cover");
+        feed.push_str(&format!(
+            "<link rel=\"http://opds-spec.org/image\" href=\"{0}\"/>\n\
+            <link rel=\"http://opds-spec.org/image/thumbnail\" href=\"{0}\"/>\n",
+            cover
+        ));
+    }
+    feed.push_str(&format!(
+        "<link rel=\"http://opds-spec.org/acquisition\" href=\"{}\" type=\"application/epub+zip\"/>\n\
+        <link rel=\"alternate\" href=\"{}\" type=\"text/html\"/>\n</entry>\n",
+        story_path(&id, ".epub"),
+        xml_text(&story.source.to_url())
+    ));
+}
+
+/// Tells e-readers how to search the catalog.
+const SEARCH_DESCRIPTION: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/">
+<ShortName>fic_archive</ShortName>
+<Description>Search stories by name, author, or ID</Description>
+<Url type="application/atom+xml;profile=opds-catalog;kind=acquisition" template="/opds?q={searchTerms}"/>
+</OpenSearchDescription>
+"#;
+
+fn body_response(content_type: &str, body: Vec<u8>) -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .unwrap_or_else(|_| status_response(StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::from(status.to_string()));
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::{StoryDates, StorySource};
+
+    fn listed(id: usize) -> ListedStory {
+        ListedStory {
+            name: format!("Story {:03}", id),
+            author: "Someone & Co".to_owned(),
+            chapter_count: 3,
+            source: StorySource::AO3(id.to_string()),
+            completed: Completed::Complete,
+            language: Some("en".to_owned()),
+            dates: StoryDates::default(),
+        }
+    }
+
+    #[test]
+    fn story_paths_round_trip() {
+        for id in ["ao3:1", "katalepsis", "wordpress:example.com/serial"] {
+            assert_eq!(
+                story_id(&story_path(id, ".epub"), ".epub").as_deref(),
+                Some(id)
+            );
+            assert_eq!(
+                story_id(&story_path(id, "/cover"), "/cover").as_deref(),
+                Some(id)
+            );
+        }
+        assert_eq!(story_id("/opds/stories/a/b.epub", ".epub"), None);
+        assert_eq!(story_id("/opds/stories/.epub", ".epub"), None);
+    }
+
+    #[test]
+    fn pages_through_the_catalog() {
+        let stories: Vec<ListedStory> = (1..=PAGE_SIZE + 5).map(listed).collect();
+        let covered = HashSet::from(["ao3:2".to_owned()]);
+
+        let first = catalog_feed(&stories, &covered, 1, None);
+        assert_eq!(first.matches("<entry>").count(), PAGE_SIZE);
+        assert!(first.contains("<link rel=\"next\" href=\"/opds?page=2\""));
+        assert!(!first.contains("rel=\"previous\""));
+        assert!(first.contains("<name>Someone &amp; Co</name>"));
+        assert!(first.contains("href=\"/opds/stories/ao3%3A1.epub\""));
+        assert_eq!(first.matches("/opds/stories/ao3%3A2/cover").count(), 2);
+
+        let last = catalog_feed(&stories, &covered, 2, Some("a b"));
+        assert_eq!(last.matches("<entry>").count(), 5);
+        assert!(last.contains("<link rel=\"previous\" href=\"/opds?q=a%20b\""));
+        assert!(!last.contains("rel=\"next\""));
+        assert!(last.contains("<opensearch:startIndex>51</opensearch:startIndex>"));
+    }
+}
@@ -1,81 +1,159 @@
-use chrono::DateTime;
-use once_cell::sync::OnceCell;
-use rayon::prelude::ParallelSliceMut;
-use rusqlite::{types::Type, Connection, Error, OptionalExtension, Row};
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
+use rusqlite::{types::Type, Connection, Error, OptionalExtension, Row, ToSql};
 
-use std::ops::{Deref, DerefMut};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::path::Path;
-use std::sync::Mutex;
+use std::time::Duration;
 
 use crate::error::ArchiveError;
+use crate::parser::date_or_placeholder;
+use crate::parser::wordpress::{self, WordPressSite};
 use crate::structs::{
-    Author, AuthorList, Chapter, ChapterText, Completed, Content, ListedStory, Section, Story,
-    StorySource,
+    Author, AuthorList, Chapter, ChapterImage, ChapterText, Completed, Content, Cover,
+    DeletedStory, ListedStory, NewChapter, ReadingProgress, SearchHit, Section, Story, StoryDates,
+    StorySource, TagCount, USER_TAG_NAMESPACE,
 };
+use crate::text::{count_words, html_to_text};
 use crate::Result;
 
-static AUTHOR_LIST_SEPARATOR: char = ';';
+/// Narrows down which stories are listed. Every filter that is set must match.
+#[derive(Debug, Clone, Default)]
+pub struct StoryFilter {
+    /// Only stories in this language, compared case-insensitively.
+    pub language: Option<String>,
+    /// Only stories with every one of these tags, compared case-insensitively. Namespaced tags
+    /// like `character:Someone` must be given with their namespace.
+    pub tags: Vec<String>,
+    /// Only stories with this completion status.
+    pub completed: Option<Completed>,
+}
+
+/// Connection settings for the database file.
+#[derive(Debug, Clone)]
+pub struct DatabaseOptions {
+    /// How long to wait for another connection to finish writing before giving up with
+    /// "database is locked".
+    pub busy_timeout: Duration,
+    /// Whether to use SQLite's write-ahead log, which lets reads carry on while something else
+    /// is writing. While the database is open this keeps two extra files next to it, with `-wal`
+    /// and `-shm` appended to its name. They are folded back in when the last connection closes
+    /// and should be kept alongside the database if it's copied while in use.
+    pub wal: bool,
+    /// How many chapters to save before committing when saving a story, so that saving a very
+    /// large story doesn't keep the database locked until it's done. If the save fails partway
+    /// through, the batches committed so far stay saved. 0 saves each story in one transaction.
+    pub save_batch_size: usize,
+}
 
-static DB_INITIALIZED: OnceCell<Mutex<bool>> = OnceCell::new();
+impl Default for DatabaseOptions {
+    fn default() -> Self {
+        Self {
+            busy_timeout: Duration::from_secs(5),
+            wal: true,
+            save_batch_size: 500,
+        }
+    }
+}
 
 pub struct Database {
     conn: Connection,
+    dedup_text: bool,
+    save_batch_size: usize,
 }
 
 impl Database {
-    pub fn new(path: &str) -> Result<Self> {
+    pub fn new(path: &str, options: &DatabaseOptions) -> Result<Self> {
         let file_exists = Path::new(path).try_exists()?;
         if !file_exists {
-            println!("Database file at {} does not exist. Creating...", path);
+            log::info!("Database file at {} does not exist. Creating...", path);
         }
-        let this = Self {
-            conn: Connection::open(path)?,
-        };
-        this.init()?;
-        Ok(this)
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(options.busy_timeout)?;
+        conn.pragma_update_and_check(
+            None,
+            "journal_mode",
+            if options.wal { "WAL" } else { "DELETE" },
+            |row| row.get::<usize, String>(0),
+        )?;
+        init_db(&conn)?;
+        Ok(Self {
+            conn,
+            dedup_text: false,
+            save_batch_size: options.save_batch_size,
+        })
     }
 
-    fn init(&self) -> Result<()> {
-        let mut lock = DB_INITIALIZED
-            .get_or_init(|| Mutex::new(false))
-            .lock()
-            .unwrap();
-        if !lock.deref() {
-            init_db(&self.conn)?;
-            *lock.deref_mut() = true;
-        }
-        Ok(())
+    /// Sets whether newly saved chapter text is stored content-addressed, so that identical
+    /// chapter bodies (from crossposts or reposts) are only kept once. Chapters saved either way
+    /// can always be read back.
+    pub fn dedup_chapter_text(mut self, enabled: bool) -> Self {
+        self.dedup_text = enabled;
+        self
     }
 
     pub fn get_all_stories(&self) -> Result<Vec<ListedStory>> {
+        self.get_stories(&StoryFilter::default())
+    }
+
+    pub fn get_stories(&self, filter: &StoryFilter) -> Result<Vec<ListedStory>> {
         let conn = &self.conn;
         let mut failed_stories = 0;
         let mut stmt = conn
             .prepare(
                 "SELECT
                     stories.name,
-                    authors.name,
+                    (SELECT GROUP_CONCAT(name, ', ') FROM (
+                        SELECT authors.name
+                        FROM story_authors INNER JOIN authors ON story_authors.author_id = authors.id
+                        WHERE story_authors.story_id = stories.id
+                        ORDER BY story_authors.rowid
+                    )),
                     stories.completed,
                     stories.url,
-                    COUNT(chapters.id) AS chapter_count
+                    COUNT(chapters.id) AS chapter_count,
+                    stories.language,
+                    stories.added_at,
+                    stories.updated_at
                 FROM stories
-                    INNER JOIN authors ON stories.author_id = authors.id
-                    INNER JOIN chapters ON stories.id = chapters.story_id
+                    LEFT JOIN chapters ON stories.id = chapters.story_id
+                WHERE
+                    (:language IS NULL OR LOWER(stories.language) = LOWER(:language))
+                    AND (:completed IS NULL OR stories.completed = :completed)
+                    AND (:tag_count = 0 OR stories.id IN
+                        (SELECT story_id FROM tag_uses
+                        WHERE tag_id IN (SELECT value FROM json_each(:tags))
+                        GROUP BY story_id
+                        HAVING COUNT(DISTINCT tag_id) = :tag_count))
                 GROUP BY stories.id",
-            )
-            .unwrap();
+        )?;
+        let completed = filter.completed.map(|completed| completed.to_string());
+        let mut tags: Vec<String> = filter.tags.iter().map(|tag| tag_id(tag)).collect();
+        tags.sort();
+        tags.dedup();
+        let tag_count = tags.len();
+        let tags = serde_json::to_string(&tags).expect("A list of strings should serialize");
+        let params: &[(&str, &dyn ToSql)] = &[
+            (":language", &filter.language),
+            (":completed", &completed),
+            (":tags", &tags),
+            (":tag_count", &tag_count),
+        ];
         let stories: Vec<ListedStory> = stmt
-            .query_map([], |row| {
+            .query_map(params, |row| {
                 Ok(ListedStory {
                     name: row.get(0)?,
-                    author: row.get(1)?,
+                    author: row.get::<usize, Option<String>>(1)?.unwrap_or_default(),
                     completed: Completed::from_string(row.get::<usize, String>(2)?.as_ref()),
-                    source: StorySource::from_url(row.get::<usize, String>(3)?.as_ref())
-                        .expect("URLs in database should be valid for sources"),
+                    source: source_from_url(row.get(3)?, 3)?,
                     chapter_count: row.get(4)?,
+                    language: row.get(5)?,
+                    dates: StoryDates {
+                        added_at: parse_timestamp(row.get(6)?),
+                        updated_at: parse_timestamp(row.get(7)?),
+                    },
                 })
-            })
-            .unwrap()
+            })?
             .filter_map(|listed| match listed {
                 Ok(story) => Some(story),
                 Err(_) => {
@@ -84,72 +162,90 @@ impl Database {
                 }
             })
             .collect();
-        println!(
-            "Got {} stories. Failed to get {failed_stories} stories.",
-            stories.len()
-        );
+        log::debug!("Got {} stories.", stories.len());
+        if failed_stories > 0 {
+            log::warn!("Failed to get {failed_stories} stories.");
+        }
 
         Ok(stories)
     }
 
     pub fn story_exists_with_id(&self, id: &str) -> Result<bool> {
         let conn = &self.conn;
-        let mut stmt = conn
-            .prepare("SELECT COUNT(*) FROM stories WHERE id = :id")
-            .unwrap();
-        let story_exists = stmt
-            .query_row(&[(":id", id)], |row| match row.get(0) {
-                Ok(0) => Ok(None),
-                Ok(1) => Ok(Some(())),
-                _ => Ok(None),
-            })
-            .unwrap_or(None);
-        Ok(story_exists.is_some())
+        let mut stmt = conn.prepare("SELECT COUNT(*) FROM stories WHERE id = :id")?;
+        let count: usize = stmt.query_row(&[(":id", id)], |row| row.get(0))?;
+        Ok(count > 0)
     }
 
     pub fn fuzzy_get_story(&self, search: &str) -> Result<Vec<String>> {
         let conn = &self.conn;
-        let mut stmt = conn
-            .prepare(
-                "SELECT stories.id
-                FROM stories INNER JOIN authors ON stories.author_id = authors.id
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT stories.id
+                FROM stories
+                    LEFT JOIN story_authors ON stories.id = story_authors.story_id
+                    LEFT JOIN authors ON story_authors.author_id = authors.id
                 WHERE
-                    stories.name LIKE %:search%
+                    stories.name LIKE '%' || :search || '%'
                     OR stories.id = :search
-                    OR authors.name LIKE %:search%",
-            )
-            .unwrap();
+                    OR authors.name LIKE '%' || :search || '%'",
+        )?;
         let matches = stmt
-            .query_map(&[(":search", search)], |row| Ok(row.get(0).unwrap()))
-            .unwrap()
-            .filter_map(|id| id.ok())
-            .collect();
+            .query_map(&[(":search", search)], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
         Ok(matches)
     }
 
+    /// Gets the name of a story without loading the rest of it.
+    pub fn story_name(&self, id: &str) -> Result<Option<String>> {
+        let name = self
+            .conn
+            .query_row("SELECT name FROM stories WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(name)
+    }
+
+    /// Gives a story a name of the user's choosing, which is kept instead of the name the site
+    /// gives it when the story is updated.
+    pub fn rename_story(&self, id: &str, name: &str) -> Result<()> {
+        let renamed = self.conn.execute(
+            "UPDATE stories SET name = ?2, renamed = 1 WHERE id = ?1",
+            (id, name),
+        )?;
+        match renamed {
+            0 => Err(ArchiveError::StoryNotExists(id.to_owned())),
+            _ => Ok(()),
+        }
+    }
+
     pub fn get_story_by_id(&self, id: &str) -> Result<Option<Story>> {
         let conn = &self.conn;
-        if !self.story_exists_with_id(id).unwrap() {
+        if !self.story_exists_with_id(id)? {
             Ok(None)
         } else {
-            let mut stmt = conn
-                .prepare(
-                    "SELECT id, name, description, url, parent_id
-                FROM sections
-                WHERE story_id = :story_id",
-                )
-                .unwrap();
-            let mut sections: Vec<(Option<String>, Section)> = stmt
+            let mut stmt = conn.prepare(
+                "SELECT
+                        sections.id,
+                        sections.name,
+                        sections.description,
+                        sections.url,
+                        sections.parent_id,
+                        sections.position,
+                        authors.id,
+                        authors.name
+                    FROM sections
+                        LEFT JOIN authors ON sections.author_id = authors.id
+                    WHERE sections.story_id = :story_id",
+            )?;
+            let sections = stmt
                 .query_map(&[(":story_id", id)], |row| {
-                    Ok((
-                        // ID of parent section, if one exists
-                        match is_null(row, 4) {
-                            true => None,
-                            false => Some(row.get(4)?),
-                        },
-                        Section {
-                            id: row.get(0).unwrap(),
-                            name: row.get(1).unwrap(),
+                    Ok(ContentRow {
+                        parent_id: row.get(4)?,
+                        position: row.get(5)?,
+                        content: Content::Section(Section {
+                            id: row.get(0)?,
+                            name: row.get(1)?,
                             description: match is_null(row, 2) {
                                 true => None,
                                 false => Some(row.get(2)?),
@@ -159,234 +255,333 @@ impl Database {
                                 true => None,
                                 false => Some(row.get(3)?),
                             },
-                            author: None,
-                        },
-                    ))
-                })
-                .unwrap()
-                .map(|sec| sec.unwrap())
-                .collect();
+                            author: content_author(row, 6)?,
+                        }),
+                    })
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            let mut rows: Vec<ContentRow> = sections;
 
-            stmt = conn
-                .prepare(
-                    "SELECT id, name, description, text, url, date_posted, section_id
+            stmt = conn.prepare(
+                "SELECT
+                        chapters.id,
+                        chapters.name,
+                        chapters.description,
+                        COALESCE(chapters.text, chapter_texts.text),
+                        chapters.url,
+                        chapters.date_posted,
+                        chapters.section_id,
+                        chapters.position,
+                        authors.id,
+                        authors.name
                     FROM chapters
-                    WHERE story_id = :story_id",
-                )
-                .unwrap();
-            let mut chapters: Vec<(Option<String>, Chapter)> = stmt
+                        LEFT JOIN chapter_texts ON chapters.content_hash = chapter_texts.hash
+                        LEFT JOIN authors ON chapters.author_id = authors.id
+                    WHERE chapters.story_id = :story_id",
+            )?;
+            let chapters = stmt
                 .query_map(&[(":story_id", id)], |row| {
-                    Ok((
-                        // ID of parent section, if one exists
-                        match is_null(row, 6) {
-                            true => None,
-                            false => Some(row.get(6)?),
-                        },
-                        Chapter {
-                            id: row.get(0).unwrap(),
-                            name: row.get(1).unwrap(),
+                    Ok(ContentRow {
+                        parent_id: row.get(6)?,
+                        position: row.get(7)?,
+                        content: Content::Chapter(Chapter {
+                            id: row.get(0)?,
+                            name: row.get(1)?,
                             description: match is_null(row, 2) {
                                 true => None,
                                 false => Some(row.get(2)?),
                             },
-                            text: ChapterText::Hydrated(row.get(3).unwrap()),
-                            url: row.get(4).unwrap(),
-                            date_posted: DateTime::parse_from_rfc3339(
-                                row.get::<usize, String>(5).unwrap().as_str(),
-                            )
-                            .unwrap_or_else(|_| {
-                                panic!(
-                                    "Chapter posted-on date ({:?}) did not conform to rfc3339",
-                                    row.get::<usize, String>(5)
-                                )
-                            }),
-                            author: None,
-                        },
-                    ))
-                })
-                .unwrap()
-                .map(|chap| chap.unwrap())
-                .collect();
+                            text: match row.get(3)? {
+                                Some(text) => ChapterText::Hydrated(text),
+                                None => ChapterText::Dehydrated,
+                            },
+                            url: row.get(4)?,
+                            date_posted: date_or_placeholder(
+                                DateTime::parse_from_rfc3339(row.get::<usize, String>(5)?.as_str())
+                                    .map_err(ArchiveError::from),
+                                row.get::<usize, String>(4)?.as_str(),
+                            ),
+                            author: content_author(row, 8)?,
+                        }),
+                    })
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            rows.extend(chapters);
 
-            if !chapters.is_empty() {
-                for idx in (0..chapters.len() - 1).rev() {
-                    if chapters[idx].0.is_some() {
-                        let (parent_id, chapter) = chapters.remove(idx);
-                        let parent_id = parent_id.unwrap();
-                        if let Some((_, section)) =
-                            sections.iter_mut().find(|(_, s)| s.id == parent_id)
-                        {
-                            section.chapters.push(Content::Chapter(chapter));
-                        } else {
-                            panic!(
-                                "Chapter {} has section_id {} that does not match any section",
-                                chapter.id, parent_id
-                            );
-                        }
-                    }
-                }
-            }
-            if !sections.is_empty() {
-                for idx in (0..sections.len() - 1).rev() {
-                    sections[idx]
-                        .1
-                        .chapters
-                        .par_sort_unstable_by(|a, b| a.id().cmp(b.id()));
-                    if sections[idx].0.is_some() {
-                        let (parent_id, section) = sections.remove(idx);
-                        let parent_id = parent_id.unwrap();
-                        if let Some((_, parent)) =
-                            sections.iter_mut().find(|(_, s)| s.id == parent_id)
-                        {
-                            parent.chapters.push(Content::Section(section));
-                        }
-                    }
-                }
+            let story_chapters = build_content_tree(None, &mut rows);
+            if let Some(orphan) = rows.first() {
+                return Err(ArchiveError::Internal(format!(
+                    "{} {} has parent section {} that does not match any section",
+                    match orphan.content {
+                        Content::Section(_) => "Section",
+                        Content::Chapter(_) => "Chapter",
+                    },
+                    orphan.content.id(),
+                    orphan.parent_id.as_deref().unwrap_or("NULL"),
+                )));
             }
-            let mut story_chapters: Vec<Content> = sections
-                .into_iter()
-                .map(|(_, section)| Content::Section(section))
-                .chain(
-                    chapters
-                        .into_iter()
-                        .map(|(_, chapter)| Content::Chapter(chapter)),
-                )
-                .collect();
-            story_chapters.par_sort_unstable_by(|a, b| a.id().cmp(b.id()));
 
-            stmt = conn
-                .prepare(
-                    "SELECT tags.name
+            stmt = conn.prepare(
+                "SELECT tags.name
                     FROM tag_uses INNER JOIN tags
                     ON tags.id = tag_uses.tag_id
                     WHERE tag_uses.story_id = :story_id",
-                )
-                .unwrap();
+            )?;
             let story_tags: Vec<String> = stmt
-                .query_map(&[(":story_id", id)], |row| row.get::<usize, String>(0))
-                .unwrap()
-                .filter(|res| res.is_ok())
-                .map(|res| res.unwrap())
-                .collect();
+                .query_map(&[(":story_id", id)], |row| row.get::<usize, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
 
-            stmt = conn
-                .prepare("SELECT author_id FROM stories WHERE id = :id")
-                .unwrap();
-            let author_ids = stmt.query_row(&[(":id", id)], |row| {
-                Ok(row
-                    .get::<usize, String>(0)?
-                    .replace(AUTHOR_LIST_SEPARATOR, ", "))
-            })?;
-            stmt = conn
-                .prepare("SELECT id, name FROM authors WHERE id IN (?)")
-                .unwrap();
+            stmt = conn.prepare(
+                "SELECT authors.id, authors.name
+                    FROM story_authors INNER JOIN authors
+                    ON authors.id = story_authors.author_id
+                    WHERE story_authors.story_id = :story_id
+                    ORDER BY story_authors.rowid",
+            )?;
             let authors: Vec<Author> = stmt
-                .query_map([author_ids], |row| {
+                .query_map(&[(":story_id", id)], |row| {
                     Ok(Author {
                         id: row.get(0)?,
                         name: row.get(1)?,
                     })
                 })?
-                .filter(|res| res.is_ok())
-                .map(|res| res.unwrap())
-                .collect();
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            let authors = AuthorList::from_list(authors)?;
 
             stmt = conn
                 .prepare(
-                    "SELECT stories.name, stories.description, stories.url, stories.completed FROM stories WHERE id = :id",
-                )
-                .unwrap();
-            let mut story = stmt
-                .query_row(&[(":id", id)], |row| {
-                    let source = StorySource::from_url(row.get::<usize, String>(2)?.as_str())
-                        .map_err(|e| {
-                            rusqlite::Error::FromSqlConversionFailure(
-                                2,
-                                rusqlite::types::Type::Text,
-                                Box::new(e),
-                            )
-                        })
-                        .unwrap();
-                    Ok((
-                        row.get::<usize, String>(2)?,
-                        Story {
-                            name: row.get(0)?,
-                            description: row.get(1)?,
-                            url: row.get(2)?,
-                            authors: AuthorList::from_list(authors),
-                            chapters: story_chapters,
-                            tags: story_tags,
-                            source,
-                            completed: Completed::from_string(
-                                row.get::<usize, String>(3)?.as_ref(),
-                            ),
-                        },
-                    ))
+                    "SELECT stories.name, stories.description, stories.url, stories.completed, stories.language, stories.cover_url, stories.published, stories.last_updated FROM stories WHERE id = :id",
+            )?;
+            let story = stmt.query_row(&[(":id", id)], |row| {
+                Ok(Story {
+                    name: row.get(0)?,
+                    description: row.get(1)?,
+                    url: row.get(2)?,
+                    authors,
+                    chapters: story_chapters,
+                    tags: story_tags,
+                    source: source_from_url(row.get(2)?, 2)?,
+                    completed: Completed::from_string(row.get::<usize, String>(3)?.as_ref()),
+                    language: row.get(4)?,
+                    cover_url: row.get(5)?,
+                    published: parse_timestamp(row.get(6)?),
+                    last_updated: parse_timestamp(row.get(7)?),
                 })
-                .map_err(ArchiveError::from)
-                .unwrap();
-            story.1.source = StorySource::from_url(story.0.as_str()).unwrap();
-            Ok(Some(story.1))
+            })?;
+            Ok(Some(story))
         }
     }
 
-    pub fn save_story(&self, story: &Story) -> Result<()> {
-        let conn = &self.conn;
-        for author in story.authors.authors() {
-            conn.execute(
-                "INSERT OR IGNORE INTO authors (id, name) VALUES (?1, ?2)",
-                (&author.id, &author.name),
-            )
-            .unwrap();
+    /// Deletes a story along with its chapters, sections, and tag uses, then removes any tags
+    /// and authors that no longer belong to a story. Everything happens in one transaction, so
+    /// a failure leaves the archive untouched.
+    pub fn delete_story_by_id(&self, id: &str) -> Result<DeletedStory> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM chapter_search WHERE rowid IN (SELECT rowid FROM chapters WHERE story_id = ?1)",
+            [id],
+        )?;
+        tx.execute(
+            "DELETE FROM chapter_images
+            WHERE chapter_id IN (SELECT id FROM chapters WHERE story_id = ?1)",
+            [id],
+        )?;
+        let chapters = tx.execute("DELETE FROM chapters WHERE story_id = ?1", [id])?;
+        let sections = tx.execute("DELETE FROM sections WHERE story_id = ?1", [id])?;
+        tx.execute("DELETE FROM tag_uses WHERE story_id = ?1", [id])?;
+        tx.execute("DELETE FROM story_authors WHERE story_id = ?1", [id])?;
+        tx.execute("DELETE FROM reading_progress WHERE story_id = ?1", [id])?;
+        tx.execute("DELETE FROM story_covers WHERE story_id = ?1", [id])?;
+        if tx.execute("DELETE FROM stories WHERE id = ?1", [id])? == 0 {
+            return Err(ArchiveError::StoryNotExists(id.to_owned()));
         }
+        let tags = tx.execute(
+            "DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM tag_uses)",
+            (),
+        )?;
+        tx.execute(
+            "DELETE FROM chapter_texts
+            WHERE hash NOT IN (SELECT content_hash FROM chapters WHERE content_hash IS NOT NULL)",
+            (),
+        )?;
+        let authors = tx.execute(
+            "DELETE FROM authors
+            WHERE id NOT IN (SELECT author_id FROM story_authors)
+                AND id NOT IN (SELECT author_id FROM chapters WHERE author_id IS NOT NULL)
+                AND id NOT IN (SELECT author_id FROM sections WHERE author_id IS NOT NULL)",
+            (),
+        )?;
+        tx.commit()?;
+        Ok(DeletedStory {
+            chapters,
+            sections,
+            authors,
+            tags,
+        })
+    }
 
+    /// Saves a new story and all of its contents. Chapters are committed in batches of the
+    /// configured size rather than all at once.
+    pub fn save_story(&self, story: &Story) -> Result<()> {
+        let conn = &self.conn;
+        let story_id = story.source.to_id();
+        let mut batch = SaveBatch::begin(conn, self.save_batch_size)?;
         conn.execute(
-            "INSERT INTO stories (id, name, description, url, completed) VALUES (?1, ?2, ?3, ?4, ?5)",
+            "INSERT INTO stories (id, name, description, url, completed, language, cover_url, published, last_updated, added_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?10)",
             (
-                &story.source.to_id(),
+                &story_id,
                 &story.name,
                 &story.description,
                 &story.url,
-                // &story.authors.authors().iter().enumerate().fold(
-                //     String::new(),
-                //     |mut acc, (idx, author)| {
-                //         acc.push_str(&author.id);
-                //         if idx < story.authors.len() - 1 {
-                //             acc.push(AUTHOR_LIST_SEPARATOR);
-                //         };
-                //         acc
-                //     },
-                // ),
-                &story.authors.authors().iter().next().unwrap().id,
                 &story.completed.to_string(),
+                &story.language,
+                &story.cover_url,
+                story.published.map(|date| date.to_rfc3339()),
+                story.last_updated.map(|date| date.to_rfc3339()),
+                now(),
             ),
-        )
-        .unwrap();
-        for content in story.chapters.iter().as_ref() {
-            self.save_content(content, &story.source.to_id(), None)
-                .unwrap();
+        )?;
+        self.save_authors_and_tags(story)?;
+        for (position, content) in story.chapters.iter().enumerate() {
+            self.insert_content(content, &story_id, None, position, Some(&mut batch))?;
+        }
+        batch.finish()
+    }
+
+    /// Replaces the details of a saved story, along with its authors and tags, with those of
+    /// `story`. Its chapters aren't touched.
+    pub fn save_story_metadata(&self, story: &Story) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        let story_id = story.source.to_id();
+        let updated = tx.execute(
+            "UPDATE stories SET name = CASE WHEN renamed THEN name ELSE ?2 END, description = ?3, completed = ?4, language = ?5, cover_url = ?6,
+                published = ?7, last_updated = ?8
+            WHERE id = ?1",
+            (
+                &story_id,
+                &story.name,
+                &story.description,
+                &story.completed.to_string(),
+                &story.language,
+                &story.cover_url,
+                story.published.map(|date| date.to_rfc3339()),
+                story.last_updated.map(|date| date.to_rfc3339()),
+            ),
+        )?;
+        if updated == 0 {
+            return Err(ArchiveError::StoryNotExists(story.url.clone()));
         }
-        for tag in story.tags.iter().as_ref() {
-            let tag_id = tag.to_lowercase();
+        tx.execute("DELETE FROM story_authors WHERE story_id = ?1", [&story_id])?;
+        // The user's own tags aren't the source's to replace.
+        tx.execute(
+            "DELETE FROM tag_uses WHERE story_id = ?1 AND tag_id NOT LIKE ?2",
+            (&story_id, format!("{}:%", USER_TAG_NAMESPACE)),
+        )?;
+        self.save_authors_and_tags(story)?;
+        tx.execute(
+            "DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM tag_uses)",
+            (),
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn save_authors_and_tags(&self, story: &Story) -> Result<()> {
+        let conn = &self.conn;
+        let story_id = story.source.to_id();
+        for author in story.authors.authors() {
             conn.execute(
-                "INSERT OR IGNORE INTO tags (id, name) VALUES (?1, ?2)",
-                (&tag_id, &tag),
-            )
-            .unwrap();
+                "INSERT OR IGNORE INTO authors (id, name) VALUES (?1, ?2)",
+                (&author.id, &author.name),
+            )?;
             conn.execute(
-                "INSERT OR IGNORE INTO tag_uses (tag_id, story_id) VALUES (?1, ?2)",
-                (&tag_id, &story.source.to_id()),
-            )
-            .unwrap();
+                "INSERT INTO story_authors (story_id, author_id) VALUES (?1, ?2)",
+                (&story_id, &author.id),
+            )?;
+        }
+        for tag in story.tags.iter() {
+            tag_story(conn, &story_id, tag)?;
         }
         Ok(())
     }
 
+    /// Gives a story one of the user's own tags, in [`USER_TAG_NAMESPACE`]. Returns whether the
+    /// story didn't have it already.
+    pub fn add_user_tag(&self, story_id: &str, name: &str) -> Result<bool> {
+        if !self.story_exists_with_id(story_id)? {
+            return Err(ArchiveError::StoryNotExists(story_id.to_owned()));
+        }
+        tag_story(&self.conn, story_id, &user_tag(name))
+    }
+
+    /// Takes one of the user's own tags off a story, removing the tag altogether if no other
+    /// story has it. Returns whether the story had it.
+    pub fn remove_user_tag(&self, story_id: &str, name: &str) -> Result<bool> {
+        let tx = self.conn.unchecked_transaction()?;
+        let removed = tx.execute(
+            "DELETE FROM tag_uses WHERE story_id = ?1 AND tag_id = ?2",
+            (story_id, tag_id(&user_tag(name))),
+        )?;
+        tx.execute(
+            "DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM tag_uses)",
+            (),
+        )?;
+        tx.commit()?;
+        Ok(removed > 0)
+    }
+
+    /// Gets the names of the user's own tags on a story, without their namespace.
+    pub fn user_tags(&self, story_id: &str) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT tags.name FROM tag_uses INNER JOIN tags ON tags.id = tag_uses.tag_id
+            WHERE tag_uses.story_id = ?1 AND tag_uses.tag_id LIKE ?2
+            ORDER BY tags.id",
+        )?;
+        let prefix = format!("{}:", USER_TAG_NAMESPACE);
+        let tags = stmt
+            .query_map((story_id, format!("{}%", prefix)), |row| {
+                row.get::<usize, String>(0)
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(tags
+            .into_iter()
+            .map(|tag| tag[prefix.len()..].to_owned())
+            .collect())
+    }
+
+    /// Saves every section and chapter of a story that's already in the archive, replacing the
+    /// saved versions of any that are there already. Like [`Database::save_story`], chapters
+    /// are committed in batches.
+    pub fn save_story_contents(&self, story: &Story) -> Result<()> {
+        let story_id = story.source.to_id();
+        let mut batch = SaveBatch::begin(&self.conn, self.save_batch_size)?;
+        for (position, content) in story.chapters.iter().enumerate() {
+            self.insert_content(content, &story_id, None, position, Some(&mut batch))?;
+        }
+        batch.finish()
+    }
+
+    /// Saves a section or chapter (and, for sections, everything inside it) as the entry at
+    /// `position` among the children of `parent_id`, or among the top-level contents of the story
+    /// if there is no parent.
     pub fn save_content(
         &self,
         content: &Content,
         story_id: &str,
         parent_id: Option<&str>,
+        position: usize,
+    ) -> Result<()> {
+        self.insert_content(content, story_id, parent_id, position, None)?;
+        self.mark_updated(story_id)
+    }
+
+    fn insert_content(
+        &self,
+        content: &Content,
+        story_id: &str,
+        parent_id: Option<&str>,
+        position: usize,
+        mut batch: Option<&mut SaveBatch>,
     ) -> Result<()> {
         let conn = &self.conn;
         match content {
@@ -398,7 +593,15 @@ impl Database {
                 url,
                 author,
             }) => {
-                conn.execute("INSERT INTO sections (id, name, description, url, story_id, parent_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                self.save_content_author(author.as_ref())?;
+                conn.execute("INSERT INTO sections (id, name, description, url, story_id, parent_id, author_id, position) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                    ON CONFLICT(id) DO UPDATE SET
+                        name = excluded.name,
+                        description = excluded.description,
+                        url = excluded.url,
+                        parent_id = excluded.parent_id,
+                        author_id = excluded.author_id,
+                        position = excluded.position",
     				(
     					id,
     					name,
@@ -406,11 +609,18 @@ impl Database {
     					url,
     					story_id,
     					parent_id,
-                        author.as_ref().map(|a| &a.id)
+                        author.as_ref().map(|a| &a.id),
+                        position,
     				)
-    			).unwrap();
-                for inner in chapters.iter() {
-                    self.save_content(inner, story_id, Some(id)).unwrap();
+    			)?;
+                for (inner_position, inner) in chapters.iter().enumerate() {
+                    self.insert_content(
+                        inner,
+                        story_id,
+                        Some(id),
+                        inner_position,
+                        batch.as_deref_mut(),
+                    )?;
                 }
             }
             Content::Chapter(Chapter {
@@ -422,118 +632,925 @@ impl Database {
                 date_posted,
                 author,
             }) => {
-                conn.execute("INSERT INTO chapters (id, name, description, text, url, date_posted, story_id, section_id, author_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                self.save_content_author(author.as_ref())?;
+                let (stored_text, content_hash) = self.store_text(text)?;
+                let words = text.word_count();
+                // Re-saving a chapter that's already there replaces it, except that a chapter fetched
+                // without its text or real date keeps the ones it has.
+                conn.execute("INSERT INTO chapters (id, name, description, text, url, date_posted, story_id, section_id, author_id, content_hash, position, word_count, added_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?13, ?14)
+                    ON CONFLICT(id) DO UPDATE SET
+                        name = excluded.name,
+                        description = excluded.description,
+                        text = CASE WHEN excluded.text IS NULL AND excluded.content_hash IS NULL
+                            THEN chapters.text ELSE excluded.text END,
+                        url = excluded.url,
+                        date_posted = CASE WHEN excluded.date_posted = ?12
+                            THEN chapters.date_posted ELSE excluded.date_posted END,
+                        section_id = excluded.section_id,
+                        author_id = excluded.author_id,
+                        content_hash = CASE WHEN excluded.text IS NULL AND excluded.content_hash IS NULL
+                            THEN chapters.content_hash ELSE excluded.content_hash END,
+                        word_count = CASE WHEN excluded.text IS NULL AND excluded.content_hash IS NULL
+                            THEN chapters.word_count ELSE excluded.word_count END,
+                        position = excluded.position",
     				(
     					id,
     					name,
     					description,
-    					text.as_str(),
+    					stored_text,
     					url,
     					&date_posted.to_rfc3339(),
     					story_id,
     					parent_id,
-                        author.as_ref().map(|a| &a.id)
+                        author.as_ref().map(|a| &a.id),
+                        &content_hash,
+                        position,
+                        &Chapter::placeholder_date().to_rfc3339(),
+                        words,
+                        now(),
     				)
-    			).expect(format!("Failed to add chapter with values\nid: {}\nname: {}\nurl: {}\ndate_posted: {}\nstory_id: {}\nsection_id {}", id, name, url, date_posted.to_rfc3339(), story_id, "NULL").as_str());
+    			)?;
+                index_chapter(conn, id)?;
+                if let Some(batch) = batch {
+                    batch.chapter_saved()?;
+                }
             }
         }
         Ok(())
     }
 
-    pub fn add_valid_site(&self, url: &str, matches: &str) -> Result<()> {
-        let conn = &self.conn;
-        conn.execute(
-            "INSERT OR IGNORE INTO valid_sites (site_url, matches_parser) VALUES (?1, ?2)",
-            (url, matches),
+    /// Makes sure the author of a section or chapter, if it has its own, has a row to refer to.
+    fn save_content_author(&self, author: Option<&Author>) -> Result<()> {
+        if let Some(author) = author {
+            self.conn.execute(
+                "INSERT OR IGNORE INTO authors (id, name) VALUES (?1, ?2)",
+                (&author.id, &author.name),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Works out what to put in a chapter's `text` and `content_hash` columns, adding the text
+    /// to `chapter_texts` if it's being deduplicated. Chapters without text store neither.
+    fn store_text<'t>(&self, text: &'t ChapterText) -> Result<(Option<&'t str>, Option<String>)> {
+        let content_hash = text.content_hash();
+        let stored_text = match (text, content_hash.as_ref()) {
+            (ChapterText::Dehydrated, _) => None,
+            (ChapterText::Hydrated(text), Some(hash)) if self.dedup_text => {
+                self.conn.execute(
+                    "INSERT OR IGNORE INTO chapter_texts (hash, text) VALUES (?1, ?2)",
+                    (hash, text),
+                )?;
+                None
+            }
+            (ChapterText::Hydrated(text), _) => Some(text.as_str()),
+        };
+        Ok((stored_text, content_hash))
+    }
+
+    /// Replaces the text of a saved chapter.
+    pub fn update_chapter_text(&self, id: &str, text: &ChapterText) -> Result<()> {
+        let (stored_text, content_hash) = self.store_text(text)?;
+        self.conn.execute(
+            "UPDATE chapters SET text = ?2, content_hash = ?3, word_count = ?4 WHERE id = ?1",
+            (id, stored_text, content_hash, text.word_count()),
+        )?;
+        index_chapter(&self.conn, id)
+    }
+
+    /// Replaces a saved chapter with a newer version of it, leaving it where it is in the story.
+    /// If the new version has no text or real date, the saved ones are kept.
+    pub fn update_chapter(&self, chapter: &Chapter) -> Result<()> {
+        self.conn.execute(
+            "UPDATE chapters SET name = ?2, description = ?3, url = ?4 WHERE id = ?1",
+            (
+                &chapter.id,
+                &chapter.name,
+                &chapter.description,
+                &chapter.url,
+            ),
+        )?;
+        if !chapter.has_placeholder_date() {
+            self.update_chapter_date(&chapter.id, &chapter.date_posted)?;
+        }
+        match chapter.text {
+            ChapterText::Hydrated(_) => self.update_chapter_text(&chapter.id, &chapter.text)?,
+            ChapterText::Dehydrated => index_chapter(&self.conn, &chapter.id)?,
+        }
+        self.conn.execute(
+            "UPDATE stories SET updated_at = ?2
+            WHERE id = (SELECT story_id FROM chapters WHERE id = ?1)",
+            (&chapter.id, now()),
         )?;
         Ok(())
     }
 
-    pub fn get_parser_for_site(&self, url: &str) -> Result<Option<String>> {
-        let conn = &self.conn;
-        let mut stmt = conn
-            .prepare("SELECT matches_parser FROM valid_sites WHERE site_url = :url")
-            .unwrap();
-        stmt.query_row(&[(":url", url)], |row| Ok(row.get::<usize, String>(0)?))
-            .optional()
-            .map_err(|e| e.into())
+    /// Replaces the posting date of a saved chapter.
+    pub fn update_chapter_date(&self, id: &str, date_posted: &DateTime<FixedOffset>) -> Result<()> {
+        self.conn.execute(
+            "UPDATE chapters SET date_posted = ?2 WHERE id = ?1",
+            (id, date_posted.to_rfc3339()),
+        )?;
+        Ok(())
     }
-}
 
-fn is_null(row: &Row, column: usize) -> bool {
-    matches!(
-        row.get::<usize, String>(column),
-        Err(Error::InvalidColumnType(_, _, Type::Null))
-    )
-}
+    /// Gets the position just past the last section or chapter saved under `parent_id` (or at
+    /// the top level of the story if there is no parent), for appending new content.
+    pub fn next_position(&self, story_id: &str, parent_id: Option<&str>) -> Result<usize> {
+        let next = self.conn.query_row(
+            "SELECT COALESCE(MAX(position) + 1, 0) FROM (
+                SELECT position FROM sections WHERE story_id = ?1 AND parent_id IS ?2
+                UNION ALL
+                SELECT position FROM chapters WHERE story_id = ?1 AND section_id IS ?2
+            )",
+            (story_id, parent_id),
+            |row| row.get(0),
+        )?;
+        Ok(next)
+    }
 
-fn init_db(conn: &Connection) -> Result<()> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS authors (
-            id TEXT PRIMARY KEY,
-            name TEXT NOT NULL
-        )",
-        (),
-    )?;
+    /// Gets where the reader left off in a story, if they've started reading it.
+    pub fn get_progress(&self, story_id: &str) -> Result<Option<ReadingProgress>> {
+        let progress = self
+            .conn
+            .query_row(
+                "SELECT chapter_id, scroll_offset FROM reading_progress WHERE story_id = ?1",
+                [story_id],
+                |row| {
+                    Ok(ReadingProgress {
+                        chapter_id: row.get(0)?,
+                        offset: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(progress)
+    }
 
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS stories (
-            id TEXT NOT NULL PRIMARY KEY,
-            name TEXT NOT NULL,
-            description TEXT,
-            url TEXT NOT NULL,
-            completed TEXT NOT NULL
-        )",
-        (),
-    )?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS story_authors (
-            story_id TEXT NOT NULL,
-            author_id TEXT NOT NULL,
-            FOREIGN KEY (story_id) REFERENCES stories(id),
-            FOREIGN KEY (author_id) REFERENCES authors(id)
-        )",
-        (),
-    )?;
+    /// Records where the reader left off in a story, replacing any earlier position.
+    pub fn set_progress(&self, story_id: &str, progress: &ReadingProgress) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO reading_progress (story_id, chapter_id, scroll_offset) VALUES (?1, ?2, ?3)
+            ON CONFLICT (story_id) DO UPDATE SET
+                chapter_id = excluded.chapter_id,
+                scroll_offset = excluded.scroll_offset",
+            (story_id, &progress.chapter_id, progress.offset),
+        )?;
+        Ok(())
+    }
 
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS sections (
-            id TEXT PRIMARY KEY,
-            name TEXT NOT NULL,
-            description TEXT,
-            url TEXT,
-            story_id TEXT NOT NULL,
-            parent_id TEXT,
-            author_id TEXT,
-            FOREIGN KEY (story_id) REFERENCES stories(id)
-            FOREIGN KEY (author_id) REFERENCES authors(id)
-        )",
-        (),
-    )?;
+    /// Gets the cover image saved for a story, if one has been downloaded.
+    pub fn get_cover(&self, story_id: &str) -> Result<Option<Cover>> {
+        let cover = self
+            .conn
+            .query_row(
+                "SELECT url, media_type, bytes FROM story_covers WHERE story_id = ?1",
+                [story_id],
+                |row| {
+                    Ok(Cover {
+                        url: row.get(0)?,
+                        media_type: row.get(1)?,
+                        bytes: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(cover)
+    }
 
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS sections (
-            id TEXT PRIMARY KEY,
-            name TEXT NOT NULL,
-            description TEXT,
-            url TEXT,
-            story_id TEXT NOT NULL,
-            parent_id TEXT,
-            author_id TEXT,
-            FOREIGN KEY (story_id) REFERENCES stories(id)
-            FOREIGN KEY (author_id) REFERENCES authors(id)
-        )",
-        (),
-    )?;
+    /// Gets the URL the saved cover of a story was downloaded from, without loading the image.
+    pub fn saved_cover_url(&self, story_id: &str) -> Result<Option<String>> {
+        let url = self
+            .conn
+            .query_row(
+                "SELECT url FROM story_covers WHERE story_id = ?1",
+                [story_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(url)
+    }
 
+    /// Saves a story's cover image, replacing any it had before.
+    pub fn save_cover(&self, story_id: &str, cover: &Cover) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO story_covers (story_id, url, media_type, bytes) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (story_id) DO UPDATE SET
+                url = excluded.url,
+                media_type = excluded.media_type,
+                bytes = excluded.bytes",
+            (story_id, &cover.url, &cover.media_type, &cover.bytes),
+        )?;
+        Ok(())
+    }
+
+    /// Saves an image from a chapter's text, replacing any saved under the same hash.
+    pub fn save_chapter_image(&self, image: &ChapterImage) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO chapter_images (chapter_id, hash, url, media_type, bytes)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT (chapter_id, hash) DO UPDATE SET
+                url = excluded.url,
+                media_type = excluded.media_type,
+                bytes = excluded.bytes",
+            (
+                &image.chapter_id,
+                &image.hash,
+                &image.url,
+                &image.media_type,
+                &image.bytes,
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// Gets every image saved from the chapters of a story.
+    pub fn get_story_images(&self, story_id: &str) -> Result<Vec<ChapterImage>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT chapter_images.chapter_id, hash, chapter_images.url, media_type, bytes
+            FROM chapter_images INNER JOIN chapters ON chapters.id = chapter_images.chapter_id
+            WHERE chapters.story_id = ?1
+            ORDER BY chapter_images.chapter_id, hash",
+        )?;
+        let images = stmt
+            .query_map([story_id], |row| {
+                Ok(ChapterImage {
+                    chapter_id: row.get(0)?,
+                    hash: row.get(1)?,
+                    url: row.get(2)?,
+                    media_type: row.get(3)?,
+                    bytes: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<ChapterImage>>>()?;
+        Ok(images)
+    }
+
+    /// Finds chapters whose name or text contains `phrase`, best matches first. Each hit comes
+    /// with a snippet of the text around the match, with the matching words wrapped in `**`.
+    pub fn search_text(&self, phrase: &str) -> Result<Vec<SearchHit>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT
+                stories.id,
+                stories.name,
+                chapters.id,
+                chapters.name,
+                snippet(chapter_search, 2, '**', '**', '...', 16)
+            FROM chapter_search
+                INNER JOIN chapters ON chapters.rowid = chapter_search.rowid
+                INNER JOIN stories ON stories.id = chapters.story_id
+            WHERE chapter_search MATCH ?1
+            ORDER BY rank",
+        )?;
+        // Search for the words as a phrase rather than with FTS5's query syntax.
+        let query = format!("\"{}\"", phrase.replace('"', "\"\""));
+        let hits = stmt
+            .query_map([query], |row| {
+                Ok(SearchHit {
+                    story_id: row.get(0)?,
+                    story_name: row.get(1)?,
+                    chapter_id: row.get(2)?,
+                    chapter_name: row.get(3)?,
+                    snippet: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<SearchHit>>>()?;
+        Ok(hits)
+    }
+
+    /// Gets the `limit` chapters most recently added to the archive, newest first.
+    pub fn recent_chapters(&self, limit: usize) -> Result<Vec<NewChapter>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT
+                stories.id,
+                stories.name,
+                chapters.id,
+                chapters.name,
+                chapters.url,
+                chapters.date_posted,
+                chapters.added_at
+            FROM chapters INNER JOIN stories ON stories.id = chapters.story_id
+            ORDER BY chapters.added_at DESC, chapters.date_posted DESC, chapters.id
+            LIMIT ?1",
+        )?;
+        let chapters = stmt
+            .query_map([limit], |row| {
+                Ok(NewChapter {
+                    story_id: row.get(0)?,
+                    story_name: row.get(1)?,
+                    chapter_id: row.get(2)?,
+                    chapter_name: row.get(3)?,
+                    url: row.get(4)?,
+                    date_posted: parse_timestamp(row.get(5)?),
+                    added_at: parse_timestamp(row.get(6)?),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<NewChapter>>>()?;
+        Ok(chapters)
+    }
+
+    /// Lists every tag in the archive with how many stories have it, most used first and
+    /// otherwise alphabetically.
+    pub fn tag_counts(&self) -> Result<Vec<TagCount>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT tags.name, COUNT(DISTINCT tag_uses.story_id) AS stories
+            FROM tag_uses INNER JOIN tags ON tags.id = tag_uses.tag_id
+            GROUP BY tags.id
+            ORDER BY stories DESC, tags.id",
+        )?;
+        let counts = stmt
+            .query_map([], |row| {
+                Ok(TagCount {
+                    name: row.get(0)?,
+                    stories: row.get(1)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<TagCount>>>()?;
+        Ok(counts)
+    }
+
+    /// Records that a story just gained or changed a chapter.
+    pub fn mark_updated(&self, story_id: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE stories SET updated_at = ?2 WHERE id = ?1",
+            (story_id, now()),
+        )?;
+        Ok(())
+    }
+
+    /// Gets when a story was first saved and when it last gained or changed a chapter.
+    pub fn get_story_dates(&self, story_id: &str) -> Result<StoryDates> {
+        let dates = self.conn.query_row(
+            "SELECT added_at, updated_at FROM stories WHERE id = ?1",
+            [story_id],
+            |row| {
+                Ok(StoryDates {
+                    added_at: parse_timestamp(row.get(0)?),
+                    updated_at: parse_timestamp(row.get(1)?),
+                })
+            },
+        )?;
+        Ok(dates)
+    }
+
+    /// Records that a story was just checked for updates.
+    pub fn mark_checked(&self, story_id: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE stories SET last_checked = ?2 WHERE id = ?1",
+            (story_id, now()),
+        )?;
+        Ok(())
+    }
+
+    /// Gets when the update run that's still in progress started, if one was interrupted.
+    pub fn update_cycle_started(&self) -> Result<Option<String>> {
+        let started = self
+            .conn
+            .query_row(
+                "SELECT value FROM archive_state WHERE key = ?1",
+                [UPDATE_CYCLE_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(started)
+    }
+
+    /// Records that an update run of the whole archive is starting now, returning its start
+    /// time. Until [`finish_update_cycle`](Self::finish_update_cycle) is called, stories checked
+    /// before this time count as still to be checked.
+    pub fn start_update_cycle(&self) -> Result<String> {
+        let started = now();
+        self.conn.execute(
+            "INSERT OR REPLACE INTO archive_state (key, value) VALUES (?1, ?2)",
+            (UPDATE_CYCLE_KEY, &started),
+        )?;
+        Ok(started)
+    }
+
+    pub fn finish_update_cycle(&self) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM archive_state WHERE key = ?1",
+            [UPDATE_CYCLE_KEY],
+        )?;
+        Ok(())
+    }
+
+    /// Gets the stories that haven't been checked since `since`, least recently checked (or
+    /// never checked) first, along with whether each one is complete.
+    pub fn stories_to_check(&self, since: &str) -> Result<Vec<(StorySource, Completed)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT url, completed FROM stories
+            WHERE last_checked IS NULL OR last_checked < ?1
+            ORDER BY last_checked IS NOT NULL, last_checked, id",
+        )?;
+        let rows = stmt
+            .query_map([since], |row| {
+                Ok((
+                    row.get::<usize, String>(0)?,
+                    Completed::from_string(row.get::<usize, String>(1)?.as_ref()),
+                ))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        rows.into_iter()
+            .map(|(url, completed)| Ok((StorySource::from_url(&url)?, completed)))
+            .collect()
+    }
+
+    /// Gets the stories that haven't gained or changed a chapter since `cutoff`, least recently
+    /// updated first. Stories with no `updated_at` are always included.
+    pub fn get_stories_needing_update(&self, cutoff: &str) -> Result<Vec<StorySource>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT url FROM stories
+            WHERE updated_at IS NULL OR updated_at < ?1
+            ORDER BY updated_at IS NOT NULL, updated_at, id",
+        )?;
+        let urls = stmt
+            .query_map([cutoff], |row| row.get::<usize, String>(0))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        urls.iter().map(|url| StorySource::from_url(url)).collect()
+    }
+
+    /// Saves a WordPress site so it's registered every time the archive is opened.
+    pub fn add_wordpress_site(&self, site: &WordPressSite) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO valid_sites
+                (site_url, matches_parser, toc_path, toc_selector, content_selector)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT(site_url) DO UPDATE SET
+                matches_parser = excluded.matches_parser,
+                toc_path = excluded.toc_path,
+                toc_selector = excluded.toc_selector,
+                content_selector = excluded.content_selector",
+            (
+                &site.host,
+                wordpress::PREFIX,
+                &site.toc_path,
+                &site.toc_selector,
+                &site.content_selector,
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// Gets every WordPress site that has been saved with [`Self::add_wordpress_site`].
+    pub fn wordpress_sites(&self) -> Result<Vec<WordPressSite>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT site_url, toc_path, toc_selector, content_selector FROM valid_sites
+            WHERE matches_parser = ?1
+            ORDER BY site_url",
+        )?;
+        let sites = stmt
+            .query_map([wordpress::PREFIX], |row| {
+                Ok(WordPressSite {
+                    host: row.get(0)?,
+                    toc_path: row.get(1)?,
+                    toc_selector: row.get(2)?,
+                    content_selector: row.get(3)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(sites)
+    }
+}
+
+/// A transaction that is committed and started again every `size` chapters while a story is
+/// saved. If it's dropped without being finished, the current batch is rolled back.
+struct SaveBatch<'a> {
+    conn: &'a Connection,
+    size: usize,
+    pending: usize,
+    finished: bool,
+}
+
+impl<'a> SaveBatch<'a> {
+    fn begin(conn: &'a Connection, size: usize) -> Result<Self> {
+        conn.execute_batch("BEGIN")?;
+        Ok(Self {
+            conn,
+            size,
+            pending: 0,
+            finished: false,
+        })
+    }
+
+    fn chapter_saved(&mut self) -> Result<()> {
+        self.pending += 1;
+        if self.size > 0 && self.pending >= self.size {
+            self.conn.execute_batch("COMMIT; BEGIN")?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.conn.execute_batch("COMMIT")?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl Drop for SaveBatch<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.conn.execute_batch("ROLLBACK");
+        }
+    }
+}
+
+/// The `archive_state` key holding when an unfinished update of the whole archive started.
+const UPDATE_CYCLE_KEY: &str = "update_cycle_started";
+
+/// The `archive_state` key recording when tags saved by older versions were merged by
+/// [`normalize_tags`].
+const TAGS_NORMALIZED_KEY: &str = "tags_normalized";
+
+/// The current time in a form that sorts correctly as text.
+fn now() -> String {
+    timestamp(Utc::now())
+}
+
+/// Writes a time the way timestamp columns hold it, so that it can be compared with them.
+pub(crate) fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// Reads a timestamp column written by [`now`] or copied from a chapter's posting date. A
+/// missing or unreadable timestamp is treated as unknown.
+fn parse_timestamp(timestamp: Option<String>) -> Option<DateTime<FixedOffset>> {
+    timestamp.and_then(|timestamp| DateTime::parse_from_rfc3339(&timestamp).ok())
+}
+
+/// A section or chapter loaded from the database, before it has been placed in its parent.
+struct ContentRow {
+    parent_id: Option<String>,
+    position: Option<usize>,
+    content: Content,
+}
+
+/// Removes the rows whose parent is `parent_id` from `rows` and returns them in reading order,
+/// with each section's contents filled in. Rows saved before positions were recorded sort after
+/// positioned ones, by id.
+fn build_content_tree(parent_id: Option<&str>, rows: &mut Vec<ContentRow>) -> Vec<Content> {
+    let (mut children, rest): (Vec<ContentRow>, Vec<ContentRow>) = std::mem::take(rows)
+        .into_iter()
+        .partition(|row| row.parent_id.as_deref() == parent_id);
+    *rows = rest;
+    children.sort_by(|a, b| match (a.position, b.position) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => natural_cmp(a.content.id(), b.content.id()),
+    });
+    children
+        .into_iter()
+        .map(|row| match row.content {
+            Content::Section(mut section) => {
+                section.chapters = build_content_tree(Some(&section.id), rows);
+                Content::Section(section)
+            }
+            chapter => chapter,
+        })
+        .collect()
+}
+
+/// Compares ids so that runs of digits are ordered by value, putting `rr:123:2` before
+/// `rr:123:10`. Only content saved before positions were recorded is ordered this way.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_end = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+                let b_end = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+                let (a_num, b_num) = (
+                    a[..a_end].trim_start_matches('0'),
+                    b[..b_end].trim_start_matches('0'),
+                );
+                let order = a_num.len().cmp(&b_num.len()).then_with(|| a_num.cmp(b_num));
+                if order != Ordering::Equal {
+                    return order;
+                }
+                a = &a[a_end..];
+                b = &b[b_end..];
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a = &a[x.len_utf8()..];
+                b = &b[y.len_utf8()..];
+            }
+        }
+    }
+}
+
+/// Brings a chapter's entry in the full-text search index up to date with what's saved.
+fn index_chapter(conn: &Connection, id: &str) -> Result<()> {
+    let (rowid, story_id, name, text) = conn.query_row(
+        "SELECT chapters.rowid, chapters.story_id, chapters.name, COALESCE(chapters.text, chapter_texts.text)
+        FROM chapters LEFT JOIN chapter_texts ON chapters.content_hash = chapter_texts.hash
+        WHERE chapters.id = ?1",
+        [id],
+        |row| {
+            Ok((
+                row.get::<usize, i64>(0)?,
+                row.get::<usize, String>(1)?,
+                row.get::<usize, String>(2)?,
+                row.get::<usize, Option<String>>(3)?,
+            ))
+        },
+    )?;
+    conn.execute("DELETE FROM chapter_search WHERE rowid = ?1", [rowid])?;
+    conn.execute(
+        "INSERT INTO chapter_search (rowid, story_id, name, text) VALUES (?1, ?2, ?3, ?4)",
+        (
+            rowid,
+            story_id,
+            name,
+            text.as_deref().map(html_to_text).unwrap_or_default(),
+        ),
+    )?;
+    Ok(())
+}
+
+/// Reads the author of a section or chapter from an author id column and the name column after
+/// it, which are both null for content without its own author.
+fn content_author(row: &Row, column: usize) -> rusqlite::Result<Option<Author>> {
+    let id: Option<String> = row.get(column)?;
+    let name: Option<String> = row.get(column + 1)?;
+    Ok(id.zip(name).map(|(id, name)| Author { id, name }))
+}
+
+/// Gets the source of a story from the URL stored for it, failing the way a column with the
+/// wrong type would if it's not a URL for a supported source.
+fn source_from_url(url: String, column: usize) -> rusqlite::Result<StorySource> {
+    StorySource::from_url(&url).map_err(|err| conversion_error(column, err))
+}
+
+/// Wraps a failure to make sense of a text column's value as a rusqlite error, so it can be
+/// returned from a row mapping.
+fn conversion_error<E: std::error::Error + Send + Sync + 'static>(column: usize, err: E) -> Error {
+    Error::FromSqlConversionFailure(column, Type::Text, Box::new(err))
+}
+
+fn is_null(row: &Row, column: usize) -> bool {
+    matches!(
+        row.get::<usize, String>(column),
+        Err(Error::InvalidColumnType(_, _, Type::Null))
+    )
+}
+
+/// Adds a column to an existing table if it isn't already there, so that databases created by
+/// older versions pick up new columns without having to be rebuilt. Returns whether the column
+/// was added.
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<usize, String>(1))?
+        .flatten()
+        .any(|name| name == column);
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            (),
+        )?;
+    }
+    Ok(!exists)
+}
+
+/// Fills in `updated_at` for stories saved by an older version with the best guess for when they
+/// were last updated: their latest chapter's posting date. Posting dates are stored with the
+/// offset they were posted at, so they're compared as times and written in UTC like every other
+/// timestamp, rather than compared as text.
+fn backfill_updated_at_from_chapters(conn: &Connection) -> Result<()> {
+    let mut stmt = conn.prepare("SELECT story_id, date_posted FROM chapters")?;
+    let mut latest: BTreeMap<String, DateTime<Utc>> = BTreeMap::new();
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<usize, String>(0)?, parse_timestamp(row.get(1)?)))
+    })?;
+    for row in rows {
+        if let (story_id, Some(posted)) = row? {
+            let posted = posted.with_timezone(&Utc);
+            latest
+                .entry(story_id)
+                .and_modify(|date| *date = (*date).max(posted))
+                .or_insert(posted);
+        }
+    }
+    for (story_id, date) in latest {
+        conn.execute(
+            "UPDATE stories SET updated_at = ?2 WHERE id = ?1",
+            (&story_id, timestamp(date)),
+        )?;
+    }
+    Ok(())
+}
+
+/// Trims a tag and collapses any runs of whitespace inside it, as tags are stored.
+fn tag_name(tag: &str) -> String {
+    tag.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The id a tag is stored under, which is the same for every way it's capitalized.
+fn tag_id(tag: &str) -> String {
+    tag_name(tag).to_lowercase()
+}
+
+/// Which of the ways a tag has been written is shown: the first one with capitals, since a
+/// tag that's all lowercase tends to be a sloppier copy, or else the first one.
+fn canonical_tag_name<'a>(mut names: impl Iterator<Item = &'a str> + Clone) -> Option<&'a str> {
+    names
+        .clone()
+        .find(|name| name.to_lowercase() != *name)
+        .or_else(|| names.next())
+}
+
+/// Gives a story a tag, unless it has it already. Returns whether it was added. If the tag is
+/// already in the archive written differently, [`canonical_tag_name`] picks which is kept.
+fn tag_story(conn: &Connection, story_id: &str, tag: &str) -> Result<bool> {
+    let tag = tag_name(tag);
+    if tag.is_empty() {
+        return Ok(false);
+    }
+    let tag_id = tag.to_lowercase();
+    conn.execute(
+        "INSERT INTO tags (id, name) VALUES (?1, ?2)
+        ON CONFLICT (id) DO UPDATE SET name = excluded.name
+            WHERE tags.name = LOWER(tags.name) AND excluded.name != LOWER(excluded.name)",
+        (&tag_id, &tag),
+    )?;
+    let added = conn.execute(
+        "INSERT INTO tag_uses (tag_id, story_id) SELECT ?1, ?2
+        WHERE NOT EXISTS (SELECT 1 FROM tag_uses WHERE tag_id = ?1 AND story_id = ?2)",
+        (&tag_id, story_id),
+    )?;
+    Ok(added > 0)
+}
+
+/// The full name of one of the user's own tags.
+fn user_tag(name: &str) -> String {
+    format!("{}:{}", USER_TAG_NAMESPACE, name)
+}
+
+fn init_db(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS authors (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS stories (
+            id TEXT NOT NULL PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT,
+            url TEXT NOT NULL,
+            completed TEXT NOT NULL,
+            language TEXT,
+            cover_url TEXT
+        )",
+        (),
+    )?;
+    add_column_if_missing(conn, "stories", "language", "TEXT")?;
+    add_column_if_missing(conn, "stories", "cover_url", "TEXT")?;
+    // When the source says the story was published and last updated.
+    add_column_if_missing(conn, "stories", "published", "TEXT")?;
+    add_column_if_missing(conn, "stories", "last_updated", "TEXT")?;
+    // Whether the story was given a name with `rename`, which updates shouldn't replace.
+    add_column_if_missing(conn, "stories", "renamed", "INTEGER NOT NULL DEFAULT 0")?;
+    // When the story was last successfully checked for updates.
+    add_column_if_missing(conn, "stories", "last_checked", "TEXT")?;
+    // When the story was first saved, and when it last gained or changed a chapter.
+    add_column_if_missing(conn, "stories", "added_at", "TEXT")?;
+    let backfill_updated_at = add_column_if_missing(conn, "stories", "updated_at", "TEXT")?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS story_authors (
+            story_id TEXT NOT NULL,
+            author_id TEXT NOT NULL,
+            FOREIGN KEY (story_id) REFERENCES stories(id),
+            FOREIGN KEY (author_id) REFERENCES authors(id)
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sections (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT,
+            url TEXT,
+            story_id TEXT NOT NULL,
+            parent_id TEXT,
+            author_id TEXT,
+            position INTEGER,
+            FOREIGN KEY (story_id) REFERENCES stories(id)
+            FOREIGN KEY (author_id) REFERENCES authors(id)
+        )",
+        (),
+    )?;
+    add_column_if_missing(conn, "sections", "position", "INTEGER")?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS chapters (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT,
+            text TEXT,
+            url TEXT NOT NULL,
+            date_posted TEXT NOT NULL,
+            story_id TEXT NOT NULL,
+            section_id TEXT,
+            author_id TEXT,
+            content_hash TEXT,
+            position INTEGER,
+            FOREIGN KEY (story_id) REFERENCES stories(id),
+            FOREIGN KEY (section_id) REFERENCES sections(id),
+            FOREIGN KEY (author_id) REFERENCES authors(id)
+        )",
+        (),
+    )?;
+    add_column_if_missing(conn, "chapters", "content_hash", "TEXT")?;
+    add_column_if_missing(conn, "chapters", "position", "INTEGER")?;
+    let backfill_word_counts =
+        add_column_if_missing(conn, "chapters", "word_count", "INTEGER NOT NULL DEFAULT 0")?;
+    // When the chapter was first saved to the archive.
+    if add_column_if_missing(conn, "chapters", "added_at", "TEXT")? {
+        // Chapters saved by an older version were saved some time after they were posted.
+        conn.execute("UPDATE chapters SET added_at = date_posted", ())?;
+    }
+    if backfill_updated_at {
+        backfill_updated_at_from_chapters(conn)?;
+    }
+    // Chapter names and plain text for full-text search, with the same rowids as `chapters`.
+    let search_exists: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE name = 'chapter_search'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !search_exists {
+        conn.execute(
+            "CREATE VIRTUAL TABLE chapter_search USING fts5(story_id UNINDEXED, name, text)",
+            (),
+        )?;
+    }
+    // Chapter bodies shared by several chapters when text deduplication is enabled.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS chapter_texts (
+            hash TEXT PRIMARY KEY,
+            text TEXT NOT NULL
+        )",
+        (),
+    )?;
+
+    if backfill_word_counts {
+        let mut stmt = conn.prepare(
+            "SELECT chapters.id, COALESCE(chapters.text, chapter_texts.text)
+            FROM chapters LEFT JOIN chapter_texts ON chapters.content_hash = chapter_texts.hash",
+        )?;
+        let counts = stmt
+            .query_map([], |row| {
+                let text: Option<String> = row.get(1)?;
+                Ok((
+                    row.get::<usize, String>(0)?,
+                    text.as_deref().map(count_words).unwrap_or(0),
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<(String, usize)>>>()?;
+        for (id, words) in counts.iter() {
+            conn.execute(
+                "UPDATE chapters SET word_count = ?2 WHERE id = ?1",
+                (id, words),
+            )?;
+        }
+    }
+    if !search_exists {
+        let mut stmt = conn.prepare("SELECT id FROM chapters")?;
+        let ids = stmt
+            .query_map([], |row| row.get::<usize, String>(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        for id in ids.iter() {
+            index_chapter(conn, id)?;
+        }
+    }
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS tags (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL
+        )",
+        (),
+    )?;
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS tags (
-            id TEXT PRIMARY KEY,
-            name TEXT NOT NULL
-        )",
-        (),
-    )?;
-    conn.execute(
         "CREATE TABLE IF NOT EXISTS tag_uses (
             tag_id TEXT NOT NULL,
             story_id TEXT NOT NULL,
@@ -543,6 +1560,50 @@ fn init_db(conn: &Connection) -> Result<()> {
         (),
     )?;
 
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS reading_progress (
+            story_id TEXT PRIMARY KEY,
+            chapter_id TEXT NOT NULL,
+            scroll_offset INTEGER NOT NULL DEFAULT 0,
+            FOREIGN KEY (story_id) REFERENCES stories(id)
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS story_covers (
+            story_id TEXT PRIMARY KEY,
+            url TEXT NOT NULL,
+            media_type TEXT NOT NULL,
+            bytes BLOB NOT NULL,
+            FOREIGN KEY (story_id) REFERENCES stories(id)
+        )",
+        (),
+    )?;
+
+    // Images from chapter text, downloaded with `--download-images`.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS chapter_images (
+            chapter_id TEXT NOT NULL,
+            hash TEXT NOT NULL,
+            url TEXT NOT NULL,
+            media_type TEXT NOT NULL,
+            bytes BLOB NOT NULL,
+            PRIMARY KEY (chapter_id, hash),
+            FOREIGN KEY (chapter_id) REFERENCES chapters(id)
+        )",
+        (),
+    )?;
+
+    // Bookkeeping that isn't about any one story, like an update run that hasn't finished.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS archive_state (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        (),
+    )?;
+
     conn.execute(
         "CREATE TABLE IF NOT EXISTS valid_sites (
             site_url TEXT PRIMARY KEY,
@@ -550,5 +1611,934 @@ fn init_db(conn: &Connection) -> Result<()> {
         )",
         (),
     )?;
+    for column in ["toc_path", "toc_selector", "content_selector"] {
+        add_column_if_missing(conn, "valid_sites", column, "TEXT NOT NULL DEFAULT ''")?;
+    }
+
+    let tags_normalized: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM archive_state WHERE key = ?1",
+        [TAGS_NORMALIZED_KEY],
+        |row| row.get(0),
+    )?;
+    if !tags_normalized {
+        normalize_tags(conn)?;
+        conn.execute(
+            "INSERT INTO archive_state (key, value) VALUES (?1, ?2)",
+            (TAGS_NORMALIZED_KEY, now()),
+        )?;
+    }
     Ok(())
 }
+
+/// Merges tags saved by older versions that only differ in whitespace or capitalization, and
+/// gives each the name [`canonical_tag_name`] picks.
+fn normalize_tags(conn: &Connection) -> Result<()> {
+    let mut stmt = conn.prepare("SELECT id, name FROM tags ORDER BY rowid")?;
+    let tags = stmt
+        .query_map([], |row| {
+            Ok((row.get::<usize, String>(0)?, row.get::<usize, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+    let mut merged: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+    for (id, name) in tags {
+        merged.entry(tag_id(&name)).or_default().push((id, name));
+    }
+    let tx = conn.unchecked_transaction()?;
+    for (new_id, tags) in merged {
+        let names: Vec<String> = tags.iter().map(|(_, name)| tag_name(name)).collect();
+        let name = canonical_tag_name(names.iter().map(|name| name.as_str()))
+            .unwrap_or_default()
+            .to_owned();
+        if tags.len() == 1 && tags[0] == (new_id.clone(), name.clone()) {
+            continue;
+        }
+        if !new_id.is_empty() {
+            tx.execute(
+                "INSERT INTO tags (id, name) VALUES (?1, ?2)
+                ON CONFLICT (id) DO UPDATE SET name = excluded.name",
+                (&new_id, &name),
+            )?;
+        }
+        for (id, _) in tags.iter().filter(|(id, _)| *id != new_id) {
+            // Tags that were only whitespace are dropped.
+            match new_id.is_empty() {
+                true => tx.execute("DELETE FROM tag_uses WHERE tag_id = ?1", [id])?,
+                false => tx.execute(
+                    "UPDATE tag_uses SET tag_id = ?2 WHERE tag_id = ?1",
+                    (id, &new_id),
+                )?,
+            };
+            tx.execute("DELETE FROM tags WHERE id = ?1", [id])?;
+        }
+    }
+    // A story that had several of the merged tags now has the same one more than once.
+    tx.execute(
+        "DELETE FROM tag_uses WHERE rowid NOT IN
+            (SELECT MIN(rowid) FROM tag_uses GROUP BY tag_id, story_id)",
+        (),
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_db(options: &DatabaseOptions) -> Database {
+        Database::new(":memory:", options).expect("In-memory database should open")
+    }
+
+    fn chapter(story_id: &str, number: usize) -> Content {
+        Content::Chapter(Chapter {
+            id: format!("{}:{}", story_id, number),
+            name: format!("Chapter {}", number),
+            description: None,
+            text: ChapterText::Hydrated(format!("<p>Text of chapter {}</p>", number)),
+            url: format!("https://www.royalroad.com/fiction/6789/chapter/{}", number),
+            date_posted: Chapter::placeholder_date(),
+            author: None,
+        })
+    }
+
+    fn story(chapters: Vec<Content>) -> Story {
+        let source = StorySource::RoyalRoad("6789".to_owned());
+        Story {
+            name: "A Very Long Serial".to_owned(),
+            authors: AuthorList::new(Author {
+                id: "rr:1".to_owned(),
+                name: "Someone".to_owned(),
+            }),
+            description: None,
+            url: source.to_url(),
+            tags: vec!["Fantasy".to_owned()],
+            chapters,
+            source,
+            completed: Completed::Incomplete,
+            language: None,
+            cover_url: None,
+            published: None,
+            last_updated: None,
+        }
+    }
+
+    #[test]
+    fn saves_large_story_in_batches() {
+        let db = memory_db(&DatabaseOptions {
+            save_batch_size: 64,
+            ..Default::default()
+        });
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        let chapters: Vec<Content> = (1..=2000).map(|n| chapter(&story_id, n)).collect();
+        db.save_story(&story(chapters)).unwrap();
+
+        let saved = db.get_story_by_id(&story_id).unwrap().unwrap();
+        let ids: Vec<&str> = saved.chapters.iter().map(|c| c.id()).collect();
+        let expected: Vec<String> = (1..=2000).map(|n| format!("{}:{}", story_id, n)).collect();
+        assert_eq!(ids, expected);
+        assert!(
+            db.conn.is_autocommit(),
+            "Save should not leave a transaction open"
+        );
+    }
+
+    #[test]
+    fn nests_last_chapter_in_its_section() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        let section = |number: usize, chapters: Vec<Content>| {
+            Content::Section(Section {
+                id: format!("{}:section:{}", story_id, number),
+                name: format!("Book {}", number),
+                description: None,
+                chapters,
+                url: None,
+                author: None,
+            })
+        };
+        let contents = vec![
+            section(1, vec![chapter(&story_id, 1), chapter(&story_id, 2)]),
+            section(2, vec![chapter(&story_id, 3), chapter(&story_id, 4)]),
+        ];
+        db.save_story(&story(contents)).unwrap();
+
+        let saved = db.get_story_by_id(&story_id).unwrap().unwrap();
+        assert_eq!(saved.chapters.len(), 2);
+        match &saved.chapters[1] {
+            Content::Section(section) => {
+                let ids: Vec<&str> = section.chapters.iter().map(|c| c.id()).collect();
+                assert_eq!(
+                    ids,
+                    vec![format!("{}:3", story_id), format!("{}:4", story_id)]
+                );
+            }
+            Content::Chapter(chapter) => panic!("Expected a section, got chapter {}", chapter.id),
+        }
+    }
+
+    #[test]
+    fn loads_chapters_in_numeric_order() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        let chapters: Vec<Content> = (1..=12).map(|n| chapter(&story_id, n)).collect();
+        db.save_story(&story(chapters)).unwrap();
+        let expected: Vec<String> = (1..=12).map(|n| format!("{}:{}", story_id, n)).collect();
+
+        let saved = db.get_story_by_id(&story_id).unwrap().unwrap();
+        let ids: Vec<&str> = saved.chapters.iter().map(|c| c.id()).collect();
+        assert_eq!(ids, expected);
+
+        // Chapters saved before positions were recorded fall back to their ids.
+        db.conn
+            .execute("UPDATE chapters SET position = NULL", ())
+            .unwrap();
+        let saved = db.get_story_by_id(&story_id).unwrap().unwrap();
+        let ids: Vec<&str> = saved.chapters.iter().map(|c| c.id()).collect();
+        assert_eq!(ids, expected);
+    }
+
+    #[test]
+    fn round_trips_section_authors() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        let author = Author {
+            id: "rr:2".to_owned(),
+            name: "Guest Writer".to_owned(),
+        };
+        let section = Content::Section(Section {
+            id: format!("{}:section:1", story_id),
+            name: "Interlude".to_owned(),
+            description: None,
+            chapters: vec![chapter(&story_id, 1)],
+            url: None,
+            author: Some(author.clone()),
+        });
+        db.save_story(&story(vec![section])).unwrap();
+
+        let saved = db.get_story_by_id(&story_id).unwrap().unwrap();
+        match &saved.chapters[0] {
+            Content::Section(section) => {
+                let saved_author = section
+                    .author
+                    .as_ref()
+                    .expect("Section should keep its author");
+                assert_eq!(saved_author.id, author.id);
+                assert_eq!(saved_author.name, author.name);
+            }
+            Content::Chapter(chapter) => panic!("Expected a section, got chapter {}", chapter.id),
+        }
+    }
+
+    #[test]
+    fn resaving_contents_replaces_edited_chapters() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        db.save_story(&story(vec![chapter(&story_id, 1), chapter(&story_id, 2)]))
+            .unwrap();
+
+        let mut edited = story(vec![chapter(&story_id, 1), chapter(&story_id, 2)]);
+        if let Content::Chapter(ref mut first) = edited.chapters[0] {
+            first.name = "Chapter 1 (Rewritten)".to_owned();
+            first.text = ChapterText::Hydrated("<p>Better text</p>".to_owned());
+        }
+        if let Content::Chapter(ref mut second) = edited.chapters[1] {
+            second.text = ChapterText::Dehydrated;
+        }
+        db.save_story_contents(&edited).unwrap();
+
+        let saved = db.get_story_by_id(&story_id).unwrap().unwrap();
+        assert_eq!(saved.num_chapters(), 2);
+        match (&saved.chapters[0], &saved.chapters[1]) {
+            (Content::Chapter(first), Content::Chapter(second)) => {
+                assert_eq!(first.name, "Chapter 1 (Rewritten)");
+                assert!(
+                    matches!(first.text, ChapterText::Hydrated(ref t) if t == "<p>Better text</p>")
+                );
+                // Fetching a chapter without its text doesn't throw away the saved text.
+                assert!(
+                    matches!(second.text, ChapterText::Hydrated(ref t) if t == "<p>Text of chapter 2</p>")
+                );
+            }
+            _ => panic!("Expected two chapters"),
+        }
+    }
+
+    #[test]
+    fn stores_word_counts() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        let mut story = story(vec![chapter(&story_id, 1), chapter(&story_id, 2)]);
+        if let Content::Chapter(ref mut second) = story.chapters[1] {
+            second.text = ChapterText::Dehydrated;
+        }
+        db.save_story(&story).unwrap();
+        let word_counts = || -> Vec<usize> {
+            let mut stmt = db
+                .conn
+                .prepare("SELECT word_count FROM chapters ORDER BY position")
+                .unwrap();
+            stmt.query_map([], |row| row.get(0))
+                .unwrap()
+                .map(|count| count.unwrap())
+                .collect()
+        };
+        assert_eq!(word_counts(), vec![4, 0]);
+        assert_eq!(story.total_word_count(), 4);
+
+        db.update_chapter_text(
+            &format!("{}:2", story_id),
+            &ChapterText::Hydrated("<p>Two <em>more</em> words</p>".to_owned()),
+        )
+        .unwrap();
+        assert_eq!(word_counts(), vec![4, 3]);
+        let saved = db.get_story_by_id(&story_id).unwrap().unwrap();
+        assert_eq!(saved.total_word_count(), 7);
+    }
+
+    #[test]
+    fn keeps_chapters_without_text_dehydrated() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        let mut skeleton = chapter(&story_id, 1);
+        if let Content::Chapter(ref mut chapter) = skeleton {
+            chapter.text = ChapterText::Dehydrated;
+        }
+        db.save_story(&story(vec![skeleton, chapter(&story_id, 2)]))
+            .unwrap();
+
+        let texts = |db: &Database| -> Vec<ChapterText> {
+            db.get_story_by_id(&story_id)
+                .unwrap()
+                .unwrap()
+                .chapters
+                .into_iter()
+                .map(|content| match content {
+                    Content::Chapter(chapter) => chapter.text,
+                    Content::Section(_) => panic!("Story has no sections"),
+                })
+                .collect()
+        };
+        assert!(matches!(texts(&db)[0], ChapterText::Dehydrated));
+        assert!(matches!(texts(&db)[1], ChapterText::Hydrated(_)));
+
+        let id = format!("{}:1", story_id);
+        let text = ChapterText::Hydrated("<p>Fetched later</p>".to_owned());
+        db.update_chapter_text(&id, &text).unwrap();
+        assert_eq!(texts(&db)[0].as_str(), "<p>Fetched later</p>");
+    }
+
+    #[test]
+    fn replaces_metadata_without_touching_chapters() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        db.save_story(&story(vec![chapter(&story_id, 1), chapter(&story_id, 2)]))
+            .unwrap();
+
+        let mut changed = story(Vec::new());
+        changed.name = "A Renamed Serial".to_owned();
+        changed.tags = vec!["Adventure".to_owned()];
+        changed.completed = Completed::Complete;
+        changed.published = DateTime::parse_from_rfc3339("2020-01-15T03:00:00+02:00").ok();
+        changed.last_updated = DateTime::parse_from_rfc3339("2021-03-02T03:00:00+02:00").ok();
+        db.save_story_metadata(&changed).unwrap();
+
+        let saved = db.get_story_by_id(&story_id).unwrap().unwrap();
+        assert_eq!(saved.name, "A Renamed Serial");
+        assert_eq!(saved.tags, vec!["Adventure".to_owned()]);
+        assert_eq!(saved.completed, Completed::Complete);
+        assert_eq!(saved.published, changed.published);
+        assert_eq!(saved.last_updated, changed.last_updated);
+        assert_eq!(saved.authors.len(), 1);
+        assert_eq!(saved.num_chapters(), 2);
+        let unused_tags: usize = db
+            .conn
+            .query_row(
+                "SELECT COUNT(*) FROM tags WHERE id = 'fantasy'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(unused_tags, 0);
+    }
+
+    #[test]
+    fn keeps_renamed_stories_name_through_updates() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        db.save_story(&story(vec![chapter(&story_id, 1)])).unwrap();
+
+        db.rename_story(&story_id, "Shelf Name").unwrap();
+        let mut updated = story(Vec::new());
+        updated.completed = Completed::Complete;
+        db.save_story_metadata(&updated).unwrap();
+
+        let saved = db.get_story_by_id(&story_id).unwrap().unwrap();
+        assert_eq!(saved.name, "Shelf Name");
+        assert_eq!(saved.url, updated.url);
+        assert_eq!(saved.completed, Completed::Complete);
+        assert!(matches!(
+            db.rename_story("rr:0", "Nothing"),
+            Err(ArchiveError::StoryNotExists(_))
+        ));
+    }
+
+    #[test]
+    fn keeps_user_tags_through_updates() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        db.save_story(&story(vec![chapter(&story_id, 1)])).unwrap();
+
+        assert!(db.add_user_tag(&story_id, "To-Reread").unwrap());
+        assert!(db.add_user_tag(&story_id, "favorites").unwrap());
+        assert!(!db.add_user_tag(&story_id, "to-reread").unwrap());
+        let mut updated = story(Vec::new());
+        updated.tags = vec!["Adventure".to_owned()];
+        db.save_story_metadata(&updated).unwrap();
+
+        let saved = db.get_story_by_id(&story_id).unwrap().unwrap();
+        let mut tags = saved.tags.clone();
+        tags.sort();
+        assert_eq!(tags, vec!["Adventure", "user:To-Reread", "user:favorites"]);
+        assert_eq!(
+            db.user_tags(&story_id).unwrap(),
+            vec!["favorites", "To-Reread"]
+        );
+        let filter = StoryFilter {
+            tags: vec!["user:to-reread".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(db.get_stories(&filter).unwrap().len(), 1);
+
+        assert!(db.remove_user_tag(&story_id, "TO-REREAD").unwrap());
+        assert!(!db.remove_user_tag(&story_id, "to-reread").unwrap());
+        assert_eq!(db.user_tags(&story_id).unwrap(), vec!["favorites"]);
+        assert!(matches!(
+            db.add_user_tag("rr:0", "favorites"),
+            Err(ArchiveError::StoryNotExists(_))
+        ));
+    }
+
+    #[test]
+    fn saves_and_lists_every_author() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        let mut story = story(vec![chapter(&story_id, 1)]);
+        story.authors = AuthorList::from_list(vec![
+            Author {
+                id: "rr:2".to_owned(),
+                name: "Second".to_owned(),
+            },
+            Author {
+                id: "rr:1".to_owned(),
+                name: "First".to_owned(),
+            },
+        ])
+        .unwrap();
+        db.save_story(&story).unwrap();
+
+        let saved = db.get_story_by_id(&story_id).unwrap().unwrap();
+        let names: Vec<&str> = saved
+            .authors
+            .authors()
+            .iter()
+            .map(|author| author.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["Second", "First"]);
+        let listed = db.get_all_stories().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].author, "Second, First");
+        assert_eq!(db.fuzzy_get_story("first").unwrap(), vec![story_id]);
+    }
+
+    #[test]
+    fn lists_stories_without_chapters() {
+        let db = memory_db(&DatabaseOptions::default());
+        db.save_story(&story(Vec::new())).unwrap();
+
+        let listed = db.get_all_stories().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "A Very Long Serial");
+        assert_eq!(listed[0].chapter_count, 0);
+    }
+
+    #[test]
+    fn constraint_violations_are_errors() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story = story(vec![chapter("rr:6789", 1)]);
+        db.save_story(&story).unwrap();
+
+        match db.save_story(&story) {
+            Err(ArchiveError::Database(_)) => (),
+            other => panic!("expected a database error, got {:?}", other),
+        }
+        assert!(db.conn.is_autocommit());
+        assert_eq!(db.get_all_stories().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn loads_unreadable_dates_as_the_placeholder() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story = story(vec![chapter("rr:6789", 1)]);
+        db.save_story(&story).unwrap();
+        db.conn
+            .execute("UPDATE chapters SET date_posted = 'last Tuesday'", ())
+            .unwrap();
+
+        let saved = db.get_story_by_id("rr:6789").unwrap().unwrap();
+        assert!(saved.all_chapters()[0].has_placeholder_date());
+    }
+
+    #[test]
+    fn records_when_stories_are_added_and_updated() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        db.save_story(&story(vec![chapter(&story_id, 1)])).unwrap();
+        let added = db.get_story_dates(&story_id).unwrap();
+        assert!(added.added_at.is_some());
+        assert_eq!(added.updated_at, added.added_at);
+
+        db.save_content(&chapter(&story_id, 2), &story_id, None, 1)
+            .unwrap();
+        let updated = db.get_story_dates(&story_id).unwrap();
+        assert_eq!(updated.added_at, added.added_at);
+        assert!(updated.updated_at > added.updated_at);
+    }
+
+    #[test]
+    fn backfills_updated_at_from_chapter_dates() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        db.save_story(&story(vec![chapter(&story_id, 1)])).unwrap();
+        db.conn
+            .execute_batch(
+                "ALTER TABLE stories DROP COLUMN added_at;
+                ALTER TABLE stories DROP COLUMN updated_at;",
+            )
+            .unwrap();
+
+        init_db(&db.conn).unwrap();
+        let dates = db.get_story_dates(&story_id).unwrap();
+        assert_eq!(dates.added_at, None);
+        assert_eq!(dates.updated_at, Some(Chapter::placeholder_date()));
+    }
+
+    #[test]
+    fn backfills_updated_at_in_utc() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        db.save_story(&story(vec![chapter(&story_id, 1), chapter(&story_id, 2)]))
+            .unwrap();
+        // The first chapter was posted later, though its date sorts first as text.
+        db.conn
+            .execute_batch(
+                "UPDATE chapters SET date_posted = '2024-01-01T23:00:00-05:00'
+                    WHERE id LIKE '%:1';
+                UPDATE chapters SET date_posted = '2024-01-02T02:00:00+00:00'
+                    WHERE id LIKE '%:2';
+                ALTER TABLE stories DROP COLUMN added_at;
+                ALTER TABLE stories DROP COLUMN updated_at;",
+            )
+            .unwrap();
+
+        init_db(&db.conn).unwrap();
+        let updated_at: String = db
+            .conn
+            .query_row(
+                "SELECT updated_at FROM stories WHERE id = ?1",
+                [&story_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(updated_at, "2024-01-02T04:00:00.000000Z");
+    }
+
+    #[test]
+    fn searches_chapter_text() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        db.save_story(&story(vec![chapter(&story_id, 1), chapter(&story_id, 2)]))
+            .unwrap();
+
+        let hits = db.search_text("of chapter 2").unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].chapter_id, format!("{}:2", story_id));
+        assert_eq!(hits[0].story_name, "A Very Long Serial");
+        assert_eq!(hits[0].snippet, "Text **of chapter 2**");
+
+        // Edited text is searchable in place of the old text.
+        db.update_chapter_text(
+            &format!("{}:2", story_id),
+            &ChapterText::Hydrated("<p>Something <em>else</em> entirely</p>".to_owned()),
+        )
+        .unwrap();
+        assert!(db.search_text("of chapter 2").unwrap().is_empty());
+        assert_eq!(db.search_text("else entirely").unwrap().len(), 1);
+
+        db.delete_story_by_id(&story_id).unwrap();
+        assert!(db.search_text("else entirely").unwrap().is_empty());
+    }
+
+    #[test]
+    fn lists_recently_added_chapters_newest_first() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        db.save_story(&story(vec![chapter(&story_id, 1), chapter(&story_id, 2)]))
+            .unwrap();
+        db.conn
+            .execute(
+                "UPDATE chapters SET added_at = '2024-01-01T00:00:00.000000Z'",
+                (),
+            )
+            .unwrap();
+        db.save_content(&chapter(&story_id, 3), &story_id, None, 2)
+            .unwrap();
+        // Saving a chapter again doesn't make it new.
+        db.save_content(&chapter(&story_id, 1), &story_id, None, 0)
+            .unwrap();
+
+        let recent = db.recent_chapters(2).unwrap();
+        let ids: Vec<&str> = recent.iter().map(|c| c.chapter_id.as_str()).collect();
+        assert_eq!(ids, [format!("{}:3", story_id), format!("{}:1", story_id)]);
+        assert_eq!(recent[0].story_name, "A Very Long Serial");
+        assert!(recent[0].added_at > recent[1].added_at);
+    }
+
+    #[test]
+    fn indexes_chapters_saved_before_search_existed() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        db.save_story(&story(vec![chapter(&story_id, 1)])).unwrap();
+        db.conn.execute("DROP TABLE chapter_search", ()).unwrap();
+
+        init_db(&db.conn).unwrap();
+        assert_eq!(db.search_text("text of chapter").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn filters_stories_by_every_tag() {
+        let db = memory_db(&DatabaseOptions::default());
+        for (id, tags) in [
+            ("1", vec!["Fandom:Worm", "Rating:Teen"]),
+            ("2", vec!["Fandom:Worm", "Rating:Explicit"]),
+            ("3", vec!["Fandom:Ward", "Rating:Teen"]),
+        ] {
+            let source = StorySource::RoyalRoad(id.to_owned());
+            let mut story = story(Vec::new());
+            story.name = format!("Story {}", id);
+            story.url = source.to_url();
+            story.source = source;
+            story.tags = tags.into_iter().map(|tag| tag.to_owned()).collect();
+            db.save_story(&story).unwrap();
+        }
+        let listed = |tags: &[&str]| -> Vec<String> {
+            let filter = StoryFilter {
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
+                ..Default::default()
+            };
+            let mut names: Vec<String> = db
+                .get_stories(&filter)
+                .unwrap()
+                .into_iter()
+                .map(|ls| ls.name)
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(listed(&["fandom:worm"]), vec!["Story 1", "Story 2"]);
+        assert_eq!(listed(&["fandom:worm", "RATING:TEEN"]), vec!["Story 1"]);
+        assert_eq!(
+            listed(&["fandom:worm", "fandom:Worm"]),
+            vec!["Story 1", "Story 2"]
+        );
+        assert!(listed(&["fandom:ward", "rating:explicit"]).is_empty());
+        assert_eq!(listed(&[]).len(), 3);
+    }
+
+    #[test]
+    fn filters_stories_by_status() {
+        let db = memory_db(&DatabaseOptions::default());
+        for (id, completed) in [("1", Completed::Complete), ("2", Completed::Unknown)] {
+            let source = StorySource::RoyalRoad(id.to_owned());
+            let mut story = story(Vec::new());
+            story.url = source.to_url();
+            story.source = source;
+            story.completed = completed;
+            db.save_story(&story).unwrap();
+        }
+        let listed = |completed: Completed| {
+            let filter = StoryFilter {
+                completed: Some(completed),
+                ..Default::default()
+            };
+            db.get_stories(&filter).unwrap()
+        };
+
+        assert_eq!(listed(Completed::Complete)[0].source.to_id(), "rr:1");
+        assert_eq!(listed(Completed::Unknown)[0].source.to_id(), "rr:2");
+        assert!(listed(Completed::Incomplete).is_empty());
+    }
+
+    #[test]
+    fn deletes_story_and_orphaned_rows() {
+        let db = memory_db(&DatabaseOptions::default());
+        let kept_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        db.save_story(&story(vec![chapter(&kept_id, 1)])).unwrap();
+        let source = StorySource::RoyalRoad("1234".to_owned());
+        let deleted_id = source.to_id();
+        let mut other = story(vec![chapter(&deleted_id, 1), chapter(&deleted_id, 2)]);
+        other.url = source.to_url();
+        other.source = source;
+        other.authors = AuthorList::new(Author {
+            id: "rr:2".to_owned(),
+            name: "Someone Else".to_owned(),
+        });
+        other.tags = vec!["Fantasy".to_owned(), "Horror".to_owned()];
+        db.save_story(&other).unwrap();
+
+        let deleted = db.delete_story_by_id(&deleted_id).unwrap();
+        assert_eq!(deleted.chapters, 2);
+        assert_eq!(deleted.sections, 0);
+        // The shared "Fantasy" tag is still used by the other story.
+        assert_eq!(deleted.tags, 1);
+        assert_eq!(deleted.authors, 1);
+        assert!(db.get_story_by_id(&deleted_id).unwrap().is_none());
+        let kept = db.get_story_by_id(&kept_id).unwrap().unwrap();
+        assert_eq!(kept.tags, vec!["Fantasy".to_owned()]);
+        assert_eq!(kept.num_chapters(), 1);
+        assert!(matches!(
+            db.delete_story_by_id(&deleted_id),
+            Err(ArchiveError::StoryNotExists(_))
+        ));
+    }
+
+    #[test]
+    fn saves_chapter_images_until_their_story_is_deleted() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        db.save_story(&story(vec![chapter(&story_id, 1), chapter(&story_id, 2)]))
+            .unwrap();
+        let image = |chapter: usize, hash: &str| ChapterImage {
+            chapter_id: format!("{}:{}", story_id, chapter),
+            hash: hash.to_owned(),
+            url: format!("https://example.com/{}.png", hash),
+            media_type: "image/png".to_owned(),
+            bytes: hash.as_bytes().to_vec(),
+        };
+        db.save_chapter_image(&image(2, "b")).unwrap();
+        db.save_chapter_image(&image(1, "a")).unwrap();
+        // Saving the same image again replaces it.
+        db.save_chapter_image(&image(1, "a")).unwrap();
+        assert_eq!(
+            db.get_story_images(&story_id).unwrap(),
+            vec![image(1, "a"), image(2, "b")]
+        );
+
+        db.delete_story_by_id(&story_id).unwrap();
+        let left: usize = db
+            .conn
+            .query_row("SELECT COUNT(*) FROM chapter_images", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn counts_stories_per_tag() {
+        let db = memory_db(&DatabaseOptions::default());
+        for (id, tags) in [
+            ("1", vec!["genre:Fantasy", "tag:Magic"]),
+            ("2", vec!["genre:Fantasy"]),
+            ("3", vec!["genre:Horror", "tag:Magic", "genre:fantasy"]),
+        ] {
+            let source = StorySource::RoyalRoad(id.to_owned());
+            let mut story = story(Vec::new());
+            story.url = source.to_url();
+            story.source = source;
+            story.tags = tags.into_iter().map(|tag| tag.to_owned()).collect();
+            db.save_story(&story).unwrap();
+        }
+        let counts: Vec<(String, usize)> = db
+            .tag_counts()
+            .unwrap()
+            .into_iter()
+            .map(|count| (count.name, count.stories))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                ("genre:Fantasy".to_owned(), 3),
+                ("tag:Magic".to_owned(), 2),
+                ("genre:Horror".to_owned(), 1),
+            ]
+        );
+    }
+
+    fn tag_counts(db: &Database) -> Vec<(String, usize)> {
+        db.tag_counts()
+            .unwrap()
+            .into_iter()
+            .map(|count| (count.name, count.stories))
+            .collect()
+    }
+
+    #[test]
+    fn merges_tags_written_differently() {
+        let db = memory_db(&DatabaseOptions::default());
+        for (id, tags) in [
+            ("1", vec!["tag:alternate  universe", "  tag:Magic "]),
+            ("2", vec!["tag:Alternate Universe", "tag:magic", "   "]),
+            ("3", vec!["tag:ALTERNATE universe\n"]),
+        ] {
+            let source = StorySource::RoyalRoad(id.to_owned());
+            let mut story = story(Vec::new());
+            story.url = source.to_url();
+            story.source = source;
+            story.tags = tags.into_iter().map(|tag| tag.to_owned()).collect();
+            db.save_story(&story).unwrap();
+        }
+        // The first way of writing a tag with capitals wins over an all-lowercase one.
+        assert_eq!(
+            tag_counts(&db),
+            vec![
+                ("tag:Alternate Universe".to_owned(), 3),
+                ("tag:Magic".to_owned(), 2),
+            ]
+        );
+        let filter = StoryFilter {
+            tags: vec![" TAG:alternate universe".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(db.get_stories(&filter).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn normalizes_tags_saved_by_older_versions() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story_id = StorySource::RoyalRoad("6789".to_owned()).to_id();
+        db.save_story(&story(Vec::new())).unwrap();
+        db.conn.execute("DELETE FROM tag_uses", ()).unwrap();
+        db.conn.execute("DELETE FROM tags", ()).unwrap();
+        for (id, name) in [
+            ("tag:magic", "tag:magic"),
+            (" tag:magic", " tag:Magic"),
+            ("tag:slow  burn", "tag:Slow  Burn"),
+            (" ", " "),
+        ] {
+            db.conn
+                .execute("INSERT INTO tags (id, name) VALUES (?1, ?2)", (id, name))
+                .unwrap();
+            db.conn
+                .execute(
+                    "INSERT INTO tag_uses (tag_id, story_id) VALUES (?1, ?2)",
+                    (id, &story_id),
+                )
+                .unwrap();
+        }
+
+        normalize_tags(&db.conn).unwrap();
+        assert_eq!(
+            tag_counts(&db),
+            vec![("tag:Magic".to_owned(), 1), ("tag:Slow Burn".to_owned(), 1),]
+        );
+        let uses: usize = db
+            .conn
+            .query_row("SELECT COUNT(*) FROM tag_uses", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(uses, 2);
+    }
+
+    #[test]
+    fn saves_and_replaces_covers() {
+        let db = memory_db(&DatabaseOptions::default());
+        let story = story(Vec::new());
+        let story_id = story.source.to_id();
+        db.save_story(&story).unwrap();
+        assert_eq!(db.get_cover(&story_id).unwrap(), None);
+
+        let mut cover = Cover {
+            url: "https://www.royalroadcdn.com/covers/6789.jpg".to_owned(),
+            media_type: "image/jpeg".to_owned(),
+            bytes: vec![0xFF, 0xD8, 0xFF],
+        };
+        db.save_cover(&story_id, &cover).unwrap();
+        cover.url = "https://www.royalroadcdn.com/covers/6789-new.png".to_owned();
+        cover.media_type = "image/png".to_owned();
+        cover.bytes = vec![0x89, b'P', b'N', b'G'];
+        db.save_cover(&story_id, &cover).unwrap();
+        assert_eq!(db.get_cover(&story_id).unwrap(), Some(cover.clone()));
+        assert_eq!(db.saved_cover_url(&story_id).unwrap(), Some(cover.url));
+
+        db.delete_story_by_id(&story_id).unwrap();
+        assert_eq!(db.get_cover(&story_id).unwrap(), None);
+    }
+
+    #[test]
+    fn resumes_with_stories_not_checked_this_cycle() {
+        let db = memory_db(&DatabaseOptions::default());
+        let sources: Vec<StorySource> = ["1", "2", "3"]
+            .into_iter()
+            .map(|id| StorySource::RoyalRoad(id.to_owned()))
+            .collect();
+        for source in sources.iter() {
+            let mut story = story(Vec::new());
+            story.url = source.to_url();
+            story.source = source.clone();
+            db.save_story(&story).unwrap();
+        }
+        db.mark_checked(&sources[0].to_id()).unwrap();
+
+        assert_eq!(db.update_cycle_started().unwrap(), None);
+        let started = db.start_update_cycle().unwrap();
+        db.mark_checked(&sources[2].to_id()).unwrap();
+        // The run is interrupted here, so the cycle is still open.
+        assert_eq!(db.update_cycle_started().unwrap(), Some(started.clone()));
+        assert_eq!(
+            db.stories_to_check(&started).unwrap(),
+            vec![
+                (sources[1].clone(), Completed::Incomplete),
+                (sources[0].clone(), Completed::Incomplete)
+            ]
+        );
+
+        db.finish_update_cycle().unwrap();
+        assert_eq!(db.update_cycle_started().unwrap(), None);
+    }
+
+    #[test]
+    fn finds_stories_not_updated_since_cutoff() {
+        let db = memory_db(&DatabaseOptions::default());
+        let sources: Vec<StorySource> = ["1", "2", "3"]
+            .into_iter()
+            .map(|id| StorySource::RoyalRoad(id.to_owned()))
+            .collect();
+        for source in sources.iter() {
+            let mut story = story(Vec::new());
+            story.url = source.to_url();
+            story.source = source.clone();
+            db.save_story(&story).unwrap();
+        }
+        db.conn
+            .execute(
+                "UPDATE stories SET updated_at = ?2 WHERE id = ?1",
+                (sources[0].to_id(), "2024-03-01T00:00:00.000000Z"),
+            )
+            .unwrap();
+        db.conn
+            .execute(
+                "UPDATE stories SET updated_at = NULL WHERE id = ?1",
+                [sources[1].to_id()],
+            )
+            .unwrap();
+
+        assert_eq!(
+            db.get_stories_needing_update("2024-06-01T00:00:00.000000Z")
+                .unwrap(),
+            vec![sources[1].clone(), sources[0].clone()]
+        );
+        assert_eq!(
+            db.get_stories_needing_update("2024-01-01T00:00:00.000000Z")
+                .unwrap(),
+            vec![sources[1].clone()]
+        );
+    }
+}
@@ -1,29 +1,44 @@
-use chrono::{DateTime, FixedOffset};
-use once_cell::sync::OnceCell;
-use regex::Regex;
+use chrono::{DateTime, FixedOffset, TimeZone};
+use clap::ValueEnum;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
 
 use crate::error::ArchiveError;
-use crate::parser::{
-    ao3::AO3Parser, katalepsis::KatalepsisParser, royalroad::RoyalRoadParser,
-    xenforo::XenforoParser, Parser,
-};
+use crate::parser::{self, wordpress, Parser};
+use crate::text::count_words;
 use crate::Result;
 
-#[derive(Debug, Clone)]
+/// A format chapter text can be converted to for output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TextFormat {
+    Html,
+    Markdown,
+    /// Plain text, with a blank line between paragraphs.
+    Text,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
 pub enum Completed {
     Complete,
+    #[value(name = "ongoing", alias = "incomplete")]
     Incomplete,
     Unknown,
 }
 
-impl Completed {
-    pub fn to_string(&self) -> String {
+impl fmt::Display for Completed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Self::Complete => "COMPLETE".to_owned(),
-            Self::Incomplete => "INCOMPLETE".to_owned(),
-            Self::Unknown => "UNKNOWN".to_owned(),
+            Self::Complete => write!(f, "COMPLETE"),
+            Self::Incomplete => write!(f, "INCOMPLETE"),
+            Self::Unknown => write!(f, "UNKNOWN"),
         }
     }
+}
+
+impl Completed {
     pub fn from_string(s: &str) -> Self {
         match s {
             "COMPLETE" => Self::Complete,
@@ -33,16 +48,34 @@ impl Completed {
     }
 }
 
-#[derive(Debug, Clone)]
+/// The namespace of tags given to stories with the `tag` command. Sources never use it, so
+/// updates leave these tags alone.
+pub const USER_TAG_NAMESPACE: &str = "user";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Story {
     pub name: String,
     pub authors: AuthorList,
     pub description: Option<String>,
     pub url: String,
+    /// Tags are namespaced by what they describe, as `namespace:name`. Sources use `fandom`,
+    /// `genre`, `rating` (in lowercase), `warning`, `category`, `relationship`, `character`, and
+    /// `lang` for tags they can tell are one of those, and `tag` for anything else, so that
+    /// `list --tag` works the same way for every source. Tags the user gives a story with the
+    /// `tag` command are in [`USER_TAG_NAMESPACE`].
     pub tags: Vec<String>,
     pub chapters: Vec<Content>,
     pub source: StorySource,
     pub completed: Completed,
+    /// The language the story is written in, as reported by the source (e.g. `en`).
+    pub language: Option<String>,
+    /// A link to the story's cover image, if the source has one.
+    pub cover_url: Option<String>,
+    /// When the source says the story was first published, for sources that say.
+    pub published: Option<DateTime<FixedOffset>>,
+    /// When the source says the story was last updated, which can differ from when its latest
+    /// chapter was posted. Only known for sources that say.
+    pub last_updated: Option<DateTime<FixedOffset>>,
 }
 
 impl Story {
@@ -53,9 +86,120 @@ impl Story {
         })
     }
 
-    pub fn find_chapter(&self, id: String) -> Option<FindChapter> {
+    /// Every chapter of the story in reading order, leaving out the sections they're in.
+    pub fn all_chapters(&self) -> Vec<&Chapter> {
+        fn collect<'a>(contents: &'a [Content], chapters: &mut Vec<&'a Chapter>) {
+            for content in contents {
+                match content {
+                    Content::Chapter(chapter) => chapters.push(chapter),
+                    Content::Section(section) => collect(&section.chapters, chapters),
+                }
+            }
+        }
+        let mut chapters = Vec::with_capacity(self.num_chapters());
+        collect(&self.chapters, &mut chapters);
+        chapters
+    }
+
+    /// Like [`Self::all_chapters`], but lets the chapters be changed, such as to fill in their
+    /// text.
+    pub fn all_chapters_mut(&mut self) -> Vec<&mut Chapter> {
+        fn collect<'a>(contents: &'a mut [Content], chapters: &mut Vec<&'a mut Chapter>) {
+            for content in contents {
+                match content {
+                    Content::Chapter(chapter) => chapters.push(chapter),
+                    Content::Section(section) => collect(&mut section.chapters, chapters),
+                }
+            }
+        }
+        let mut chapters = Vec::with_capacity(self.num_chapters());
+        collect(&mut self.chapters, &mut chapters);
+        chapters
+    }
+
+    /// A copy of the story with every section but only the chapters for which `keep` returns
+    /// true, leaving the others' text uncopied.
+    pub fn with_chapters(&self, keep: &dyn Fn(&str) -> bool) -> Story {
+        fn copy(contents: &[Content], keep: &dyn Fn(&str) -> bool) -> Vec<Content> {
+            contents
+                .iter()
+                .filter_map(|content| match content {
+                    Content::Chapter(chapter) => keep(&chapter.id).then(|| content.clone()),
+                    Content::Section(section) => Some(Content::Section(Section {
+                        id: section.id.clone(),
+                        name: section.name.clone(),
+                        description: section.description.clone(),
+                        chapters: copy(&section.chapters, keep),
+                        url: section.url.clone(),
+                        author: section.author.clone(),
+                    })),
+                })
+                .collect()
+        }
+        Story {
+            name: self.name.clone(),
+            authors: self.authors.clone(),
+            description: self.description.clone(),
+            url: self.url.clone(),
+            tags: self.tags.clone(),
+            chapters: copy(&self.chapters, keep),
+            source: self.source.clone(),
+            completed: self.completed,
+            language: self.language.clone(),
+            cover_url: self.cover_url.clone(),
+            published: self.published,
+            last_updated: self.last_updated,
+        }
+    }
+
+    /// How many words there are in all of the story's chapters that have text.
+    pub fn total_word_count(&self) -> usize {
+        self.all_chapters()
+            .iter()
+            .map(|chapter| chapter.word_count())
+            .sum()
+    }
+
+    /// The story's tags grouped by namespace (the part before the first `:`), with
+    /// un-namespaced tags under the empty string.
+    pub fn tags_by_namespace(&self) -> BTreeMap<String, Vec<String>> {
+        let mut tags: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for tag in self.tags.iter() {
+            let (namespace, name) = tag.split_once(':').unwrap_or(("", tag));
+            tags.entry(namespace.to_owned())
+                .or_default()
+                .push(name.to_owned());
+        }
+        tags
+    }
+
+    /// Finds a chapter by its number in reading order (counting from 1), its id, or its name.
+    /// Names are compared case-insensitively, and part of a name is enough if only one chapter
+    /// has it.
+    pub fn chapter_matching(&self, search: &str) -> Option<&Chapter> {
+        let chapters = self.all_chapters();
+        if let Ok(number) = search.parse::<usize>() {
+            return chapters.get(number.checked_sub(1)?).copied();
+        }
+        let search = search.to_lowercase();
+        if let Some(chapter) = chapters
+            .iter()
+            .find(|chapter| chapter.id == search || chapter.name.to_lowercase() == search)
+        {
+            return Some(chapter);
+        }
+        let mut partial = chapters
+            .into_iter()
+            .filter(|chapter| chapter.name.to_lowercase().contains(&search));
+        match (partial.next(), partial.next()) {
+            (Some(chapter), None) => Some(chapter),
+            _ => None,
+        }
+    }
+
+    pub fn find_chapter(&self, id: String) -> Option<FindChapter<'_>> {
         self.chapters.iter().find_map(|con| {
-            if con.id() == &id {
+            if con.id() == id {
                 Some(FindChapter {
                     chapter: con,
                     parent: None,
@@ -69,13 +213,94 @@ impl Story {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ListedStory {
     pub name: String,
     pub author: String,
     pub chapter_count: usize,
     pub source: StorySource,
     pub completed: Completed,
+    pub language: Option<String>,
+    #[serde(flatten)]
+    pub dates: StoryDates,
+}
+
+/// When a story was first saved to the archive and when it last gained or changed a chapter.
+/// Either may be unknown for stories saved by older versions.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct StoryDates {
+    pub added_at: Option<DateTime<FixedOffset>>,
+    pub updated_at: Option<DateTime<FixedOffset>>,
+}
+
+/// A chapter found by a full-text search.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub story_id: String,
+    pub story_name: String,
+    pub chapter_id: String,
+    pub chapter_name: String,
+    /// Text from around the match, with the matching words wrapped in `**`.
+    pub snippet: String,
+}
+
+/// A chapter in the feed of what was recently added to the archive.
+#[derive(Debug, Clone, Serialize)]
+pub struct NewChapter {
+    pub story_id: String,
+    pub story_name: String,
+    pub chapter_id: String,
+    pub chapter_name: String,
+    pub url: String,
+    pub date_posted: Option<DateTime<FixedOffset>>,
+    /// When the chapter was saved to the archive, if known.
+    pub added_at: Option<DateTime<FixedOffset>>,
+}
+
+/// A tag and how many stories in the archive have it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TagCount {
+    pub name: String,
+    pub stories: usize,
+}
+
+/// Where a reader left off in a story.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadingProgress {
+    pub chapter_id: String,
+    /// How many lines into the chapter the reader had scrolled.
+    pub offset: usize,
+}
+
+/// A story's cover image, as downloaded from its `cover_url`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cover {
+    /// Where the image was downloaded from, so that a changed cover is noticed.
+    pub url: String,
+    /// The image's MIME type, like `image/jpeg`.
+    pub media_type: String,
+    pub bytes: Vec<u8>,
+}
+
+/// An image from a chapter's text, saved so that it doesn't depend on the site it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChapterImage {
+    pub chapter_id: String,
+    /// What the chapter's HTML refers to the image by.
+    pub hash: String,
+    /// Where the image was downloaded from.
+    pub url: String,
+    pub media_type: String,
+    pub bytes: Vec<u8>,
+}
+
+/// How many rows were removed from the archive when a story was deleted.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeletedStory {
+    pub chapters: usize,
+    pub sections: usize,
+    pub authors: usize,
+    pub tags: usize,
 }
 
 pub struct FindChapter<'a> {
@@ -83,7 +308,7 @@ pub struct FindChapter<'a> {
     pub parent: Option<&'a Content>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Content {
     Section(Section),
     Chapter(Chapter),
@@ -97,7 +322,14 @@ impl Content {
         }
     }
 
-    pub fn find_child(&self, id: &str) -> Option<FindChapter> {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Chapter(c) => &c.name,
+            Self::Section(s) => &s.name,
+        }
+    }
+
+    pub fn find_child(&self, id: &str) -> Option<FindChapter<'_>> {
         match self {
             Self::Chapter(_) => None,
             Self::Section(s) => s.chapters.iter().find_map(|con| {
@@ -107,7 +339,7 @@ impl Content {
                         parent: Some(self),
                     })
                 } else if let Content::Section(_) = con {
-                    con.find_child(&id)
+                    con.find_child(id)
                 } else {
                     None
                 }
@@ -116,7 +348,7 @@ impl Content {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Section {
     pub id: String,
     pub name: String,
@@ -135,7 +367,7 @@ impl Section {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Chapter {
     pub id: String,
     pub name: String,
@@ -147,6 +379,22 @@ pub struct Chapter {
 }
 
 impl Chapter {
+    /// The date given to chapters whose real posting date isn't known until they're fetched.
+    pub fn placeholder_date() -> DateTime<FixedOffset> {
+        FixedOffset::east(0).timestamp(0, 0)
+    }
+
+    /// How many words of text the chapter has, not counting markup. Chapters without text have
+    /// none.
+    pub fn word_count(&self) -> usize {
+        self.text.word_count()
+    }
+
+    /// Whether this chapter still has the placeholder date instead of its real posting date.
+    pub fn has_placeholder_date(&self) -> bool {
+        self.date_posted.timestamp() == 0
+    }
+
     pub fn chapter_id(&self) -> String {
         match self.id.rfind(":") {
             Some(idx) => self.id[idx + 1..].to_string(),
@@ -155,7 +403,7 @@ impl Chapter {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ChapterText {
     Hydrated(String),
     Dehydrated,
@@ -168,9 +416,30 @@ impl ChapterText {
             Self::Dehydrated => "",
         }
     }
+
+    /// A hex-encoded SHA-256 hash of the chapter text, or `None` if it hasn't been fetched.
+    /// How many words the text has, not counting markup. Missing text has none.
+    pub fn word_count(&self) -> usize {
+        match self {
+            Self::Hydrated(html) => count_words(html),
+            Self::Dehydrated => 0,
+        }
+    }
+
+    pub fn content_hash(&self) -> Option<String> {
+        match self {
+            Self::Hydrated(s) => Some(
+                Sha256::digest(s.as_bytes())
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect(),
+            ),
+            Self::Dehydrated => None,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Author {
     pub name: String,
     pub id: String,
@@ -185,113 +454,233 @@ impl Author {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Serializes as a plain list of authors. Like [`AuthorList::from_list`], deserializing an empty
+/// list fails.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "Vec<Author>", into = "Vec<Author>")]
 pub struct AuthorList {
     authors: Vec<Author>,
 }
 
 impl AuthorList {
     pub fn new(author: Author) -> AuthorList {
-        let mut authors = Vec::with_capacity(1);
-        authors.push(author);
-        AuthorList { authors }
+        AuthorList {
+            authors: vec![author],
+        }
     }
 
-    pub fn from_list<T: Into<Vec<Author>>>(authors: T) -> AuthorList {
+    /// Builds a list from one or more authors. Every story has at least one, so an empty list
+    /// is an error.
+    pub fn from_list<T: Into<Vec<Author>>>(authors: T) -> Result<AuthorList> {
         let authors = authors.into();
-        assert!(authors.len() > 0); // TODO: This will panic if 0 authors are passed in
-        AuthorList { authors }
+        if authors.is_empty() {
+            return Err(ArchiveError::NoAuthors);
+        }
+        Ok(AuthorList { authors })
     }
 
     pub fn authors(&self) -> &Vec<Author> {
         &self.authors
     }
 
+    #[allow(dead_code)]
     pub fn authors_mut(&mut self) -> &mut Vec<Author> {
         &mut self.authors
     }
 
+    #[allow(dead_code)]
     pub fn len(&self) -> usize {
         self.authors.len()
     }
 }
 
-#[derive(Debug, Clone)]
+impl TryFrom<Vec<Author>> for AuthorList {
+    type Error = ArchiveError;
+
+    fn try_from(authors: Vec<Author>) -> Result<AuthorList> {
+        AuthorList::from_list(authors)
+    }
+}
+
+impl From<AuthorList> for Vec<Author> {
+    fn from(list: AuthorList) -> Vec<Author> {
+        list.authors
+    }
+}
+
+impl fmt::Display for AuthorList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (idx, author) in self.authors.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", author.name)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum StorySource {
     AO3(String),
+    /// An AO3 series, with each of its works as a section.
+    AO3Series(String),
+    FFNet(String),
     Katalepsis,
+    QuestionableQuesting(String),
     RoyalRoad(String),
     SpaceBattles(String),
     SufficientVelocity(String),
+    /// A serial on a WordPress site registered with
+    /// [`register_site`](crate::parser::wordpress::register_site).
+    WordPress {
+        base_url: String,
+    },
+    /// A story from a source added with [`register_parser`](crate::parser::register_parser)
+    /// that has no variant of its own.
+    Registered {
+        prefix: String,
+        id: Option<String>,
+    },
+}
+
+/// Describes a supported source: the prefix its story ids start with, what it's called, and
+/// what its story URLs look like.
+#[derive(Debug, Serialize)]
+pub struct SourceInfo {
+    pub prefix: &'static str,
+    pub name: &'static str,
+    pub url_template: &'static str,
+    pub example: &'static str,
 }
 
-pub static SOURCES_LIST: [&str; 5] = [
-    "Archive of Our Own: https://archiveofourown.org/works/<id>",
-    "Katalepsis: https://katalepsis.net",
-    "RoyalRoad: https://www.royalroad.com/fiction/<id>",
-    "SpaceBattles: https://forums.spacebattles.com/threads/thread_name.<id>",
-    "SufficientVelocity: https://forums.sufficientvelocity.com/threads/thread_name.<id>",
-    // "XenForo: https://<site>/threads/thread_name.<id>",
+impl fmt::Display for SourceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.url_template)
+    }
+}
+
+pub static SOURCES: [SourceInfo; 8] = [
+    SourceInfo {
+        prefix: "ao3",
+        name: "Archive of Our Own",
+        url_template: "https://archiveofourown.org/works/<id>",
+        example: "https://archiveofourown.org/works/12345",
+    },
+    SourceInfo {
+        prefix: "ao3series",
+        name: "Archive of Our Own series",
+        url_template: "https://archiveofourown.org/series/<id>",
+        example: "https://archiveofourown.org/series/12345",
+    },
+    SourceInfo {
+        prefix: "ffnet",
+        name: "FanFiction.Net",
+        url_template: "https://www.fanfiction.net/s/<id>",
+        example: "https://www.fanfiction.net/s/12345/1/a-story-name",
+    },
+    SourceInfo {
+        prefix: "katalepsis",
+        name: "Katalepsis",
+        url_template: "https://katalepsis.net",
+        example: "https://katalepsis.net",
+    },
+    SourceInfo {
+        prefix: "qq",
+        name: "Questionable Questing",
+        url_template: "https://forum.questionablequesting.com/threads/thread_name.<id>",
+        example: "https://forum.questionablequesting.com/threads/a-long-quest.12345",
+    },
+    SourceInfo {
+        prefix: "rr",
+        name: "RoyalRoad",
+        url_template: "https://www.royalroad.com/fiction/<id>",
+        example: "https://www.royalroad.com/fiction/12345",
+    },
+    SourceInfo {
+        prefix: "sb",
+        name: "SpaceBattles",
+        url_template: "https://forums.spacebattles.com/threads/thread_name.<id>",
+        example: "https://forums.spacebattles.com/threads/a-long-quest.12345",
+    },
+    SourceInfo {
+        prefix: "sv",
+        name: "SufficientVelocity",
+        url_template: "https://forums.sufficientvelocity.com/threads/thread_name.<id>",
+        example: "https://forums.sufficientvelocity.com/threads/a-long-quest.12345",
+    },
 ];
 
-static REGEXES: OnceCell<Vec<(&'static str, Regex)>> = OnceCell::new();
-#[rustfmt::skip]
-fn init_regexes() -> Vec<(&'static str, Regex)> {
-    vec![
-        ("ao3", r"^https://archiveofourown.org/works/(?P<id>\d+)/?.*"),
-        ("ffnet", r"^https?://(?:www)?\.fanfiction\.net/s/(?P<id>\d+)/?.*"),
-        ("katalepsis", r"^https?://katalepsis\.net/?.*"),
-        ("rr", r"^https?://(?:www)?\.royalroad\.com/fiction/(?P<id>\d+)/?.*"),
-        ("sb", r"^https?://forums\.spacebattles\.com/threads/([^.]+\.)?(?P<id>\d+)/?.*"),
-        ("sv", r"^https?://forums\.sufficientvelocity\.com/threads/([^.]+\.)?(?P<id>\d+)/?.*"),
-    ]
-    .into_iter()
-    .map(|(src, reg_src)| (src, Regex::new(reg_src).unwrap()))
-    .collect()
+/// Lists the sources stories can be added from.
+pub fn supported_sources() -> &'static [SourceInfo] {
+    &SOURCES
 }
 
 impl StorySource {
+    /// The name of the site the story comes from, like "Archive of Our Own".
+    pub fn site_name(&self) -> &'static str {
+        if let Self::WordPress { .. } = self {
+            return "WordPress";
+        }
+        SOURCES
+            .iter()
+            .find(|source| source.prefix == self.prefix())
+            .map(|source| source.name)
+            .unwrap_or("Unknown site")
+    }
+
     pub fn from_url(url: &str) -> Result<StorySource> {
-        let regex_map = REGEXES.get_or_init(init_regexes);
-        match regex_map.iter().find(|(_, regex)| regex.is_match(url)) {
-            Some((name, regex)) => {
-                let id = regex.captures(url).unwrap().name("id");
-                Ok(match *name {
-                    "ao3" => Self::AO3(
-                        id.ok_or(ArchiveError::NoIdInSource(url.to_owned(), name.to_string()))?
-                            .as_str()
-                            .to_owned(),
-                    ),
-                    "katalepsis" => Self::Katalepsis,
-                    "rr" => Self::RoyalRoad(
-                        id.ok_or(ArchiveError::NoIdInSource(url.to_owned(), name.to_string()))?
-                            .as_str()
-                            .to_owned(),
-                    ),
-                    "sb" => Self::SpaceBattles(
-                        id.ok_or(ArchiveError::NoIdInSource(url.to_owned(), name.to_string()))?
-                            .as_str()
-                            .to_owned(),
-                    ),
-                    "sv" => Self::SufficientVelocity(
-                        id.ok_or(ArchiveError::NoIdInSource(url.to_owned(), name.to_string()))?
-                            .as_str()
-                            .to_owned(),
-                    ),
-                    _ => panic!("URL matched source {name}, which has not been fully implemented"),
-                })
-            }
-            None => Err(ArchiveError::BadSource(url.to_owned())),
+        let (prefix, id) =
+            parser::match_url(url).ok_or_else(|| ArchiveError::BadSource(url.to_owned()))?;
+        let id = || {
+            id.clone().ok_or(ArchiveError::NoIdInSource(
+                url.to_owned(),
+                prefix.to_owned(),
+            ))
+        };
+        Ok(match prefix {
+            "ao3" => Self::AO3(id()?),
+            "ao3series" => Self::AO3Series(id()?),
+            "ffnet" => Self::FFNet(id()?),
+            "katalepsis" => Self::Katalepsis,
+            "qq" => Self::QuestionableQuesting(id()?),
+            "rr" => Self::RoyalRoad(id()?),
+            "sb" => Self::SpaceBattles(id()?),
+            "sv" => Self::SufficientVelocity(id()?),
+            wordpress::PREFIX => Self::WordPress {
+                base_url: format!("https://{}", id()?),
+            },
+            prefix => Self::Registered {
+                prefix: prefix.to_owned(),
+                id: id().ok(),
+            },
+        })
+    }
+
+    /// The part of the story's id after its prefix, if it has one.
+    fn site_id(&self) -> Option<&str> {
+        match self {
+            Self::AO3(id)
+            | Self::AO3Series(id)
+            | Self::FFNet(id)
+            | Self::QuestionableQuesting(id)
+            | Self::RoyalRoad(id)
+            | Self::SpaceBattles(id)
+            | Self::SufficientVelocity(id) => Some(id),
+            Self::Katalepsis => None,
+            Self::WordPress { base_url } => Some(
+                base_url
+                    .split_once("://")
+                    .map_or(base_url.as_str(), |(_, host)| host),
+            ),
+            Self::Registered { id, .. } => id.as_deref(),
         }
     }
 
     pub fn to_id(&self) -> String {
-        match self {
-            Self::AO3(id) => format!("{}:{}", self.prefix(), id),
-            Self::Katalepsis => self.prefix().to_owned(),
-            Self::RoyalRoad(id) => format!("{}:{}", self.prefix(), id),
-            Self::SpaceBattles(id) => format!("{}:{}", self.prefix(), id),
-            Self::SufficientVelocity(id) => format!("{}:{}", self.prefix(), id),
+        match self.site_id() {
+            Some(id) => format!("{}:{}", self.prefix(), id),
+            None => self.prefix().to_owned(),
         }
     }
 
@@ -299,41 +688,360 @@ impl StorySource {
     pub fn prefix(&self) -> &str {
         match self {
             Self::AO3(_) => "ao3",
+            Self::AO3Series(_) => "ao3series",
+            Self::FFNet(_) => "ffnet",
             Self::Katalepsis => "katalepsis",
+            Self::QuestionableQuesting(_) => "qq",
             Self::RoyalRoad(_) => "rr",
             Self::SpaceBattles(_) => "sb",
             Self::SufficientVelocity(_) => "sv",
+            Self::WordPress { .. } => wordpress::PREFIX,
+            Self::Registered { prefix, .. } => prefix,
         }
     }
 
     pub fn to_url(&self) -> String {
-        match self {
-            Self::AO3(id) => {
-                format!("https://archiveofourown.org/works/{}", id)
-            }
-            Self::Katalepsis => "https://katalepsis.net".to_owned(),
-            Self::RoyalRoad(id) => format!("https://www.royalroad.com/fiction/{}", id),
-            Self::SpaceBattles(id) => format!("https://forums.spacebattles.com/threads/{}", id),
-            Self::SufficientVelocity(id) => {
-                format!("https://forums.sufficientvelocity.com/threads/{}", id)
-            }
+        // A WordPress site's URL is kept as given, since its scheme isn't part of the id.
+        if let Self::WordPress { base_url } = self {
+            return base_url.clone();
         }
+        parser::story_url(self.prefix(), self.site_id()).unwrap_or_else(|| {
+            panic!(
+                "No source is registered for prefix {}, which should never happen",
+                self.prefix()
+            )
+        })
     }
 
+    /// The scheme and host of the site the story is on, like `https://forums.spacebattles.com`,
+    /// without a trailing slash.
     pub fn to_base_url(&self) -> String {
         let url = self.to_url();
-        let start = url.find("://").map(|pos| pos + 3).unwrap_or(0);
-        let end = url[start..].find("/").unwrap_or(url[start..].len());
-        url[0..end + start].to_owned()
+        match Url::parse(&url) {
+            Ok(parsed) => parsed.origin().ascii_serialization(),
+            // Every source's URL should parse, but if one doesn't, the base is everything up to
+            // the first slash after the scheme.
+            Err(_) => {
+                let start = url.find("://").map_or(0, |pos| pos + 3);
+                let end = url[start..].find('/').map_or(url.len(), |pos| start + pos);
+                url[..end].to_owned()
+            }
+        }
     }
 
     pub fn parser(&self) -> Box<dyn Parser> {
-        match self {
-            Self::AO3(_) => Box::new(AO3Parser {}),
-            Self::Katalepsis => Box::new(KatalepsisParser {}),
-            Self::RoyalRoad(_) => Box::new(RoyalRoadParser {}),
-            Self::SpaceBattles(_) => Box::new(XenforoParser {}),
-            Self::SufficientVelocity(_) => Box::new(XenforoParser {}),
+        parser::parser_for(self.prefix()).unwrap_or_else(|| {
+            panic!(
+                "No parser is registered for source {}, which should never happen",
+                self.prefix()
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// URLs that should parse, along with the source each should parse to.
+    fn valid_urls() -> Vec<(&'static str, StorySource)> {
+        use StorySource::*;
+        let ao3 = || AO3("12345".to_owned());
+        let series = || AO3Series("678".to_owned());
+        let ffnet = || FFNet("12345".to_owned());
+        let qq = || QuestionableQuesting("789".to_owned());
+        let rr = || RoyalRoad("6789".to_owned());
+        let sb = || SpaceBattles("456".to_owned());
+        let sv = || SufficientVelocity("456".to_owned());
+        vec![
+            ("https://archiveofourown.org/works/12345", ao3()),
+            ("https://archiveofourown.org/works/12345/", ao3()),
+            ("http://archiveofourown.org/works/12345", ao3()),
+            ("https://www.archiveofourown.org/works/12345", ao3()),
+            ("https://archiveofourown.org/works/12345/chapters/987654", ao3()),
+            ("https://archiveofourown.org/works/12345?view_adult=true", ao3()),
+            ("https://archiveofourown.org/works/12345#main", ao3()),
+            ("https://archiveofourown.org/works/12345/chapters/987654?view_full_work=true#workskin", ao3()),
+            ("https://archiveofourown.org/series/678", series()),
+            ("https://www.archiveofourown.org/series/678/", series()),
+            ("https://archiveofourown.org/series/678?page=2", series()),
+            ("https://www.fanfiction.net/s/12345", ffnet()),
+            ("https://www.fanfiction.net/s/12345/1/", ffnet()),
+            ("https://fanfiction.net/s/12345/3/A-Story-Name", ffnet()),
+            ("https://m.fanfiction.net/s/12345/1/", ffnet()),
+            ("https://katalepsis.net", Katalepsis),
+            ("https://katalepsis.net/", Katalepsis),
+            ("http://www.katalepsis.net/table-of-contents/", Katalepsis),
+            ("https://katalepsis.net/2019/02/16/mind-correction-1-1/", Katalepsis),
+            ("https://forum.questionablequesting.com/threads/789", qq()),
+            ("https://forum.questionablequesting.com/threads/a-quest.789/", qq()),
+            ("https://forums.questionablequesting.com/threads/a-quest.789/threadmarks", qq()),
+            ("https://forum.questionablequesting.com/threads/a-quest.789/page-4#post-5000", qq()),
+            ("https://www.royalroad.com/fiction/6789", rr()),
+            ("https://royalroad.com/fiction/6789", rr()),
+            ("https://www.royalroad.com/fiction/6789/", rr()),
+            ("https://www.royalroad.com/fiction/6789/some-story-name", rr()),
+            ("https://www.royalroad.com/fiction/6789/some-story-name/chapter/111/chapter-one", rr()),
+            ("https://www.royalroad.com/fiction/6789?sorting=new", rr()),
+            ("https://forums.spacebattles.com/threads/456", sb()),
+            ("https://forums.spacebattles.com/threads/a-thread.456", sb()),
+            ("https://forums.spacebattles.com/threads/a-thread.456/", sb()),
+            ("https://forums.spacebattles.com/threads/worm-2-0.v2.456/", sb()),
+            ("https://forums.spacebattles.com/threads/a-thread.456/page-12", sb()),
+            ("https://forums.spacebattles.com/threads/a-thread.456/threadmarks?page=2", sb()),
+            ("https://forums.spacebattles.com/threads/a-thread.456/page-3#post-1000", sb()),
+            ("https://forums.sufficientvelocity.com/threads/456", sv()),
+            ("https://forums.sufficientvelocity.com/threads/a-thread.456/", sv()),
+            ("http://forums.sufficientvelocity.com/threads/a-thread.456/reader/page-2", sv()),
+        ]
+    }
+
+    #[test]
+    fn parses_valid_urls() {
+        for (url, expected) in valid_urls() {
+            match StorySource::from_url(url) {
+                Ok(source) => assert_eq!(source, expected, "parsing {}", url),
+                Err(err) => panic!("parsing {} failed: {}", url, err),
+            }
+        }
+    }
+
+    #[test]
+    fn source_examples_parse_to_their_prefix() {
+        for info in supported_sources() {
+            let source = StorySource::from_url(info.example)
+                .unwrap_or_else(|e| panic!("example for {} should parse: {}", info.name, e));
+            assert_eq!(source.prefix(), info.prefix, "parsing {}", info.example);
+            assert_eq!(source.site_name(), info.name);
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_urls() {
+        let urls = [
+            "",
+            "not a url",
+            "https://example.com/works/12345",
+            "https://archiveofourown.org/",
+            "https://archiveofourown.org/works/",
+            "https://archiveofourown.org/works/abc",
+            "https://archiveofourown.org/works/12345abc",
+            "https://archiveofourown.org/series/",
+            "https://archiveofourown.org.evil.com/works/12345",
+            "https://katalepsis.network",
+            "https://www.royalroad.com/fictions/best-rated",
+            "https://www.royalroad.com/fiction/",
+            "https://forums.spacebattles.com/forums/creative-writing.18/",
+            "https://forums.spacebattles.com/threads/",
+            "https://forum.questionablequesting.com/forums/creative-writing.19/",
+            "https://forums.spacebattles.com/threads/a-thread/",
+            "https://www.fanfiction.net/u/12345/someone",
+            "https://www.fanfiction.net/s/",
+            "ftp://archiveofourown.org/works/12345",
+        ];
+        for url in urls {
+            match StorySource::from_url(url) {
+                Err(ArchiveError::BadSource(bad)) => assert_eq!(bad, url),
+                other => panic!("expected BadSource for {:?}, got {:?}", url, other),
+            }
+        }
+    }
+
+    #[test]
+    fn to_url_round_trips() {
+        for (url, expected) in valid_urls() {
+            let reparsed = StorySource::from_url(&expected.to_url()).unwrap();
+            assert_eq!(reparsed, expected, "round-tripping {}", url);
+            assert_eq!(reparsed.to_id(), expected.to_id());
+        }
+    }
+
+    #[test]
+    fn builds_prefixed_ids() {
+        let cases = [
+            (StorySource::AO3("1".to_owned()), "ao3", "ao3:1"),
+            (
+                StorySource::AO3Series("6".to_owned()),
+                "ao3series",
+                "ao3series:6",
+            ),
+            (StorySource::FFNet("5".to_owned()), "ffnet", "ffnet:5"),
+            (StorySource::Katalepsis, "katalepsis", "katalepsis"),
+            (
+                StorySource::QuestionableQuesting("7".to_owned()),
+                "qq",
+                "qq:7",
+            ),
+            (StorySource::RoyalRoad("2".to_owned()), "rr", "rr:2"),
+            (StorySource::SpaceBattles("3".to_owned()), "sb", "sb:3"),
+            (
+                StorySource::SufficientVelocity("4".to_owned()),
+                "sv",
+                "sv:4",
+            ),
+        ];
+        for (source, prefix, id) in cases {
+            assert_eq!(source.prefix(), prefix);
+            assert_eq!(source.to_id(), id);
+            let (id_prefix, rest) = id.split_once(':').unwrap_or((id, ""));
+            assert_eq!(id_prefix, source.prefix());
+            match source {
+                StorySource::Katalepsis => assert_eq!(rest, ""),
+                _ => assert!(source.to_url().ends_with(rest)),
+            }
+        }
+    }
+
+    #[test]
+    fn author_lists_need_an_author() {
+        assert!(matches!(
+            AuthorList::from_list(Vec::new()),
+            Err(ArchiveError::NoAuthors)
+        ));
+        let author = Author {
+            id: "rr:1".to_owned(),
+            name: "Someone".to_owned(),
+        };
+        assert_eq!(AuthorList::from_list(vec![author]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn finds_chapters_by_number_id_or_name() {
+        let chapter = |number: usize, name: &str| {
+            Content::Chapter(Chapter {
+                id: format!("rr:6789:{}", number),
+                name: name.to_owned(),
+                description: None,
+                text: ChapterText::Dehydrated,
+                url: format!("https://www.royalroad.com/fiction/6789/chapter/{}", number),
+                date_posted: Chapter::placeholder_date(),
+                author: None,
+            })
+        };
+        let source = StorySource::RoyalRoad("6789".to_owned());
+        let story = Story {
+            name: "Chapters".to_owned(),
+            authors: AuthorList::new(Author::new("Someone", "rr:1")),
+            description: None,
+            url: source.to_url(),
+            tags: Vec::new(),
+            chapters: vec![
+                chapter(10, "Prologue"),
+                chapter(11, "The Storm, Part 1"),
+                chapter(12, "The Storm, Part 2"),
+            ],
+            source,
+            completed: Completed::Incomplete,
+            language: None,
+            cover_url: None,
+            published: None,
+            last_updated: None,
+        };
+        let found = |search: &str| story.chapter_matching(search).map(|c| c.id.as_str());
+        assert_eq!(found("2"), Some("rr:6789:11"));
+        assert_eq!(found("0"), None);
+        assert_eq!(found("4"), None);
+        assert_eq!(found("rr:6789:12"), Some("rr:6789:12"));
+        assert_eq!(found("PROLOGUE"), Some("rr:6789:10"));
+        assert_eq!(found("part 2"), Some("rr:6789:12"));
+        assert_eq!(found("storm"), None);
+    }
+
+    #[test]
+    fn stories_round_trip_through_json() {
+        let source = StorySource::RoyalRoad("6789".to_owned());
+        let chapter = Chapter {
+            id: "rr:6789:1".to_owned(),
+            name: "Prologue".to_owned(),
+            description: None,
+            text: ChapterText::Hydrated("<p>Once upon a time.</p>".to_owned()),
+            url: "https://www.royalroad.com/fiction/6789/chapter/1".to_owned(),
+            date_posted: FixedOffset::east(3600).timestamp(1500000000, 0),
+            author: None,
+        };
+        let story = Story {
+            name: "Round Trip".to_owned(),
+            authors: AuthorList::new(Author::new("Someone", "rr:1")),
+            description: Some("<p>A story.</p>".to_owned()),
+            url: source.to_url(),
+            tags: vec!["genre:Fantasy".to_owned()],
+            chapters: vec![Content::Section(Section {
+                id: "rr:6789:book-1".to_owned(),
+                name: "Book One".to_owned(),
+                description: None,
+                chapters: vec![Content::Chapter(chapter)],
+                url: None,
+                author: None,
+            })],
+            source,
+            completed: Completed::Incomplete,
+            language: Some("en".to_owned()),
+            cover_url: None,
+            published: None,
+            last_updated: None,
+        };
+        let json = serde_json::to_string(&story).unwrap();
+        let read: Story = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.name, story.name);
+        assert_eq!(read.source, story.source);
+        assert_eq!(read.authors.to_string(), "Someone");
+        assert_eq!(read.tags, story.tags);
+        let chapters = read.all_chapters();
+        assert_eq!(chapters.len(), 1);
+        assert_eq!(chapters[0].text.as_str(), "<p>Once upon a time.</p>");
+        assert_eq!(chapters[0].date_posted, story.all_chapters()[0].date_posted);
+
+        let no_authors = json.replace(r#"[{"name":"Someone","id":"rr:1"}]"#, "[]");
+        assert!(serde_json::from_str::<Story>(&no_authors).is_err());
+    }
+
+    #[test]
+    fn builds_base_urls() {
+        let cases = [
+            (
+                StorySource::AO3("1".to_owned()),
+                "https://archiveofourown.org",
+            ),
+            (
+                StorySource::AO3Series("6".to_owned()),
+                "https://archiveofourown.org",
+            ),
+            (
+                StorySource::FFNet("5".to_owned()),
+                "https://www.fanfiction.net",
+            ),
+            (StorySource::Katalepsis, "https://katalepsis.net"),
+            (
+                StorySource::QuestionableQuesting("7".to_owned()),
+                "https://forum.questionablequesting.com",
+            ),
+            (
+                StorySource::RoyalRoad("2".to_owned()),
+                "https://www.royalroad.com",
+            ),
+            (
+                StorySource::SpaceBattles("3".to_owned()),
+                "https://forums.spacebattles.com",
+            ),
+            (
+                StorySource::SufficientVelocity("4".to_owned()),
+                "https://forums.sufficientvelocity.com",
+            ),
+            (
+                StorySource::WordPress {
+                    base_url: "https://serial.example.com".to_owned(),
+                },
+                "https://serial.example.com",
+            ),
+            (
+                StorySource::WordPress {
+                    base_url: "http://serial.example.com:8080/".to_owned(),
+                },
+                "http://serial.example.com:8080",
+            ),
+        ];
+        for (source, base) in cases {
+            assert_eq!(source.to_base_url(), base);
         }
     }
 }
@@ -0,0 +1,29 @@
+use crossterm::style::Stylize;
+use once_cell::sync::OnceCell;
+
+use std::env;
+use std::io::{self, IsTerminal};
+
+static USE_STYLE: OnceCell<bool> = OnceCell::new();
+
+/// Whether output to stdout should contain colors and other styling.
+///
+/// Styling is turned off when `NO_COLOR` is set to a non-empty value (see <https://no-color.org>),
+/// when `TERM` is `dumb`, and when stdout is not a terminal, so that piped output and log files
+/// get plain text. The decision is made once and reused for the rest of the run.
+pub(crate) fn should_style() -> bool {
+    *USE_STYLE.get_or_init(|| {
+        let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        let dumb_term = env::var("TERM").is_ok_and(|t| t == "dumb");
+        !no_color && !dumb_term && io::stdout().is_terminal()
+    })
+}
+
+/// Renders `text` in bold if output is being styled.
+pub(crate) fn bold(text: &str) -> String {
+    if should_style() {
+        text.bold().to_string()
+    } else {
+        text.to_owned()
+    }
+}
@@ -0,0 +1,319 @@
+use select::{
+    document::Document,
+    node::{Data, Node},
+    predicate,
+};
+
+/// Elements that are dropped from chapter HTML along with everything inside them.
+const REMOVED_ELEMENTS: [&str; 12] = [
+    "script", "style", "noscript", "iframe", "object", "embed", "form", "button", "input",
+    "select", "textarea", "template",
+];
+
+/// Classes of the widgets sites put around or inside chapter text: Xenforo's expand links and
+/// reaction bars, WordPress sharing and related-post boxes, and ad slots.
+const REMOVED_CLASSES: [&str; 13] = [
+    "bbCodeBlock-expandLink",
+    "bbCodeBlock-shrinkLink",
+    "message-actionBar",
+    "reactionsBar",
+    "js-selectToQuote",
+    "shareButtons",
+    "sharedaddy",
+    "sd-sharing-enabled",
+    "jp-relatedposts",
+    "wpcnt",
+    "wpa",
+    "adsbygoogle",
+    "advertisement",
+];
+
+/// Classes of elements that only wrap their contents in something interactive, like a spoiler
+/// that has to be clicked open. They are replaced by what's inside them.
+const UNWRAPPED_CLASSES: [&str; 3] = [
+    "bbCodeSpoiler",
+    "bbCodeSpoiler-content",
+    "bbCodeBlock-expandContent",
+];
+
+/// Elements that never have content, which are written without a closing tag.
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Turns stored chapter or description HTML into readable plain text: one paragraph per line
+/// group with a blank line between paragraphs, `<br>` as a line break, list items on their own
+/// lines, and scene breaks as `* * *`. Tags are dropped and entities decoded.
+pub(crate) fn html_to_text(html: &str) -> String {
+    html_to_lines(html).join("\n")
+}
+
+/// Counts the words in stored chapter HTML, leaving out the markup.
+pub(crate) fn count_words(html: &str) -> usize {
+    html_to_text(html).split_whitespace().count()
+}
+
+/// Cleans scraped chapter HTML so that it stands on its own: scripts, styles, form controls,
+/// comments, tracking pixels, and [known widgets](REMOVED_CLASSES) are removed, and spoilers
+/// and other [click-to-open wrappers](UNWRAPPED_CLASSES) are replaced by their contents.
+pub(crate) fn sanitize_html(html: &str) -> String {
+    let document = Document::from(html);
+    let mut sanitized = String::new();
+    if let Some(body) = document.find(predicate::Name("body")).next() {
+        for child in body.children() {
+            push_sanitized(&mut sanitized, &child);
+        }
+    }
+    sanitized
+}
+
+fn push_sanitized(html: &mut String, node: &Node) {
+    let name = match node.data() {
+        Data::Text(text) => return html.push_str(&escape_html(text, false)),
+        Data::Comment(_) => return,
+        Data::Element(..) => node.name().unwrap_or_default(),
+    };
+    let has_class = |classes: &[&str]| {
+        node.attr("class")
+            .is_some_and(|class| class.split_whitespace().any(|c| classes.contains(&c)))
+    };
+    if REMOVED_ELEMENTS.contains(&name) || has_class(&REMOVED_CLASSES) || is_tracking_pixel(node) {
+        return;
+    }
+    let unwrapped = has_class(&UNWRAPPED_CLASSES);
+    if !unwrapped {
+        html.push('<');
+        html.push_str(name);
+        for (attr, value) in node.attrs() {
+            html.push_str(&format!(" {}=\"{}\"", attr, escape_html(value, true)));
+        }
+        html.push('>');
+        if VOID_ELEMENTS.contains(&name) {
+            return;
+        }
+    }
+    for child in node.children() {
+        push_sanitized(html, &child);
+    }
+    if !unwrapped {
+        html.push_str(&format!("</{}>", name));
+    }
+}
+
+/// Whether an image is too small to be anything but a tracker.
+fn is_tracking_pixel(node: &Node) -> bool {
+    let tiny = |attr: &str| {
+        node.attr(attr)
+            .and_then(|size| size.trim_end_matches("px").parse::<u32>().ok())
+            .is_some_and(|size| size <= 1)
+    };
+    node.name() == Some("img") && tiny("width") && tiny("height")
+}
+
+/// Escapes text for writing into HTML, either as element content or, if `attribute` is set, as
+/// a quoted attribute value.
+pub(crate) fn escape_html(text: &str, attribute: bool) -> String {
+    let escaped = text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\u{a0}', "&nbsp;");
+    match attribute {
+        true => escaped.replace('"', "&quot;"),
+        false => escaped,
+    }
+}
+
+/// Like [`html_to_text`], but returns the lines separately.
+pub(crate) fn html_to_lines(html: &str) -> Vec<String> {
+    let document = Document::from(html);
+    let mut lines = vec![Line::default()];
+    if let Some(root) = document.nth(0) {
+        push_text(&root, &mut lines, &mut Vec::new());
+    }
+    let mut lines: Vec<String> = lines
+        .into_iter()
+        .map(|line| {
+            let text = line.text.split_whitespace().collect::<Vec<_>>().join(" ");
+            match text.is_empty() {
+                true => text,
+                false => format!("{}{}", "  ".repeat(line.indent), text),
+            }
+        })
+        .collect();
+    lines.dedup_by(|a, b| a.is_empty() && b.is_empty());
+    while lines.first().is_some_and(|line| line.is_empty()) {
+        lines.remove(0);
+    }
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+#[derive(Default)]
+struct Line {
+    /// How many levels of nested list the line is in, past the first.
+    indent: usize,
+    text: String,
+}
+
+/// How list items at each level of nesting are marked: `None` for bullets, or the number of
+/// the next item in an ordered list.
+type ListStack = Vec<Option<usize>>;
+
+fn push_text(node: &Node, lines: &mut Vec<Line>, lists: &mut ListStack) {
+    if let Some(text) = node.as_text() {
+        current(lines).text.push_str(text);
+        return;
+    }
+    match node.name() {
+        Some("br") => new_line(lines, lists),
+        Some("hr") => {
+            for text in ["", "* * *", ""] {
+                lines.push(Line {
+                    indent: 0,
+                    text: text.to_owned(),
+                });
+            }
+        }
+        Some(list @ ("ul" | "ol")) => {
+            // Nested lists carry on from their item's line; top-level ones are set apart.
+            let nested = !lists.is_empty();
+            match nested {
+                true => break_line(lines, lists),
+                false => lines.push(Line::default()),
+            }
+            lists.push((list == "ol").then_some(1));
+            push_children(node, lines, lists);
+            lists.pop();
+            match nested {
+                true => break_line(lines, lists),
+                false => lines.push(Line::default()),
+            }
+        }
+        Some("li") => {
+            break_line(lines, lists);
+            let marker = match lists.last_mut() {
+                Some(Some(number)) => {
+                    *number += 1;
+                    format!("{}. ", *number - 1)
+                }
+                _ => "- ".to_owned(),
+            };
+            current(lines).text.push_str(&marker);
+            push_children(node, lines, lists);
+            // Items of one list stay together instead of being separated like paragraphs.
+            break_line(lines, lists);
+        }
+        Some(
+            "p" | "div" | "blockquote" | "details" | "summary" | "h1" | "h2" | "h3" | "h4" | "h5"
+            | "h6" | "tr",
+        ) => {
+            new_line(lines, lists);
+            push_children(node, lines, lists);
+            new_line(lines, lists);
+        }
+        Some("script" | "style") => (),
+        _ => push_children(node, lines, lists),
+    }
+}
+
+fn push_children(node: &Node, lines: &mut Vec<Line>, lists: &mut ListStack) {
+    for child in node.children() {
+        push_text(&child, lines, lists);
+    }
+}
+
+fn current(lines: &mut [Line]) -> &mut Line {
+    lines.last_mut().expect("Always at least one line")
+}
+
+fn new_line(lines: &mut Vec<Line>, lists: &ListStack) {
+    lines.push(Line {
+        indent: lists.len().saturating_sub(1),
+        text: String::new(),
+    });
+}
+
+/// Starts a new line unless the current one has nothing on it yet.
+fn break_line(lines: &mut Vec<Line>, lists: &ListStack) {
+    let line = current(lines);
+    if line.text.trim().is_empty() {
+        line.text.clear();
+        line.indent = lists.len().saturating_sub(1);
+    } else {
+        new_line(lines, lists);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separates_paragraphs_and_breaks_lines() {
+        let html = "<p>First paragraph,\n  wrapped.</p><p>Second<br>line two</p><hr><p>After</p>";
+        assert_eq!(
+            html_to_text(html),
+            "First paragraph, wrapped.\n\nSecond\nline two\n\n* * *\n\nAfter"
+        );
+    }
+
+    #[test]
+    fn flattens_nested_formatting_and_links() {
+        let html = "<p>Some <b>bold <i>and <u>nested</u></i></b> text with \
+            <a href=\"https://example.com\">a link</a>.</p>";
+        assert_eq!(html_to_text(html), "Some bold and nested text with a link.");
+    }
+
+    #[test]
+    fn decodes_entities() {
+        let html = "<p>Fish &amp; chips &lt;3 &quot;quoted&quot; &#8212; caf&eacute;&nbsp;au&nbsp;lait</p>";
+        assert_eq!(
+            html_to_text(html),
+            "Fish & chips <3 \"quoted\" \u{2014} caf\u{e9} au lait"
+        );
+    }
+
+    #[test]
+    fn puts_list_items_on_their_own_lines() {
+        let html = "<p>Before</p>\n<ul>\n  <li>One</li>\n  <li>Two\n    <ol><li>Nested</li><li>Again</li></ol>\n  </li>\n</ul><p>After</p>";
+        assert_eq!(
+            html_to_lines(html),
+            vec![
+                "Before",
+                "",
+                "- One",
+                "- Two",
+                "  1. Nested",
+                "  2. Again",
+                "",
+                "After"
+            ]
+        );
+    }
+
+    #[test]
+    fn sanitizes_scraped_html() {
+        let html = "<p>Kept <em class=\"x\">as&nbsp;is</em> &amp; escaped &lt;3</p>\
+            <script>track()</script><!-- comment --><img src=\"pixel.gif\" width=\"1\" height=\"1\">\
+            <div class=\"sharedaddy sd-block\"><a href=\"#\">Share</a></div>\
+            <div class=\"bbCodeSpoiler\"><button class=\"bbCodeSpoiler-button\">Spoiler</button>\
+            <div class=\"bbCodeSpoiler-content\"><p>Hidden<br>text</p></div></div>\
+            <div class=\"bbCodeBlock-expandLink js-expandLink\"><a>Click to expand...</a></div>\
+            <p><img src=\"art.png\" width=\"400\"></p>";
+        assert_eq!(
+            sanitize_html(html),
+            "<p>Kept <em class=\"x\">as&nbsp;is</em> &amp; escaped &lt;3</p>\
+            <p>Hidden<br>text</p><p><img src=\"art.png\" width=\"400\"></p>"
+        );
+    }
+
+    #[test]
+    fn drops_scripts_and_styles() {
+        let html = "<style>p { color: red; }</style><p>Visible</p><script>alert(1)</script>";
+        assert_eq!(html_to_text(html), "Visible");
+    }
+}
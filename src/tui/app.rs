@@ -0,0 +1,417 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use tui::widgets::ListState;
+
+use super::detail::{DetailLoader, DetailState, StoryDetail};
+use super::fetch::ChapterFetcher;
+use super::keys::{action_for, Action, View};
+use super::reader::{Download, Reader};
+use crate::sql::{Database, StoryFilter};
+use crate::structs::{Completed, ListedStory, Story};
+use crate::Result;
+
+/// A prompt drawn over the rest of the UI that takes all input until it's dismissed.
+pub(crate) enum Modal {
+    /// Asks whether to delete the story with the given id and name.
+    ConfirmDelete { id: String, name: String },
+    /// Reads a tag to filter the list by.
+    TagFilter { input: String },
+    /// Lists every key binding.
+    Help,
+    /// Shows the open story's table of contents, with the entry at the given index selected.
+    Contents { selected: usize },
+}
+
+/// Everything the TUI knows about what's on screen.
+pub(crate) struct App {
+    pub stories: Vec<ListedStory>,
+    pub list_state: ListState,
+    pub detail: DetailState,
+    pub modal: Option<Modal>,
+    /// A one-line message for the status bar, such as the result of the last action.
+    pub message: Option<String>,
+    pub filter: StoryFilter,
+    /// The story being read, if the reader is open.
+    pub reader: Option<Reader>,
+    pub should_quit: bool,
+    loader: DetailLoader,
+    fetcher: ChapterFetcher,
+}
+
+impl App {
+    pub fn new(db: &Database, loader: DetailLoader, fetcher: ChapterFetcher) -> Result<App> {
+        let filter = StoryFilter::default();
+        let stories = db.get_stories(&filter)?;
+        let mut app = App {
+            stories,
+            list_state: ListState::default(),
+            detail: DetailState::Empty,
+            modal: None,
+            message: None,
+            filter,
+            reader: None,
+            should_quit: false,
+            loader,
+            fetcher,
+        };
+        if !app.stories.is_empty() {
+            app.select(0);
+        }
+        Ok(app)
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent, db: &Database) {
+        if let Some(modal) = self.modal.take() {
+            self.handle_modal_key(modal, key, db);
+            return;
+        }
+        let action = match action_for(self.view(), key.code) {
+            Some(action) => action,
+            None => return,
+        };
+        match action {
+            Action::Quit => self.quit(db),
+            Action::Up => self.move_selection(-1),
+            Action::Down => self.move_selection(1),
+            Action::Open => self.open_reader(db),
+            Action::Close => self.close_reader(db),
+            Action::ScrollUp => self.with_reader(|reader| reader.scroll_by(-1)),
+            Action::ScrollDown => self.with_reader(|reader| reader.scroll_by(1)),
+            Action::PageUp => self.with_reader(|reader| reader.page_by(-1)),
+            Action::PageDown => self.with_reader(|reader| reader.page_by(1)),
+            Action::NextChapter => {
+                self.with_reader(Reader::next_chapter);
+                self.chapter_changed(db);
+            }
+            Action::PreviousChapter => {
+                self.with_reader(Reader::previous_chapter);
+                self.chapter_changed(db);
+            }
+            Action::ShowContents => {
+                if let Some(ref reader) = self.reader {
+                    let selected = reader
+                        .contents()
+                        .iter()
+                        .position(|entry| entry.chapter == Some(reader.chapter()))
+                        .unwrap_or(0);
+                    self.modal = Some(Modal::Contents { selected });
+                }
+            }
+            Action::Delete => self.confirm_delete(),
+            Action::ToggleHelp => self.modal = Some(Modal::Help),
+            Action::CycleStatusFilter => {
+                self.filter.completed = match self.filter.completed {
+                    None => Some(Completed::Complete),
+                    Some(Completed::Complete) => Some(Completed::Incomplete),
+                    Some(_) => None,
+                };
+                self.reload(db);
+            }
+            Action::FilterByTag => {
+                self.modal = Some(Modal::TagFilter {
+                    input: self.filter.tags.first().cloned().unwrap_or_default(),
+                })
+            }
+        }
+    }
+
+    pub fn view(&self) -> View {
+        match self.reader {
+            Some(_) => View::Reader,
+            None => View::List,
+        }
+    }
+
+    /// Quits the TUI, first saving the reader's place if a story is open.
+    pub fn quit(&mut self, db: &Database) {
+        self.save_progress(db);
+        self.should_quit = true;
+    }
+
+    fn with_reader<F: FnOnce(&mut Reader)>(&mut self, f: F) {
+        if let Some(ref mut reader) = self.reader {
+            f(reader);
+        }
+    }
+
+    /// Opens the selected story in the reader, picking up where it was last left off.
+    fn open_reader(&mut self, db: &Database) {
+        let id = match self.selected_id() {
+            Some(id) => id,
+            None => return,
+        };
+        let opened = db
+            .get_story_by_id(&id)
+            .map(|story| story.map(|story| (story, db.get_progress(&id))));
+        match opened {
+            Ok(Some((story, Ok(progress)))) => self.reader = Some(Reader::open(story, progress)),
+            Ok(Some((story, Err(err)))) => {
+                self.message = Some(format!("Could not load reading progress: {}", err));
+                self.reader = Some(Reader::open(story, None));
+            }
+            Ok(None) => self.message = Some(format!("Story {} is no longer in the archive", id)),
+            Err(err) => self.message = Some(format!("Could not open story: {}", err)),
+        }
+        self.fetch_if_needed();
+    }
+
+    /// Saves the reader's place after it moves to another chapter, and starts downloading the
+    /// chapter's text if it has none.
+    fn chapter_changed(&mut self, db: &Database) {
+        self.save_progress(db);
+        self.fetch_if_needed();
+    }
+
+    fn fetch_if_needed(&mut self) {
+        if let Some(ref mut reader) = self.reader {
+            if matches!(reader.download, Download::Running) {
+                return;
+            }
+            if let Some(chapter_id) = reader.missing_chapter() {
+                let chapter_id = chapter_id.to_owned();
+                reader.download = Download::Running;
+                self.fetcher.request(reader.story_id.clone(), chapter_id);
+            }
+        }
+    }
+
+    /// Takes in a story with a chapter that was downloaded in the background, if it's still the
+    /// one being read. If the reader has moved on to another missing chapter in the meantime,
+    /// that one is downloaded next.
+    pub fn chapters_fetched(&mut self, id: String, story: std::result::Result<Box<Story>, String>) {
+        let reader = match self.reader {
+            Some(ref mut reader) if reader.story_id == id => reader,
+            _ => return,
+        };
+        match story {
+            Ok(story) => {
+                reader.download = Download::Idle;
+                reader.fill_in(*story);
+                self.fetch_if_needed();
+            }
+            Err(err) => {
+                self.message = Some(format!("Could not download chapter: {}", err));
+                reader.download = Download::Failed(err);
+            }
+        }
+    }
+
+    fn close_reader(&mut self, db: &Database) {
+        self.save_progress(db);
+        self.reader = None;
+    }
+
+    fn save_progress(&mut self, db: &Database) {
+        let saved = match self.reader {
+            Some(ref reader) => match reader.progress() {
+                Some(progress) => db.set_progress(&reader.story_id, &progress),
+                None => Ok(()),
+            },
+            None => Ok(()),
+        };
+        if let Err(err) = saved {
+            self.message = Some(format!("Could not save reading progress: {}", err));
+        }
+    }
+
+    /// Describes how many stories are listed and which filters are narrowing them down.
+    pub fn status_line(&self) -> String {
+        let mut line = format!(
+            "{} stor{}",
+            self.stories.len(),
+            if self.stories.len() == 1 { "y" } else { "ies" }
+        );
+        if let Some(completed) = self.filter.completed {
+            line.push_str(&format!(
+                " | status: {}",
+                completed.to_string().to_lowercase()
+            ));
+        }
+        if !self.filter.tags.is_empty() {
+            line.push_str(&format!(" | tag: {}", self.filter.tags.join(", ")));
+        }
+        if let Some(ref message) = self.message {
+            line.push_str(" | ");
+            line.push_str(message);
+        }
+        line
+    }
+
+    /// Reloads the list with the current filters, keeping the same story selected if it's still
+    /// there.
+    fn reload(&mut self, db: &Database) {
+        let selected = self.selected_id();
+        match db.get_stories(&self.filter) {
+            Ok(stories) => self.stories = stories,
+            Err(err) => {
+                self.message = Some(format!("Could not load stories: {}", err));
+                self.stories = Vec::new();
+            }
+        }
+        let idx = selected.and_then(|id| {
+            self.stories
+                .iter()
+                .position(|story| story.source.to_id() == id)
+        });
+        match idx {
+            _ if self.stories.is_empty() => {
+                self.list_state.select(None);
+                self.detail = DetailState::Empty;
+            }
+            Some(idx) if Some(idx) == self.list_state.selected() => (),
+            Some(idx) => self.select(idx),
+            None => self.select(0),
+        }
+    }
+
+    /// Handles a key press while a modal is open. For the delete confirmation, anything other
+    /// than an explicit yes dismisses the modal without doing anything.
+    fn handle_modal_key(&mut self, modal: Modal, key: KeyEvent, db: &Database) {
+        match modal {
+            Modal::ConfirmDelete { id, name } => match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => self.delete(&id, &name, db),
+                _ => self.message = Some(format!("Did not delete \"{}\"", name)),
+            },
+            Modal::TagFilter { mut input } => match key.code {
+                KeyCode::Enter => {
+                    let tag = input.trim();
+                    self.filter.tags = match tag.is_empty() {
+                        true => Vec::new(),
+                        false => vec![tag.to_owned()],
+                    };
+                    self.reload(db);
+                }
+                KeyCode::Esc => (),
+                KeyCode::Backspace => {
+                    input.pop();
+                    self.modal = Some(Modal::TagFilter { input });
+                }
+                KeyCode::Char(c) => {
+                    input.push(c);
+                    self.modal = Some(Modal::TagFilter { input });
+                }
+                _ => self.modal = Some(Modal::TagFilter { input }),
+            },
+            Modal::Contents { selected } => {
+                let entries = match self.reader {
+                    Some(ref reader) => reader.contents().len(),
+                    None => return,
+                };
+                match action_for(View::Contents, key.code) {
+                    Some(Action::Up) => {
+                        self.modal = Some(Modal::Contents {
+                            selected: selected.saturating_sub(1),
+                        })
+                    }
+                    Some(Action::Down) => {
+                        self.modal = Some(Modal::Contents {
+                            selected: (selected + 1).min(entries.saturating_sub(1)),
+                        })
+                    }
+                    Some(Action::Open) => self.open_contents_entry(selected, db),
+                    Some(Action::Close) => (),
+                    _ => self.modal = Some(Modal::Contents { selected }),
+                }
+            }
+            Modal::Help => match key.code {
+                KeyCode::Esc => (),
+                code if action_for(self.view(), code) == Some(Action::ToggleHelp) => (),
+                _ => self.modal = Some(Modal::Help),
+            },
+        }
+    }
+
+    /// Goes to the chapter at the given entry in the table of contents, or for a section, the
+    /// first chapter in it.
+    fn open_contents_entry(&mut self, selected: usize, db: &Database) {
+        let chapter = self.reader.as_ref().and_then(|reader| {
+            reader.contents()[selected..]
+                .iter()
+                .find_map(|entry| entry.chapter)
+        });
+        if let Some(chapter) = chapter {
+            self.with_reader(|reader| reader.go_to_chapter(chapter));
+            self.chapter_changed(db);
+        }
+    }
+
+    fn confirm_delete(&mut self) {
+        if let Some(story) = self
+            .list_state
+            .selected()
+            .and_then(|idx| self.stories.get(idx))
+        {
+            self.modal = Some(Modal::ConfirmDelete {
+                id: story.source.to_id(),
+                name: story.name.clone(),
+            });
+        }
+    }
+
+    fn delete(&mut self, id: &str, name: &str, db: &Database) {
+        match db.delete_story_by_id(id) {
+            Ok(deleted) => {
+                self.message = Some(format!(
+                    "Deleted \"{}\" ({} chapter{})",
+                    name,
+                    deleted.chapters,
+                    if deleted.chapters == 1 { "" } else { "s" }
+                ));
+                self.stories.retain(|story| story.source.to_id() != id);
+                match self.list_state.selected() {
+                    _ if self.stories.is_empty() => {
+                        self.list_state.select(None);
+                        self.detail = DetailState::Empty;
+                    }
+                    Some(idx) => self.select(idx.min(self.stories.len() - 1)),
+                    None => self.select(0),
+                }
+            }
+            Err(err) => self.message = Some(format!("Could not delete \"{}\": {}", name, err)),
+        }
+    }
+
+    /// Takes in details loaded in the background, ignoring any that are for a story that is no
+    /// longer selected.
+    pub fn detail_loaded(
+        &mut self,
+        id: String,
+        detail: std::result::Result<Box<StoryDetail>, String>,
+    ) {
+        if self.selected_id().as_deref() != Some(id.as_str()) {
+            return;
+        }
+        self.detail = match detail {
+            Ok(detail) => DetailState::Loaded(detail),
+            Err(err) => DetailState::Failed(err),
+        };
+    }
+
+    fn selected_id(&self) -> Option<String> {
+        self.list_state
+            .selected()
+            .and_then(|idx| self.stories.get(idx))
+            .map(|story| story.source.to_id())
+    }
+
+    fn move_selection(&mut self, by: isize) {
+        if self.stories.is_empty() {
+            return;
+        }
+        let current = self.list_state.selected().unwrap_or(0) as isize;
+        let next = (current + by).clamp(0, self.stories.len() as isize - 1) as usize;
+        if Some(next) != self.list_state.selected() {
+            self.select(next);
+        }
+    }
+
+    fn select(&mut self, idx: usize) {
+        self.list_state.select(Some(idx));
+        match self.selected_id() {
+            Some(id) => {
+                self.detail = DetailState::Loading;
+                self.loader.request(id);
+            }
+            None => self.detail = DetailState::Empty,
+        }
+    }
+}
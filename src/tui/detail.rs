@@ -0,0 +1,162 @@
+use chrono::{DateTime, FixedOffset};
+use crossterm::event::KeyEvent;
+use tui::{
+    backend::Backend,
+    layout::Rect,
+    style::{Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Paragraph, Wrap},
+    Frame,
+};
+
+use super::event::Event;
+use crate::sql::{Database, DatabaseOptions};
+use crate::structs::{Completed, Story};
+use crate::text::html_to_lines;
+
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+use std::thread;
+
+/// Everything the detail panel shows about a story, worked out once when it's loaded.
+pub(crate) struct StoryDetail {
+    name: String,
+    authors: String,
+    description: Option<Vec<String>>,
+    /// Tags grouped by namespace (the part before the first `:`), with un-namespaced tags
+    /// under the empty string.
+    tags: BTreeMap<String, Vec<String>>,
+    completed: Completed,
+    chapters: usize,
+    words: usize,
+    last_updated: Option<DateTime<FixedOffset>>,
+}
+
+impl StoryDetail {
+    pub fn from_story(story: &Story) -> StoryDetail {
+        let chapters = story.all_chapters();
+        StoryDetail {
+            name: story.name.clone(),
+            authors: story.authors.to_string(),
+            description: story
+                .description
+                .as_deref()
+                .map(html_to_lines)
+                .filter(|description| !description.is_empty()),
+            tags: story.tags_by_namespace(),
+            completed: story.completed,
+            chapters: chapters.len(),
+            words: story.total_word_count(),
+            last_updated: chapters.iter().map(|chapter| chapter.date_posted).max(),
+        }
+    }
+}
+
+/// Loads story details on a background thread with its own database connection, so moving
+/// through the list never waits on the database.
+pub(crate) struct DetailLoader {
+    requests: mpsc::Sender<String>,
+}
+
+impl DetailLoader {
+    pub fn spawn(
+        db_path: String,
+        db_options: DatabaseOptions,
+        events: mpsc::Sender<Event<KeyEvent>>,
+    ) -> DetailLoader {
+        let (requests, incoming) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            let db = Database::new(&db_path, &db_options).map_err(|err| err.to_string());
+            while let Ok(mut id) = incoming.recv() {
+                // Only the most recent request matters if the selection moved on in the meantime.
+                while let Ok(newer) = incoming.try_recv() {
+                    id = newer;
+                }
+                let detail = match db {
+                    Ok(ref db) => match db.get_story_by_id(&id) {
+                        Ok(Some(story)) => Ok(Box::new(StoryDetail::from_story(&story))),
+                        Ok(None) => Err(format!("Story {} is no longer in the archive", id)),
+                        Err(err) => Err(err.to_string()),
+                    },
+                    Err(ref err) => Err(err.clone()),
+                };
+                if events.send(Event::Detail(id, detail)).is_err() {
+                    break;
+                }
+            }
+        });
+        DetailLoader { requests }
+    }
+
+    pub fn request(&self, id: String) {
+        let _ = self.requests.send(id);
+    }
+}
+
+/// The state of the detail panel for the selected story.
+pub(crate) enum DetailState {
+    Empty,
+    Loading,
+    Loaded(Box<StoryDetail>),
+    Failed(String),
+}
+
+pub(crate) fn render<B: Backend>(frame: &mut Frame<B>, area: Rect, state: &DetailState) {
+    let block = Block::default().borders(Borders::ALL).title("Details");
+    let lines = match state {
+        DetailState::Empty => vec![Spans::from("No story selected.")],
+        DetailState::Loading => vec![Spans::from("Loading...")],
+        DetailState::Failed(err) => vec![Spans::from(format!("Could not load story: {}", err))],
+        DetailState::Loaded(detail) => detail_lines(detail),
+    };
+    let paragraph = Paragraph::new(lines).block(block).wrap(Wrap { trim: true });
+    frame.render_widget(paragraph, area);
+}
+
+fn detail_lines(detail: &StoryDetail) -> Vec<Spans<'static>> {
+    let label = |text: &str| {
+        Span::styled(
+            format!("{}: ", text),
+            Style::default().add_modifier(Modifier::BOLD),
+        )
+    };
+    let mut lines = vec![
+        Spans::from(Span::styled(
+            detail.name.clone(),
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+        Spans::from(vec![label("By"), Span::raw(detail.authors.clone())]),
+        Spans::from(vec![
+            label("Status"),
+            Span::raw(detail.completed.to_string()),
+        ]),
+        Spans::from(vec![
+            label("Chapters"),
+            Span::raw(format!("{} ({} words)", detail.chapters, detail.words)),
+        ]),
+        Spans::from(vec![
+            label("Last updated"),
+            Span::raw(
+                detail
+                    .last_updated
+                    .map(|date| date.format("%Y-%m-%d").to_string())
+                    .unwrap_or_else(|| "unknown".to_owned()),
+            ),
+        ]),
+    ];
+    for (namespace, tags) in detail.tags.iter() {
+        let namespace = match namespace.as_str() {
+            "" => "Tags",
+            namespace => namespace,
+        };
+        lines.push(Spans::from(vec![
+            label(namespace),
+            Span::raw(tags.join(", ")),
+        ]));
+    }
+    if let Some(ref description) = detail.description {
+        lines.push(Spans::from(""));
+        lines.extend(description.iter().map(|line| Spans::from(line.clone())));
+    }
+    lines
+}
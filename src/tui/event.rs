@@ -1,17 +1,31 @@
 use crossterm::event::{poll, read, Event as TermEvent, KeyCode, KeyEvent, KeyModifiers};
 
+use super::detail::StoryDetail;
+use crate::structs::Story;
+
 use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
 
+/// How long to wait for input before sending a tick, so the UI keeps redrawing promptly even
+/// when nothing is being pressed.
+const TICK_RATE: Duration = Duration::from_millis(250);
+
 pub enum Event<I> {
     Input(I),
+    /// Details for the story with the given id have finished loading in the background.
+    Detail(String, Result<Box<StoryDetail>, String>),
+    /// The chapters of the story with the given id that had no text have been downloaded.
+    Fetched(String, Result<Box<Story>, String>),
+    /// The terminal was resized to the given number of columns and rows.
+    Resize(u16, u16),
     Quit,
     Tick,
 }
 
 pub struct Events {
     recv: mpsc::Receiver<Event<KeyEvent>>,
+    sender: mpsc::Sender<Event<KeyEvent>>,
     _input_handle: thread::JoinHandle<()>,
 }
 
@@ -21,15 +35,13 @@ impl Events {
         let _input_handle = {
             let sender = sender.clone();
             thread::spawn(move || loop {
-                let event = match poll(Duration::from_millis(3000))
-                    .expect("Docs say this will never return Err")
-                {
+                let event = match poll(TICK_RATE).expect("Docs say this will never return Err") {
                     true => match read() {
                         Ok(TermEvent::Key(event)) => match (event.code, event.modifiers) {
-                            (KeyCode::Char('q'), KeyModifiers::NONE)
-                            | (KeyCode::Char('c'), KeyModifiers::CONTROL) => Event::Quit,
+                            (KeyCode::Char('c'), KeyModifiers::CONTROL) => Event::Quit,
                             _ => Event::Input(event),
                         },
+                        Ok(TermEvent::Resize(cols, rows)) => Event::Resize(cols, rows),
                         Ok(_) => Event::Tick,
                         Err(_) => Event::Tick,
                     },
@@ -40,10 +52,16 @@ impl Events {
         };
         Events {
             recv: reciever,
+            sender,
             _input_handle,
         }
     }
 
+    /// Gets a handle that background work can use to send events to the UI loop.
+    pub fn sender(&self) -> mpsc::Sender<Event<KeyEvent>> {
+        self.sender.clone()
+    }
+
     pub fn next(&self) -> Event<KeyEvent> {
         match self.recv.recv() {
             Ok(e) => e,
@@ -0,0 +1,72 @@
+use crossterm::event::KeyEvent;
+use reqwest::Client;
+
+use super::event::Event;
+use crate::client::{new_client, ClientOptions};
+use crate::error::ArchiveError;
+use crate::sql::{Database, DatabaseOptions};
+use crate::structs::Story;
+use crate::Result;
+
+use std::sync::mpsc;
+use std::thread;
+
+/// Downloads the text of chapters that were saved without it on a background thread, with its
+/// own database connection and HTTP client, so the reader stays responsive while it waits. The
+/// connection stores chapter text the way `--dedup-text` says, like the main one.
+pub(crate) struct ChapterFetcher {
+    requests: mpsc::Sender<(String, String)>,
+}
+
+impl ChapterFetcher {
+    pub fn spawn(
+        db_path: String,
+        db_options: DatabaseOptions,
+        dedup_text: bool,
+        client_options: ClientOptions,
+        events: mpsc::Sender<Event<KeyEvent>>,
+    ) -> ChapterFetcher {
+        let (requests, incoming) = mpsc::channel::<(String, String)>();
+        thread::spawn(move || {
+            let setup = || -> Result<_> {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?;
+                let db = Database::new(&db_path, &db_options)?.dedup_chapter_text(dedup_text);
+                let client = new_client(&client_options)?;
+                Ok((runtime, db, client))
+            };
+            let setup = setup().map_err(|err| err.to_string());
+            while let Ok((id, chapter_id)) = incoming.recv() {
+                let story = match setup {
+                    Ok((ref runtime, ref db, ref client)) => runtime
+                        .block_on(fetch_chapter(db, client, &id, &chapter_id))
+                        .map_err(|err| err.to_string()),
+                    Err(ref err) => Err(err.clone()),
+                };
+                if events.send(Event::Fetched(id, story)).is_err() {
+                    break;
+                }
+            }
+        });
+        ChapterFetcher { requests }
+    }
+
+    /// Asks for the text of one chapter of the story with the given id to be downloaded.
+    pub fn request(&self, id: String, chapter_id: String) {
+        let _ = self.requests.send((id, chapter_id));
+    }
+}
+
+/// Downloads and saves one chapter's text, then loads the story back with it filled in.
+async fn fetch_chapter(
+    db: &Database,
+    client: &Client,
+    id: &str,
+    chapter_id: &str,
+) -> Result<Box<Story>> {
+    db.hydrate_chapter(client, id, chapter_id).await?;
+    db.get_story_by_id(id)?
+        .map(Box::new)
+        .ok_or_else(|| ArchiveError::StoryNotExists(id.to_owned()))
+}
@@ -0,0 +1,206 @@
+use crossterm::event::KeyCode;
+
+/// Which part of the TUI has focus, and so which bindings apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum View {
+    List,
+    Reader,
+    /// The table of contents shown over the reader.
+    Contents,
+}
+
+/// Something the user can ask the TUI to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Action {
+    Up,
+    Down,
+    Open,
+    Delete,
+    CycleStatusFilter,
+    FilterByTag,
+    ToggleHelp,
+    Quit,
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    NextChapter,
+    PreviousChapter,
+    ShowContents,
+    Close,
+}
+
+pub(crate) struct Binding {
+    pub view: View,
+    pub keys: &'static [KeyCode],
+    /// How the keys are shown in the help overlay.
+    pub label: &'static str,
+    pub description: &'static str,
+    pub action: Action,
+}
+
+/// Every key binding in the TUI. Key handling and the help overlay both read from this,
+/// so a binding only has to be added here to work and be documented.
+pub(crate) static BINDINGS: &[Binding] = &[
+    Binding {
+        view: View::List,
+        keys: &[KeyCode::Up, KeyCode::Char('k')],
+        label: "Up, k",
+        description: "Select the previous story",
+        action: Action::Up,
+    },
+    Binding {
+        view: View::List,
+        keys: &[KeyCode::Down, KeyCode::Char('j')],
+        label: "Down, j",
+        description: "Select the next story",
+        action: Action::Down,
+    },
+    Binding {
+        view: View::List,
+        keys: &[KeyCode::Enter],
+        label: "Enter",
+        description: "Read the selected story",
+        action: Action::Open,
+    },
+    Binding {
+        view: View::List,
+        keys: &[KeyCode::Char('d')],
+        label: "d",
+        description: "Delete the selected story",
+        action: Action::Delete,
+    },
+    Binding {
+        view: View::List,
+        keys: &[KeyCode::Char('s')],
+        label: "s",
+        description: "Cycle the completion filter (all/complete/incomplete)",
+        action: Action::CycleStatusFilter,
+    },
+    Binding {
+        view: View::List,
+        keys: &[KeyCode::Char('t')],
+        label: "t",
+        description: "Filter by tag",
+        action: Action::FilterByTag,
+    },
+    Binding {
+        view: View::List,
+        keys: &[KeyCode::Char('?')],
+        label: "?",
+        description: "Show or hide this help",
+        action: Action::ToggleHelp,
+    },
+    Binding {
+        view: View::List,
+        keys: &[KeyCode::Char('q')],
+        label: "q, Ctrl-C",
+        description: "Quit",
+        action: Action::Quit,
+    },
+    Binding {
+        view: View::Reader,
+        keys: &[KeyCode::Up, KeyCode::Char('k')],
+        label: "Up, k",
+        description: "Scroll up",
+        action: Action::ScrollUp,
+    },
+    Binding {
+        view: View::Reader,
+        keys: &[KeyCode::Down, KeyCode::Char('j')],
+        label: "Down, j",
+        description: "Scroll down",
+        action: Action::ScrollDown,
+    },
+    Binding {
+        view: View::Reader,
+        keys: &[KeyCode::PageUp],
+        label: "PageUp",
+        description: "Scroll up a page",
+        action: Action::PageUp,
+    },
+    Binding {
+        view: View::Reader,
+        keys: &[KeyCode::PageDown, KeyCode::Char(' ')],
+        label: "PageDown, Space",
+        description: "Scroll down a page",
+        action: Action::PageDown,
+    },
+    Binding {
+        view: View::Reader,
+        keys: &[KeyCode::Right, KeyCode::Char('n')],
+        label: "Right, n",
+        description: "Go to the next chapter",
+        action: Action::NextChapter,
+    },
+    Binding {
+        view: View::Reader,
+        keys: &[KeyCode::Left, KeyCode::Char('p')],
+        label: "Left, p",
+        description: "Go to the previous chapter",
+        action: Action::PreviousChapter,
+    },
+    Binding {
+        view: View::Reader,
+        keys: &[KeyCode::Char('c')],
+        label: "c",
+        description: "Show the table of contents",
+        action: Action::ShowContents,
+    },
+    Binding {
+        view: View::Reader,
+        keys: &[KeyCode::Esc],
+        label: "Esc",
+        description: "Go back to the story list",
+        action: Action::Close,
+    },
+    Binding {
+        view: View::Reader,
+        keys: &[KeyCode::Char('?')],
+        label: "?",
+        description: "Show or hide this help",
+        action: Action::ToggleHelp,
+    },
+    Binding {
+        view: View::Reader,
+        keys: &[KeyCode::Char('q')],
+        label: "q, Ctrl-C",
+        description: "Quit",
+        action: Action::Quit,
+    },
+    Binding {
+        view: View::Contents,
+        keys: &[KeyCode::Up, KeyCode::Char('k')],
+        label: "Up, k",
+        description: "Select the previous chapter",
+        action: Action::Up,
+    },
+    Binding {
+        view: View::Contents,
+        keys: &[KeyCode::Down, KeyCode::Char('j')],
+        label: "Down, j",
+        description: "Select the next chapter",
+        action: Action::Down,
+    },
+    Binding {
+        view: View::Contents,
+        keys: &[KeyCode::Enter],
+        label: "Enter",
+        description: "Read the selected chapter",
+        action: Action::Open,
+    },
+    Binding {
+        view: View::Contents,
+        keys: &[KeyCode::Esc, KeyCode::Char('c')],
+        label: "Esc, c",
+        description: "Go back to the chapter being read",
+        action: Action::Close,
+    },
+];
+
+pub(crate) fn action_for(view: View, code: KeyCode) -> Option<Action> {
+    BINDINGS
+        .iter()
+        .find(|binding| binding.view == view && binding.keys.contains(&code))
+        .map(|binding| binding.action)
+}
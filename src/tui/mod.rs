@@ -1,18 +1,42 @@
 use crossterm::{
-    event::{poll, read, DisableMouseCapture, EnableMouseCapture, Event},
+    event::{DisableMouseCapture, EnableMouseCapture},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use tui::{backend::CrosstermBackend, Terminal};
+use tui::{
+    backend::{Backend, CrosstermBackend},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
+    Frame, Terminal,
+};
 
 use std::io::stdout;
 
+mod app;
+mod detail;
 mod event;
+mod fetch;
+mod keys;
+mod reader;
 
+use self::app::{App, Modal};
+use self::detail::DetailLoader;
+use self::fetch::ChapterFetcher;
+use self::keys::{View, BINDINGS};
+use self::reader::Reader;
+use crate::logger;
+use crate::progress;
+use crate::sql::Database;
+use crate::structs::{Completed, ListedStory};
 use crate::Args;
 use crate::Result;
 
-pub(crate) async fn start_tui(_args: Args) -> Result<()> {
+pub(crate) async fn start_tui(args: Args, db: &Database) -> Result<()> {
+    let _paused = logger::pause();
+    // Chapters fetched in the background would otherwise draw their progress over the TUI.
+    progress::set_enabled(false);
     enable_raw_mode()?;
     let mut stdout = stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
@@ -20,22 +44,208 @@ pub(crate) async fn start_tui(_args: Args) -> Result<()> {
     let mut terminal = Terminal::new(backend)?;
 
     let events = event::Events::new();
+    let app = App::new(
+        db,
+        DetailLoader::spawn(args.db.clone(), args.database_options(), events.sender()),
+        ChapterFetcher::spawn(
+            args.db.clone(),
+            args.database_options(),
+            args.dedup_text,
+            args.client_options(),
+            events.sender(),
+        ),
+    );
+    let mut app = match app {
+        Ok(app) => app,
+        Err(err) => {
+            restore_terminal(&mut terminal)?;
+            return Err(err);
+        }
+    };
 
-    loop {
+    while !app.should_quit {
+        terminal.draw(|frame| draw(frame, &mut app))?;
         match events.next() {
-            event::Event::Input(input) => match input {
-                _ => continue,
-            },
-            event::Event::Quit => break,
+            event::Event::Input(input) => app.handle_key(input, db),
+            event::Event::Detail(id, detail) => app.detail_loaded(id, detail),
+            event::Event::Fetched(id, story) => app.chapters_fetched(id, story),
+            // Resizing clears the screen so the next draw lays everything out from scratch.
+            event::Event::Resize(cols, rows) => terminal.resize(Rect::new(0, 0, cols, rows))?,
+            event::Event::Quit => app.quit(db),
             event::Event::Tick => continue,
         };
     }
+    restore_terminal(&mut terminal)
+}
+
+fn restore_terminal<B: Backend + std::io::Write>(terminal: &mut Terminal<B>) -> Result<()> {
     disable_raw_mode()?;
     execute!(
         terminal.backend_mut(),
         LeaveAlternateScreen,
         DisableMouseCapture
     )?;
-    terminal.show_cursor();
+    terminal.show_cursor()?;
     Ok(())
 }
+
+fn draw<B: Backend>(frame: &mut Frame<B>, app: &mut App) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(1)])
+        .split(frame.size());
+    frame.render_widget(Paragraph::new(app.status_line()), rows[1]);
+
+    if let Some(ref mut reader) = app.reader {
+        reader.render(frame, rows[0]);
+    } else {
+        draw_list(frame, rows[0], app);
+    }
+
+    if let Some(ref modal) = app.modal {
+        draw_modal(frame, modal, app);
+    }
+}
+
+fn draw_list<B: Backend>(frame: &mut Frame<B>, area: Rect, app: &mut App) {
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(area);
+
+    let items: Vec<ListItem> = app.stories.iter().map(list_item).collect();
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title("Stories"))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, chunks[0], &mut app.list_state);
+
+    detail::render(frame, chunks[1], &app.detail);
+}
+
+/// Shows a story in the list the way `list` prints it: its status, name, author, and how many
+/// chapters it has.
+fn list_item(story: &ListedStory) -> ListItem<'static> {
+    let status = match story.completed {
+        Completed::Complete => "[Complete]",
+        Completed::Incomplete => "[Ongoing]",
+        Completed::Unknown => "[?]",
+    };
+    ListItem::new(Spans::from(vec![
+        Span::raw(format!("{} ", status)),
+        Span::styled(
+            story.name.clone(),
+            Style::default().add_modifier(Modifier::BOLD),
+        ),
+        Span::raw(format!(
+            " by {} ({} chapter{})",
+            story.author,
+            story.chapter_count,
+            if story.chapter_count == 1 { "" } else { "s" }
+        )),
+    ]))
+}
+
+fn draw_modal<B: Backend>(frame: &mut Frame<B>, modal: &Modal, app: &App) {
+    match modal {
+        Modal::ConfirmDelete { name, .. } => {
+            draw_prompt(frame, "Delete story", format!("Delete \"{}\"? y/N", name))
+        }
+        Modal::TagFilter { input } => draw_prompt(
+            frame,
+            "Filter by tag (empty to clear)",
+            format!("{}_", input),
+        ),
+        Modal::Help => draw_help(frame, app.view()),
+        Modal::Contents { selected } => {
+            if let Some(ref reader) = app.reader {
+                draw_contents(frame, reader, *selected)
+            }
+        }
+    }
+}
+
+fn draw_contents<B: Backend>(frame: &mut Frame<B>, reader: &Reader, selected: usize) {
+    let items: Vec<ListItem> = reader
+        .contents()
+        .iter()
+        .map(|entry| {
+            let indent = "  ".repeat(entry.depth);
+            match entry.chapter {
+                Some(chapter) if chapter == reader.chapter() => ListItem::new(Spans::from(vec![
+                    Span::raw(indent),
+                    Span::styled(
+                        entry.name.clone(),
+                        Style::default().add_modifier(Modifier::UNDERLINED),
+                    ),
+                ])),
+                Some(_) => ListItem::new(format!("{}{}", indent, entry.name)),
+                None => ListItem::new(Spans::from(vec![
+                    Span::raw(indent),
+                    Span::styled(
+                        entry.name.clone(),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                ])),
+            }
+        })
+        .collect();
+    let size = frame.size();
+    let area = centered_rect(60, size.height.saturating_sub(4), size);
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Contents (Enter to read, Esc to close)"),
+        )
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut state = ListState::default();
+    state.select(Some(selected));
+    frame.render_widget(Clear, area);
+    frame.render_stateful_widget(list, area, &mut state);
+}
+
+fn draw_prompt<B: Backend>(frame: &mut Frame<B>, title: &str, text: String) {
+    let area = centered_rect(50, 5, frame.size());
+    let paragraph = Paragraph::new(vec![Spans::from(""), Spans::from(text)])
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: true });
+    frame.render_widget(Clear, area);
+    frame.render_widget(paragraph, area);
+}
+
+fn draw_help<B: Backend>(frame: &mut Frame<B>, view: View) {
+    let bindings = BINDINGS.iter().filter(|binding| binding.view == view);
+    let label_width = bindings.clone().map(|b| b.label.len()).max().unwrap_or(0);
+    let lines: Vec<Spans> = bindings
+        .map(|binding| {
+            Spans::from(vec![
+                Span::styled(
+                    format!("{:width$}  ", binding.label, width = label_width),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(binding.description),
+            ])
+        })
+        .collect();
+    let area = centered_rect(60, lines.len() as u16 + 2, frame.size());
+    let paragraph = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Keys (? or Esc to close)"),
+    );
+    frame.render_widget(Clear, area);
+    frame.render_widget(paragraph, area);
+}
+
+/// Gets a rectangle `percent_x` percent as wide as `area` and `height` rows tall, centered in it.
+fn centered_rect(percent_x: u16, height: u16, area: Rect) -> Rect {
+    let width = area.width * percent_x / 100;
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
@@ -0,0 +1,231 @@
+use tui::{
+    backend::Backend,
+    layout::Rect,
+    text::Spans,
+    widgets::{Block, Borders, Paragraph, Wrap},
+    Frame,
+};
+
+use crate::structs::{ChapterText, Content, ReadingProgress, Story};
+use crate::text::html_to_lines;
+
+use std::collections::HashMap;
+
+struct ReaderChapter {
+    id: String,
+    name: String,
+    /// The chapter's text, or `None` if it hasn't been downloaded yet.
+    html: Option<String>,
+}
+
+/// A line in the story's table of contents: a section, or a chapter with its index in reading
+/// order.
+pub(crate) struct ContentsEntry {
+    pub depth: usize,
+    pub name: String,
+    pub chapter: Option<usize>,
+}
+
+/// Whether the text of a chapter that wasn't saved with any is being downloaded.
+pub(crate) enum Download {
+    Idle,
+    Running,
+    Failed(String),
+}
+
+/// A story opened for reading, one chapter at a time.
+pub(crate) struct Reader {
+    pub story_id: String,
+    story_name: String,
+    chapters: Vec<ReaderChapter>,
+    contents: Vec<ContentsEntry>,
+    chapter: usize,
+    pub download: Download,
+    /// The current chapter as lines of plain text, worked out when the chapter is opened.
+    lines: Vec<String>,
+    scroll: u16,
+    /// How many lines fit on screen the last time the reader was drawn, for paging.
+    page_height: u16,
+}
+
+impl Reader {
+    /// Opens a story at the position in `progress`, or at the start if there is none or it
+    /// points to a chapter that no longer exists.
+    pub fn open(story: Story, progress: Option<ReadingProgress>) -> Reader {
+        let mut chapters = Vec::with_capacity(story.num_chapters());
+        let mut contents = Vec::new();
+        collect_chapters(story.chapters, 0, &mut chapters, &mut contents);
+        let (chapter, scroll) = progress
+            .and_then(|progress| {
+                chapters
+                    .iter()
+                    .position(|chapter| chapter.id == progress.chapter_id)
+                    .map(|idx| (idx, progress.offset.min(u16::MAX as usize) as u16))
+            })
+            .unwrap_or((0, 0));
+        let mut reader = Reader {
+            story_id: story.source.to_id(),
+            story_name: story.name,
+            chapters,
+            contents,
+            chapter: 0,
+            download: Download::Idle,
+            lines: Vec::new(),
+            scroll: 0,
+            page_height: 0,
+        };
+        reader.go_to_chapter(chapter);
+        reader.scroll = scroll;
+        reader
+    }
+
+    /// Where the reader currently is, or `None` if the story has no chapters.
+    pub fn progress(&self) -> Option<ReadingProgress> {
+        self.chapters
+            .get(self.chapter)
+            .map(|chapter| ReadingProgress {
+                chapter_id: chapter.id.clone(),
+                offset: self.scroll as usize,
+            })
+    }
+
+    pub fn scroll_by(&mut self, lines: i32) {
+        let max = self.lines.len().saturating_sub(1).min(u16::MAX as usize) as i32;
+        self.scroll = (self.scroll as i32 + lines).clamp(0, max) as u16;
+    }
+
+    pub fn page_by(&mut self, pages: i32) {
+        self.scroll_by(pages * (self.page_height.max(2) as i32 - 1));
+    }
+
+    pub fn next_chapter(&mut self) {
+        if self.chapter + 1 < self.chapters.len() {
+            self.go_to_chapter(self.chapter + 1);
+        }
+    }
+
+    pub fn previous_chapter(&mut self) {
+        if self.chapter > 0 {
+            self.go_to_chapter(self.chapter - 1);
+        }
+    }
+
+    pub fn go_to_chapter(&mut self, idx: usize) {
+        self.chapter = idx;
+        self.scroll = 0;
+        self.lines = self
+            .chapters
+            .get(idx)
+            .and_then(|chapter| chapter.html.as_deref())
+            .map(html_to_lines)
+            .unwrap_or_default();
+    }
+
+    /// The story's sections and chapters, in reading order.
+    pub fn contents(&self) -> &[ContentsEntry] {
+        &self.contents
+    }
+
+    /// The index in reading order of the chapter being read.
+    pub fn chapter(&self) -> usize {
+        self.chapter
+    }
+
+    /// The id of the chapter being read, if it hasn't been downloaded yet.
+    pub fn missing_chapter(&self) -> Option<&str> {
+        self.chapters
+            .get(self.chapter)
+            .filter(|chapter| chapter.html.is_none())
+            .map(|chapter| chapter.id.as_str())
+    }
+
+    /// Takes in the text of chapters that have been downloaded since the story was opened.
+    pub fn fill_in(&mut self, story: Story) {
+        let mut fetched = Vec::new();
+        collect_chapters(story.chapters, 0, &mut fetched, &mut Vec::new());
+        let mut fetched: HashMap<String, String> = fetched
+            .into_iter()
+            .filter_map(|chapter| Some((chapter.id, chapter.html?)))
+            .collect();
+        for chapter in self.chapters.iter_mut() {
+            if chapter.html.is_none() {
+                chapter.html = fetched.remove(&chapter.id);
+            }
+        }
+        if self.lines.is_empty() {
+            let scroll = self.scroll;
+            self.go_to_chapter(self.chapter);
+            self.scroll = scroll;
+        }
+    }
+
+    pub fn render<B: Backend>(&mut self, frame: &mut Frame<B>, area: Rect) {
+        self.page_height = area.height.saturating_sub(2);
+        let title = match self.chapters.get(self.chapter) {
+            Some(chapter) => format!(
+                "{} - {} ({}/{})",
+                self.story_name,
+                chapter.name,
+                self.chapter + 1,
+                self.chapters.len()
+            ),
+            None => self.story_name.clone(),
+        };
+        let text: Vec<Spans> = match (self.chapters.is_empty(), &self.download) {
+            (true, _) => vec![Spans::from("This story has no chapters.")],
+            (false, _) if self.missing_chapter().is_none() => self
+                .lines
+                .iter()
+                .map(|line| Spans::from(line.as_str()))
+                .collect(),
+            (false, Download::Running) => vec![Spans::from("Downloading chapter text...")],
+            (false, Download::Failed(err)) => vec![Spans::from(format!(
+                "Could not download this chapter: {}",
+                err
+            ))],
+            (false, Download::Idle) => {
+                vec![Spans::from("This chapter hasn't been downloaded yet.")]
+            }
+        };
+        let paragraph = Paragraph::new(text)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .wrap(Wrap { trim: false })
+            .scroll((self.scroll, 0));
+        frame.render_widget(paragraph, area);
+    }
+}
+
+fn collect_chapters(
+    contents: Vec<Content>,
+    depth: usize,
+    chapters: &mut Vec<ReaderChapter>,
+    entries: &mut Vec<ContentsEntry>,
+) {
+    for content in contents {
+        match content {
+            Content::Chapter(chapter) => {
+                entries.push(ContentsEntry {
+                    depth,
+                    name: chapter.name.clone(),
+                    chapter: Some(chapters.len()),
+                });
+                chapters.push(ReaderChapter {
+                    id: chapter.id,
+                    name: chapter.name,
+                    html: match chapter.text {
+                        ChapterText::Hydrated(html) => Some(html),
+                        ChapterText::Dehydrated => None,
+                    },
+                });
+            }
+            Content::Section(section) => {
+                entries.push(ContentsEntry {
+                    depth,
+                    name: section.name,
+                    chapter: None,
+                });
+                collect_chapters(section.chapters, depth + 1, chapters, entries);
+            }
+        }
+    }
+}
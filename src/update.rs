@@ -0,0 +1,788 @@
+use chrono::{DateTime, Duration as TimeSpan, NaiveDate, Utc};
+use reqwest::{header::CONTENT_TYPE, Client};
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use crate::client::get;
+use crate::error::ArchiveError;
+use crate::images::{
+    decoded_url, downloading_images, image_hash, localize, remote_image_urls, with_remote_urls,
+};
+use crate::progress;
+use crate::sql::{timestamp, Database};
+use crate::structs::{
+    Chapter, ChapterImage, ChapterText, Completed, Content, Cover, Story, StorySource,
+};
+use crate::Result;
+
+/// How stories should be brought up to date.
+#[derive(Debug, Clone, Default)]
+pub struct UpdateOptions {
+    /// Download each story in full again instead of only fetching chapters that are missing.
+    pub force_refresh: bool,
+    /// Only refresh each story's details, like its name, tags, and authors, leaving chapters
+    /// alone.
+    pub metadata_only: bool,
+    /// If an earlier update of the whole archive was interrupted, only check the stories it
+    /// hadn't got to yet instead of starting over.
+    pub resume: bool,
+    /// The longest to spend updating any one story before counting it as failed, so that a
+    /// single slow story can't hold up the rest. `None` waits as long as it takes.
+    pub timeout: Option<Duration>,
+    /// When updating the whole archive, only check stories that haven't gained or changed a
+    /// chapter since this time.
+    pub since: Option<DateTime<Utc>>,
+    /// When updating the whole archive, also check stories marked complete. They rarely gain
+    /// chapters, so they're skipped otherwise.
+    pub include_complete: bool,
+    /// Only work out what would change from each story's chapter list, without fetching
+    /// chapters or saving anything, not even when stories were checked.
+    pub dry_run: bool,
+}
+
+/// Reads a cutoff for `update --since`: either how long ago it was, like `12h`, `7d`, or `2w`,
+/// or a date or time like `2024-01-31` or `2024-01-31T12:00:00Z`. Dates are taken as midnight
+/// UTC.
+pub(crate) fn parse_cutoff(value: &str) -> std::result::Result<DateTime<Utc>, String> {
+    cutoff_before(value, Utc::now())
+}
+
+fn cutoff_before(value: &str, now: DateTime<Utc>) -> std::result::Result<DateTime<Utc>, String> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(DateTime::from_utc(date.and_hms(0, 0, 0), Utc));
+    }
+    match parse_span(value) {
+        Some(span) => Ok(now - span),
+        None => Err("expected an age like 12h, 7d, or 2w, or a date like 2024-01-31".to_owned()),
+    }
+}
+
+/// Reads how often `daemon` should check for updates, like `30m`, `6h`, or `1d`.
+pub(crate) fn parse_interval(value: &str) -> std::result::Result<Duration, String> {
+    parse_span(value.trim())
+        .and_then(|span| span.to_std().ok())
+        .filter(|interval| !interval.is_zero())
+        .ok_or_else(|| "expected a length of time like 30m, 6h, or 1d".to_owned())
+}
+
+/// Reads a length of time given as a number of minutes, hours, days, or weeks, like `12h`.
+/// Negative lengths aren't accepted.
+fn parse_span(value: &str) -> Option<TimeSpan> {
+    let (split, unit) = value.char_indices().last()?;
+    let amount = match &value[..split] {
+        amount if amount.starts_with(['-', '+']) => return None,
+        amount => i64::from(amount.parse::<u32>().ok()?),
+    };
+    match unit {
+        'm' => Some(TimeSpan::minutes(amount)),
+        'h' => Some(TimeSpan::hours(amount)),
+        'd' => Some(TimeSpan::days(amount)),
+        'w' => Some(TimeSpan::weeks(amount)),
+        _ => None,
+    }
+}
+
+/// What changed in a story when it was brought up to date.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StoryUpdate {
+    pub new_chapters: usize,
+    /// Chapters that were already saved but have been edited since.
+    pub changed_chapters: usize,
+    pub repaired_dates: usize,
+}
+
+/// The outcome of updating one story.
+#[derive(Debug)]
+pub struct UpdateReport {
+    pub source: StorySource,
+    pub result: Result<StoryUpdate>,
+}
+
+/// The outcome of updating the whole archive.
+#[derive(Debug)]
+pub struct ArchiveUpdate {
+    pub reports: Vec<UpdateReport>,
+    /// Complete stories that weren't checked because [`UpdateOptions::include_complete`] was
+    /// unset.
+    pub skipped_complete: usize,
+}
+
+impl Database {
+    /// Brings one story up to date with its source.
+    pub async fn update_one(
+        &self,
+        client: &Client,
+        source: StorySource,
+        options: &UpdateOptions,
+    ) -> UpdateReport {
+        let update = self.update_story(client, source.clone(), options);
+        let result = match options.timeout {
+            Some(limit) => tokio::time::timeout(limit, update)
+                .await
+                .unwrap_or_else(|_| Err(ArchiveError::TimedOut(source.to_url(), limit))),
+            None => update.await,
+        };
+        if result.is_ok() && !options.dry_run {
+            if let Err(err) = self.mark_checked(&source.to_id()) {
+                return UpdateReport {
+                    source,
+                    result: Err(err),
+                };
+            }
+        }
+        UpdateReport { source, result }
+    }
+
+    /// Brings every story in the archive up to date, fetching them all concurrently. A story
+    /// that fails to update doesn't stop the others; its report holds the error.
+    ///
+    /// Each story is marked as checked as soon as it's updated, so if the run is interrupted,
+    /// the next one with [`UpdateOptions::resume`] set only checks the stories that were left.
+    /// Stories that failed are tried again.
+    pub async fn update_all(
+        &self,
+        client: &Client,
+        options: &UpdateOptions,
+    ) -> Result<ArchiveUpdate> {
+        let started = match self.update_cycle_started()? {
+            Some(started) if options.resume => {
+                log::info!("Resuming an update that was interrupted.");
+                started
+            }
+            _ if options.dry_run => timestamp(Utc::now()),
+            _ => self.start_update_cycle()?,
+        };
+        let mut stories = self.stories_to_check(&started)?;
+        if let Some(since) = options.since {
+            let stale: HashSet<String> = self
+                .get_stories_needing_update(&timestamp(since))?
+                .iter()
+                .map(StorySource::to_id)
+                .collect();
+            stories.retain(|(source, _)| stale.contains(&source.to_id()));
+        }
+        let (stories, complete): (Vec<_>, Vec<_>) =
+            stories.into_iter().partition(|(_, completed)| {
+                options.include_complete || *completed != Completed::Complete
+            });
+        let reports = progress::join_counted(
+            "Checking stories for updates",
+            stories
+                .into_iter()
+                .map(|(source, _)| self.update_one(client, source, options)),
+        )
+        .await;
+        if !options.dry_run {
+            self.finish_update_cycle()?;
+        }
+        Ok(ArchiveUpdate {
+            reports,
+            skipped_complete: complete.len(),
+        })
+    }
+
+    async fn update_story(
+        &self,
+        client: &Client,
+        source: StorySource,
+        options: &UpdateOptions,
+    ) -> Result<StoryUpdate> {
+        if options.dry_run {
+            self.preview_story(client, source).await
+        } else if options.metadata_only {
+            if !self.story_exists_with_id(&source.to_id())? {
+                return Err(ArchiveError::StoryNotExists(source.to_url()));
+            }
+            let story = source.parser().get_metadata(client, source).await?;
+            self.save_story_metadata(&story)?;
+            self.update_cover(client, &story).await?;
+            Ok(StoryUpdate::default())
+        } else if options.force_refresh {
+            let existing_story = self
+                .get_story_by_id(source.to_id().as_str())?
+                .ok_or_else(|| ArchiveError::StoryNotExists(source.to_url()))?;
+            let story = source.parser().get_story(client, source).await?;
+            let mut existing = HashMap::new();
+            chapters_by_id(&mut existing, &existing_story.chapters);
+            let mut fetched = HashMap::new();
+            chapters_by_id(&mut fetched, &story.chapters);
+
+            let mut update = StoryUpdate::default();
+            for (id, chapter) in fetched.into_iter() {
+                match existing.get(id) {
+                    None => update.new_chapters += 1,
+                    Some(old) if chapter_changed(old, chapter) => update.changed_chapters += 1,
+                    Some(_) => (),
+                }
+            }
+            self.save_story_metadata(&story)?;
+            self.save_story_contents(&story)?;
+            self.update_cover(client, &story).await?;
+            if update.new_chapters + update.changed_chapters > 0 {
+                self.mark_updated(&story.source.to_id())?;
+            }
+            self.download_images(client, &story.source.to_id(), None)
+                .await?;
+            Ok(update)
+        } else {
+            let story_id = source.to_id();
+            let update = self.refresh_story(client, source, true).await?;
+            self.download_images(client, &story_id, None).await?;
+            Ok(update)
+        }
+    }
+
+    /// Fetches a story's chapter list, then fetches only the chapters that need it: ones that
+    /// aren't saved yet or have been edited since they were saved, if `add_new` is set, and
+    /// saved ones that still have the placeholder date because their real one wasn't known when
+    /// they were added.
+    ///
+    /// Edits are spotted from the chapter list alone: by the text's hash for sources whose
+    /// chapter list comes with text, and otherwise by a changed name or posting date.
+    pub async fn refresh_story(
+        &self,
+        client: &Client,
+        source: StorySource,
+        add_new: bool,
+    ) -> Result<StoryUpdate> {
+        let parser = source.parser();
+        let existing_story = self
+            .get_story_by_id(source.to_id().as_str())?
+            .ok_or_else(|| ArchiveError::StoryNotExists(source.to_url()))?;
+        let mut skeleton = parser.get_skeleton(client, source).await?;
+        self.update_cover(client, &skeleton).await?;
+
+        let mut undated = HashSet::new();
+        placeholder_dated(&mut undated, &existing_story.chapters);
+
+        let (new_chapters, changed) = match add_new {
+            true => listed_changes(&existing_story, &skeleton),
+            false => (Vec::new(), HashSet::new()),
+        };
+        if new_chapters.is_empty() && undated.is_empty() && changed.is_empty() {
+            return Ok(StoryUpdate::default());
+        }
+
+        let needed: HashSet<&str> = new_chapters
+            .iter()
+            .chain(undated.iter())
+            .chain(changed.iter())
+            .map(|id| id.as_str())
+            .collect();
+        retain_chapters(&mut skeleton.chapters, &|id| needed.contains(id));
+        let story = parser.fill_skeleton(client, skeleton).await?;
+        let story_id = story.source.to_id();
+
+        let mut update = StoryUpdate::default();
+        for chapter in new_chapters.iter() {
+            let found = story
+                .find_chapter(chapter.to_owned())
+                .expect("Chapters to add came from this story's skeleton");
+            let parent_id = found.parent.map(|content| content.id());
+            // New sections are saved along with everything in them.
+            if parent_id.is_some_and(|parent| new_chapters.iter().any(|id| id == parent)) {
+                continue;
+            }
+            self.save_content(
+                found.chapter,
+                &story_id,
+                parent_id,
+                self.next_position(&story_id, parent_id)?,
+            )?;
+            update.new_chapters += 1;
+        }
+        for id in changed.iter() {
+            if let Some(Content::Chapter(chapter)) =
+                story.find_chapter(id.to_owned()).map(|found| found.chapter)
+            {
+                self.update_chapter(chapter)?;
+                update.changed_chapters += 1;
+            }
+        }
+        for id in undated.into_iter().filter(|id| !changed.contains(id)) {
+            if let Some(Content::Chapter(chapter)) =
+                story.find_chapter(id).map(|found| found.chapter)
+            {
+                if !chapter.has_placeholder_date() {
+                    self.update_chapter_date(&chapter.id, &chapter.date_posted)?;
+                    update.repaired_dates += 1;
+                }
+            }
+        }
+        Ok(update)
+    }
+
+    /// Works out how many chapters updating a story would add or change, going by its chapter
+    /// list alone as [`Self::refresh_story`] does. Nothing is saved.
+    async fn preview_story(&self, client: &Client, source: StorySource) -> Result<StoryUpdate> {
+        let existing_story = self
+            .get_story_by_id(source.to_id().as_str())?
+            .ok_or_else(|| ArchiveError::StoryNotExists(source.to_url()))?;
+        let skeleton = source.parser().get_skeleton(client, source).await?;
+        let (new_contents, changed) = listed_changes(&existing_story, &skeleton);
+        // New sections are listed along with their chapters, but only chapters are counted.
+        let new_chapters = skeleton
+            .all_chapters()
+            .iter()
+            .filter(|chapter| new_contents.contains(&chapter.id))
+            .count();
+        Ok(StoryUpdate {
+            new_chapters,
+            changed_chapters: changed.len(),
+            repaired_dates: 0,
+        })
+    }
+
+    /// Downloads and saves the text of one chapter of a saved story without fetching the rest
+    /// of it, returning the chapter with its text.
+    pub async fn hydrate_chapter(
+        &self,
+        client: &Client,
+        story_id: &str,
+        chapter_id: &str,
+    ) -> Result<Chapter> {
+        let story = self
+            .get_story_by_id(story_id)?
+            .ok_or_else(|| ArchiveError::StoryNotExists(story_id.to_owned()))?;
+        let mut chapter = story
+            .all_chapters()
+            .into_iter()
+            .find(|chapter| chapter.id == chapter_id)
+            .cloned()
+            .ok_or_else(|| ArchiveError::ChapterNotExists(chapter_id.to_owned()))?;
+        let was_undated = chapter.has_placeholder_date();
+        story
+            .source
+            .parser()
+            .hydrate_chapter(client, &story, &mut chapter)
+            .await?;
+        self.update_chapter_text(&chapter.id, &chapter.text)?;
+        if was_undated && !chapter.has_placeholder_date() {
+            self.update_chapter_date(&chapter.id, &chapter.date_posted)?;
+        }
+        self.download_images(client, story_id, Some(&[chapter.id.as_str()]))
+            .await?;
+        Ok(chapter)
+    }
+
+    /// Downloads a story's cover image if it has one that isn't saved yet, such as when the
+    /// story is new or its cover has changed. A cover that can't be downloaded is only warned
+    /// about, since the story is fine without it.
+    pub async fn update_cover(&self, client: &Client, story: &Story) -> Result<()> {
+        let url = match story.cover_url {
+            Some(ref url) => url,
+            None => return Ok(()),
+        };
+        let story_id = story.source.to_id();
+        if self.saved_cover_url(&story_id)?.as_ref() == Some(url) {
+            return Ok(());
+        }
+        match download_image(client, url).await {
+            Ok(cover) => self.save_cover(&story_id, &cover),
+            Err(err) => {
+                log::warn!(
+                    "Could not download the cover of \"{}\": {}",
+                    story.name,
+                    err
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Downloads the images in a saved story's chapters that are still on the sites they came
+    /// from and points the chapters at the saved copies, returning how many were saved. Only
+    /// the chapters in `chapter_ids` are looked at if it's given, such as ones that were just
+    /// fetched. Does nothing unless `--download-images` is on. An image that can't be downloaded
+    /// is only warned about, and the chapter keeps pointing at the site.
+    pub async fn download_images(
+        &self,
+        client: &Client,
+        story_id: &str,
+        chapter_ids: Option<&[&str]>,
+    ) -> Result<usize> {
+        if !downloading_images() {
+            return Ok(0);
+        }
+        let story = self
+            .get_story_by_id(story_id)?
+            .ok_or_else(|| ArchiveError::StoryNotExists(story_id.to_owned()))?;
+        let chapters: Vec<(&Chapter, Vec<&str>)> = story
+            .all_chapters()
+            .into_iter()
+            .filter(|chapter| chapter_ids.is_none_or(|ids| ids.contains(&chapter.id.as_str())))
+            .map(|chapter| (chapter, remote_image_urls(chapter.text.as_str())))
+            .filter(|(_, urls)| !urls.is_empty())
+            .collect();
+        let mut urls: Vec<&str> = chapters
+            .iter()
+            .flat_map(|(_, urls)| urls.iter().copied())
+            .collect();
+        urls.sort_unstable();
+        urls.dedup();
+        if urls.is_empty() {
+            return Ok(0);
+        }
+        let downloads = progress::join_counted(
+            format!("Downloading images for \"{}\"", story.name),
+            urls.iter().map(|url| async move {
+                // The URLs are as written in the HTML, so entities have to be decoded first.
+                let result = download_image(client, &decoded_url(url)).await;
+                (*url, result)
+            }),
+        )
+        .await;
+        let mut images = HashMap::new();
+        for (url, result) in downloads {
+            match result {
+                Ok(image) => {
+                    images.insert(url, image);
+                }
+                Err(err) => {
+                    log::warn!("Could not download an image in \"{}\": {}", story.name, err)
+                }
+            }
+        }
+        let mut saved = 0;
+        for (chapter, urls) in chapters {
+            let mut text = chapter.text.as_str().to_owned();
+            for url in urls {
+                if let Some(image) = images.get(url) {
+                    let hash = image_hash(&image.bytes);
+                    self.save_chapter_image(&ChapterImage {
+                        chapter_id: chapter.id.clone(),
+                        hash: hash.clone(),
+                        url: image.url.clone(),
+                        media_type: image.media_type.clone(),
+                        bytes: image.bytes.clone(),
+                    })?;
+                    text = localize(&text, url, &hash);
+                    saved += 1;
+                }
+            }
+            self.update_chapter_text(&chapter.id, &ChapterText::Hydrated(text))?;
+        }
+        Ok(saved)
+    }
+
+    /// Downloads the text of every chapter of a saved story that was stored without it,
+    /// returning how many chapters were filled in.
+    pub async fn hydrate_story(&self, client: &Client, source: StorySource) -> Result<usize> {
+        let story = self
+            .get_story_by_id(source.to_id().as_str())?
+            .ok_or_else(|| ArchiveError::StoryNotExists(source.to_url()))?;
+        let mut dehydrated = HashSet::new();
+        dehydrated_chapters(&mut dehydrated, &story.chapters);
+        if dehydrated.is_empty() {
+            return Ok(0);
+        }
+        // The saved story doubles as a skeleton: parsers only fetch the chapters without text.
+        let story = source.parser().fill_skeleton(client, story).await?;
+        let mut hydrated = Vec::new();
+        for id in dehydrated.into_iter() {
+            if let Some(Content::Chapter(chapter)) =
+                story.find_chapter(id).map(|found| found.chapter)
+            {
+                if let ChapterText::Hydrated(_) = chapter.text {
+                    self.update_chapter_text(&chapter.id, &chapter.text)?;
+                    hydrated.push(chapter.id.as_str());
+                }
+            }
+        }
+        self.download_images(client, &story.source.to_id(), Some(&hydrated))
+            .await?;
+        Ok(hydrated.len())
+    }
+}
+
+/// Downloads an image, such as a cover, taking its type from the response if the server says.
+async fn download_image(client: &Client, url: &str) -> Result<Cover> {
+    let response = get(client, url).await?;
+    let media_type = match response.headers().get(CONTENT_TYPE) {
+        Some(header) => {
+            let header = header.to_str().unwrap_or_default();
+            let media_type = header.split(';').next().unwrap_or_default().trim();
+            if !media_type.starts_with("image/") {
+                return Err(ArchiveError::PageError(format!(
+                    "{} is {}, not an image",
+                    url, header
+                )));
+            }
+            media_type.to_lowercase()
+        }
+        None => media_type_from_url(url).to_owned(),
+    };
+    Ok(Cover {
+        url: url.to_owned(),
+        media_type,
+        bytes: response.bytes().await?.to_vec(),
+    })
+}
+
+/// Guesses an image's type from its file extension, for servers that don't say.
+fn media_type_from_url(url: &str) -> &'static str {
+    let path = url.split(['?', '#']).next().unwrap_or(url).to_lowercase();
+    match path.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        _ => "image/jpeg",
+    }
+}
+
+fn dehydrated_chapters(ids: &mut HashSet<String>, contents: &[Content]) {
+    for content in contents {
+        match content {
+            Content::Chapter(chapter) if matches!(chapter.text, ChapterText::Dehydrated) => {
+                ids.insert(chapter.id.clone());
+            }
+            Content::Chapter(_) => (),
+            Content::Section(section) => dehydrated_chapters(ids, &section.chapters),
+        }
+    }
+}
+
+fn chapters_by_id<'a>(chapters: &mut HashMap<&'a str, &'a Chapter>, contents: &'a [Content]) {
+    for content in contents {
+        match content {
+            Content::Chapter(chapter) => {
+                chapters.insert(&chapter.id, chapter);
+            }
+            Content::Section(section) => chapters_by_id(chapters, &section.chapters),
+        }
+    }
+}
+
+/// Compares a story's freshly fetched chapter list with the saved story, returning the ids of
+/// the sections and chapters that aren't saved yet, in reading order so that they can be saved
+/// in the right positions, and of the saved chapters that have changed.
+fn listed_changes(saved: &Story, listed: &Story) -> (Vec<String>, HashSet<String>) {
+    let mut saved_ids = Vec::new();
+    flatten_content(&mut saved_ids, &saved.chapters);
+    let saved_ids: HashSet<String> = saved_ids.into_iter().collect();
+    let mut new_contents = Vec::new();
+    flatten_content(&mut new_contents, &listed.chapters);
+    new_contents.retain(|id| !saved_ids.contains(id));
+
+    let mut saved_chapters = HashMap::new();
+    chapters_by_id(&mut saved_chapters, &saved.chapters);
+    let mut listed_chapters = HashMap::new();
+    chapters_by_id(&mut listed_chapters, &listed.chapters);
+    let changed = listed_chapters
+        .into_iter()
+        .filter_map(|(id, chapter)| {
+            let saved = saved_chapters.get(id)?;
+            chapter_changed(saved, chapter).then(|| id.to_owned())
+        })
+        .collect();
+    (new_contents, changed)
+}
+
+/// Whether a freshly fetched chapter differs from the saved one. Text is compared by its hash,
+/// and only when both have it; dates only when both are real.
+fn chapter_changed(saved: &Chapter, fetched: &Chapter) -> bool {
+    let text_changed = match (&saved.text, &fetched.text) {
+        (ChapterText::Hydrated(saved), ChapterText::Hydrated(_)) => {
+            // Saved images point into the archive, which the site's copy doesn't.
+            let saved = ChapterText::Hydrated(with_remote_urls(saved).into_owned());
+            saved.content_hash() != fetched.text.content_hash()
+        }
+        _ => false,
+    };
+    let date_changed = !saved.has_placeholder_date()
+        && !fetched.has_placeholder_date()
+        && saved.date_posted != fetched.date_posted;
+    text_changed || date_changed || saved.name != fetched.name
+}
+
+fn flatten_content(ids: &mut Vec<String>, contents: &[Content]) {
+    for content in contents {
+        ids.push(content.id().to_owned());
+        if let Content::Section(s) = content {
+            flatten_content(ids, &s.chapters);
+        }
+    }
+}
+
+/// Collects the ids of chapters that still have the placeholder date.
+pub(crate) fn placeholder_dated(ids: &mut HashSet<String>, contents: &[Content]) {
+    for content in contents {
+        match content {
+            Content::Chapter(chapter) if chapter.has_placeholder_date() => {
+                ids.insert(chapter.id.clone());
+            }
+            Content::Chapter(_) => (),
+            Content::Section(section) => placeholder_dated(ids, &section.chapters),
+        }
+    }
+}
+
+/// Drops chapters for which `keep` returns false, leaving sections in place.
+fn retain_chapters(contents: &mut Vec<Content>, keep: &dyn Fn(&str) -> bool) {
+    contents.retain_mut(|content| match content {
+        Content::Chapter(chapter) => keep(&chapter.id),
+        Content::Section(section) => {
+            retain_chapters(&mut section.chapters, keep);
+            true
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::structs::{Author, AuthorList, Section};
+    use chrono::{FixedOffset, TimeZone};
+
+    fn chapter(name: &str, text: ChapterText, timestamp: i64) -> Chapter {
+        Chapter {
+            id: "rr:6789:1".to_owned(),
+            name: name.to_owned(),
+            description: None,
+            text,
+            url: "https://www.royalroad.com/fiction/6789/chapter/1".to_owned(),
+            date_posted: FixedOffset::east(0).timestamp(timestamp, 0),
+            author: None,
+        }
+    }
+
+    fn numbered(number: usize, name: &str) -> Content {
+        let mut chapter = chapter(name, ChapterText::Dehydrated, 0);
+        chapter.id = format!("rr:6789:{}", number);
+        Content::Chapter(chapter)
+    }
+
+    fn story(chapters: Vec<Content>) -> Story {
+        let source = StorySource::RoyalRoad("6789".to_owned());
+        Story {
+            name: "A Serial".to_owned(),
+            authors: AuthorList::new(Author::new("Someone", "rr:1")),
+            description: None,
+            url: source.to_url(),
+            tags: Vec::new(),
+            chapters,
+            source,
+            completed: Completed::Incomplete,
+            language: None,
+            cover_url: None,
+            published: None,
+            last_updated: None,
+        }
+    }
+
+    #[test]
+    fn lists_new_and_changed_chapters() {
+        let saved = story(vec![numbered(1, "One"), numbered(2, "Two")]);
+        let listed = story(vec![
+            numbered(1, "One"),
+            numbered(2, "Two (Revised)"),
+            Content::Section(Section {
+                id: "rr:6789:part-2".to_owned(),
+                name: "Part Two".to_owned(),
+                description: None,
+                chapters: vec![numbered(3, "Three")],
+                url: None,
+                author: None,
+            }),
+        ]);
+        let (new_contents, changed) = listed_changes(&saved, &listed);
+        assert_eq!(new_contents, vec!["rr:6789:part-2", "rr:6789:3"]);
+        assert_eq!(changed, HashSet::from(["rr:6789:2".to_owned()]));
+        let (new_contents, changed) = listed_changes(&listed, &listed);
+        assert!(new_contents.is_empty() && changed.is_empty());
+    }
+
+    #[test]
+    fn reads_update_cutoffs() {
+        let now = Utc.ymd(2024, 3, 10).and_hms(12, 0, 0);
+        assert_eq!(
+            cutoff_before("7d", now),
+            Ok(Utc.ymd(2024, 3, 3).and_hms(12, 0, 0))
+        );
+        assert_eq!(
+            cutoff_before("36h", now),
+            Ok(Utc.ymd(2024, 3, 9).and_hms(0, 0, 0))
+        );
+        assert_eq!(
+            cutoff_before("2024-01-31", now),
+            Ok(Utc.ymd(2024, 1, 31).and_hms(0, 0, 0))
+        );
+        assert_eq!(
+            cutoff_before("2024-01-31T08:30:00+02:00", now),
+            Ok(Utc.ymd(2024, 1, 31).and_hms(6, 30, 0))
+        );
+        assert!(cutoff_before("7 days", now).is_err());
+        assert!(cutoff_before("-7d", now).is_err());
+        assert!(cutoff_before("+7d", now).is_err());
+        assert!(cutoff_before("", now).is_err());
+    }
+
+    #[test]
+    fn reads_daemon_intervals() {
+        assert_eq!(parse_interval("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_interval("6h"), Ok(Duration::from_secs(6 * 60 * 60)));
+        assert!(parse_interval("0h").is_err());
+        assert!(parse_interval("-1d").is_err());
+        assert!(parse_interval("6").is_err());
+    }
+
+    #[test]
+    fn guesses_cover_types_from_extensions() {
+        assert_eq!(
+            media_type_from_url("https://example.com/cover.PNG?v=2"),
+            "image/png"
+        );
+        assert_eq!(
+            media_type_from_url("https://example.com/cover.jpg"),
+            "image/jpeg"
+        );
+        assert_eq!(
+            media_type_from_url("https://example.com/cover"),
+            "image/jpeg"
+        );
+    }
+
+    #[test]
+    fn spots_edited_chapters() {
+        let text = |text: &str| ChapterText::Hydrated(text.to_owned());
+        let saved = chapter("One", text("<p>Tpyo</p>"), 1_600_000_000);
+
+        assert!(!chapter_changed(&saved, &saved.clone()));
+        assert!(chapter_changed(
+            &saved,
+            &chapter("One", text("<p>Typo</p>"), 1_600_000_000)
+        ));
+        assert!(chapter_changed(
+            &saved,
+            &chapter("One (Revised)", ChapterText::Dehydrated, 1_600_000_000)
+        ));
+        assert!(chapter_changed(
+            &saved,
+            &chapter("One", ChapterText::Dehydrated, 1_700_000_000)
+        ));
+        // A chapter list without text or dates says nothing about either.
+        assert!(!chapter_changed(
+            &saved,
+            &chapter("One", ChapterText::Dehydrated, 0)
+        ));
+        // Nor does saving its images.
+        let fetched = chapter("One", text("<img src=\"https://a.example/1.png\">"), 0);
+        let saved = chapter(
+            "One",
+            text(&localize(
+                fetched.text.as_str(),
+                "https://a.example/1.png",
+                "abc",
+            )),
+            0,
+        );
+        assert!(!chapter_changed(&saved, &fetched));
+    }
+}